@@ -0,0 +1,11 @@
+-- Add migration script here
+CREATE TABLE api_tokens(
+    id uuid PRIMARY KEY,
+    user_id uuid NOT NULL REFERENCES users (user_id),
+    name TEXT NOT NULL,
+    token_hash TEXT NOT NULL UNIQUE,
+    scopes TEXT[] NOT NULL,
+    created_at timestamptz NOT NULL,
+    last_used_at timestamptz,
+    revoked_at timestamptz
+);
@@ -0,0 +1,11 @@
+-- Add migration script here
+CREATE TABLE user_sessions(
+    id uuid PRIMARY KEY,
+    user_id uuid NOT NULL REFERENCES users (user_id),
+    created_at timestamptz NOT NULL,
+    last_seen_at timestamptz NOT NULL,
+    ip_address TEXT,
+    user_agent TEXT,
+    revoked_at timestamptz
+);
+CREATE INDEX user_sessions_user_id_idx ON user_sessions (user_id);
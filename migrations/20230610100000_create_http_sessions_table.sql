@@ -0,0 +1,7 @@
+-- Add migration script here
+CREATE TABLE http_sessions(
+    session_key TEXT PRIMARY KEY,
+    state TEXT NOT NULL,
+    expires_at timestamptz NOT NULL
+);
+CREATE INDEX http_sessions_expires_at_idx ON http_sessions (expires_at);
@@ -0,0 +1,23 @@
+-- Case variants of an address used to be stored as distinct subscribers.
+-- Which of them to keep is not ours to guess: list them, and let an operator
+-- merge or delete them before enforcing case-insensitive uniqueness.
+DO $$
+DECLARE
+    conflicts TEXT;
+BEGIN
+    SELECT string_agg(addresses, '; ' ORDER BY addresses) INTO conflicts
+    FROM (
+        SELECT string_agg(email || ' (' || id || ')', ', ' ORDER BY subscribed_at) AS addresses
+        FROM subscriptions
+        GROUP BY lower(email)
+        HAVING count(*) > 1
+    ) duplicates;
+    IF conflicts IS NOT NULL THEN
+        RAISE EXCEPTION 'Subscribers differ only by the case of their email: %', conflicts
+            USING HINT = 'Merge or delete the duplicates, then run the migration again.';
+    END IF;
+END
+$$;
+
+ALTER TABLE subscriptions DROP CONSTRAINT subscriptions_email_key;
+CREATE UNIQUE INDEX subscriptions_email_lower_key ON subscriptions (lower(email));
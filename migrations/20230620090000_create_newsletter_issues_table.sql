@@ -0,0 +1,13 @@
+CREATE TABLE newsletter_issues (
+    id uuid NOT NULL,
+    PRIMARY KEY (id),
+    title TEXT NOT NULL,
+    text_content TEXT NOT NULL,
+    html_content TEXT NOT NULL,
+    created_at timestamptz NOT NULL,
+    updated_at timestamptz NOT NULL,
+    -- Drafts have not been published yet.
+    published_at timestamptz NULL
+);
+CREATE INDEX newsletter_issues_created_at_idx ON newsletter_issues (created_at, id);
+CREATE INDEX subscriptions_subscribed_at_idx ON subscriptions (subscribed_at, id);
@@ -0,0 +1,44 @@
+-- Admin-configured receivers of lifecycle events.
+CREATE TABLE webhook_endpoints(
+    id uuid PRIMARY KEY,
+    url TEXT NOT NULL,
+    -- Needed in clear to sign the payloads.
+    secret TEXT NOT NULL,
+    event_types TEXT[] NOT NULL,
+    created_at timestamptz NOT NULL
+);
+
+-- The outbox: events are written in the transaction of the change they describe.
+CREATE TABLE webhook_events(
+    id uuid PRIMARY KEY,
+    event_type TEXT NOT NULL,
+    payload jsonb NOT NULL,
+    created_at timestamptz NOT NULL
+);
+
+-- One row per event and subscribed endpoint, picked up by the dispatcher once due.
+CREATE TABLE webhook_deliveries(
+    event_id uuid NOT NULL REFERENCES webhook_events (id) ON DELETE CASCADE,
+    endpoint_id uuid NOT NULL REFERENCES webhook_endpoints (id) ON DELETE CASCADE,
+    status TEXT NOT NULL,
+    attempts INT NOT NULL,
+    next_attempt_at timestamptz NOT NULL,
+    delivered_at timestamptz,
+    PRIMARY KEY (event_id, endpoint_id)
+);
+CREATE INDEX webhook_deliveries_due_idx
+    ON webhook_deliveries (next_attempt_at) WHERE status = 'pending';
+
+CREATE TABLE webhook_delivery_attempts(
+    id uuid PRIMARY KEY,
+    event_id uuid NOT NULL,
+    endpoint_id uuid NOT NULL,
+    attempted_at timestamptz NOT NULL,
+    response_status SMALLINT,
+    error TEXT,
+    duration_ms INT NOT NULL,
+    FOREIGN KEY (event_id, endpoint_id)
+        REFERENCES webhook_deliveries (event_id, endpoint_id) ON DELETE CASCADE
+);
+CREATE INDEX webhook_delivery_attempts_endpoint_idx
+    ON webhook_delivery_attempts (endpoint_id, attempted_at);
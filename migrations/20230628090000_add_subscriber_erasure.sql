@@ -0,0 +1,12 @@
+-- Deleting a subscriber takes their confirmation tokens along.
+ALTER TABLE subscription_tokens
+    DROP CONSTRAINT subscription_tokens_subscriber_id_fkey,
+    ADD CONSTRAINT subscription_tokens_subscriber_id_fkey
+        FOREIGN KEY (subscriber_id) REFERENCES subscriptions (id) ON DELETE CASCADE;
+
+-- What is left of erased subscribers: the SHA-256 of their lowercased address,
+-- so that they are not imported again.
+CREATE TABLE erased_subscribers(
+    email_hash TEXT PRIMARY KEY,
+    erased_at timestamptz NOT NULL
+);
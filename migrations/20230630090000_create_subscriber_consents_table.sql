@@ -0,0 +1,27 @@
+-- Evidence of how and when each subscriber agreed to receive the newsletter.
+CREATE TABLE subscriber_consents(
+    id uuid NOT NULL PRIMARY KEY,
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    action TEXT NOT NULL CHECK (action IN ('signup', 'confirmation')),
+    source TEXT NOT NULL CHECK (source IN ('form', 'api', 'import', 'email')),
+    ip TEXT,
+    user_agent TEXT,
+    consent_text_version TEXT,
+    recorded_at timestamptz NOT NULL
+);
+CREATE INDEX subscriber_consents_subscriber_id_idx ON subscriber_consents (subscriber_id, recorded_at);
+
+-- The log is append-only. Rows only go away along with their subscriber, when the
+-- foreign key cascades: that delete runs one trigger level deeper than a direct one.
+CREATE FUNCTION reject_consent_changes() RETURNS trigger AS $$
+BEGIN
+    IF TG_OP = 'DELETE' AND pg_trigger_depth() > 1 THEN
+        RETURN OLD;
+    END IF;
+    RAISE EXCEPTION 'subscriber_consents is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER subscriber_consents_append_only
+    BEFORE UPDATE OR DELETE ON subscriber_consents
+    FOR EACH ROW EXECUTE FUNCTION reject_consent_changes();
@@ -0,0 +1,14 @@
+ALTER TABLE subscriptions
+    ADD COLUMN frequency TEXT NOT NULL DEFAULT 'every_issue'
+        CHECK (frequency IN ('every_issue', 'weekly_digest')),
+    ADD COLUMN paused_until timestamptz NULL,
+    -- Weekly digests cover the issues published since then.
+    ADD COLUMN digest_sent_at timestamptz NULL;
+
+-- A new address only replaces the current one once its owner follows the link mailed to it.
+CREATE TABLE email_change_requests(
+    token TEXT NOT NULL PRIMARY KEY,
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    new_email TEXT NOT NULL,
+    requested_at timestamptz NOT NULL
+);
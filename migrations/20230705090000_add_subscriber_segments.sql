@@ -0,0 +1,25 @@
+ALTER TABLE subscriptions
+    -- Custom attributes, as an object of strings.
+    ADD COLUMN attributes jsonb NOT NULL DEFAULT '{}',
+    -- Last time the subscriber followed one of our links.
+    ADD COLUMN last_engaged_at timestamptz NULL;
+-- Confirming was the last thing we know confirmed subscribers did.
+UPDATE subscriptions SET last_engaged_at = subscribed_at WHERE status = 'confirmed';
+
+CREATE TABLE subscriber_tags(
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (subscriber_id, tag)
+);
+CREATE INDEX subscriber_tags_tag_idx ON subscriber_tags (tag);
+
+CREATE TABLE segments(
+    id uuid NOT NULL PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE,
+    filter TEXT NOT NULL,
+    created_at timestamptz NOT NULL
+);
+CREATE INDEX segments_created_at_idx ON segments (created_at, id);
+
+-- Issues sent to a segment keep its filter: later digests must reach the same audience.
+ALTER TABLE newsletter_issues ADD COLUMN segment_filter TEXT NULL;
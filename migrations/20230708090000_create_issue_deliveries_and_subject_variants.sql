@@ -0,0 +1,30 @@
+-- One row per issue emailed to a subscriber. The id keys the open tracking pixel.
+CREATE TABLE issue_deliveries(
+    id uuid NOT NULL PRIMARY KEY,
+    issue_id uuid NOT NULL REFERENCES newsletter_issues (id) ON DELETE CASCADE,
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    -- Position of the subject variant sent to the test slice of an A/B test.
+    variant INT NULL,
+    sent_at timestamptz NOT NULL,
+    opened_at timestamptz NULL,
+    UNIQUE (issue_id, subscriber_id)
+);
+CREATE INDEX issue_deliveries_subscriber_id_idx ON issue_deliveries (subscriber_id);
+
+CREATE TABLE subject_variants(
+    issue_id uuid NOT NULL REFERENCES newsletter_issues (id) ON DELETE CASCADE,
+    position INT NOT NULL,
+    subject TEXT NOT NULL,
+    -- The results the winner was picked from, stored when the test ends.
+    test_recipients BIGINT NULL,
+    test_opens BIGINT NULL,
+    PRIMARY KEY (issue_id, position)
+);
+
+ALTER TABLE newsletter_issues
+    -- The remainder of the recipients gets the winning subject then.
+    ADD COLUMN ab_test_ends_at timestamptz NULL,
+    ADD COLUMN ab_test_finished_at timestamptz NULL,
+    ADD COLUMN winning_variant INT NULL;
+CREATE INDEX newsletter_issues_ab_test_ends_at_idx ON newsletter_issues (ab_test_ends_at)
+    WHERE ab_test_finished_at IS NULL;
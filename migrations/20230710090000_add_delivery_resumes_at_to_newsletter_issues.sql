@@ -0,0 +1,5 @@
+-- Set when the email quota ran out before every recipient got the issue.
+ALTER TABLE newsletter_issues ADD COLUMN delivery_resumes_at timestamptz NULL;
+CREATE INDEX newsletter_issues_delivery_resumes_at_idx
+    ON newsletter_issues (delivery_resumes_at)
+    WHERE delivery_resumes_at IS NOT NULL;
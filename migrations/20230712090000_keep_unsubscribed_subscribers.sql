@@ -0,0 +1,6 @@
+-- Unsubscribing keeps the subscriber, as `unsubscribed`, so that their consent log
+-- survives: the withdrawal of their consent is its last entry.
+ALTER TABLE subscriber_consents
+    DROP CONSTRAINT subscriber_consents_action_check,
+    ADD CONSTRAINT subscriber_consents_action_check
+        CHECK (action IN ('signup', 'confirmation', 'withdrawal'));
@@ -0,0 +1,6 @@
+-- A failed delivery keeps its row, so that its attempts are counted: the issue is sent
+-- to it again once `delivery_resumes_at` is due, until it runs out of attempts.
+ALTER TABLE issue_deliveries
+    ADD COLUMN failed_attempts INT NOT NULL DEFAULT 0,
+    -- Set while the last attempt failed.
+    ADD COLUMN failed_at timestamptz NULL;
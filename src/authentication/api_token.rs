@@ -0,0 +1,174 @@
+use actix_web::http::header::HeaderMap;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use secrecy::{ExposeSecret, Secret};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Prefix shared by every token we mint, to make them easy to recognise in logs and secret scanners.
+const TOKEN_PREFIX: &str = "z2p_";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ApiScope {
+    IssuesPublish,
+    SubscribersRead,
+}
+
+impl ApiScope {
+    pub const ALL: [ApiScope; 2] = [ApiScope::IssuesPublish, ApiScope::SubscribersRead];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiScope::IssuesPublish => "issues:publish",
+            ApiScope::SubscribersRead => "subscribers:read",
+        }
+    }
+}
+
+impl TryFrom<&str> for ApiScope {
+    type Error = String;
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        ApiScope::ALL
+            .into_iter()
+            .find(|scope| scope.as_str() == value)
+            .ok_or_else(|| format!("{} is not a supported API token scope.", value))
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ApiTokenError {
+    #[error("Invalid API token.")]
+    InvalidToken(#[source] anyhow::Error),
+    #[error("The API token is missing the `{0}` scope.")]
+    InsufficientScope(&'static str),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+pub struct ApiToken {
+    pub id: Uuid,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+/// Mint a new token for `user_id`.
+/// Only its hash is stored: the returned secret cannot be retrieved again.
+#[tracing::instrument(name = "Create API token", skip(pool))]
+pub async fn create_api_token(
+    user_id: Uuid,
+    name: &str,
+    scopes: &[ApiScope],
+    pool: &PgPool,
+) -> Result<Secret<String>, anyhow::Error> {
+    let token = generate_api_token();
+    let scopes: Vec<String> = scopes.iter().map(|s| s.as_str().to_string()).collect();
+    sqlx::query!(
+        r#"
+    INSERT INTO api_tokens (id, user_id, name, token_hash, scopes, created_at)
+    VALUES ($1, $2, $3, $4, $5, $6)
+    "#,
+        Uuid::new_v4(),
+        user_id,
+        name,
+        hash_api_token(token.expose_secret()),
+        &scopes,
+        Utc::now(),
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store a new API token in the database.")?;
+    Ok(token)
+}
+
+/// Check that `token` is a live token carrying `required_scope` and return its owner.
+#[tracing::instrument(name = "Validate API token", skip(token, pool))]
+pub async fn validate_api_token(
+    token: Secret<String>,
+    required_scope: ApiScope,
+    pool: &PgPool,
+) -> Result<Uuid, ApiTokenError> {
+    let row = sqlx::query!(
+        r#"
+    UPDATE api_tokens SET last_used_at = now()
+    WHERE token_hash = $1 AND revoked_at IS NULL
+    RETURNING user_id, scopes
+    "#,
+        hash_api_token(token.expose_secret()),
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to validate an API token.")?
+    .ok_or_else(|| ApiTokenError::InvalidToken(anyhow::anyhow!("Unknown or revoked API token.")))?;
+
+    if !row.scopes.iter().any(|s| s == required_scope.as_str()) {
+        return Err(ApiTokenError::InsufficientScope(required_scope.as_str()));
+    }
+    Ok(row.user_id)
+}
+
+/// The token of an `Authorization: Bearer` header, if there is one.
+pub fn bearer_token(headers: &HeaderMap) -> Option<Secret<String>> {
+    headers
+        .get("Authorization")?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(|token| Secret::new(token.trim().to_string()))
+}
+
+#[tracing::instrument(name = "List API tokens", skip(pool))]
+pub async fn get_api_tokens(user_id: Uuid, pool: &PgPool) -> Result<Vec<ApiToken>, anyhow::Error> {
+    let tokens = sqlx::query_as!(
+        ApiToken,
+        r#"
+    SELECT id, name, scopes, created_at, last_used_at, revoked_at
+    FROM api_tokens
+    WHERE user_id = $1
+    ORDER BY created_at DESC
+    "#,
+        user_id,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to perform a query to retrieve API tokens.")?;
+    Ok(tokens)
+}
+
+/// Returns `false` if no live token with this id belongs to `user_id`.
+#[tracing::instrument(name = "Revoke API token", skip(pool))]
+pub async fn revoke_api_token(
+    user_id: Uuid,
+    token_id: Uuid,
+    pool: &PgPool,
+) -> Result<bool, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"UPDATE api_tokens SET revoked_at = now() WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL"#,
+        token_id,
+        user_id,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to revoke an API token in the database.")?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Tokens carry 256 bits of entropy, so a fast unsalted hash is enough
+/// and lets us look them up by hash.
+fn hash_api_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+fn generate_api_token() -> Secret<String> {
+    let mut rng = thread_rng();
+    let random: String = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(43)
+        .collect();
+    Secret::new(format!("{}{}", TOKEN_PREFIX, random))
+}
@@ -0,0 +1,100 @@
+use crate::{libs::e500, session_state::TypedSession};
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::{
+        header::{ContentType, HeaderMap},
+        Method,
+    },
+    web, FromRequest, HttpResponse,
+};
+use actix_web_flash_messages::FlashMessage;
+use actix_web_lab::middleware::Next;
+
+/// Name of the hidden form field carrying the token.
+pub const CSRF_FIELD: &str = "csrf_token";
+/// Header accepted as an alternative to the form field, for scripted clients.
+pub const CSRF_HEADER: &str = "X-CSRF-Token";
+
+/// Hidden input to embed in every form that posts to a CSRF-protected route.
+pub fn csrf_field(session: &TypedSession) -> Result<String, actix_web::Error> {
+    let token = session.get_or_insert_csrf_token().map_err(e500)?;
+    Ok(format!(
+        r#"<input type="hidden" name="{}" value="{}">"#,
+        CSRF_FIELD, token
+    ))
+}
+
+/// Reject state-changing requests whose token does not match the one stored in the session.
+pub async fn reject_invalid_csrf_tokens(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    if matches!(
+        *req.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE
+    ) {
+        return next.call(req).await.map(|res| res.map_into_boxed_body());
+    }
+
+    let session = {
+        let (http_request, payload) = req.parts_mut();
+        TypedSession::from_request(http_request, payload).await
+    }?;
+    let expected = session.get_csrf_token().map_err(e500)?;
+
+    let submitted = match req.headers().get(CSRF_HEADER).and_then(|h| h.to_str().ok()) {
+        Some(token) => Some(token.to_string()),
+        None => {
+            // The token travels in the form body: read it, then put it back for the handler.
+            let body = req.extract::<web::Bytes>().await?;
+            let token = serde_urlencoded::from_bytes::<Vec<(String, String)>>(&body)
+                .ok()
+                .and_then(|pairs| {
+                    pairs
+                        .into_iter()
+                        .find(|(key, _)| key == CSRF_FIELD)
+                        .map(|(_, value)| value)
+                });
+            let (_, mut payload) = actix_http::h1::Payload::create(true);
+            payload.unread_data(body);
+            req.set_payload(payload.into());
+            token
+        }
+    };
+
+    match (expected, submitted) {
+        (Some(expected), Some(submitted)) if constant_time_eq(&expected, &submitted) => {
+            next.call(req).await.map(|res| res.map_into_boxed_body())
+        }
+        _ => {
+            // Respond rather than error out, otherwise the flash message cookie is never set.
+            tracing::warn!("Rejected a request with a missing or invalid CSRF token.");
+            FlashMessage::error("Your form has expired. Please try again.").send();
+            let response = HttpResponse::Forbidden()
+                .content_type(ContentType::html())
+                .body("<p>Your form has expired. Please reload the page and try again.</p>");
+            Ok(req.into_response(response))
+        }
+    }
+}
+
+/// Check the header sent by scripted clients that authenticate with the session cookie.
+pub fn has_valid_csrf_header(
+    headers: &HeaderMap,
+    session: &TypedSession,
+) -> Result<bool, anyhow::Error> {
+    let submitted = headers.get(CSRF_HEADER).and_then(|h| h.to_str().ok());
+    Ok(match (session.get_csrf_token()?, submitted) {
+        (Some(expected), Some(submitted)) => constant_time_eq(&expected, submitted),
+        _ => false,
+    })
+}
+
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
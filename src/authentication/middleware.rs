@@ -1,4 +1,5 @@
 use crate::{
+    authentication::{touch_user_session, SessionTtl},
     libs::{e500, see_other},
     session_state::TypedSession,
 };
@@ -6,9 +7,10 @@ use actix_web::{
     body::MessageBody,
     dev::{ServiceRequest, ServiceResponse},
     error::InternalError,
-    FromRequest, HttpMessage,
+    web, FromRequest, HttpMessage,
 };
 use actix_web_lab::middleware::Next;
+use sqlx::PgPool;
 use std::ops::Deref;
 use uuid::Uuid;
 
@@ -21,17 +23,38 @@ pub async fn reject_anonymous_users(
         TypedSession::from_request(http_request, payload).await
     }?;
 
-    match session.get_user_id().map_err(e500)? {
-        Some(user_id) => {
-            req.extensions_mut().insert(UserId(user_id));
-            next.call(req).await
-        }
+    let user_id = match session.get_user_id().map_err(e500)? {
+        Some(user_id) => user_id,
         None => {
             let response = see_other("/login");
             let e = anyhow::anyhow!("The user has not logged in");
-            Err(InternalError::from_response(e, response).into())
+            return Err(InternalError::from_response(e, response).into());
         }
+    };
+
+    // Sessions revoked from another device, expired (or created before session
+    // tracking existed) are treated as logged out.
+    let pool = req
+        .app_data::<web::Data<PgPool>>()
+        .ok_or_else(|| e500("The database pool is not registered."))?;
+    let ttl = ***req
+        .app_data::<web::Data<SessionTtl>>()
+        .ok_or_else(|| e500("The session TTL is not registered."))?;
+    let is_live = match session.get_session_id().map_err(e500)? {
+        Some(session_id) => touch_user_session(user_id, session_id, ttl, pool)
+            .await
+            .map_err(e500)?,
+        None => false,
+    };
+    if !is_live {
+        session.log_out();
+        let response = see_other("/login");
+        let e = anyhow::anyhow!("The session has been revoked");
+        return Err(InternalError::from_response(e, response).into());
     }
+
+    req.extensions_mut().insert(UserId(user_id));
+    next.call(req).await
 }
 
 #[derive(Copy, Clone, Debug)]
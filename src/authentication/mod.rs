@@ -1,5 +1,11 @@
+mod api_token;
+mod csrf;
 mod middleware;
 mod password;
+mod user_session;
 
+pub use api_token::*;
+pub use csrf::*;
 pub use middleware::*;
 pub use password::*;
+pub use user_session::*;
@@ -1,12 +1,18 @@
+use actix_web::http::header::HeaderMap;
 use anyhow::Context;
 use argon2::{
     password_hash::SaltString, Algorithm, Argon2, Params, PasswordHash, PasswordHasher,
     PasswordVerifier, Version,
 };
+use base64::Engine;
 use secrecy::{ExposeSecret, Secret};
 use sqlx::PgPool;
+use tracing::Instrument;
 
-use crate::telemetry::spawn_blocking_with_tracing;
+use crate::{
+    configuration::Argon2Settings, domain::new_password::NewPassword,
+    telemetry::spawn_blocking_with_tracing,
+};
 
 #[derive(thiserror::Error, Debug)]
 pub enum AuthError {
@@ -21,32 +27,136 @@ pub struct Credentials {
     pub password: Secret<String>,
 }
 
-#[tracing::instrument(name = "Validate credentials", skip(credentials, pool))]
+/// A hash to verify passwords against when the username is unknown, so that the
+/// response takes as long as for a known user. It must be computed with the configured
+/// parameters: the verification time depends on them.
+#[derive(Clone)]
+pub struct DummyPasswordHash(Secret<String>);
+
+impl DummyPasswordHash {
+    pub fn new(argon2_settings: &Argon2Settings) -> Result<Self, anyhow::Error> {
+        let password = Secret::new(uuid::Uuid::new_v4().to_string());
+        compute_password_hash(password, argon2_settings).map(Self)
+    }
+}
+
+#[tracing::instrument(
+    name = "Validate credentials",
+    skip(credentials, argon2_settings, dummy_password_hash, pool)
+)]
 pub async fn validate_credentials(
     credentials: Credentials,
+    argon2_settings: &Argon2Settings,
+    dummy_password_hash: &DummyPasswordHash,
     pool: &PgPool,
 ) -> Result<uuid::Uuid, AuthError> {
     let mut user_id = None;
-    let mut expected_password_hash = Secret::new(
-        "$argon2id$v=19$m=15000,t=2,p=1$gZiV/M1gPc22ElAH/Jh1Hw$CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno".to_string(),
-    );
+    let mut expected_password_hash = dummy_password_hash.0.clone();
 
     if let Some((stored_user_id, stored_password_hash)) =
-        get_stored_credentials(&credentials.username, &pool).await?
+        get_stored_credentials(&credentials.username, pool).await?
     {
         user_id = Some(stored_user_id);
         expected_password_hash = stored_password_hash;
     }
 
+    let stored_password_hash = expected_password_hash.clone();
+    let password = credentials.password.clone();
     spawn_blocking_with_tracing(move || {
         verify_password_hash(expected_password_hash, credentials.password)
     })
     .await
     .context("Failed to spawn blocking task.")??;
 
-    user_id
+    let user_id = user_id
         .ok_or_else(|| anyhow::anyhow!("Unknown username."))
-        .map_err(AuthError::InvalidCredentials)
+        .map_err(AuthError::InvalidCredentials)?;
+
+    if needs_rehash(&stored_password_hash, argon2_settings) {
+        spawn_rehash(
+            user_id,
+            password,
+            stored_password_hash,
+            argon2_settings.clone(),
+            pool.clone(),
+        );
+    }
+
+    Ok(user_id)
+}
+
+/// Whether a stored PHC string was computed with anything other than
+/// Argon2id v19 and the currently configured cost parameters.
+fn needs_rehash(password_hash: &Secret<String>, argon2_settings: &Argon2Settings) -> bool {
+    let password_hash = match PasswordHash::new(password_hash.expose_secret()) {
+        Ok(password_hash) => password_hash,
+        Err(_) => return true,
+    };
+    if password_hash.algorithm != Algorithm::Argon2id.ident()
+        || password_hash.version != Some(Version::V0x13.into())
+    {
+        return true;
+    }
+    match Params::try_from(&password_hash) {
+        Ok(params) => {
+            params.m_cost() != argon2_settings.memory_cost
+                || params.t_cost() != argon2_settings.time_cost
+                || params.p_cost() != argon2_settings.parallelism
+        }
+        Err(_) => true,
+    }
+}
+
+/// Rehash the password with the current parameters without holding up the login.
+/// The update is skipped if the stored hash changed in the meantime,
+/// e.g. because the user changed their password concurrently.
+fn spawn_rehash(
+    user_id: uuid::Uuid,
+    password: Secret<String>,
+    outdated_password_hash: Secret<String>,
+    argon2_settings: Argon2Settings,
+    pool: PgPool,
+) {
+    let span = tracing::info_span!("Upgrade password hash", %user_id);
+    tokio::spawn(
+        async move {
+            if let Err(e) = upgrade_password_hash(
+                user_id,
+                password,
+                outdated_password_hash,
+                argon2_settings,
+                &pool,
+            )
+            .await
+            {
+                tracing::error!(error.cause_chain = ?e, "Failed to upgrade password hash.");
+            }
+        }
+        .instrument(span),
+    );
+}
+
+async fn upgrade_password_hash(
+    user_id: uuid::Uuid,
+    password: Secret<String>,
+    outdated_password_hash: Secret<String>,
+    argon2_settings: Argon2Settings,
+    pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+    let password_hash =
+        spawn_blocking_with_tracing(move || compute_password_hash(password, &argon2_settings))
+            .await?
+            .context("Failed to hash password")?;
+    sqlx::query!(
+        r#"UPDATE users SET password_hash = $1 WHERE user_id = $2 AND password_hash = $3"#,
+        password_hash.expose_secret(),
+        user_id,
+        outdated_password_hash.expose_secret(),
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store the upgraded password hash in the database.")?;
+    Ok(())
 }
 
 #[tracing::instrument(name = "Get stored credentials", skip(username, pool))]
@@ -86,15 +196,19 @@ fn verify_password_hash(
         .map_err(AuthError::InvalidCredentials)
 }
 
-#[tracing::instrument(name = "Change password", skip(password, pool))]
+#[tracing::instrument(name = "Change password", skip(password, argon2_settings, pool))]
 pub async fn change_password(
     user_id: uuid::Uuid,
-    password: Secret<String>,
+    password: NewPassword,
+    argon2_settings: &Argon2Settings,
     pool: &PgPool,
 ) -> Result<(), anyhow::Error> {
-    let password_hash = spawn_blocking_with_tracing(move || compute_password_hash(password))
-        .await?
-        .context("Failed to hash password")?;
+    let password = password.into_inner();
+    let argon2_settings = argon2_settings.clone();
+    let password_hash =
+        spawn_blocking_with_tracing(move || compute_password_hash(password, &argon2_settings))
+            .await?
+            .context("Failed to hash password")?;
     sqlx::query!(
         r#"UPDATE users SET password_hash = $1 WHERE user_id = $2"#,
         password_hash.expose_secret(),
@@ -106,14 +220,74 @@ pub async fn change_password(
     Ok(())
 }
 
-fn compute_password_hash(password: Secret<String>) -> Result<Secret<String>, anyhow::Error> {
+fn compute_password_hash(
+    password: Secret<String>,
+    argon2_settings: &Argon2Settings,
+) -> Result<Secret<String>, anyhow::Error> {
     let salt = SaltString::generate(&mut rand::thread_rng());
     let password_hash = Argon2::new(
         Algorithm::Argon2id,
         Version::V0x13,
-        Params::new(15000, 2, 1, None).unwrap(),
+        argon2_settings
+            .params()
+            .context("Invalid Argon2 parameters.")?,
     )
     .hash_password(password.expose_secret().as_bytes(), &salt)?
     .to_string();
     Ok(Secret::new(password_hash))
 }
+
+/// Extract `Basic` credentials from the `Authorization` header.
+pub fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Error> {
+    // The header value, if present, must be a valid UTF8 string
+    let header_value = headers
+        .get("Authorization")
+        .context("The 'Authorization' header was missing")?
+        .to_str()
+        .context("The 'Authorization' header was not a valid UTF8 string.")?;
+    let base64_encoded_segment = header_value
+        .strip_prefix("Basic ")
+        .context("The authorization scheme was not 'Basic'.")?;
+    let decoded_bytes = base64::engine::general_purpose::STANDARD
+        .decode(base64_encoded_segment)
+        .context("Failed to base64-decode 'Basic' credentials.")?;
+    let decoded_credentials = String::from_utf8(decoded_bytes)
+        .context("The decoded credential string is not valid UTF8.")?;
+    // Split into two segments, using ':' as delimiter
+    let mut credentials = decoded_credentials.splitn(2, ':');
+    let username = credentials
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("A username must be provided in 'Basic' auth."))?
+        .to_string();
+    let password = credentials
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("A password must be provided in 'Basic' auth."))?
+        .to_string();
+    Ok(Credentials {
+        username,
+        password: Secret::new(password),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DummyPasswordHash;
+    use crate::configuration::Argon2Settings;
+    use argon2::{Params, PasswordHash};
+    use secrecy::ExposeSecret;
+
+    #[test]
+    fn the_dummy_hash_uses_the_configured_parameters() {
+        let argon2_settings = Argon2Settings {
+            memory_cost: 8192,
+            time_cost: 3,
+            parallelism: 2,
+        };
+        let dummy_password_hash = DummyPasswordHash::new(&argon2_settings).unwrap();
+        let password_hash = PasswordHash::new(dummy_password_hash.0.expose_secret()).unwrap();
+        let params = Params::try_from(&password_hash).unwrap();
+        assert_eq!(params.m_cost(), argon2_settings.memory_cost);
+        assert_eq!(params.t_cost(), argon2_settings.time_cost);
+        assert_eq!(params.p_cost(), argon2_settings.parallelism);
+    }
+}
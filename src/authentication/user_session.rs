@@ -0,0 +1,158 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::postgres::types::PgInterval;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// How long a session lives without activity. The session store keeps the state for as
+/// long, extending it on every request: records not seen for longer are expired.
+#[derive(Clone, Copy, Debug)]
+pub struct SessionTtl(pub chrono::Duration);
+
+/// Metadata we keep about each admin login, next to the session state in the session store.
+/// It is what allows listing and revoking sessions: the store itself cannot be queried by user.
+pub struct UserSession {
+    pub id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+#[tracing::instrument(name = "Create user session", skip(pool))]
+pub async fn create_user_session(
+    user_id: Uuid,
+    ip_address: Option<&str>,
+    user_agent: Option<&str>,
+    pool: &PgPool,
+) -> Result<Uuid, anyhow::Error> {
+    let session_id = Uuid::new_v4();
+    let now = Utc::now();
+    sqlx::query!(
+        r#"
+    INSERT INTO user_sessions (id, user_id, created_at, last_seen_at, ip_address, user_agent)
+    VALUES ($1, $2, $3, $3, $4, $5)
+    "#,
+        session_id,
+        user_id,
+        now,
+        ip_address,
+        user_agent,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store a new user session in the database.")?;
+    Ok(session_id)
+}
+
+/// Record activity on a session.
+/// Returns `false` if the session does not exist, was revoked, expired or does not belong
+/// to `user_id`.
+#[tracing::instrument(name = "Touch user session", skip(pool))]
+pub async fn touch_user_session(
+    user_id: Uuid,
+    session_id: Uuid,
+    ttl: SessionTtl,
+    pool: &PgPool,
+) -> Result<bool, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"
+    UPDATE user_sessions SET last_seen_at = now()
+    WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL AND last_seen_at > now() - $3::interval
+    "#,
+        session_id,
+        user_id,
+        pg_interval(ttl),
+    )
+    .execute(pool)
+    .await
+    .context("Failed to update a user session in the database.")?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Sessions that are neither revoked nor expired.
+#[tracing::instrument(name = "List active user sessions", skip(pool))]
+pub async fn get_active_user_sessions(
+    user_id: Uuid,
+    ttl: SessionTtl,
+    pool: &PgPool,
+) -> Result<Vec<UserSession>, anyhow::Error> {
+    let sessions = sqlx::query_as!(
+        UserSession,
+        r#"
+    SELECT id, created_at, last_seen_at, ip_address, user_agent
+    FROM user_sessions
+    WHERE user_id = $1 AND revoked_at IS NULL AND last_seen_at > now() - $2::interval
+    ORDER BY last_seen_at DESC
+    "#,
+        user_id,
+        pg_interval(ttl),
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to perform a query to retrieve user sessions.")?;
+    Ok(sessions)
+}
+
+/// Returns `false` if no live session with this id belongs to `user_id`.
+#[tracing::instrument(name = "Revoke user session", skip(pool))]
+pub async fn revoke_user_session(
+    user_id: Uuid,
+    session_id: Uuid,
+    pool: &PgPool,
+) -> Result<bool, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"UPDATE user_sessions SET revoked_at = now() WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL"#,
+        session_id,
+        user_id,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to revoke a user session in the database.")?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Revoke every live session of `user_id`, except `keep` if provided.
+#[tracing::instrument(name = "Revoke all user sessions", skip(pool))]
+pub async fn revoke_user_sessions(
+    user_id: Uuid,
+    keep: Option<Uuid>,
+    pool: &PgPool,
+) -> Result<u64, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"
+    UPDATE user_sessions SET revoked_at = now()
+    WHERE user_id = $1 AND revoked_at IS NULL AND ($2::uuid IS NULL OR id <> $2)
+    "#,
+        user_id,
+        keep,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to revoke user sessions in the database.")?;
+    Ok(result.rows_affected())
+}
+
+/// Revoked and expired sessions cannot be used again: their records only take up room.
+#[tracing::instrument(name = "Delete expired user sessions", skip(pool))]
+pub async fn delete_expired_user_sessions(
+    ttl: SessionTtl,
+    pool: &PgPool,
+) -> Result<u64, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"DELETE FROM user_sessions WHERE revoked_at IS NOT NULL OR last_seen_at <= now() - $1::interval"#,
+        pg_interval(ttl),
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete expired user sessions.")?;
+    Ok(result.rows_affected())
+}
+
+fn pg_interval(ttl: SessionTtl) -> PgInterval {
+    PgInterval {
+        months: 0,
+        days: 0,
+        microseconds: ttl.0.num_microseconds().unwrap_or(i64::MAX),
+    }
+}
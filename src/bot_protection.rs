@@ -0,0 +1,259 @@
+use crate::{configuration::BotProtectionSettings, startup::HmacSecret};
+use hmac::{Hmac, Mac};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use secrecy::ExposeSecret;
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+
+/// Hidden form field that humans never see, and therefore leave empty.
+pub const HONEYPOT_FIELD: &str = "website";
+
+/// Why a submission of the subscription form looks automated.
+#[derive(Debug, PartialEq, Eq)]
+pub enum BotSignal {
+    HoneypotFilled,
+    MissingFormToken,
+    InvalidFormToken,
+    ReusedFormToken,
+    SubmittedTooFast,
+    FormExpired,
+    InvalidProofOfWork,
+}
+
+impl std::fmt::Display for BotSignal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let signal = match self {
+            BotSignal::HoneypotFilled => "the honeypot field was filled in",
+            BotSignal::MissingFormToken => "the form token is missing",
+            BotSignal::InvalidFormToken => "the form token signature is invalid",
+            BotSignal::ReusedFormToken => "the form token was already used",
+            BotSignal::SubmittedTooFast => "the form was submitted too fast",
+            BotSignal::FormExpired => "the form token has expired",
+            BotSignal::InvalidProofOfWork => "the proof of work is missing or invalid",
+        };
+        f.write_str(signal)
+    }
+}
+
+/// Issues the tokens embedded in the subscription form and inspects them on submission.
+///
+/// A form token is `<issued at>.<salt>.<signature>`: the signed timestamp tells how long
+/// the visitor took to fill in the form, and doubles as the proof-of-work challenge.
+/// Each token is good for one submission: the caller records its use until it expires.
+#[derive(Clone)]
+pub struct BotProtection {
+    settings: BotProtectionSettings,
+    secret: HmacSecret,
+}
+
+impl BotProtection {
+    pub fn new(settings: BotProtectionSettings, secret: HmacSecret) -> Self {
+        Self { settings, secret }
+    }
+
+    /// Leading zero bits the proof-of-work hash must have, if the challenge is enabled.
+    pub fn proof_of_work_difficulty(&self) -> Option<u32> {
+        self.settings.proof_of_work_difficulty
+    }
+
+    /// How long a form token, and the proof of work solved for it, can be used.
+    pub fn max_form_age(&self) -> Duration {
+        Duration::from_secs(self.settings.max_form_age_in_secs.max(0) as u64)
+    }
+
+    /// `issued_at` is a UNIX timestamp, in seconds.
+    pub fn issue_form_token(&self, issued_at: i64) -> String {
+        let mut rng = thread_rng();
+        let salt: String = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+            .map(char::from)
+            .take(16)
+            .collect();
+        let payload = format!("{}.{}", issued_at, salt);
+        let signature = hex::encode(self.mac(&payload).finalize().into_bytes());
+        format!("{}.{}", payload, signature)
+    }
+
+    pub fn inspect(
+        &self,
+        honeypot: &str,
+        form_token: Option<&str>,
+        proof_of_work: Option<&str>,
+        now: i64,
+    ) -> Result<(), BotSignal> {
+        if !honeypot.is_empty() {
+            return Err(BotSignal::HoneypotFilled);
+        }
+        let form_token = form_token.ok_or(BotSignal::MissingFormToken)?;
+        let issued_at = self.verify_form_token(form_token)?;
+        let elapsed = now - issued_at;
+        if elapsed < self.settings.min_submit_time_in_secs {
+            return Err(BotSignal::SubmittedTooFast);
+        }
+        if elapsed > self.settings.max_form_age_in_secs {
+            return Err(BotSignal::FormExpired);
+        }
+        if let Some(difficulty) = self.settings.proof_of_work_difficulty {
+            let nonce = proof_of_work.ok_or(BotSignal::InvalidProofOfWork)?;
+            if leading_zero_bits(&proof_of_work_hash(form_token, nonce)) < difficulty {
+                return Err(BotSignal::InvalidProofOfWork);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns when the token was issued.
+    fn verify_form_token(&self, form_token: &str) -> Result<i64, BotSignal> {
+        let (payload, signature) = form_token
+            .rsplit_once('.')
+            .ok_or(BotSignal::InvalidFormToken)?;
+        let signature = hex::decode(signature).map_err(|_| BotSignal::InvalidFormToken)?;
+        self.mac(payload)
+            .verify_slice(&signature)
+            .map_err(|_| BotSignal::InvalidFormToken)?;
+        payload
+            .split_once('.')
+            .and_then(|(issued_at, _)| issued_at.parse().ok())
+            .ok_or(BotSignal::InvalidFormToken)
+    }
+
+    fn mac(&self, payload: &str) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.secret.0.expose_secret().as_bytes())
+            .expect("HMAC can take a key of any size.");
+        mac.update(payload.as_bytes());
+        mac
+    }
+}
+
+/// The client must find a nonce such that `sha256("<form token>:<nonce>")`
+/// starts with enough zero bits. See `proof_of_work.js`.
+fn proof_of_work_hash(form_token: &str, nonce: &str) -> [u8; 32] {
+    Sha256::digest(format!("{}:{}", form_token, nonce).as_bytes()).into()
+}
+
+fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in hash {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{leading_zero_bits, proof_of_work_hash, BotProtection, BotSignal};
+    use crate::{configuration::BotProtectionSettings, startup::HmacSecret};
+    use claims::{assert_err_eq, assert_ok};
+    use secrecy::Secret;
+
+    const NOW: i64 = 1_700_000_000;
+
+    fn bot_protection(proof_of_work_difficulty: Option<u32>) -> BotProtection {
+        BotProtection::new(
+            BotProtectionSettings {
+                min_submit_time_in_secs: 3,
+                max_form_age_in_secs: 3600,
+                proof_of_work_difficulty,
+            },
+            HmacSecret(Secret::new("secret".into())),
+        )
+    }
+
+    #[test]
+    fn a_form_filled_in_at_human_speed_is_accepted() {
+        let protection = bot_protection(None);
+        let token = protection.issue_form_token(NOW - 10);
+        assert_ok!(protection.inspect("", Some(&token), None, NOW));
+    }
+
+    #[test]
+    fn a_filled_honeypot_is_rejected() {
+        let protection = bot_protection(None);
+        let token = protection.issue_form_token(NOW - 10);
+        assert_err_eq!(
+            protection.inspect("http://spam.com", Some(&token), None, NOW),
+            BotSignal::HoneypotFilled
+        );
+    }
+
+    #[test]
+    fn a_missing_form_token_is_rejected() {
+        assert_err_eq!(
+            bot_protection(None).inspect("", None, None, NOW),
+            BotSignal::MissingFormToken
+        );
+    }
+
+    #[test]
+    fn a_tampered_timestamp_is_rejected() {
+        let protection = bot_protection(None);
+        let token = protection.issue_form_token(NOW);
+        let tampered = token.replacen(&NOW.to_string(), &(NOW - 10).to_string(), 1);
+        assert_err_eq!(
+            protection.inspect("", Some(&tampered), None, NOW),
+            BotSignal::InvalidFormToken
+        );
+    }
+
+    #[test]
+    fn a_token_signed_with_another_secret_is_rejected() {
+        let other = BotProtection::new(
+            bot_protection(None).settings,
+            HmacSecret(Secret::new("another-secret".into())),
+        );
+        let token = other.issue_form_token(NOW - 10);
+        assert_err_eq!(
+            bot_protection(None).inspect("", Some(&token), None, NOW),
+            BotSignal::InvalidFormToken
+        );
+    }
+
+    #[test]
+    fn forms_submitted_too_fast_or_too_late_are_rejected() {
+        let protection = bot_protection(None);
+        let token = protection.issue_form_token(NOW - 1);
+        assert_err_eq!(
+            protection.inspect("", Some(&token), None, NOW),
+            BotSignal::SubmittedTooFast
+        );
+        let token = protection.issue_form_token(NOW - 3601);
+        assert_err_eq!(
+            protection.inspect("", Some(&token), None, NOW),
+            BotSignal::FormExpired
+        );
+    }
+
+    #[test]
+    fn the_proof_of_work_is_checked_when_enabled() {
+        let protection = bot_protection(Some(8));
+        let token = protection.issue_form_token(NOW - 10);
+        let nonce = (0..)
+            .map(|n: u32| n.to_string())
+            .find(|n| leading_zero_bits(&proof_of_work_hash(&token, n)) >= 8)
+            .unwrap();
+        let wrong_nonce = (0..)
+            .map(|n: u32| n.to_string())
+            .find(|n| leading_zero_bits(&proof_of_work_hash(&token, n)) < 8)
+            .unwrap();
+
+        assert_ok!(protection.inspect("", Some(&token), Some(&nonce), NOW));
+        assert_err_eq!(
+            protection.inspect("", Some(&token), Some(&wrong_nonce), NOW),
+            BotSignal::InvalidProofOfWork
+        );
+        assert_err_eq!(
+            protection.inspect("", Some(&token), None, NOW),
+            BotSignal::InvalidProofOfWork
+        );
+    }
+
+    #[test]
+    fn leading_zero_bits_are_counted_across_bytes() {
+        assert_eq!(leading_zero_bits(&[0, 0x10, 0xff]), 11);
+        assert_eq!(leading_zero_bits(&[0x80]), 0);
+        assert_eq!(leading_zero_bits(&[0, 0]), 16);
+    }
+}
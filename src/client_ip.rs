@@ -0,0 +1,113 @@
+use actix_web::{web, HttpRequest};
+use std::net::IpAddr;
+
+/// The proxies allowed to report, through `X-Forwarded-For`, the address of the client they
+/// forward a request for. Anyone else could write whatever address they like in the header.
+#[derive(Clone, Debug, Default)]
+pub struct TrustedProxies(Vec<IpAddr>);
+
+impl TrustedProxies {
+    pub fn new(proxies: Vec<IpAddr>) -> Self {
+        Self(proxies)
+    }
+
+    fn contains(&self, ip: &IpAddr) -> bool {
+        self.0.contains(ip)
+    }
+
+    /// The address of the client a request comes from.
+    /// `X-Forwarded-For` is walked from the right, as each proxy appends the address it got the
+    /// request from: the first hop that is not a trusted proxy is the client.
+    /// If every hop is one of our proxies, the leftmost one is as close to the client as we get.
+    pub fn client_ip(&self, request: &HttpRequest) -> Option<IpAddr> {
+        let peer = request.peer_addr()?.ip();
+        if !self.contains(&peer) {
+            return Some(peer);
+        }
+        let hops: Vec<&str> = request
+            .headers()
+            .get_all("X-Forwarded-For")
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .collect();
+        let mut client = peer;
+        for hop in hops.into_iter().rev() {
+            // Past a malformed hop, we cannot tell who wrote the rest of the header.
+            let Ok(hop) = hop.trim().parse::<IpAddr>() else {
+                break;
+            };
+            client = hop;
+            if !self.contains(&hop) {
+                break;
+            }
+        }
+        Some(client)
+    }
+}
+
+/// The address of the client a request comes from, honouring the `TrustedProxies` of the
+/// application. Only `None` in unit tests, where requests have no peer.
+pub fn client_ip(request: &HttpRequest) -> Option<IpAddr> {
+    match request.app_data::<web::Data<TrustedProxies>>() {
+        Some(proxies) => proxies.client_ip(request),
+        None => TrustedProxies::default().client_ip(request),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TrustedProxies;
+    use actix_web::test::TestRequest;
+    use claims::assert_some_eq;
+    use std::net::{IpAddr, SocketAddr};
+
+    const PROXY: &str = "10.0.0.1";
+
+    fn proxies() -> TrustedProxies {
+        TrustedProxies::new(vec![PROXY.parse().unwrap()])
+    }
+
+    fn request_from(peer: &str, forwarded_for: Option<&str>) -> actix_web::HttpRequest {
+        let peer: IpAddr = peer.parse().unwrap();
+        let request = TestRequest::default().peer_addr(SocketAddr::new(peer, 4242));
+        match forwarded_for {
+            Some(value) => request.insert_header(("X-Forwarded-For", value)),
+            None => request,
+        }
+        .to_http_request()
+    }
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn the_header_is_ignored_when_the_peer_is_not_a_trusted_proxy() {
+        let request = request_from("203.0.113.7", Some("198.51.100.1"));
+        assert_some_eq!(proxies().client_ip(&request), ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn a_trusted_proxy_reports_the_client_address() {
+        let request = request_from(PROXY, Some("198.51.100.1"));
+        assert_some_eq!(proxies().client_ip(&request), ip("198.51.100.1"));
+    }
+
+    #[test]
+    fn addresses_prepended_by_the_client_are_ignored() {
+        let request = request_from(PROXY, Some("192.0.2.99, 198.51.100.1"));
+        assert_some_eq!(proxies().client_ip(&request), ip("198.51.100.1"));
+    }
+
+    #[test]
+    fn a_malformed_hop_stops_the_walk() {
+        let request = request_from(PROXY, Some("198.51.100.1, unknown, 10.0.0.1"));
+        assert_some_eq!(proxies().client_ip(&request), ip(PROXY));
+    }
+
+    #[test]
+    fn a_trusted_proxy_without_the_header_is_the_client() {
+        let request = request_from(PROXY, None);
+        assert_some_eq!(proxies().client_ip(&request), ip(PROXY));
+    }
+}
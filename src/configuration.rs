@@ -1,24 +1,87 @@
+use actix_web::cookie::{time::Duration, SameSite};
 use secrecy::{ExposeSecret, Secret};
-use serde_aux::field_attributes::deserialize_number_from_string;
+use serde::Serializer;
+use serde_aux::field_attributes::{
+    deserialize_number_from_string, deserialize_option_number_from_string,
+};
 use sqlx::{
     postgres::{PgConnectOptions, PgSslMode},
     ConnectOptions,
 };
 
-use crate::domain::subscriber_email::SubscriberEmail;
+use crate::domain::{new_password::PasswordPolicy, subscriber_email::SubscriberEmail};
 
-#[derive(serde::Deserialize, Clone)]
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
 pub struct ApplicationSettings {
     pub host: String,
     #[serde(deserialize_with = "deserialize_number_from_string")]
     pub port: u16,
     pub base_url: String,
+    #[serde(serialize_with = "redact")]
     pub hmac_secret: Secret<String>,
+    pub session_cookie: CookieSettings,
+    pub flash_cookie: CookieSettings,
+    pub security_headers: SecurityHeadersSettings,
+    /// How long in-flight requests and background work get to finish on shutdown.
+    pub shutdown_timeout_in_secs: u64,
+    /// Addresses of the reverse proxies whose `X-Forwarded-For` header is believed.
+    pub trusted_proxies: Vec<std::net::IpAddr>,
+}
+
+impl ApplicationSettings {
+    pub fn shutdown_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.shutdown_timeout_in_secs)
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum SameSiteSetting {
+    Strict,
+    Lax,
+    None,
+}
+
+impl From<SameSiteSetting> for SameSite {
+    fn from(value: SameSiteSetting) -> Self {
+        match value {
+            SameSiteSetting::Strict => SameSite::Strict,
+            SameSiteSetting::Lax => SameSite::Lax,
+            SameSiteSetting::None => SameSite::None,
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+pub struct CookieSettings {
+    pub name: String,
+    pub same_site: SameSiteSetting,
+    pub secure: bool,
+    pub domain: Option<String>,
+    /// Cookies without a max age only live as long as the browser session.
+    pub max_age_in_secs: Option<i64>,
 }
 
-#[derive(serde::Deserialize, Clone)]
+impl CookieSettings {
+    pub fn max_age(&self) -> Option<Duration> {
+        self.max_age_in_secs.map(Duration::seconds)
+    }
+}
+
+/// Headers added to every response. Unset values are not sent.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+pub struct SecurityHeadersSettings {
+    pub content_security_policy: Option<String>,
+    pub frame_options: Option<String>,
+    pub referrer_policy: Option<String>,
+    /// `Strict-Transport-Security` is only meaningful behind HTTPS.
+    pub hsts_max_age_in_secs: Option<u64>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
 pub struct DatabaseSettings {
     pub username: String,
+    #[serde(serialize_with = "redact")]
     pub password: Secret<String>,
     #[serde(deserialize_with = "deserialize_number_from_string")]
     pub port: u16,
@@ -27,12 +90,15 @@ pub struct DatabaseSettings {
     pub require_ssl: bool,
 }
 
-#[derive(serde::Deserialize, Clone)]
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
 pub struct EmailSettings {
     pub base_url: String,
     pub sender_email: String,
+    #[serde(serialize_with = "redact")]
     pub auth_token: Secret<String>,
     pub timeout_in_milli: u64,
+    pub throttle: EmailThrottleSettings,
+    pub delivery_retries: DeliveryRetrySettings,
 }
 
 impl EmailSettings {
@@ -45,12 +111,314 @@ impl EmailSettings {
     }
 }
 
-#[derive(serde::Deserialize, Clone)]
+/// Limits on outgoing email, applied by each instance to stay within what the provider
+/// accepts.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+pub struct EmailThrottleSettings {
+    /// Sustained rate, also the size of the bursts allowed after a quiet period.
+    pub messages_per_second: u32,
+    pub max_concurrent_sends: usize,
+    /// Emails sent per quota window. Unset for no quota.
+    pub quota_per_window: Option<u64>,
+    /// Windows are counted from the UNIX epoch: 86400 starts a new one at midnight UTC.
+    pub quota_window_in_secs: u64,
+    /// A provider asking to wait longer than this on a 429 fails the email instead.
+    pub max_retry_after_in_secs: u64,
+    /// How often issues paused by the quota are checked for a new window.
+    pub resume_poll_interval_in_milli: u64,
+}
+
+impl EmailThrottleSettings {
+    pub fn max_retry_after(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.max_retry_after_in_secs)
+    }
+
+    pub fn resume_poll_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.resume_poll_interval_in_milli)
+    }
+}
+
+/// How issue emails that failed for another reason than the quota are retried.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+pub struct DeliveryRetrySettings {
+    /// Attempts per recipient, the first one included.
+    pub max_attempts: i32,
+    /// Wait before the first retry, doubled for each of the next ones.
+    pub delay_in_secs: u64,
+}
+
+impl DeliveryRetrySettings {
+    /// Wait before the next attempt, after `failed_attempts` failures.
+    pub fn delay_after(&self, failed_attempts: i32) -> chrono::Duration {
+        let doublings = failed_attempts.saturating_sub(1).clamp(0, 16) as u32;
+        chrono::Duration::seconds(self.delay_in_secs.saturating_mul(1 << doublings) as i64)
+    }
+}
+
+/// Argon2id cost parameters used when hashing new passwords.
+/// Stored hashes computed with different parameters are upgraded on the next successful login.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+pub struct Argon2Settings {
+    pub memory_cost: u32,
+    pub time_cost: u32,
+    pub parallelism: u32,
+}
+
+impl Argon2Settings {
+    pub fn params(&self) -> Result<argon2::Params, argon2::Error> {
+        argon2::Params::new(self.memory_cost, self.time_cost, self.parallelism, None)
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionStoreBackend {
+    Redis,
+    Postgres,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct SessionSettings {
+    pub store: SessionStoreBackend,
+    /// How often expired sessions are purged, from the `postgres` store and from the
+    /// records listed on the sessions page.
+    pub cleanup_interval_in_secs: u64,
+}
+
+impl SessionSettings {
+    pub fn cleanup_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.cleanup_interval_in_secs)
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RateLimitBackend {
+    /// Buckets live in the process: each instance enforces its own limits.
+    Memory,
+    /// Buckets are shared by every instance through `redis_uri`.
+    Redis,
+}
+
+/// A token bucket: bursts of up to `capacity` requests, then one every `refill_interval_in_secs`.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, Debug)]
+pub struct TokenBucketSettings {
+    pub capacity: u32,
+    pub refill_interval_in_secs: u64,
+}
+
+impl TokenBucketSettings {
+    pub fn refill_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.refill_interval_in_secs)
+    }
+}
+
+/// Limits applied to a single route. Unset buckets are not enforced.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+pub struct RouteRateLimits {
+    pub per_ip: Option<TokenBucketSettings>,
+    /// Keyed by the address the request acts upon: the subscriber email, or, for logins,
+    /// the username together with the client address.
+    pub per_target: Option<TokenBucketSettings>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+pub struct RateLimitSettings {
+    pub backend: RateLimitBackend,
+    /// Namespace of the keys stored in Redis.
+    pub key_prefix: String,
+    pub subscribe: RouteRateLimits,
+    pub confirm: RouteRateLimits,
+    pub login: RouteRateLimits,
+    pub data_request: RouteRateLimits,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MxLookup {
+    Disabled,
+    /// Query the DNS servers of the system configuration.
+    Dns,
+    /// Only accept the domains listed in `static_mx_domains`.
+    Static,
+}
+
+/// Checks run on the domain of new subscribers, on top of the syntax checks of `SubscriberEmail`.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+pub struct DeliverabilitySettings {
+    pub mx_lookup: MxLookup,
+    #[serde(default)]
+    pub static_mx_domains: Vec<String>,
+    pub timeout_in_milli: u64,
+}
+
+impl DeliverabilitySettings {
+    pub fn timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.timeout_in_milli)
+    }
+}
+
+/// Heuristics flagging automated submissions of the subscription form.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+pub struct BotProtectionSettings {
+    /// Humans take a few seconds to fill in the form.
+    pub min_submit_time_in_secs: i64,
+    /// Older forms must be reloaded before being submitted.
+    pub max_form_age_in_secs: i64,
+    /// Leading zero bits required from the proof-of-work hash. Unset disables the challenge.
+    pub proof_of_work_difficulty: Option<u32>,
+}
+
+/// Self-service export and erasure of subscriber data.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+pub struct DataRequestSettings {
+    /// How long the links mailed to subscribers stay valid.
+    pub link_ttl_in_secs: i64,
+}
+
+/// The preference center and the weekly digests sent by `preferences::DigestSender`.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+pub struct PreferenceSettings {
+    /// How long the preference links included in issues stay valid.
+    pub link_ttl_in_secs: i64,
+    /// How long the link confirming a new email address stays valid.
+    pub email_change_ttl_in_secs: i64,
+    /// Time between two digests sent to the same subscriber.
+    pub digest_interval_in_secs: i64,
+    /// How often subscribers are checked for a due digest.
+    pub digest_poll_interval_in_milli: u64,
+    /// Digests sent per poll.
+    pub digest_batch_size: i64,
+}
+
+impl PreferenceSettings {
+    pub fn email_change_ttl(&self) -> chrono::Duration {
+        chrono::Duration::seconds(self.email_change_ttl_in_secs)
+    }
+
+    pub fn digest_poll_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.digest_poll_interval_in_milli)
+    }
+}
+
+/// A/B tests of subject lines, ended by `delivery::AbTestFinisher`.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+pub struct AbTestSettings {
+    /// Share of the recipients in the test slices, split evenly between the variants.
+    pub test_percent: u8,
+    /// Time the test slices have to open the issue before the winner is picked.
+    pub wait_in_secs: i64,
+    /// How often issues are checked for a finished test.
+    pub poll_interval_in_milli: u64,
+}
+
+impl AbTestSettings {
+    pub fn wait(&self) -> chrono::Duration {
+        chrono::Duration::seconds(self.wait_in_secs)
+    }
+
+    pub fn poll_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.poll_interval_in_milli)
+    }
+}
+
+/// Where `/metrics` is served.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+pub struct MetricsSettings {
+    /// A separate port on `application.host`, to keep the metrics off the public one.
+    /// Unset to serve them on the application port.
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub port: Option<u16>,
+}
+
+/// Export of spans to an OpenTelemetry collector.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+pub struct TracingSettings {
+    /// The OTLP/HTTP traces endpoint of the collector, e.g.
+    /// `http://localhost:4318/v1/traces`. Unset to keep spans in the logs only.
+    pub otlp_endpoint: Option<String>,
+    /// Reported as the `service.name` resource of the exported spans.
+    pub service_name: String,
+    pub export_timeout_in_milli: u64,
+}
+
+impl TracingSettings {
+    pub fn export_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.export_timeout_in_milli)
+    }
+}
+
+/// Whether `/health/ready` checks a dependency, and whether the check must pass.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DependencyCheck {
+    Disabled,
+    /// Reported, without making the application unready when it fails.
+    Optional,
+    Required,
+}
+
+/// The dependency checks of `/health/ready`.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+pub struct HealthSettings {
+    /// Each check fails once it takes longer than this.
+    pub timeout_in_milli: u64,
+    /// The provider is reachable when `email.base_url` answers without a server error.
+    pub email_provider: DependencyCheck,
+}
+
+impl HealthSettings {
+    pub fn timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.timeout_in_milli)
+    }
+}
+
+/// Delivery of outbound webhooks by `webhooks::WebhookDispatcher`.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+pub struct WebhookSettings {
+    /// How often the outbox is checked for due deliveries.
+    pub poll_interval_in_milli: u64,
+    pub timeout_in_milli: u64,
+    /// Deliveries claimed per poll.
+    pub batch_size: i64,
+    /// Deliveries are marked as failed after this many unsuccessful attempts.
+    pub max_attempts: i32,
+    /// Delay before the first retry, doubled after each failure up to `max_backoff_in_secs`.
+    pub initial_backoff_in_secs: u64,
+    pub max_backoff_in_secs: u64,
+}
+
+impl WebhookSettings {
+    pub fn poll_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.poll_interval_in_milli)
+    }
+
+    pub fn timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.timeout_in_milli)
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
 pub struct Settings {
     pub application: ApplicationSettings,
     pub database: DatabaseSettings,
     pub email: EmailSettings,
-    pub redis_uri: Secret<String>,
+    /// Only required when `session.store` or `rate_limit.backend` is `redis`.
+    #[serde(serialize_with = "redact_option")]
+    pub redis_uri: Option<Secret<String>>,
+    pub session: SessionSettings,
+    pub password_policy: PasswordPolicy,
+    pub argon2: Argon2Settings,
+    pub rate_limit: RateLimitSettings,
+    pub bot_protection: BotProtectionSettings,
+    pub deliverability: DeliverabilitySettings,
+    pub webhooks: WebhookSettings,
+    pub data_requests: DataRequestSettings,
+    pub preferences: PreferenceSettings,
+    pub ab_testing: AbTestSettings,
+    pub metrics: MetricsSettings,
+    pub tracing: TracingSettings,
+    pub health: HealthSettings,
 }
 
 impl DatabaseSettings {
@@ -105,14 +473,280 @@ impl TryFrom<String> for Environment {
     }
 }
 
-pub fn get_config() -> Result<Settings, config::ConfigError> {
-    let base_path = std::env::current_dir().expect("Failed to determine the current directory");
+/// `Key::from` panics on shorter secrets.
+const MIN_HMAC_SECRET_LENGTH: usize = 64;
+
+/// Why the configuration could not be used.
+#[derive(thiserror::Error, Debug)]
+pub enum ConfigurationError {
+    #[error("Failed to determine the current directory.")]
+    CurrentDirectory(#[source] std::io::Error),
+    #[error("Failed to parse APP_ENVIRONMENT: {0}")]
+    Environment(String),
+    #[error("Failed to load the configuration.")]
+    Load(#[from] config::ConfigError),
+    #[error(transparent)]
+    Invalid(#[from] InvalidConfiguration),
+}
+
+/// Every problem found in the configuration, not just the first one.
+#[derive(thiserror::Error, Debug)]
+pub struct InvalidConfiguration {
+    pub problems: Vec<String>,
+}
+
+impl std::fmt::Display for InvalidConfiguration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The configuration is invalid:")?;
+        for problem in &self.problems {
+            write!(f, "\n  - {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+/// The problems found so far, each prefixed with the key of the setting at fault.
+#[derive(Default)]
+struct Problems(Vec<String>);
+
+impl Problems {
+    fn add(&mut self, key: &str, problem: impl std::fmt::Display) {
+        self.0.push(format!("`{}` {}", key, problem));
+    }
+
+    fn check(&mut self, valid: bool, key: &str, problem: &str) {
+        if !valid {
+            self.add(key, problem);
+        }
+    }
+
+    fn positive<T: PartialOrd + Default>(&mut self, key: &str, value: T) {
+        self.check(value > T::default(), key, "must be greater than 0.");
+    }
+
+    fn port(&mut self, key: &str, port: u16) {
+        self.check(port != 0, key, "must be a port between 1 and 65535.");
+    }
+
+    fn url(&mut self, key: &str, value: &str) {
+        if let Err(e) = reqwest::Url::parse(value) {
+            self.add(key, format_args!("is not a valid URL: {}.", e));
+        }
+    }
+}
+
+impl Settings {
+    /// Catch the settings the application would fail on, or misbehave with, before it
+    /// starts. Every problem is reported, so that they can all be fixed at once.
+    pub fn validate(&self) -> Result<(), InvalidConfiguration> {
+        let mut problems = Problems::default();
+
+        let application = &self.application;
+        problems.port("application.port", application.port);
+        problems.url("application.base_url", &application.base_url);
+        problems.check(
+            application.hmac_secret.expose_secret().len() >= MIN_HMAC_SECRET_LENGTH,
+            "application.hmac_secret",
+            &format!("must be at least {} bytes long.", MIN_HMAC_SECRET_LENGTH),
+        );
+
+        problems.port("database.port", self.database.port);
+
+        let email = &self.email;
+        problems.url("email.base_url", &email.base_url);
+        if let Err(e) = email.sender() {
+            problems.add("email.sender_email", format_args!("is invalid: {}.", e));
+        }
+        problems.positive("email.timeout_in_milli", email.timeout_in_milli);
+        let throttle = &email.throttle;
+        problems.positive(
+            "email.throttle.messages_per_second",
+            throttle.messages_per_second,
+        );
+        problems.positive(
+            "email.throttle.max_concurrent_sends",
+            throttle.max_concurrent_sends,
+        );
+        if let Some(quota) = throttle.quota_per_window {
+            problems.positive("email.throttle.quota_per_window", quota);
+        }
+        problems.positive(
+            "email.throttle.quota_window_in_secs",
+            throttle.quota_window_in_secs,
+        );
+        problems.positive(
+            "email.throttle.resume_poll_interval_in_milli",
+            throttle.resume_poll_interval_in_milli,
+        );
+        problems.positive(
+            "email.delivery_retries.max_attempts",
+            email.delivery_retries.max_attempts,
+        );
+
+        let uses_redis = self.session.store == SessionStoreBackend::Redis
+            || self.rate_limit.backend == RateLimitBackend::Redis;
+        match &self.redis_uri {
+            // The URI is not echoed: it may hold a password.
+            Some(uri) if reqwest::Url::parse(uri.expose_secret()).is_err() => {
+                problems.add("redis_uri", "is not a valid URL.")
+            }
+            None if uses_redis => problems.add(
+                "redis_uri",
+                "must be set when `session.store` or `rate_limit.backend` is `redis`.",
+            ),
+            _ => {}
+        }
+        problems.positive(
+            "session.cleanup_interval_in_secs",
+            self.session.cleanup_interval_in_secs,
+        );
+
+        problems.check(
+            self.password_policy.min_length <= self.password_policy.max_length,
+            "password_policy.min_length",
+            "must not be greater than `password_policy.max_length`.",
+        );
+        if let Err(e) = self.argon2.params() {
+            problems.add("argon2", format_args!("is invalid: {}.", e));
+        }
+
+        for (route, limits) in [
+            ("subscribe", &self.rate_limit.subscribe),
+            ("confirm", &self.rate_limit.confirm),
+            ("login", &self.rate_limit.login),
+            ("data_request", &self.rate_limit.data_request),
+        ] {
+            for (bucket, settings) in [
+                ("per_ip", &limits.per_ip),
+                ("per_target", &limits.per_target),
+            ] {
+                if let Some(settings) = settings {
+                    let key = format!("rate_limit.{}.{}", route, bucket);
+                    problems.positive(&format!("{}.capacity", key), settings.capacity);
+                    problems.positive(
+                        &format!("{}.refill_interval_in_secs", key),
+                        settings.refill_interval_in_secs,
+                    );
+                }
+            }
+        }
+
+        problems.check(
+            self.bot_protection.min_submit_time_in_secs < self.bot_protection.max_form_age_in_secs,
+            "bot_protection.min_submit_time_in_secs",
+            "must be less than `bot_protection.max_form_age_in_secs`.",
+        );
+        problems.positive(
+            "deliverability.timeout_in_milli",
+            self.deliverability.timeout_in_milli,
+        );
+
+        let webhooks = &self.webhooks;
+        problems.positive(
+            "webhooks.poll_interval_in_milli",
+            webhooks.poll_interval_in_milli,
+        );
+        problems.positive("webhooks.timeout_in_milli", webhooks.timeout_in_milli);
+        problems.positive("webhooks.batch_size", webhooks.batch_size);
+        problems.positive("webhooks.max_attempts", webhooks.max_attempts);
+        problems.check(
+            webhooks.initial_backoff_in_secs <= webhooks.max_backoff_in_secs,
+            "webhooks.initial_backoff_in_secs",
+            "must not be greater than `webhooks.max_backoff_in_secs`.",
+        );
+
+        problems.positive(
+            "data_requests.link_ttl_in_secs",
+            self.data_requests.link_ttl_in_secs,
+        );
+        let preferences = &self.preferences;
+        problems.positive("preferences.link_ttl_in_secs", preferences.link_ttl_in_secs);
+        problems.positive(
+            "preferences.email_change_ttl_in_secs",
+            preferences.email_change_ttl_in_secs,
+        );
+        problems.positive(
+            "preferences.digest_interval_in_secs",
+            preferences.digest_interval_in_secs,
+        );
+        problems.positive(
+            "preferences.digest_poll_interval_in_milli",
+            preferences.digest_poll_interval_in_milli,
+        );
+        problems.positive(
+            "preferences.digest_batch_size",
+            preferences.digest_batch_size,
+        );
+
+        problems.check(
+            (1..=100).contains(&self.ab_testing.test_percent),
+            "ab_testing.test_percent",
+            "must be between 1 and 100.",
+        );
+        problems.positive(
+            "ab_testing.poll_interval_in_milli",
+            self.ab_testing.poll_interval_in_milli,
+        );
+
+        if let Some(port) = self.metrics.port {
+            problems.port("metrics.port", port);
+            problems.check(
+                port != application.port,
+                "metrics.port",
+                "must differ from `application.port`: unset it to serve the metrics there.",
+            );
+        }
+        if let Some(endpoint) = &self.tracing.otlp_endpoint {
+            problems.url("tracing.otlp_endpoint", endpoint);
+        }
+        problems.positive(
+            "tracing.export_timeout_in_milli",
+            self.tracing.export_timeout_in_milli,
+        );
+        problems.positive("health.timeout_in_milli", self.health.timeout_in_milli);
+
+        if problems.0.is_empty() {
+            Ok(())
+        } else {
+            Err(InvalidConfiguration {
+                problems: problems.0,
+            })
+        }
+    }
+}
+
+/// Secrets are left out of the configuration printed by `zero2prod config check`.
+fn redact<S: Serializer>(_: &Secret<String>, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str("[REDACTED]")
+}
+
+fn redact_option<S: Serializer>(
+    secret: &Option<Secret<String>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match secret {
+        Some(secret) => redact(secret, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Read and validate the configuration.
+pub fn get_config() -> Result<Settings, ConfigurationError> {
+    let settings = load_config()?;
+    settings.validate()?;
+    Ok(settings)
+}
+
+/// Merge the configuration files and the `APP_` environment variables, without validating
+/// the result.
+pub fn load_config() -> Result<Settings, ConfigurationError> {
+    let base_path = std::env::current_dir().map_err(ConfigurationError::CurrentDirectory)?;
     let config_dir = base_path.join("config");
 
     let enviroment: Environment = std::env::var("APP_ENVIRONMENT")
         .unwrap_or_else(|_| "local".into())
         .try_into()
-        .expect("Failed to parse APP_ENVIRONMENT");
+        .map_err(ConfigurationError::Environment)?;
 
     let enviroment_filename = format!("{}.yaml", enviroment.as_str());
 
@@ -127,5 +761,91 @@ pub fn get_config() -> Result<Settings, config::ConfigError> {
         )
         .build()?;
 
-    settings.try_deserialize::<Settings>()
+    Ok(settings.try_deserialize::<Settings>()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load_config, DeliveryRetrySettings, Settings};
+    use claims::assert_ok;
+    use secrecy::Secret;
+
+    fn settings() -> Settings {
+        load_config().expect("Failed to load the configuration")
+    }
+
+    #[test]
+    fn the_shipped_configuration_is_valid() {
+        assert_ok!(settings().validate());
+    }
+
+    #[test]
+    fn every_problem_is_reported() {
+        let mut settings = settings();
+        settings.application.hmac_secret = Secret::new("too-short".into());
+        settings.email.sender_email = "not-an-email".into();
+        settings.email.base_url = "no scheme".into();
+        settings.email.throttle.messages_per_second = 0;
+        settings.metrics.port = Some(settings.application.port);
+
+        let problems = settings.validate().unwrap_err().problems;
+
+        let keys: Vec<_> = problems
+            .iter()
+            .map(|problem| problem.split('`').nth(1).unwrap())
+            .collect();
+        assert_eq!(
+            keys,
+            [
+                "application.hmac_secret",
+                "email.base_url",
+                "email.sender_email",
+                "email.throttle.messages_per_second",
+                "metrics.port",
+            ]
+        );
+    }
+
+    #[test]
+    fn delivery_retries_back_off_exponentially() {
+        let retries = DeliveryRetrySettings {
+            max_attempts: 5,
+            delay_in_secs: 60,
+        };
+        assert_eq!(retries.delay_after(1), chrono::Duration::seconds(60));
+        assert_eq!(retries.delay_after(2), chrono::Duration::seconds(120));
+        assert_eq!(retries.delay_after(4), chrono::Duration::seconds(480));
+    }
+
+    #[test]
+    fn a_redis_backend_requires_a_redis_uri() {
+        let mut settings = settings();
+        settings.redis_uri = None;
+
+        let problems = settings.validate().unwrap_err().problems;
+
+        assert_eq!(
+            problems,
+            ["`redis_uri` must be set when `session.store` or `rate_limit.backend` is `redis`."]
+        );
+    }
+
+    #[test]
+    fn secrets_are_redacted_when_printed() {
+        let mut settings = settings();
+        settings.application.hmac_secret = Secret::new("a".repeat(64));
+        settings.redis_uri = Some(Secret::new("redis://:hunter2@localhost".into()));
+
+        let printed = serde_json::to_value(&settings).unwrap();
+
+        assert_eq!(printed["application"]["hmac_secret"], "[REDACTED]");
+        assert_eq!(printed["database"]["password"], "[REDACTED]");
+        assert_eq!(printed["email"]["auth_token"], "[REDACTED]");
+        assert_eq!(printed["redis_uri"], "[REDACTED]");
+        assert!(!printed.to_string().contains("hunter2"));
+        assert_eq!(
+            printed["email"]["sender_email"],
+            settings.email.sender_email
+        );
+    }
 }
@@ -0,0 +1,141 @@
+//! Consent record keeping: an append-only log of how and when each subscriber
+//! agreed to receive the newsletter, and withdrew their consent.
+use crate::client_ip::client_ip;
+use actix_web::HttpRequest;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+/// Shown next to the subscription form. Bump the version whenever the text changes,
+/// so that the log tells which wording each subscriber agreed to.
+pub const CONSENT_TEXT: &str =
+    "By subscribing, you agree to receive our newsletter by email. You can unsubscribe at any time.";
+pub const CONSENT_TEXT_VERSION: &str = "2023-06-30";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ConsentAction {
+    Signup,
+    Confirmation,
+    /// The subscriber unsubscribed.
+    Withdrawal,
+}
+
+impl ConsentAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConsentAction::Signup => "signup",
+            ConsentAction::Confirmation => "confirmation",
+            ConsentAction::Withdrawal => "withdrawal",
+        }
+    }
+}
+
+/// Where consent was given.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ConsentSource {
+    /// The subscription form on the website.
+    Form,
+    /// The JSON API, on behalf of the subscriber.
+    Api,
+    /// The link in the confirmation email.
+    Email,
+}
+
+impl ConsentSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConsentSource::Form => "form",
+            ConsentSource::Api => "api",
+            ConsentSource::Email => "email",
+        }
+    }
+}
+
+/// The circumstances of a consent action, captured from the request that carried it.
+#[derive(Debug)]
+pub struct ConsentEvidence {
+    pub source: ConsentSource,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    /// `None` when no consent text was shown, e.g. for API clients.
+    pub consent_text_version: Option<&'static str>,
+}
+
+impl ConsentEvidence {
+    pub fn from_request(
+        source: ConsentSource,
+        consent_text_version: Option<&'static str>,
+        request: &HttpRequest,
+    ) -> Self {
+        Self {
+            source,
+            ip: client_ip(request).map(|ip| ip.to_string()),
+            user_agent: request
+                .headers()
+                .get("User-Agent")
+                .and_then(|h| h.to_str().ok())
+                .map(String::from),
+            consent_text_version,
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct ConsentRecord {
+    pub action: String,
+    pub source: String,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub consent_text_version: Option<String>,
+    pub recorded_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(name = "Record consent", skip(executor, evidence))]
+pub async fn record_consent(
+    executor: impl PgExecutor<'_>,
+    subscriber_id: Uuid,
+    action: ConsentAction,
+    evidence: &ConsentEvidence,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+    INSERT INTO subscriber_consents
+        (id, subscriber_id, action, source, ip, user_agent, consent_text_version, recorded_at)
+    VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+    "#,
+        Uuid::new_v4(),
+        subscriber_id,
+        action.as_str(),
+        evidence.source.as_str(),
+        evidence.ip,
+        evidence.user_agent,
+        evidence.consent_text_version,
+        Utc::now(),
+    )
+    .execute(executor)
+    .await
+    .context("Failed to record consent.")?;
+    Ok(())
+}
+
+/// Oldest first.
+#[tracing::instrument(name = "Get the consent log of a subscriber", skip(pool))]
+pub async fn get_consent_log(
+    subscriber_id: Uuid,
+    pool: &PgPool,
+) -> Result<Vec<ConsentRecord>, anyhow::Error> {
+    sqlx::query_as!(
+        ConsentRecord,
+        r#"
+    SELECT action, source, ip, user_agent, consent_text_version, recorded_at
+    FROM subscriber_consents
+    WHERE subscriber_id = $1
+    ORDER BY recorded_at
+    "#,
+        subscriber_id,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the consent log of a subscriber.")
+}
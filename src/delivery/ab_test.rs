@@ -0,0 +1,324 @@
+use super::{IssueMailer, OutgoingIssue};
+use crate::configuration::AbTestSettings;
+use crate::segments::Filter;
+use crate::shutdown::Shutdown;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+const MAX_SUBJECT_VARIANTS: usize = 5;
+/// Tests ended per poll.
+const BATCH_SIZE: i64 = 10;
+
+/// An A/B test needs two to five non-empty subject lines.
+pub fn parse_subject_variants(subjects: Vec<String>) -> Result<Vec<String>, String> {
+    if !(2..=MAX_SUBJECT_VARIANTS).contains(&subjects.len()) {
+        return Err(format!(
+            "`subject_variants` must hold between 2 and {} subjects.",
+            MAX_SUBJECT_VARIANTS
+        ));
+    }
+    if subjects.iter().any(|s| s.trim().is_empty()) {
+        return Err("Subject variants cannot be empty.".into());
+    }
+    Ok(subjects)
+}
+
+/// How many recipients get each variant during the test: `test_percent` of them in all,
+/// rounded up, as long as every variant gets as many.
+pub fn test_slice_size(recipients: usize, variants: usize, test_percent: u8) -> usize {
+    let per_variant = (recipients * test_percent as usize).div_ceil(100 * variants);
+    per_variant.min(recipients / variants)
+}
+
+#[derive(Debug)]
+pub struct VariantResult {
+    pub position: i32,
+    pub subject: String,
+    pub recipients: i64,
+    pub opens: i64,
+}
+
+impl VariantResult {
+    /// Empty slices rate as 0.
+    fn open_rate(&self) -> f64 {
+        if self.recipients == 0 {
+            0.0
+        } else {
+            self.opens as f64 / self.recipients as f64
+        }
+    }
+}
+
+/// The variant with the best open rate, the first one on a tie.
+pub fn pick_winner(results: &[VariantResult]) -> Option<&VariantResult> {
+    results.iter().reduce(|best, candidate| {
+        if candidate.open_rate() > best.open_rate() {
+            candidate
+        } else {
+            best
+        }
+    })
+}
+
+/// Store the variants of the issue, and when the test ends.
+pub(super) async fn start_ab_test(
+    pool: &PgPool,
+    issue_id: Uuid,
+    subjects: &[String],
+    ends_at: DateTime<Utc>,
+) -> Result<(), anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    sqlx::query!(
+        r#"
+    INSERT INTO subject_variants (issue_id, position, subject)
+    SELECT $1, position - 1, subject FROM unnest($2::text[]) WITH ORDINALITY AS v(subject, position)
+    "#,
+        issue_id,
+        subjects,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to store the subject variants of an issue.")?;
+    sqlx::query!(
+        r#"UPDATE newsletter_issues SET ab_test_ends_at = $2 WHERE id = $1"#,
+        issue_id,
+        ends_at,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to schedule the end of an A/B test.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to start an A/B test.")?;
+    Ok(())
+}
+
+/// Ends the A/B tests of subject lines: once an issue's test slices had
+/// `ab_testing.wait_in_secs` to open it, the subject with the best open rate is sent to
+/// the rest of the recipients.
+///
+/// An issue is claimed by setting its `ab_test_finished_at` before anything is sent, so
+/// that several instances can poll at once. Emails that fail to send are retried by
+/// `DeliveryResumer`.
+pub struct AbTestFinisher {
+    pool: PgPool,
+    mailer: IssueMailer,
+    settings: AbTestSettings,
+}
+
+struct FinishedTest {
+    id: Uuid,
+    title: String,
+    html_content: String,
+    text_content: String,
+    segment_filter: Option<String>,
+}
+
+impl AbTestFinisher {
+    pub fn new(pool: PgPool, mailer: IssueMailer, settings: AbTestSettings) -> Self {
+        Self {
+            pool,
+            mailer,
+            settings,
+        }
+    }
+
+    /// Poll for finished tests until shutdown starts.
+    pub fn spawn(self, shutdown: &Shutdown) -> JoinHandle<()> {
+        let stop = shutdown.clone();
+        shutdown.spawn("A/B test finisher", async move {
+            let mut interval = tokio::time::interval(self.settings.poll_interval());
+            while stop.tick(&mut interval).await {
+                if let Err(e) = self.finish_due_tests().await {
+                    tracing::error!(error.cause_chain = ?e, "Failed to end A/B tests.");
+                }
+            }
+        })
+    }
+
+    /// End the tests that are due, and return how many issues were claimed.
+    pub async fn finish_due_tests(&self) -> Result<usize, anyhow::Error> {
+        let tests = self.claim_due_tests().await?;
+        for test in &tests {
+            if let Err(e) = self.finish(test).await {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    issue_id = %test.id,
+                    "Failed to end an A/B test."
+                );
+            }
+        }
+        Ok(tests.len())
+    }
+
+    #[tracing::instrument(name = "Claim due A/B tests", skip(self))]
+    async fn claim_due_tests(&self) -> Result<Vec<FinishedTest>, anyhow::Error> {
+        sqlx::query_as!(
+            FinishedTest,
+            r#"
+        UPDATE newsletter_issues i
+        SET ab_test_finished_at = now()
+        FROM (
+            SELECT id FROM newsletter_issues
+            WHERE ab_test_finished_at IS NULL AND ab_test_ends_at <= now()
+            ORDER BY ab_test_ends_at
+            LIMIT $1
+            FOR UPDATE SKIP LOCKED
+        ) due
+        WHERE i.id = due.id
+        RETURNING i.id, i.title, i.html_content, i.text_content, i.segment_filter
+        "#,
+            BATCH_SIZE,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to claim due A/B tests.")
+    }
+
+    #[tracing::instrument(name = "End an A/B test", skip(self, test), fields(issue_id = %test.id))]
+    async fn finish(&self, test: &FinishedTest) -> Result<(), anyhow::Error> {
+        let results = sqlx::query_as!(
+            VariantResult,
+            r#"
+        SELECT v.position, v.subject,
+            count(d.id) AS "recipients!", count(d.opened_at) AS "opens!"
+        FROM subject_variants v
+        LEFT JOIN issue_deliveries d
+            ON d.issue_id = v.issue_id AND d.variant = v.position AND d.failed_at IS NULL
+        WHERE v.issue_id = $1
+        GROUP BY v.position, v.subject
+        ORDER BY v.position
+        "#,
+            test.id,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to count the opens of the subject variants.")?;
+        let winner = pick_winner(&results).context("The A/B test has no subject variants.")?;
+        self.store_results(test.id, &results, winner.position)
+            .await?;
+
+        let segment = test
+            .segment_filter
+            .as_deref()
+            .map(Filter::parse)
+            .transpose()
+            .map_err(anyhow::Error::msg)?;
+        let issue = OutgoingIssue {
+            id: test.id,
+            title: &test.title,
+            html_content: &test.html_content,
+            text_content: &test.text_content,
+        };
+        let sent = self
+            .mailer
+            .send_to_remainder(&issue, segment.as_ref(), &winner.subject)
+            .await?;
+        self.mailer.announce_sent(&issue).await?;
+        tracing::info!(
+            winning_variant = winner.position,
+            recipients = sent,
+            "Sent the winning subject."
+        );
+        Ok(())
+    }
+
+    async fn store_results(
+        &self,
+        issue_id: Uuid,
+        results: &[VariantResult],
+        winning_variant: i32,
+    ) -> Result<(), anyhow::Error> {
+        let mut transaction = self
+            .pool
+            .begin()
+            .await
+            .context("Failed to acquire a Postgres connection from the pool")?;
+        sqlx::query!(
+            r#"
+        UPDATE subject_variants v
+        SET test_recipients = r.recipients, test_opens = r.opens
+        FROM unnest($2::int[], $3::bigint[], $4::bigint[]) AS r(position, recipients, opens)
+        WHERE v.issue_id = $1 AND v.position = r.position
+        "#,
+            issue_id,
+            &results.iter().map(|r| r.position).collect::<Vec<_>>(),
+            &results.iter().map(|r| r.recipients).collect::<Vec<_>>(),
+            &results.iter().map(|r| r.opens).collect::<Vec<_>>(),
+        )
+        .execute(&mut transaction)
+        .await
+        .context("Failed to store the results of an A/B test.")?;
+        sqlx::query!(
+            r#"UPDATE newsletter_issues SET winning_variant = $2 WHERE id = $1"#,
+            issue_id,
+            winning_variant,
+        )
+        .execute(&mut transaction)
+        .await
+        .context("Failed to store the winner of an A/B test.")?;
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit SQL transaction to store the results of an A/B test.")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_subject_variants, pick_winner, test_slice_size, VariantResult};
+    use claims::assert_err;
+
+    fn variant(position: i32, recipients: i64, opens: i64) -> VariantResult {
+        VariantResult {
+            position,
+            subject: format!("Subject {}", position),
+            recipients,
+            opens,
+        }
+    }
+
+    #[test]
+    fn the_best_open_rate_wins() {
+        let results = [variant(0, 10, 2), variant(1, 9, 3), variant(2, 10, 3)];
+        assert_eq!(pick_winner(&results).unwrap().position, 1);
+    }
+
+    #[test]
+    fn ties_and_empty_slices_go_to_the_first_variant() {
+        let results = [variant(0, 10, 1), variant(1, 10, 1)];
+        assert_eq!(pick_winner(&results).unwrap().position, 0);
+        let results = [variant(0, 0, 0), variant(1, 0, 0)];
+        assert_eq!(pick_winner(&results).unwrap().position, 0);
+        let results = [variant(0, 0, 0), variant(1, 5, 1)];
+        assert_eq!(pick_winner(&results).unwrap().position, 1);
+    }
+
+    #[test]
+    fn test_slices_are_rounded_up_but_never_overlap() {
+        assert_eq!(test_slice_size(1000, 2, 20), 100);
+        assert_eq!(test_slice_size(10, 3, 20), 1);
+        assert_eq!(test_slice_size(3, 2, 100), 1);
+        assert_eq!(test_slice_size(1, 2, 20), 0);
+        assert_eq!(test_slice_size(0, 2, 20), 0);
+    }
+
+    #[test]
+    fn an_ab_test_needs_two_to_five_subjects() {
+        assert_err!(parse_subject_variants(vec!["A".into()]));
+        assert_err!(parse_subject_variants(vec!["A".into(), " ".into()]));
+        assert_err!(parse_subject_variants(vec!["A".into(); 6]));
+        assert_eq!(
+            parse_subject_variants(vec!["A".into(), "B".into()]).unwrap(),
+            ["A", "B"]
+        );
+    }
+}
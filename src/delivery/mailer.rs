@@ -0,0 +1,376 @@
+use super::{start_ab_test, test_slice_size};
+use crate::configuration::{AbTestSettings, DeliveryRetrySettings};
+use crate::domain::subscriber_email::SubscriberEmail;
+use crate::preferences::PreferenceLinks;
+use crate::segments::{audience_query, Filter};
+use crate::services::email::{EmailError, EmailKind, EmailService};
+use crate::shutdown::Shutdown;
+use crate::webhooks::{enqueue_event, EventType, IssueData};
+use actix_web::web;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use rand::seq::SliceRandom;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Sends issues to the subscribers who want each of them.
+#[derive(Clone)]
+pub struct IssueMailer {
+    pool: PgPool,
+    email_service: web::Data<EmailService>,
+    preference_links: PreferenceLinks,
+    base_url: String,
+    ab_testing: AbTestSettings,
+    retries: DeliveryRetrySettings,
+    shutdown: Shutdown,
+}
+
+/// A published issue, on its way to the subscribers.
+pub struct OutgoingIssue<'a> {
+    pub id: Uuid,
+    pub title: &'a str,
+    pub html_content: &'a str,
+    pub text_content: &'a str,
+}
+
+struct ConfirmedSubscriber {
+    id: Uuid,
+    email: SubscriberEmail,
+}
+
+enum SendOutcome {
+    Sent,
+    AlreadySent,
+    QuotaExhausted {
+        resets_at: DateTime<Utc>,
+    },
+    /// `retry_at` is unset once the recipient ran out of attempts.
+    Failed {
+        retry_at: Option<DateTime<Utc>>,
+    },
+}
+
+impl IssueMailer {
+    pub fn new(
+        pool: PgPool,
+        email_service: web::Data<EmailService>,
+        preference_links: PreferenceLinks,
+        base_url: String,
+        ab_testing: AbTestSettings,
+        retries: DeliveryRetrySettings,
+        shutdown: Shutdown,
+    ) -> Self {
+        Self {
+            pool,
+            email_service,
+            preference_links,
+            base_url,
+            ab_testing,
+            retries,
+            shutdown,
+        }
+    }
+
+    /// Send an issue to every confirmed subscriber who wants each issue and has not paused
+    /// delivery, then notify the webhook endpoints. Digest subscribers get it with their
+    /// next digest. With a segment, only its subscribers get the issue.
+    ///
+    /// With subject variants, only a random test slice per variant gets the issue now:
+    /// `AbTestFinisher` sends the winning subject to the others once the test ends, and
+    /// notifies the webhook endpoints then.
+    ///
+    /// A failed email does not stop the others: `DeliveryResumer` retries it later. If the
+    /// email quota runs out, delivery is paused: `DeliveryResumer` sends the issue to the
+    /// others in the next quota window. Past the shutdown deadline, it sends it to them
+    /// after the restart.
+    #[tracing::instrument(
+        name = "Send a newsletter issue to confirmed subscribers",
+        skip(self, issue, segment),
+        fields(issue_id = %issue.id)
+    )]
+    pub async fn publish(
+        &self,
+        issue: &OutgoingIssue<'_>,
+        segment: Option<&Filter>,
+        subject_variants: &[String],
+    ) -> Result<(), anyhow::Error> {
+        let mut recipients = self.pending_recipients(issue.id, segment).await?;
+        let mut sends = Vec::with_capacity(recipients.len());
+        if subject_variants.is_empty() {
+            sends.extend(recipients.iter().map(|r| (r, issue.title, None)));
+        } else {
+            recipients.shuffle(&mut rand::thread_rng());
+            let slice_size = test_slice_size(
+                recipients.len(),
+                subject_variants.len(),
+                self.ab_testing.test_percent,
+            );
+            let ends_at = Utc::now() + self.ab_testing.wait();
+            start_ab_test(&self.pool, issue.id, subject_variants, ends_at).await?;
+            let test_slices = recipients.iter().take(slice_size * subject_variants.len());
+            sends.extend(test_slices.enumerate().map(|(i, recipient)| {
+                let variant = i % subject_variants.len();
+                (
+                    recipient,
+                    subject_variants[variant].as_str(),
+                    Some(variant as i32),
+                )
+            }));
+        }
+        self.deliver(issue, &sends).await?;
+        if subject_variants.is_empty() {
+            self.announce_sent(issue).await?;
+        }
+        Ok(())
+    }
+
+    /// Enqueue the `issue.sent` event, once every recipient was sent the issue or
+    /// left for `DeliveryResumer`.
+    pub async fn announce_sent(&self, issue: &OutgoingIssue<'_>) -> Result<(), anyhow::Error> {
+        enqueue_event(
+            &self.pool,
+            EventType::IssueSent,
+            IssueData {
+                id: issue.id,
+                title: issue.title,
+            },
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Send the issue to the subscribers it has not reached yet, failed deliveries with
+    /// attempts left included, and return how many got it. A failed email does not stop
+    /// the others, but running out of quota pauses delivery until the next window, and the
+    /// shutdown deadline until the restart.
+    #[tracing::instrument(
+        name = "Send a newsletter issue to the remaining subscribers",
+        skip(self, issue, segment),
+        fields(issue_id = %issue.id)
+    )]
+    pub async fn send_to_remainder(
+        &self,
+        issue: &OutgoingIssue<'_>,
+        segment: Option<&Filter>,
+        subject: &str,
+    ) -> Result<usize, anyhow::Error> {
+        let recipients = self.pending_recipients(issue.id, segment).await?;
+        let sends: Vec<_> = recipients.iter().map(|r| (r, subject, None)).collect();
+        self.deliver(issue, &sends).await
+    }
+
+    /// Send each email in turn, and return how many were sent. Delivery is paused for
+    /// `DeliveryResumer` when the quota runs out, past the shutdown deadline, or until the
+    /// earliest retry of a failed email.
+    async fn deliver(
+        &self,
+        issue: &OutgoingIssue<'_>,
+        sends: &[(&ConfirmedSubscriber, &str, Option<i32>)],
+    ) -> Result<usize, anyhow::Error> {
+        let mut sent = 0;
+        let mut retry_at: Option<DateTime<Utc>> = None;
+        for (done, &(recipient, subject, variant)) in sends.iter().enumerate() {
+            if self.shutdown.deadline_passed() {
+                self.defer_to_restart(issue.id, sends.len() - done).await?;
+                return Ok(sent);
+            }
+            match self.send(issue, recipient, subject, variant).await {
+                Ok(SendOutcome::Sent) => sent += 1,
+                Ok(SendOutcome::AlreadySent) => {}
+                Ok(SendOutcome::QuotaExhausted { resets_at }) => {
+                    tracing::warn!("The email quota ran out before every recipient got the issue.");
+                    self.pause_delivery(issue.id, resets_at).await?;
+                    return Ok(sent);
+                }
+                Ok(SendOutcome::Failed { retry_at: next }) => {
+                    if let Some(next) = next {
+                        retry_at = Some(retry_at.map_or(next, |r| r.min(next)));
+                    }
+                }
+                Err(e) => tracing::error!(error.cause_chain = ?e, "Failed to send an issue."),
+            }
+        }
+        if let Some(retry_at) = retry_at {
+            self.pause_delivery(issue.id, retry_at).await?;
+        }
+        Ok(sent)
+    }
+
+    /// Leave the recipients left to `DeliveryResumer`, as soon as the application is back.
+    async fn defer_to_restart(
+        &self,
+        issue_id: Uuid,
+        recipients_left: usize,
+    ) -> Result<(), anyhow::Error> {
+        tracing::warn!(
+            recipients_left,
+            "Shutdown stopped the delivery of an issue before every recipient got it."
+        );
+        self.shutdown
+            .record_deferred_issue(issue_id, recipients_left);
+        self.pause_delivery(issue_id, Utc::now()).await
+    }
+
+    #[tracing::instrument(name = "Pause the delivery of an issue", skip(self))]
+    async fn pause_delivery(
+        &self,
+        issue_id: Uuid,
+        resumes_at: DateTime<Utc>,
+    ) -> Result<(), anyhow::Error> {
+        sqlx::query!(
+            r#"UPDATE newsletter_issues SET delivery_resumes_at = $2 WHERE id = $1"#,
+            issue_id,
+            resumes_at,
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to pause the delivery of an issue.")?;
+        Ok(())
+    }
+
+    /// The subscribers who should get the issue and have not yet: never tried, or failed
+    /// with attempts left.
+    #[tracing::instrument(name = "Get confirmed subscribers", skip(self, segment))]
+    async fn pending_recipients(
+        &self,
+        issue_id: Uuid,
+        segment: Option<&Filter>,
+    ) -> Result<Vec<ConfirmedSubscriber>, anyhow::Error> {
+        let mut query = audience_query("s.id, s.email", segment);
+        query
+            .push(
+                " AND s.frequency = 'every_issue' AND NOT EXISTS \
+                (SELECT 1 FROM issue_deliveries d WHERE d.subscriber_id = s.id AND d.issue_id = ",
+            )
+            .push_bind(issue_id)
+            .push(" AND (d.failed_at IS NULL OR d.failed_attempts >= ")
+            .push_bind(self.retries.max_attempts)
+            .push("))");
+        let rows = query
+            .build_query_as::<(Uuid, String)>()
+            .fetch_all(&self.pool)
+            .await
+            .context("Failed to retrieve the recipients of an issue.")?;
+        let mut recipients = Vec::with_capacity(rows.len());
+        for (id, email) in rows {
+            match SubscriberEmail::parse(email) {
+                Ok(email) => recipients.push(ConfirmedSubscriber { id, email }),
+                Err(error) => {
+                    tracing::warn!(
+                        // We record the error chain as a structured field
+                        // on the log record.
+                        error.cause_chain = ?error,
+                        subscriber_id = %id,
+                        // Using `\` to split a long string literal over
+                        // two lines, without creating a `\n` character.
+                        "Skipping a confirmed subscriber. \
+                        Their stored contact details are invalid",
+                    );
+                }
+            }
+        }
+        Ok(recipients)
+    }
+
+    async fn send(
+        &self,
+        issue: &OutgoingIssue<'_>,
+        recipient: &ConfirmedSubscriber,
+        subject: &str,
+        variant: Option<i32>,
+    ) -> Result<SendOutcome, anyhow::Error> {
+        // Recorded first, so that overlapping senders send each issue once per subscriber.
+        // A failed delivery with attempts left is claimed again, keeping its id.
+        let claimed = sqlx::query!(
+            r#"
+        INSERT INTO issue_deliveries (id, issue_id, subscriber_id, variant, sent_at)
+        VALUES ($1, $2, $3, $4, now())
+        ON CONFLICT (issue_id, subscriber_id) DO UPDATE
+        SET variant = EXCLUDED.variant, sent_at = now(), failed_at = NULL
+        WHERE issue_deliveries.failed_at IS NOT NULL
+            AND issue_deliveries.failed_attempts < $5
+        RETURNING id
+        "#,
+            Uuid::new_v4(),
+            issue.id,
+            recipient.id,
+            variant,
+            self.retries.max_attempts,
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to record the delivery of an issue.")?;
+        let delivery_id = match claimed {
+            Some(claimed) => claimed.id,
+            None => return Ok(SendOutcome::AlreadySent),
+        };
+
+        let (html, text) =
+            self.preference_links
+                .add_footer(recipient.id, issue.html_content, issue.text_content);
+        let html = format!(
+            r#"{}<img src="{}/open/{}" width="1" height="1" alt="">"#,
+            html, self.base_url, delivery_id
+        );
+        let outcome = self
+            .email_service
+            .send_email(EmailKind::Issue, &recipient.email, subject, &html, &text)
+            .await;
+        match outcome {
+            Ok(()) => Ok(SendOutcome::Sent),
+            // Not the recipient's fault: the attempt does not count.
+            Err(EmailError::QuotaExhausted(e)) => {
+                self.record_failure(delivery_id, false).await?;
+                Ok(SendOutcome::QuotaExhausted {
+                    resets_at: e.resets_at,
+                })
+            }
+            Err(e) => {
+                let failed_attempts = self.record_failure(delivery_id, true).await?;
+                let retry_at = (failed_attempts < self.retries.max_attempts)
+                    .then(|| Utc::now() + self.retries.delay_after(failed_attempts));
+                let e = anyhow::Error::new(e).context(format!(
+                    "Failed to send newsletter issue to {}",
+                    recipient.email
+                ));
+                match retry_at {
+                    Some(retry_at) => tracing::warn!(
+                        error.cause_chain = ?e,
+                        failed_attempts,
+                        %retry_at,
+                        "Failed to send an issue, it will be retried."
+                    ),
+                    None => tracing::error!(
+                        error.cause_chain = ?e,
+                        failed_attempts,
+                        "Failed to send an issue, giving up on this recipient."
+                    ),
+                }
+                Ok(SendOutcome::Failed { retry_at })
+            }
+        }
+    }
+
+    /// Keep the delivery as failed, so that a later attempt sends it. Returns how many
+    /// attempts failed so far.
+    async fn record_failure(
+        &self,
+        delivery_id: Uuid,
+        counts_as_attempt: bool,
+    ) -> Result<i32, anyhow::Error> {
+        let delivery = sqlx::query!(
+            r#"
+        UPDATE issue_deliveries
+        SET failed_at = now(), failed_attempts = failed_attempts + $2
+        WHERE id = $1
+        RETURNING failed_attempts
+        "#,
+            delivery_id,
+            i32::from(counts_as_attempt),
+        )
+        .fetch_one(&self.pool)
+        .await
+        .context("Failed to record a failed delivery.")?;
+        Ok(delivery.failed_attempts)
+    }
+}
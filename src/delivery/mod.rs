@@ -0,0 +1,36 @@
+//! Delivery of issues: one email per subscriber, with their preference link and a
+//! pixel tracking opens, A/B tests of subject lines, deliveries paused by the email
+//! quota, and retries of the emails that failed.
+mod ab_test;
+mod mailer;
+mod resume;
+
+pub use ab_test::*;
+pub use mailer::*;
+pub use resume::*;
+
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// The subscriber's email client loaded the tracking pixel of a delivery. Only the first
+/// open is kept, and counts as engagement.
+#[tracing::instrument(name = "Record an open", skip(pool))]
+pub async fn record_open(delivery_id: Uuid, pool: &PgPool) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+    WITH opened AS (
+        UPDATE issue_deliveries SET opened_at = COALESCE(opened_at, now())
+        WHERE id = $1
+        RETURNING subscriber_id
+    )
+    UPDATE subscriptions SET last_engaged_at = now()
+    WHERE id IN (SELECT subscriber_id FROM opened)
+    "#,
+        delivery_id,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to record an open.")?;
+    Ok(())
+}
@@ -0,0 +1,127 @@
+use super::{IssueMailer, OutgoingIssue};
+use crate::configuration::EmailThrottleSettings;
+use crate::segments::Filter;
+use crate::shutdown::Shutdown;
+use anyhow::Context;
+use sqlx::PgPool;
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+/// Issues resumed per poll.
+const BATCH_SIZE: i64 = 10;
+
+/// Resumes the delivery of issues paused when the email quota ran out, once the next
+/// quota window starts, and retries the emails that failed, once their delay is over.
+/// Issues still in an A/B test wait for `AbTestFinisher`, which sends them with the
+/// winning subject.
+///
+/// An issue is claimed by clearing its `delivery_resumes_at`; sending to the remainder
+/// sets it again if the quota runs out once more.
+pub struct DeliveryResumer {
+    pool: PgPool,
+    mailer: IssueMailer,
+    settings: EmailThrottleSettings,
+}
+
+struct PausedIssue {
+    id: Uuid,
+    title: String,
+    html_content: String,
+    text_content: String,
+    segment_filter: Option<String>,
+    subject: String,
+}
+
+impl DeliveryResumer {
+    pub fn new(pool: PgPool, mailer: IssueMailer, settings: EmailThrottleSettings) -> Self {
+        Self {
+            pool,
+            mailer,
+            settings,
+        }
+    }
+
+    /// Poll for paused issues until shutdown starts.
+    pub fn spawn(self, shutdown: &Shutdown) -> JoinHandle<()> {
+        let stop = shutdown.clone();
+        shutdown.spawn("delivery resumer", async move {
+            let mut interval = tokio::time::interval(self.settings.resume_poll_interval());
+            while stop.tick(&mut interval).await {
+                if let Err(e) = self.resume_due_deliveries().await {
+                    tracing::error!(error.cause_chain = ?e, "Failed to resume deliveries.");
+                }
+            }
+        })
+    }
+
+    /// Resume the deliveries that are due, and return how many issues were claimed.
+    pub async fn resume_due_deliveries(&self) -> Result<usize, anyhow::Error> {
+        let issues = self.claim_due_issues().await?;
+        for issue in &issues {
+            if let Err(e) = self.resume(issue).await {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    issue_id = %issue.id,
+                    "Failed to resume the delivery of an issue."
+                );
+            }
+        }
+        Ok(issues.len())
+    }
+
+    #[tracing::instrument(name = "Claim paused issues", skip(self))]
+    async fn claim_due_issues(&self) -> Result<Vec<PausedIssue>, anyhow::Error> {
+        sqlx::query_as!(
+            PausedIssue,
+            r#"
+        UPDATE newsletter_issues i
+        SET delivery_resumes_at = NULL
+        FROM (
+            SELECT id FROM newsletter_issues
+            WHERE delivery_resumes_at <= now()
+                AND (ab_test_ends_at IS NULL OR winning_variant IS NOT NULL)
+            ORDER BY delivery_resumes_at
+            LIMIT $1
+            FOR UPDATE SKIP LOCKED
+        ) due
+        WHERE i.id = due.id
+        RETURNING i.id, i.title, i.html_content, i.text_content, i.segment_filter,
+            COALESCE(
+                (SELECT subject FROM subject_variants v
+                WHERE v.issue_id = i.id AND v.position = i.winning_variant),
+                i.title
+            ) AS "subject!"
+        "#,
+            BATCH_SIZE,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to claim paused issues.")
+    }
+
+    #[tracing::instrument(
+        name = "Resume the delivery of an issue",
+        skip(self, paused),
+        fields(issue_id = %paused.id)
+    )]
+    async fn resume(&self, paused: &PausedIssue) -> Result<(), anyhow::Error> {
+        let segment = paused
+            .segment_filter
+            .as_deref()
+            .map(Filter::parse)
+            .transpose()
+            .map_err(anyhow::Error::msg)?;
+        let issue = OutgoingIssue {
+            id: paused.id,
+            title: &paused.title,
+            html_content: &paused.html_content,
+            text_content: &paused.text_content,
+        };
+        let sent = self
+            .mailer
+            .send_to_remainder(&issue, segment.as_ref(), &paused.subject)
+            .await?;
+        tracing::info!(recipients = sent, "Resumed the delivery of an issue.");
+        Ok(())
+    }
+}
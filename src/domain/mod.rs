@@ -1,3 +1,4 @@
+pub mod new_password;
 pub mod new_subscriber;
 pub mod subscriber_email;
 pub mod subscriber_name;
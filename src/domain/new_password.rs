@@ -0,0 +1,129 @@
+use secrecy::{ExposeSecret, Secret};
+use std::collections::HashSet;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Common passwords that are rejected regardless of the configured policy.
+static COMMON_PASSWORDS: &str = include_str!("common_passwords.txt");
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub max_length: usize,
+}
+
+/// A password that satisfies the configured `PasswordPolicy`.
+/// The only way to build one is through `NewPassword::parse`.
+#[derive(Debug)]
+pub struct NewPassword(Secret<String>);
+
+impl NewPassword {
+    /// Returns every rule violated by `password`, not just the first one,
+    /// so that they can all be reported back to the user at once.
+    pub fn parse(
+        password: Secret<String>,
+        username: &str,
+        policy: &PasswordPolicy,
+    ) -> Result<Self, Vec<String>> {
+        let candidate = password.expose_secret();
+        let length = candidate.graphemes(true).count();
+        let mut violations = Vec::new();
+
+        if length < policy.min_length {
+            violations.push(format!(
+                "The new password must be at least {} characters long.",
+                policy.min_length
+            ));
+        }
+        if length > policy.max_length {
+            violations.push(format!(
+                "The new password must be at most {} characters long.",
+                policy.max_length
+            ));
+        }
+        if is_common_password(candidate) {
+            violations.push("The new password is too common.".to_string());
+        }
+        if !username.is_empty() && candidate.to_lowercase() == username.to_lowercase() {
+            violations.push("The new password must not be the same as your username.".to_string());
+        }
+
+        if violations.is_empty() {
+            Ok(Self(password))
+        } else {
+            Err(violations)
+        }
+    }
+
+    pub fn into_inner(self) -> Secret<String> {
+        self.0
+    }
+}
+
+fn is_common_password(candidate: &str) -> bool {
+    static DENYLIST: std::sync::OnceLock<HashSet<&'static str>> = std::sync::OnceLock::new();
+    DENYLIST
+        .get_or_init(|| {
+            COMMON_PASSWORDS
+                .lines()
+                .map(str::trim)
+                .filter(|l| !l.is_empty())
+                .collect()
+        })
+        .contains(candidate.to_lowercase().as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{NewPassword, PasswordPolicy};
+    use claims::{assert_err, assert_ok};
+    use secrecy::Secret;
+
+    fn policy() -> PasswordPolicy {
+        PasswordPolicy {
+            min_length: 12,
+            max_length: 128,
+        }
+    }
+
+    fn parse(password: &str, username: &str) -> Result<NewPassword, Vec<String>> {
+        NewPassword::parse(Secret::new(password.to_string()), username, &policy())
+    }
+
+    #[test]
+    fn a_password_shorter_than_the_minimum_is_rejected() {
+        assert_err!(parse("a-b-c-d", "ursula"));
+    }
+
+    #[test]
+    fn a_password_longer_than_the_maximum_is_rejected() {
+        assert_err!(parse(&"a".repeat(129), "ursula"));
+    }
+
+    #[test]
+    fn length_is_counted_in_graphemes() {
+        // 12 graphemes, 24 bytes
+        assert_ok!(parse(&"ё".repeat(12), "ursula"));
+        assert_err!(parse(&"ё".repeat(11), "ursula"));
+    }
+
+    #[test]
+    fn a_common_password_is_rejected_regardless_of_case() {
+        assert_err!(parse("QwertyUIOP123", "ursula"));
+    }
+
+    #[test]
+    fn a_password_equal_to_the_username_is_rejected() {
+        assert_err!(parse("ursula-le-guin", "Ursula-Le-Guin"));
+    }
+
+    #[test]
+    fn every_violated_rule_is_reported() {
+        let violations = parse("admin", "admin").err().unwrap();
+        assert_eq!(violations.len(), 3);
+    }
+
+    #[test]
+    fn a_valid_password_is_parsed_successfully() {
+        assert_ok!(parse("the-left-hand-of-darkness", "ursula"));
+    }
+}
@@ -1,18 +1,63 @@
+use std::collections::HashSet;
 use validator::validate_email;
 
+/// Throwaway mailbox providers: confirmation emails sent there never reach an actual reader.
+static DISPOSABLE_DOMAINS: &str = include_str!("disposable_domains.txt");
+
 #[derive(Debug)]
 pub struct SubscriberEmail(String);
 
 impl SubscriberEmail {
+    /// Surrounding whitespace is trimmed and the domain is lowercased and converted to its
+    /// ASCII (punycode) form, so that equivalent spellings of an address compare equal.
+    /// The local part keeps its case, as we mail it back as given, but addresses differing
+    /// only by case are one subscriber: subscriptions are unique on `lower(email)`.
     pub fn parse(email: String) -> Result<Self, String> {
-        if validate_email(&email) {
-            Ok(Self(email))
-        } else {
-            Err(format!("{} is not a valid subscriber email", email))
+        let trimmed = email.trim();
+        let (local_part, domain) = trimmed
+            .rsplit_once('@')
+            .filter(|(local_part, domain)| !local_part.is_empty() && !domain.is_empty())
+            .ok_or_else(|| format!("{} is not a valid subscriber email", email))?;
+        let domain = idna::domain_to_ascii(domain)
+            .map_err(|_| format!("{} is not a valid email domain", domain))?;
+        let normalized = format!("{}@{}", local_part, domain);
+
+        if !validate_email(&normalized) {
+            return Err(format!("{} is not a valid subscriber email", email));
+        }
+        if is_disposable_domain(&domain) {
+            return Err(format!(
+                "{} is a disposable email provider, please use a permanent address",
+                domain
+            ));
         }
+        Ok(Self(normalized))
+    }
+
+    pub fn domain(&self) -> &str {
+        self.0
+            .rsplit_once('@')
+            .map(|(_, domain)| domain)
+            .expect("A parsed email address contains an @.")
     }
 }
 
+/// Subdomains of a disposable provider are disposable too.
+fn is_disposable_domain(domain: &str) -> bool {
+    static DENYLIST: std::sync::OnceLock<HashSet<&'static str>> = std::sync::OnceLock::new();
+    let denylist = DENYLIST.get_or_init(|| {
+        DISPOSABLE_DOMAINS
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+            .collect()
+    });
+    std::iter::successors(Some(domain), |d| {
+        d.split_once('.').map(|(_, parent)| parent)
+    })
+    .any(|d| denylist.contains(d))
+}
+
 impl AsRef<str> for SubscriberEmail {
     fn as_ref(&self) -> &str {
         &self.0
@@ -28,7 +73,7 @@ impl std::fmt::Display for SubscriberEmail {
 #[cfg(test)]
 mod tests {
     use super::SubscriberEmail;
-    use claims::assert_err;
+    use claims::{assert_err, assert_ok};
     use fake::{faker::internet::en::SafeEmail, Fake};
 
     #[derive(Debug, Clone)]
@@ -59,6 +104,34 @@ mod tests {
         assert_err!(SubscriberEmail::parse(email));
     }
 
+    #[test]
+    fn the_domain_is_lowercased_and_surrounding_whitespace_trimmed() {
+        let email = SubscriberEmail::parse("  Ursula@Domain.COM \n".to_string()).unwrap();
+        assert_eq!(email.as_ref(), "Ursula@domain.com");
+    }
+
+    #[test]
+    fn internationalized_domains_are_converted_to_punycode() {
+        let email = SubscriberEmail::parse("ursula@Bücher.example".to_string()).unwrap();
+        assert_eq!(email.as_ref(), "ursula@xn--bcher-kva.example");
+        assert_eq!(email.domain(), "xn--bcher-kva.example");
+    }
+
+    #[test]
+    fn an_invalid_internationalized_domain_is_rejected() {
+        let error = SubscriberEmail::parse("ursula@xn--a.example".to_string()).unwrap_err();
+        assert!(error.contains("is not a valid email domain"), "{}", error);
+    }
+
+    #[test]
+    fn disposable_domains_and_their_subdomains_are_rejected() {
+        for email in ["ursula@mailinator.com", "ursula@eu.Mailinator.com"] {
+            let error = SubscriberEmail::parse(email.to_string()).unwrap_err();
+            assert!(error.contains("disposable email provider"), "{}", error);
+        }
+        assert_ok!(SubscriberEmail::parse("ursula@notyopmail.com".to_string()));
+    }
+
     #[quickcheck_macros::quickcheck]
     fn valid_emails_are_parsed_successfully(valid_email: ValidEmailFixture) -> bool {
         SubscriberEmail::parse(valid_email.0).is_ok()
@@ -0,0 +1,194 @@
+//! The dependency checks behind `/health/ready`.
+use crate::configuration::{DependencyCheck, HealthSettings};
+use anyhow::Context;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+/// The state of the application, or of one of its dependencies.
+#[derive(serde::Serialize, utoipa::ToSchema, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    Up,
+    Down,
+    /// The check took longer than `health.timeout_in_milli`.
+    Timeout,
+}
+
+#[derive(serde::Serialize, utoipa::ToSchema, Debug)]
+pub struct DependencyHealth {
+    pub status: HealthStatus,
+    /// Whether the application is unready while this dependency is not up.
+    pub required: bool,
+    pub latency_ms: u64,
+    pub timeout_ms: u64,
+    /// What went wrong, when the dependency is down. The details are logged.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(serde::Serialize, utoipa::ToSchema, Debug)]
+pub struct Liveness {
+    pub status: HealthStatus,
+}
+
+#[derive(serde::Serialize, utoipa::ToSchema, Debug)]
+pub struct Readiness {
+    /// `up` when every required dependency is up, `down` otherwise.
+    pub status: HealthStatus,
+    /// By dependency: `database`, `redis` when a store uses it, and `email_provider`
+    /// unless `health.email_provider` is `disabled`.
+    pub checks: BTreeMap<String, DependencyHealth>,
+}
+
+/// Probes the dependencies of the application, concurrently and each within
+/// `health.timeout_in_milli`.
+pub struct HealthChecker {
+    pool: PgPool,
+    redis: Option<redis::Client>,
+    email_provider: Option<EmailProviderProbe>,
+    timeout: Duration,
+}
+
+struct EmailProviderProbe {
+    client: reqwest::Client,
+    base_url: String,
+    required: bool,
+}
+
+impl HealthChecker {
+    /// `redis_uri` is given when the session store or the rate limiter relies on it.
+    pub fn new(
+        pool: PgPool,
+        redis_uri: Option<&Secret<String>>,
+        email_base_url: String,
+        settings: HealthSettings,
+    ) -> Result<Self, anyhow::Error> {
+        let redis = redis_uri
+            .map(|uri| redis::Client::open(uri.expose_secret().as_str()))
+            .transpose()
+            .context("Invalid `redis_uri`.")?;
+        let email_provider = match settings.email_provider {
+            DependencyCheck::Disabled => None,
+            check => Some(EmailProviderProbe {
+                // `probe` enforces the timeout.
+                client: reqwest::Client::new(),
+                base_url: email_base_url,
+                required: check == DependencyCheck::Required,
+            }),
+        };
+        Ok(Self {
+            pool,
+            redis,
+            email_provider,
+            timeout: settings.timeout(),
+        })
+    }
+
+    #[tracing::instrument(name = "Check dependencies", skip(self))]
+    pub async fn check(&self) -> Readiness {
+        let (database, redis, email_provider) = tokio::join!(
+            self.probe("database", true, self.ping_database()),
+            async {
+                match &self.redis {
+                    Some(client) => Some(self.probe("redis", true, ping_redis(client)).await),
+                    None => None,
+                }
+            },
+            async {
+                match &self.email_provider {
+                    Some(email) => Some(
+                        self.probe("email_provider", email.required, email.reach())
+                            .await,
+                    ),
+                    None => None,
+                }
+            },
+        );
+        let checks: BTreeMap<_, _> = [Some(database), redis, email_provider]
+            .into_iter()
+            .flatten()
+            .map(|(name, health)| (name.to_owned(), health))
+            .collect();
+        let ready = checks
+            .values()
+            .all(|health| !health.required || health.status == HealthStatus::Up);
+        Readiness {
+            status: if ready {
+                HealthStatus::Up
+            } else {
+                HealthStatus::Down
+            },
+            checks,
+        }
+    }
+
+    async fn probe(
+        &self,
+        name: &'static str,
+        required: bool,
+        check: impl Future<Output = Result<(), anyhow::Error>>,
+    ) -> (&'static str, DependencyHealth) {
+        let started = Instant::now();
+        let outcome = tokio::time::timeout(self.timeout, check).await;
+        let latency_ms = started.elapsed().as_millis() as u64;
+        let (status, error) = match outcome {
+            Ok(Ok(())) => (HealthStatus::Up, None),
+            Ok(Err(e)) => {
+                tracing::warn!(error.cause_chain = ?e, dependency = name, "A dependency is down.");
+                (HealthStatus::Down, Some(e.to_string()))
+            }
+            Err(_) => {
+                tracing::warn!(dependency = name, "A dependency check timed out.");
+                (HealthStatus::Timeout, None)
+            }
+        };
+        let health = DependencyHealth {
+            status,
+            required,
+            latency_ms,
+            timeout_ms: self.timeout.as_millis() as u64,
+            error,
+        };
+        (name, health)
+    }
+
+    async fn ping_database(&self) -> Result<(), anyhow::Error> {
+        sqlx::query("SELECT 1")
+            .execute(&self.pool)
+            .await
+            .context("Failed to query the database.")?;
+        Ok(())
+    }
+}
+
+async fn ping_redis(client: &redis::Client) -> Result<(), anyhow::Error> {
+    let mut connection = client
+        .get_async_connection()
+        .await
+        .context("Failed to connect to Redis.")?;
+    redis::cmd("PING")
+        .query_async::<_, String>(&mut connection)
+        .await
+        .context("Redis did not answer PING.")?;
+    Ok(())
+}
+
+impl EmailProviderProbe {
+    /// Any answer but a server error shows that the provider is reachable: its API
+    /// has no dedicated health endpoint.
+    async fn reach(&self) -> Result<(), anyhow::Error> {
+        let response = self
+            .client
+            .get(&self.base_url)
+            .send()
+            .await
+            .context("Failed to reach the email provider.")?;
+        if response.status().is_server_error() {
+            anyhow::bail!("The email provider answered {}.", response.status());
+        }
+        Ok(())
+    }
+}
@@ -1,9 +1,25 @@
 pub mod authentication;
+pub mod bot_protection;
+pub mod client_ip;
 pub mod configuration;
+pub mod consent;
+pub mod delivery;
 pub mod domain;
+pub mod health;
 pub mod libs;
+pub mod metrics;
+pub mod preferences;
+pub mod problem;
+pub mod rate_limit;
 pub mod routes;
+pub mod security;
+pub mod segments;
 pub mod services;
 pub mod session_state;
+pub mod session_store;
+pub mod shutdown;
+pub mod signed_link;
 pub mod startup;
+pub mod subscriber_data;
 pub mod telemetry;
+pub mod webhooks;
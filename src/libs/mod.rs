@@ -1,3 +1,4 @@
+use crate::problem::UnexpectedError;
 use actix_web::{http::header::LOCATION, HttpResponse};
 
 pub fn error_chain_fmt(
@@ -17,7 +18,7 @@ pub fn e500<T>(e: T) -> actix_web::Error
 where
     T: std::fmt::Debug + std::fmt::Display + 'static,
 {
-    actix_web::error::ErrorInternalServerError(e)
+    UnexpectedError(format!("{:?}", e)).into()
 }
 
 pub fn see_other(location: &str) -> HttpResponse {
@@ -1,18 +1,49 @@
 use zero2prod::{
-    configuration::get_config,
+    configuration::{get_config, load_config},
     startup::Application,
-    telemetry::{get_subscriber, init_subscriber},
+    telemetry::{get_subscriber, get_tracer_provider, init_subscriber},
 };
 
+const USAGE: &str = "Usage: zero2prod [config check]";
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    // Setup logging
-    let subscriber = get_subscriber("zero2prod".into(), "info".into(), std::io::stdout);
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        [] => serve().await,
+        ["config", "check"] => check_config(),
+        _ => anyhow::bail!(USAGE),
+    }
+}
+
+async fn serve() -> anyhow::Result<()> {
+    // Refuse to start with an invalid configuration, listing every problem
+    let config = get_config()?;
+
+    // Setup logging, and the export of spans
+    let tracer_provider = get_tracer_provider(&config.tracing)?;
+    let subscriber = get_subscriber(
+        "zero2prod".into(),
+        "info".into(),
+        std::io::stdout,
+        &tracer_provider,
+    );
     init_subscriber(subscriber);
 
-    // Panic if we cant read config
-    let config = get_config().expect("Failed to read configuration.");
     let application = Application::build(config).await?;
     application.run_until_stopped().await?;
+    // Export the spans still buffered: the provider blocks on the exporter as it shuts down.
+    tokio::task::spawn_blocking(move || drop(tracer_provider)).await?;
+    Ok(())
+}
+
+/// Print the configuration the application would run with, secrets redacted, then the
+/// problems found in it. Fails when there are any.
+fn check_config() -> anyhow::Result<()> {
+    let config = load_config()?;
+    println!("{}", serde_json::to_string_pretty(&config)?);
+    config.validate()?;
+    eprintln!("The configuration is valid.");
     Ok(())
 }
@@ -0,0 +1,230 @@
+//! Prometheus metrics, served at `/metrics`: on the application port, or on
+//! `metrics.port` when set.
+use crate::services::email::EmailService;
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::Method,
+    web, HttpResponse,
+};
+use actix_web_lab::middleware::Next;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+use sqlx::PgPool;
+use std::time::Instant;
+
+/// Requests that match no route share a label, so that scanners cannot grow the series.
+const UNMATCHED_ROUTE: &str = "unmatched";
+/// Likewise for the methods outside of the standard ones: clients can send any token.
+const OTHER_METHOD: &str = "other";
+
+/// The metrics of one application. Clones share their series.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_request_duration: HistogramVec,
+    db_pool_connections: IntGaugeVec,
+    emails_sent: IntCounterVec,
+    emails_failed: IntCounterVec,
+    emails_sent_last_minute: IntGauge,
+    emails_sent_in_quota_window: IntGauge,
+    subscriptions: IntCounterVec,
+    confirmations: IntCounter,
+    login_failures: IntCounterVec,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new();
+        let metrics = Self {
+            http_requests: IntCounterVec::new(
+                Opts::new("http_requests_total", "HTTP requests handled."),
+                &["method", "route", "status"],
+            )
+            .unwrap(),
+            http_request_duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "http_request_duration_seconds",
+                    "Time taken to answer HTTP requests.",
+                ),
+                &["method", "route"],
+            )
+            .unwrap(),
+            db_pool_connections: IntGaugeVec::new(
+                Opts::new(
+                    "db_pool_connections",
+                    "Connections open in the database pool.",
+                ),
+                &["state"],
+            )
+            .unwrap(),
+            emails_sent: IntCounterVec::new(
+                Opts::new("emails_sent_total", "Emails accepted by the provider."),
+                &["type"],
+            )
+            .unwrap(),
+            emails_failed: IntCounterVec::new(
+                Opts::new("emails_failed_total", "Emails that could not be sent."),
+                &["type", "reason"],
+            )
+            .unwrap(),
+            emails_sent_last_minute: IntGauge::new(
+                "emails_sent_last_minute",
+                "Emails sent by this instance over the last minute.",
+            )
+            .unwrap(),
+            emails_sent_in_quota_window: IntGauge::new(
+                "emails_sent_in_quota_window",
+                "Emails counted against the quota of the current window.",
+            )
+            .unwrap(),
+            subscriptions: IntCounterVec::new(
+                Opts::new(
+                    "subscriptions_total",
+                    "New subscribers, pending confirmation.",
+                ),
+                &["source"],
+            )
+            .unwrap(),
+            confirmations: IntCounter::new(
+                "subscription_confirmations_total",
+                "Subscribers who confirmed their address.",
+            )
+            .unwrap(),
+            login_failures: IntCounterVec::new(
+                Opts::new("login_failures_total", "Rejected admin credentials."),
+                &["method"],
+            )
+            .unwrap(),
+            registry,
+        };
+        metrics.register_all();
+        metrics
+    }
+
+    fn register_all(&self) {
+        let collectors: [Box<dyn prometheus::core::Collector>; 10] = [
+            Box::new(self.http_requests.clone()),
+            Box::new(self.http_request_duration.clone()),
+            Box::new(self.db_pool_connections.clone()),
+            Box::new(self.emails_sent.clone()),
+            Box::new(self.emails_failed.clone()),
+            Box::new(self.emails_sent_last_minute.clone()),
+            Box::new(self.emails_sent_in_quota_window.clone()),
+            Box::new(self.subscriptions.clone()),
+            Box::new(self.confirmations.clone()),
+            Box::new(self.login_failures.clone()),
+        ];
+        for collector in collectors {
+            self.registry
+                .register(collector)
+                .expect("Metric names are unique.");
+        }
+    }
+
+    pub fn email_sent(&self, kind: &str) {
+        self.emails_sent.with_label_values(&[kind]).inc();
+    }
+
+    pub fn email_failed(&self, kind: &str, reason: &str) {
+        self.emails_failed.with_label_values(&[kind, reason]).inc();
+    }
+
+    /// `source` is the channel of the signup, as in the consent log.
+    pub fn subscription(&self, source: &str) {
+        self.subscriptions.with_label_values(&[source]).inc();
+    }
+
+    pub fn confirmation(&self) {
+        self.confirmations.inc();
+    }
+
+    /// `method` is `form` for the login page, `basic` for `Basic` credentials.
+    pub fn login_failure(&self, method: &str) {
+        self.login_failures.with_label_values(&[method]).inc();
+    }
+
+    /// Render every series in the Prometheus text format, after sampling the gauges.
+    fn render(&self, pool: &PgPool, email_service: &EmailService) -> String {
+        let idle = pool.num_idle() as i64;
+        let open = i64::from(pool.size());
+        self.db_pool_connections
+            .with_label_values(&["idle"])
+            .set(idle);
+        self.db_pool_connections
+            .with_label_values(&["in_use"])
+            .set(open - idle);
+        let stats = email_service.stats();
+        self.emails_sent_last_minute
+            .set(stats.sent_last_minute as i64);
+        self.emails_sent_in_quota_window
+            .set(stats.sent_in_quota_window as i64);
+
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("Failed to encode the metrics.");
+        String::from_utf8(buffer).expect("Metrics are valid UTF-8.")
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Count requests and time them, per route pattern.
+pub async fn record_http_metrics(
+    metrics: web::Data<Metrics>,
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let started = Instant::now();
+    let method = method_label(req.method());
+    let route = req
+        .match_pattern()
+        .unwrap_or_else(|| UNMATCHED_ROUTE.to_string());
+    let response = next.call(req).await;
+    let status = match &response {
+        Ok(response) => response.status(),
+        Err(e) => e.as_response_error().status_code(),
+    };
+    metrics
+        .http_requests
+        .with_label_values(&[method, &route, status.as_str()])
+        .inc();
+    metrics
+        .http_request_duration
+        .with_label_values(&[method, &route])
+        .observe(started.elapsed().as_secs_f64());
+    response
+}
+
+fn method_label(method: &Method) -> &'static str {
+    match *method {
+        Method::GET => "GET",
+        Method::HEAD => "HEAD",
+        Method::POST => "POST",
+        Method::PUT => "PUT",
+        Method::DELETE => "DELETE",
+        Method::CONNECT => "CONNECT",
+        Method::OPTIONS => "OPTIONS",
+        Method::TRACE => "TRACE",
+        Method::PATCH => "PATCH",
+        _ => OTHER_METHOD,
+    }
+}
+
+pub async fn serve_metrics(
+    metrics: web::Data<Metrics>,
+    pool: web::Data<PgPool>,
+    email_service: web::Data<EmailService>,
+) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(prometheus::TEXT_FORMAT)
+        .body(metrics.render(&pool, &email_service))
+}
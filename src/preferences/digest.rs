@@ -0,0 +1,202 @@
+use super::PreferenceLinks;
+use crate::configuration::PreferenceSettings;
+use crate::domain::subscriber_email::SubscriberEmail;
+use crate::segments::{is_in_segment, Filter};
+use crate::services::email::{EmailKind, EmailService};
+use crate::shutdown::Shutdown;
+use actix_web::web;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::fmt::Write;
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+/// Sends the weekly digests: the issues published since the previous digest of each
+/// subscriber, in one email.
+///
+/// A subscriber is claimed by moving their `digest_sent_at` forward before their digest
+/// is sent, so that several instances can poll at once. A digest that fails to send is
+/// not retried.
+pub struct DigestSender {
+    pool: PgPool,
+    email_service: web::Data<EmailService>,
+    links: PreferenceLinks,
+    settings: PreferenceSettings,
+    shutdown: Shutdown,
+}
+
+struct DueDigest {
+    id: Uuid,
+    email: String,
+    /// Issues published after this are included.
+    since: DateTime<Utc>,
+}
+
+struct DigestIssue {
+    title: String,
+    html_content: String,
+    text_content: String,
+    segment_filter: Option<String>,
+}
+
+impl DigestSender {
+    pub fn new(
+        pool: PgPool,
+        email_service: web::Data<EmailService>,
+        links: PreferenceLinks,
+        settings: PreferenceSettings,
+        shutdown: Shutdown,
+    ) -> Self {
+        Self {
+            pool,
+            email_service,
+            links,
+            settings,
+            shutdown,
+        }
+    }
+
+    /// Poll for due digests until shutdown starts.
+    pub fn spawn(self, shutdown: &Shutdown) -> JoinHandle<()> {
+        let stop = shutdown.clone();
+        shutdown.spawn("digest sender", async move {
+            let mut interval = tokio::time::interval(self.settings.digest_poll_interval());
+            while stop.tick(&mut interval).await {
+                if let Err(e) = self.send_due_digests().await {
+                    tracing::error!(error.cause_chain = ?e, "Failed to send digests.");
+                }
+            }
+        })
+    }
+
+    /// Send the digests that are due, and return how many subscribers were claimed.
+    /// Past the shutdown deadline, the claimed digests left are released, to be sent
+    /// after the restart.
+    pub async fn send_due_digests(&self) -> Result<usize, anyhow::Error> {
+        let digests = self.claim_due_digests().await?;
+        for (done, digest) in digests.iter().enumerate() {
+            if self.shutdown.deadline_passed() {
+                self.release(&digests[done..]).await?;
+                break;
+            }
+            if let Err(e) = self.send(digest).await {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    subscriber_id = %digest.id,
+                    "Failed to send a digest."
+                );
+            }
+        }
+        Ok(digests.len())
+    }
+
+    /// Issues published while the subscriber paused delivery are skipped.
+    #[tracing::instrument(name = "Claim due digests", skip(self))]
+    async fn claim_due_digests(&self) -> Result<Vec<DueDigest>, anyhow::Error> {
+        sqlx::query_as!(
+            DueDigest,
+            r#"
+        UPDATE subscriptions s
+        SET digest_sent_at = now()
+        FROM (
+            SELECT id, GREATEST(digest_sent_at, paused_until) AS since FROM subscriptions
+            WHERE status = 'confirmed'
+                AND frequency = 'weekly_digest'
+                AND (paused_until IS NULL OR paused_until <= now())
+                AND digest_sent_at <= now() - make_interval(secs => $1)
+            ORDER BY digest_sent_at
+            LIMIT $2
+            FOR UPDATE SKIP LOCKED
+        ) due
+        WHERE s.id = due.id
+        RETURNING s.id, s.email, due.since AS "since!"
+        "#,
+            self.settings.digest_interval_in_secs as f64,
+            self.settings.digest_batch_size,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to claim due digests.")
+    }
+
+    #[tracing::instrument(name = "Release claimed digests", skip_all, fields(count = digests.len()))]
+    async fn release(&self, digests: &[DueDigest]) -> Result<(), anyhow::Error> {
+        tracing::warn!("Shutdown cut a digest pass short.");
+        let ids: Vec<_> = digests.iter().map(|d| d.id).collect();
+        let since: Vec<_> = digests.iter().map(|d| d.since).collect();
+        sqlx::query!(
+            r#"
+        UPDATE subscriptions s
+        SET digest_sent_at = released.since
+        FROM UNNEST($1::uuid[], $2::timestamptz[]) AS released(id, since)
+        WHERE s.id = released.id
+        "#,
+            &ids,
+            &since,
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to release claimed digests.")?;
+        self.shutdown.record_postponed_digests(digests.len());
+        Ok(())
+    }
+
+    /// Issues sent to a segment are included if the subscriber is part of it when the
+    /// digest goes out.
+    #[tracing::instrument(name = "Send a digest", skip(self, digest), fields(subscriber_id = %digest.id))]
+    async fn send(&self, digest: &DueDigest) -> Result<(), anyhow::Error> {
+        let issues = sqlx::query_as!(
+            DigestIssue,
+            r#"
+        SELECT title, html_content, text_content, segment_filter FROM newsletter_issues
+        WHERE published_at > $1
+        ORDER BY published_at
+        "#,
+            digest.since,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to retrieve the issues of a digest.")?;
+        let mut included = Vec::with_capacity(issues.len());
+        for issue in issues {
+            if let Some(segment) = &issue.segment_filter {
+                let segment = Filter::parse(segment).map_err(anyhow::Error::msg)?;
+                if !is_in_segment(&segment, digest.id, &self.pool).await? {
+                    continue;
+                }
+            }
+            included.push(issue);
+        }
+        let issues = included;
+        if issues.is_empty() {
+            return Ok(());
+        }
+        let email = SubscriberEmail::parse(digest.email.clone()).map_err(anyhow::Error::msg)?;
+
+        let mut html = String::new();
+        let mut text = String::new();
+        for issue in &issues {
+            write!(
+                html,
+                "<h1>{}</h1>{}<hr />",
+                htmlescape::encode_minimal(&issue.title),
+                issue.html_content
+            )
+            .unwrap();
+            write!(text, "{}\n\n{}\n\n", issue.title, issue.text_content).unwrap();
+        }
+        let (html, text) = self.links.add_footer(digest.id, &html, &text);
+        self.email_service
+            .send_email(
+                EmailKind::Digest,
+                &email,
+                "Your weekly digest",
+                &html,
+                &text,
+            )
+            .await
+            .context("Failed to send a digest.")?;
+        Ok(())
+    }
+}
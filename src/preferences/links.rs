@@ -0,0 +1,48 @@
+use crate::signed_link::{LinkPurpose, LinkSigner};
+use chrono::Utc;
+use uuid::Uuid;
+
+/// Builds the links to the preference center included in the emails sent to subscribers.
+#[derive(Clone)]
+pub struct PreferenceLinks {
+    base_url: String,
+    signer: LinkSigner,
+    ttl_in_secs: i64,
+}
+
+impl PreferenceLinks {
+    pub fn new(base_url: String, signer: LinkSigner, ttl_in_secs: i64) -> Self {
+        Self {
+            base_url,
+            signer,
+            ttl_in_secs,
+        }
+    }
+
+    pub fn url(&self, subscriber_id: Uuid) -> String {
+        let expires_at = Utc::now().timestamp() + self.ttl_in_secs;
+        let token = self
+            .signer
+            .sign(LinkPurpose::Preferences, subscriber_id, expires_at);
+        format!(
+            "{}/preferences?token={}",
+            self.base_url,
+            urlencoding::encode(&token)
+        )
+    }
+
+    /// Append the link to both versions of an email.
+    pub fn add_footer(&self, subscriber_id: Uuid, html: &str, text: &str) -> (String, String) {
+        let url = self.url(subscriber_id);
+        (
+            format!(
+                "{}<hr /><p><a href=\"{}\">Manage your preferences or unsubscribe</a></p>",
+                html, url
+            ),
+            format!(
+                "{}\n\n--\nManage your preferences or unsubscribe: {}",
+                text, url
+            ),
+        )
+    }
+}
@@ -0,0 +1,35 @@
+//! Subscriber preferences, managed from the preference center linked in every issue:
+//! how often subscribers hear from us, pauses, and changes to their details.
+mod digest;
+mod links;
+mod store;
+
+pub use digest::*;
+pub use links::*;
+pub use store::*;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Frequency {
+    EveryIssue,
+    /// The issues of the week, in one email.
+    WeeklyDigest,
+}
+
+impl Frequency {
+    pub const ALL: [Frequency; 2] = [Frequency::EveryIssue, Frequency::WeeklyDigest];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Frequency::EveryIssue => "every_issue",
+            Frequency::WeeklyDigest => "weekly_digest",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Frequency::EveryIssue => "Every issue",
+            Frequency::WeeklyDigest => "A weekly digest",
+        }
+    }
+}
@@ -0,0 +1,313 @@
+use super::Frequency;
+use crate::consent::{record_consent, ConsentAction, ConsentEvidence};
+use crate::domain::{subscriber_email::SubscriberEmail, subscriber_name::SubscriberName};
+use crate::routes::generate_subscription_token;
+use crate::subscriber_data::is_erased;
+use crate::webhooks::{enqueue_event, EventType, SubscriberData};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+pub struct SubscriberPreferences {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub frequency: String,
+    pub paused_until: Option<DateTime<Utc>>,
+}
+
+pub enum EmailChange {
+    Changed {
+        email: String,
+    },
+    /// Another subscriber took the address since the change was requested.
+    Taken {
+        email: String,
+    },
+    /// The owner of the address asked for their data to be erased since.
+    Erased {
+        email: String,
+    },
+    /// The link was followed after the change request expired.
+    Expired,
+    UnknownToken,
+}
+
+#[tracing::instrument(name = "Get the preferences of a subscriber", skip(pool))]
+pub async fn get_preferences(
+    subscriber_id: Uuid,
+    pool: &PgPool,
+) -> Result<Option<SubscriberPreferences>, anyhow::Error> {
+    sqlx::query_as!(
+        SubscriberPreferences,
+        r#"
+    SELECT id, email, name, frequency, paused_until FROM subscriptions
+    WHERE id = $1 AND status <> 'unsubscribed'
+    "#,
+        subscriber_id,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the preferences of a subscriber.")
+}
+
+/// Returns whether the subscriber exists.
+#[tracing::instrument(name = "Change the name of a subscriber", skip(name, pool))]
+pub async fn set_name(
+    subscriber_id: Uuid,
+    name: &SubscriberName,
+    pool: &PgPool,
+) -> Result<bool, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"UPDATE subscriptions SET name = $2 WHERE id = $1 AND status <> 'unsubscribed'"#,
+        subscriber_id,
+        name.as_ref(),
+    )
+    .execute(pool)
+    .await
+    .context("Failed to change the name of a subscriber.")?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Subscribers switching to the digest get their first one after a full interval.
+#[tracing::instrument(name = "Change the delivery frequency of a subscriber", skip(pool))]
+pub async fn set_frequency(
+    subscriber_id: Uuid,
+    frequency: Frequency,
+    pool: &PgPool,
+) -> Result<bool, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"
+    UPDATE subscriptions
+    SET frequency = $2,
+        digest_sent_at = CASE WHEN $2 = 'weekly_digest' THEN COALESCE(digest_sent_at, now()) END
+    WHERE id = $1 AND status <> 'unsubscribed'
+    "#,
+        subscriber_id,
+        frequency.as_str(),
+    )
+    .execute(pool)
+    .await
+    .context("Failed to change the delivery frequency of a subscriber.")?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// `None` resumes delivery.
+#[tracing::instrument(name = "Pause delivery to a subscriber", skip(pool))]
+pub async fn set_paused_until(
+    subscriber_id: Uuid,
+    paused_until: Option<DateTime<Utc>>,
+    pool: &PgPool,
+) -> Result<bool, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"
+    UPDATE subscriptions SET paused_until = $2
+    WHERE id = $1 AND status <> 'unsubscribed'
+    "#,
+        subscriber_id,
+        paused_until,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to pause delivery to a subscriber.")?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Whether another subscriber already uses the address, regardless of case.
+#[tracing::instrument(
+    name = "Check whether an email address is taken",
+    skip(executor, email)
+)]
+pub async fn is_email_taken(
+    executor: impl PgExecutor<'_>,
+    email: &str,
+    subscriber_id: Uuid,
+) -> Result<bool, anyhow::Error> {
+    let taken = sqlx::query!(
+        r#"SELECT id FROM subscriptions WHERE lower(email) = lower($1) AND id <> $2"#,
+        email,
+        subscriber_id,
+    )
+    .fetch_optional(executor)
+    .await
+    .context("Failed to check whether an email address is already subscribed.")?;
+    Ok(taken.is_some())
+}
+
+/// Returns the token of the confirmation link to mail to the new address.
+#[tracing::instrument(name = "Request an email change", skip(new_email, pool))]
+pub async fn request_email_change(
+    subscriber_id: Uuid,
+    new_email: &SubscriberEmail,
+    pool: &PgPool,
+) -> Result<String, anyhow::Error> {
+    let token = generate_subscription_token();
+    sqlx::query!(
+        r#"
+    INSERT INTO email_change_requests (token, subscriber_id, new_email, requested_at)
+    VALUES ($1, $2, $3, now())
+    "#,
+        token,
+        subscriber_id,
+        new_email.as_ref(),
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store an email change request.")?;
+    Ok(token)
+}
+
+/// Replace the address of the subscriber once the new one is confirmed, which is
+/// recorded in their consent log and sent to the webhook endpoints as a
+/// `subscriber.confirmed` event. Pending changes to other addresses are dropped.
+/// Requests older than `ttl` are dropped without being applied.
+#[tracing::instrument(name = "Apply an email change", skip(token, evidence, pool))]
+pub async fn apply_email_change(
+    token: &str,
+    ttl: chrono::Duration,
+    evidence: &ConsentEvidence,
+    pool: &PgPool,
+) -> Result<EmailChange, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let request = sqlx::query!(
+        r#"
+    DELETE FROM email_change_requests WHERE token = $1
+    RETURNING subscriber_id, new_email, requested_at
+    "#,
+        token,
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to retrieve an email change request.")?;
+    let request = match request {
+        Some(request) => request,
+        None => return Ok(EmailChange::UnknownToken),
+    };
+    if request.requested_at <= Utc::now() - ttl {
+        transaction
+            .commit()
+            .await
+            .context("Failed to drop an expired email change request.")?;
+        return Ok(EmailChange::Expired);
+    }
+    if is_email_taken(&mut transaction, &request.new_email, request.subscriber_id).await? {
+        return Ok(EmailChange::Taken {
+            email: request.new_email,
+        });
+    }
+    if is_erased(&request.new_email, pool).await? {
+        return Ok(EmailChange::Erased {
+            email: request.new_email,
+        });
+    }
+    let subscriber = sqlx::query!(
+        r#"UPDATE subscriptions SET email = $2 WHERE id = $1 RETURNING name"#,
+        request.subscriber_id,
+        request.new_email,
+    )
+    .fetch_one(&mut transaction)
+    .await
+    .context("Failed to change the email address of a subscriber.")?;
+    sqlx::query!(
+        r#"DELETE FROM email_change_requests WHERE subscriber_id = $1"#,
+        request.subscriber_id,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to drop the other email change requests of a subscriber.")?;
+    record_consent(
+        &mut transaction,
+        request.subscriber_id,
+        ConsentAction::Confirmation,
+        evidence,
+    )
+    .await?;
+    enqueue_event(
+        &mut transaction,
+        EventType::SubscriberConfirmed,
+        SubscriberData {
+            id: request.subscriber_id,
+            email: &request.new_email,
+            name: &subscriber.name,
+        },
+    )
+    .await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to change an email address.")?;
+    Ok(EmailChange::Changed {
+        email: request.new_email,
+    })
+}
+
+/// Mark the subscriber as unsubscribed, withdraw their consent in its log, and notify the
+/// webhook endpoints. The subscriber is kept along with the log: only erasure deletes them.
+/// Returns whether they were still subscribed.
+#[tracing::instrument(name = "Unsubscribe a subscriber", skip(evidence, pool))]
+pub async fn unsubscribe_subscriber(
+    subscriber_id: Uuid,
+    evidence: &ConsentEvidence,
+    pool: &PgPool,
+) -> Result<bool, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let unsubscribed = sqlx::query!(
+        r#"
+    UPDATE subscriptions SET status = 'unsubscribed'
+    WHERE id = $1 AND status <> 'unsubscribed'
+    RETURNING email, name
+    "#,
+        subscriber_id
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to unsubscribe a subscriber.")?;
+    let unsubscribed = match unsubscribed {
+        Some(unsubscribed) => unsubscribed,
+        None => return Ok(false),
+    };
+    // Links mailed before must not bring them back.
+    sqlx::query!(
+        r#"DELETE FROM subscription_tokens WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to delete the confirmation tokens of an unsubscribed subscriber.")?;
+    sqlx::query!(
+        r#"DELETE FROM email_change_requests WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to drop the email change requests of an unsubscribed subscriber.")?;
+    record_consent(
+        &mut transaction,
+        subscriber_id,
+        ConsentAction::Withdrawal,
+        evidence,
+    )
+    .await?;
+    enqueue_event(
+        &mut transaction,
+        EventType::SubscriberUnsubscribed,
+        SubscriberData {
+            id: subscriber_id,
+            email: &unsubscribed.email,
+            name: &unsubscribed.name,
+        },
+    )
+    .await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to unsubscribe a subscriber.")?;
+    Ok(true)
+}
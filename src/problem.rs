@@ -0,0 +1,294 @@
+use crate::libs::error_chain_fmt;
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    error::{InternalError, JsonPayloadError, QueryPayloadError, UrlencodedError},
+    http::{
+        header::{self, Accept, ContentType, Header, HeaderValue, LOCATION},
+        StatusCode,
+    },
+    HttpMessage, HttpRequest, HttpResponse, ResponseError,
+};
+use actix_web_flash_messages::FlashMessage;
+use actix_web_lab::middleware::Next;
+use tracing_actix_web::RequestId;
+
+pub const PROBLEM_JSON: &str = "application/problem+json";
+/// Echoes the id of the request, as recorded on its tracing span.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Errors returned to clients as RFC 7807 problem details.
+///
+/// Implementors build their response with `problem_response`, and `render_problems`
+/// picks the representation the client asked for.
+pub trait ProblemError: ResponseError {
+    /// Stable identifier of the error, for clients to match on.
+    fn code(&self) -> &'static str;
+
+    /// Browsers submitting a form are sent back there, with the detail as a flash message.
+    fn redirect_to(&self) -> Option<String> {
+        None
+    }
+}
+
+/// An `application/problem+json` document.
+///
+/// Besides the standard members, it carries the stable `code` of the error and the id of the
+/// request, to be quoted when reporting an issue.
+#[derive(serde::Serialize, utoipa::ToSchema, Clone, Debug)]
+pub struct Problem {
+    /// Always `about:blank`: `code` identifies the problem.
+    #[serde(rename = "type")]
+    pub problem_type: &'static str,
+    pub title: &'static str,
+    pub status: u16,
+    pub detail: String,
+    /// One of `invalid_name`, `invalid_email`, `undeliverable_email`, `invalid_token`,
+    /// `auth_failed`, `invalid_api_token`, `insufficient_scope`, `malformed_request`,
+    /// `validation_error`, `unauthorized`, `forbidden`, `not_found`, `conflict`,
+    /// `rate_limited` or `internal_error`.
+    pub code: &'static str,
+    /// Path of the request.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    #[serde(skip)]
+    redirect_to: Option<String>,
+}
+
+impl Problem {
+    pub fn new(e: &impl ProblemError) -> Self {
+        let status = e.status_code();
+        // Server errors are logged with their cause chain: clients only get the request id.
+        let detail = if status.is_server_error() {
+            "Something went wrong. Please try again later.".to_string()
+        } else {
+            e.to_string()
+        };
+        Self {
+            problem_type: "about:blank",
+            title: status.canonical_reason().unwrap_or("Error"),
+            status: status.as_u16(),
+            detail,
+            code: e.code(),
+            instance: None,
+            request_id: None,
+            redirect_to: e.redirect_to(),
+        }
+    }
+}
+
+/// The error response of `e`, completed by `render_problems` once the request is known.
+pub fn problem_response(e: &impl ProblemError) -> HttpResponse {
+    let problem = Problem::new(e);
+    let mut response = HttpResponse::build(e.status_code())
+        .content_type(PROBLEM_JSON)
+        .json(&problem);
+    response.extensions_mut().insert(problem);
+    response
+}
+
+/// Fill in the request details of problem responses, and turn them into
+/// redirects or HTML pages for browsers.
+///
+/// It must run within the flash messages middleware.
+pub async fn render_problems(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let context = RequestContext {
+        request_id: req.extensions().get::<RequestId>().map(|id| id.to_string()),
+        path: req.path().to_string(),
+        prefers_html: prefers_html(req.request()),
+    };
+    match next.call(req).await {
+        Ok(response) => {
+            let (request, response) = response.into_parts();
+            Ok(ServiceResponse::new(request, context.render(response)))
+        }
+        Err(e) => {
+            let response = context.render(e.error_response());
+            Err(InternalError::from_response(e, response).into())
+        }
+    }
+}
+
+struct RequestContext {
+    request_id: Option<String>,
+    path: String,
+    prefers_html: bool,
+}
+
+impl RequestContext {
+    fn render<B: MessageBody + 'static>(&self, response: HttpResponse<B>) -> HttpResponse {
+        let problem = response.extensions().get::<Problem>().cloned();
+        let mut problem = match problem {
+            Some(problem) => problem,
+            None => return response.map_into_boxed_body(),
+        };
+        problem.instance = Some(self.path.clone());
+        problem.request_id = self.request_id.clone();
+
+        let mut rendered = match (self.prefers_html, problem.redirect_to.as_deref()) {
+            (true, Some(location)) => {
+                FlashMessage::error(problem.detail.clone()).send();
+                HttpResponse::SeeOther()
+                    .insert_header((LOCATION, location))
+                    .finish()
+            }
+            (true, None) => HttpResponse::build(response.status())
+                .content_type(ContentType::html())
+                .body(problem_page(&problem)),
+            (false, _) => HttpResponse::build(response.status())
+                .content_type(PROBLEM_JSON)
+                .json(&problem),
+        };
+        // Keep headers such as `WWW-Authenticate` or `Retry-After`.
+        for (name, value) in response.headers() {
+            if name != header::CONTENT_TYPE && name != header::CONTENT_LENGTH {
+                rendered.headers_mut().append(name.clone(), value.clone());
+            }
+        }
+        if let Some(request_id) = problem
+            .request_id
+            .as_deref()
+            .and_then(|id| HeaderValue::from_str(id).ok())
+        {
+            rendered.headers_mut().insert(
+                header::HeaderName::from_static(REQUEST_ID_HEADER),
+                request_id,
+            );
+        }
+        rendered.extensions_mut().insert(problem);
+        rendered
+    }
+}
+
+/// Browsers list `text/html` before any JSON type; API clients ask for JSON or nothing in particular.
+fn prefers_html(req: &HttpRequest) -> bool {
+    Accept::parse(req)
+        .ok()
+        .and_then(|accept| {
+            accept.ranked().into_iter().find(|mime| {
+                matches!(
+                    mime.essence_str(),
+                    "text/html" | "application/json" | PROBLEM_JSON
+                )
+            })
+        })
+        .is_some_and(|mime| mime.essence_str() == "text/html")
+}
+
+fn problem_page(problem: &Problem) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>{title}</title>
+</head>
+<body>
+    <h1>{title}</h1>
+    <p>{detail}</p>
+    <p><small>Reference: {request_id}</small></p>
+    <p><a href="/">Back to the home page</a></p>
+</body>
+</html>"#,
+        title = problem.title,
+        detail = htmlescape::encode_minimal(&problem.detail),
+        request_id = problem.request_id.as_deref().unwrap_or("unknown"),
+    )
+}
+
+/// Errors without a more specific type, such as those of `e500`.
+#[derive(thiserror::Error)]
+#[error("{0}")]
+pub struct UnexpectedError(pub String);
+
+impl std::fmt::Debug for UnexpectedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for UnexpectedError {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::INTERNAL_SERVER_ERROR
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        problem_response(self)
+    }
+}
+
+impl ProblemError for UnexpectedError {
+    fn code(&self) -> &'static str {
+        "internal_error"
+    }
+}
+
+/// A body or query string the extractors could not make sense of.
+#[derive(thiserror::Error, Debug)]
+#[error("{0}")]
+pub struct MalformedRequest(String);
+
+impl ResponseError for MalformedRequest {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::BAD_REQUEST
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        problem_response(self)
+    }
+}
+
+impl ProblemError for MalformedRequest {
+    fn code(&self) -> &'static str {
+        "malformed_request"
+    }
+}
+
+pub fn form_error_handler(e: UrlencodedError, _req: &HttpRequest) -> actix_web::Error {
+    MalformedRequest(e.to_string()).into()
+}
+
+pub fn json_error_handler(e: JsonPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    MalformedRequest(e.to_string()).into()
+}
+
+pub fn query_error_handler(e: QueryPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    MalformedRequest(e.to_string()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::prefers_html;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn browsers_prefer_html() {
+        let req = TestRequest::default()
+            .insert_header((
+                "Accept",
+                "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8",
+            ))
+            .to_http_request();
+        assert!(prefers_html(&req));
+    }
+
+    #[test]
+    fn api_clients_do_not_prefer_html() {
+        for accept in [
+            "application/json",
+            "application/problem+json, text/html;q=0.5",
+            "*/*",
+        ] {
+            let req = TestRequest::default()
+                .insert_header(("Accept", accept))
+                .to_http_request();
+            assert!(!prefers_html(&req), "{}", accept);
+        }
+        assert!(!prefers_html(&TestRequest::default().to_http_request()));
+    }
+}
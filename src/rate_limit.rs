@@ -0,0 +1,374 @@
+use crate::client_ip::client_ip;
+use crate::configuration::{
+    RateLimitBackend, RateLimitSettings, RouteRateLimits, TokenBucketSettings,
+};
+use crate::problem::{problem_response, ProblemError};
+use actix_web::{
+    http::{
+        header::{HeaderValue, RETRY_AFTER},
+        StatusCode,
+    },
+    HttpRequest, HttpResponse, ResponseError,
+};
+use anyhow::Context;
+use redis::aio::ConnectionManager;
+use secrecy::{ExposeSecret, Secret};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Past this many buckets, full ones are dropped from the in-memory store:
+/// they hold no more information than a missing bucket.
+const MAX_IN_MEMORY_BUCKETS: usize = 100_000;
+/// Past this many single-use values, expired ones are dropped from the in-memory store.
+const MAX_IN_MEMORY_USED_VALUES: usize = 100_000;
+
+/// Routes with their own set of limits.
+#[derive(Copy, Clone, Debug)]
+pub enum LimitedRoute {
+    Subscribe,
+    Confirm,
+    Login,
+    DataRequest,
+}
+
+impl LimitedRoute {
+    fn as_str(&self) -> &'static str {
+        match self {
+            LimitedRoute::Subscribe => "subscribe",
+            LimitedRoute::Confirm => "confirm",
+            LimitedRoute::Login => "login",
+            LimitedRoute::DataRequest => "data_request",
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("Too many requests. Please try again later.")]
+pub struct RateLimited {
+    pub retry_after: Duration,
+}
+
+impl ResponseError for RateLimited {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::TOO_MANY_REQUESTS
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        // Round up, so that clients honouring the header never retry too early.
+        let retry_after =
+            self.retry_after.as_secs() + u64::from(self.retry_after.subsec_nanos() > 0);
+        let mut response = problem_response(self);
+        response
+            .headers_mut()
+            .insert(RETRY_AFTER, HeaderValue::from(retry_after));
+        response
+    }
+}
+
+impl ProblemError for RateLimited {
+    fn code(&self) -> &'static str {
+        "rate_limited"
+    }
+}
+
+pub struct RateLimiter {
+    settings: RateLimitSettings,
+    backend: Backend,
+}
+
+enum Backend {
+    Memory(InMemoryBuckets, InMemoryUsedValues),
+    Redis(RedisBuckets),
+}
+
+impl RateLimiter {
+    pub async fn build(
+        settings: RateLimitSettings,
+        redis_uri: Option<&Secret<String>>,
+    ) -> Result<Self, anyhow::Error> {
+        let backend = match settings.backend {
+            RateLimitBackend::Memory => {
+                Backend::Memory(InMemoryBuckets::default(), InMemoryUsedValues::default())
+            }
+            RateLimitBackend::Redis => {
+                let redis_uri = redis_uri
+                    .context("`redis_uri` must be set to use the `redis` rate limit backend.")?;
+                Backend::Redis(RedisBuckets::connect(redis_uri).await?)
+            }
+        };
+        Ok(Self { settings, backend })
+    }
+
+    /// Take a token from every bucket that applies to this request.
+    /// `target` is the email address or username the request acts upon, if any.
+    pub async fn check(
+        &self,
+        route: LimitedRoute,
+        request: &HttpRequest,
+        target: Option<&str>,
+    ) -> Result<(), RateLimited> {
+        let limits = self.route_limits(route);
+        let ip = client_ip(request).map(|ip| ip.to_string());
+        if let (Some(bucket), Some(ip)) = (limits.per_ip, &ip) {
+            self.take(&self.key(route, "ip", ip), bucket).await?;
+        }
+        if let (Some(bucket), Some(target)) = (limits.per_target, target) {
+            let mut target = target.trim().to_lowercase();
+            // Anyone can try to log in as the admin: keyed on the username alone,
+            // failed attempts from a stranger would lock the admin out.
+            if let LimitedRoute::Login = route {
+                target = format!("{}:{}", target, ip.as_deref().unwrap_or("unknown"));
+            }
+            self.take(&self.key(route, "target", &target), bucket)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Record the use of a value meant to be used once, such as a form token, for `ttl`.
+    /// Returns `false` if it was already used.
+    #[tracing::instrument(name = "Record a single-use value", skip(self, value))]
+    pub async fn use_once(&self, kind: &str, value: &str, ttl: Duration) -> bool {
+        let key = format!("{}:used:{}:{}", self.settings.key_prefix, kind, value);
+        let outcome = match &self.backend {
+            Backend::Memory(_, used) => Ok(used.use_once(&key, ttl, Instant::now())),
+            Backend::Redis(buckets) => buckets.use_once(&key, ttl).await,
+        };
+        // Fail open, as `take` does.
+        outcome.unwrap_or_else(|e| {
+            tracing::error!(error.cause_chain = ?e, "Failed to record a single-use value.");
+            true
+        })
+    }
+
+    fn route_limits(&self, route: LimitedRoute) -> &RouteRateLimits {
+        match route {
+            LimitedRoute::Subscribe => &self.settings.subscribe,
+            LimitedRoute::Confirm => &self.settings.confirm,
+            LimitedRoute::Login => &self.settings.login,
+            LimitedRoute::DataRequest => &self.settings.data_request,
+        }
+    }
+
+    fn key(&self, route: LimitedRoute, kind: &str, value: &str) -> String {
+        format!(
+            "{}:{}:{}:{}",
+            self.settings.key_prefix,
+            route.as_str(),
+            kind,
+            value
+        )
+    }
+
+    #[tracing::instrument(name = "Take a rate limit token", skip(self, bucket))]
+    async fn take(&self, key: &str, bucket: TokenBucketSettings) -> Result<(), RateLimited> {
+        let outcome = match &self.backend {
+            Backend::Memory(buckets, _) => Ok(buckets.take(key, bucket, Instant::now())),
+            Backend::Redis(buckets) => buckets.take(key, bucket).await,
+        };
+        match outcome {
+            Ok(None) => Ok(()),
+            Ok(Some(retry_after)) => {
+                tracing::warn!(retry_after = ?retry_after, "Rate limit exceeded.");
+                Err(RateLimited { retry_after })
+            }
+            // Fail open: an unreachable limiter should not take the routes it protects down with it.
+            Err(e) => {
+                tracing::error!(error.cause_chain = ?e, "Failed to check the rate limit.");
+                Ok(())
+            }
+        }
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+#[derive(Default)]
+struct InMemoryBuckets {
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl InMemoryBuckets {
+    /// Returns how long to wait before retrying if the bucket is empty.
+    fn take(&self, key: &str, settings: TokenBucketSettings, now: Instant) -> Option<Duration> {
+        let capacity = f64::from(settings.capacity);
+        let interval = settings.refill_interval().as_secs_f64();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_IN_MEMORY_BUCKETS {
+            buckets.retain(|_, b| {
+                b.tokens + now.duration_since(b.updated_at).as_secs_f64() / interval < capacity
+            });
+        }
+
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: capacity,
+            updated_at: now,
+        });
+        let refilled = now.duration_since(bucket.updated_at).as_secs_f64() / interval;
+        bucket.tokens = capacity.min(bucket.tokens + refilled);
+        bucket.updated_at = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            None
+        } else {
+            Some(Duration::from_secs_f64((1.0 - bucket.tokens) * interval))
+        }
+    }
+}
+
+/// When each single-use value may be used again.
+#[derive(Default)]
+struct InMemoryUsedValues {
+    expires_at: Mutex<HashMap<String, Instant>>,
+}
+
+impl InMemoryUsedValues {
+    fn use_once(&self, key: &str, ttl: Duration, now: Instant) -> bool {
+        let mut expires_at = self.expires_at.lock().unwrap();
+        if expires_at.len() >= MAX_IN_MEMORY_USED_VALUES {
+            expires_at.retain(|_, expiry| *expiry > now);
+        }
+        if expires_at.get(key).is_some_and(|expiry| *expiry > now) {
+            return false;
+        }
+        // Past what an `Instant` can hold, the value is as good as used for ever.
+        let expiry = now
+            .checked_add(ttl)
+            .unwrap_or(now + Duration::from_secs(u32::MAX.into()));
+        expires_at.insert(key.to_string(), expiry);
+        true
+    }
+}
+
+/// Same algorithm as `InMemoryBuckets`, run as a script so that concurrent instances
+/// cannot both take the last token. Returns the number of milliseconds to wait, 0 if allowed.
+const TAKE_TOKEN_SCRIPT: &str = r#"
+local capacity = tonumber(ARGV[1])
+local interval = tonumber(ARGV[2])
+local now = tonumber(ARGV[3])
+local bucket = redis.call('HMGET', KEYS[1], 'tokens', 'updated_at')
+local tokens = tonumber(bucket[1]) or capacity
+local updated_at = tonumber(bucket[2]) or now
+tokens = math.min(capacity, tokens + math.max(0, now - updated_at) / interval)
+local retry_after = 0
+if tokens >= 1 then
+  tokens = tokens - 1
+else
+  retry_after = math.ceil((1 - tokens) * interval)
+end
+redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'updated_at', tostring(now))
+redis.call('PEXPIRE', KEYS[1], math.ceil(capacity * interval))
+return retry_after
+"#;
+
+struct RedisBuckets {
+    connection: ConnectionManager,
+    script: redis::Script,
+}
+
+impl RedisBuckets {
+    async fn connect(redis_uri: &Secret<String>) -> Result<Self, anyhow::Error> {
+        let client = redis::Client::open(redis_uri.expose_secret().as_str())
+            .context("Invalid `redis_uri`.")?;
+        let connection = client
+            .get_tokio_connection_manager()
+            .await
+            .context("Failed to connect to Redis.")?;
+        Ok(Self {
+            connection,
+            script: redis::Script::new(TAKE_TOKEN_SCRIPT),
+        })
+    }
+
+    async fn take(
+        &self,
+        key: &str,
+        settings: TokenBucketSettings,
+    ) -> Result<Option<Duration>, anyhow::Error> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .context("The system clock is set before the UNIX epoch.")?;
+        let retry_after_ms: u64 = self
+            .script
+            .key(key)
+            .arg(settings.capacity)
+            .arg(settings.refill_interval().as_millis() as u64)
+            .arg(now.as_millis() as u64)
+            .invoke_async(&mut self.connection.clone())
+            .await
+            .context("Failed to run the rate limit script.")?;
+        Ok((retry_after_ms > 0).then(|| Duration::from_millis(retry_after_ms)))
+    }
+
+    async fn use_once(&self, key: &str, ttl: Duration) -> Result<bool, anyhow::Error> {
+        let stored: Option<String> = redis::cmd("SET")
+            .arg(key)
+            .arg(1)
+            .arg("NX")
+            .arg("PX")
+            .arg((ttl.as_millis() as u64).max(1))
+            .query_async(&mut self.connection.clone())
+            .await
+            .context("Failed to record a single-use value.")?;
+        Ok(stored.is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{InMemoryBuckets, InMemoryUsedValues};
+    use crate::configuration::TokenBucketSettings;
+    use claims::{assert_none, assert_some_eq};
+    use std::time::{Duration, Instant};
+
+    const BUCKET: TokenBucketSettings = TokenBucketSettings {
+        capacity: 2,
+        refill_interval_in_secs: 10,
+    };
+
+    #[test]
+    fn a_full_bucket_allows_a_burst_up_to_its_capacity() {
+        let buckets = InMemoryBuckets::default();
+        let now = Instant::now();
+        assert_none!(buckets.take("key", BUCKET, now));
+        assert_none!(buckets.take("key", BUCKET, now));
+        assert_some_eq!(buckets.take("key", BUCKET, now), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn an_empty_bucket_refills_over_time() {
+        let buckets = InMemoryBuckets::default();
+        let now = Instant::now();
+        buckets.take("key", BUCKET, now);
+        buckets.take("key", BUCKET, now);
+        assert_some_eq!(
+            buckets.take("key", BUCKET, now + Duration::from_secs(4)),
+            Duration::from_secs(6)
+        );
+        assert_none!(buckets.take("key", BUCKET, now + Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn buckets_are_independent() {
+        let buckets = InMemoryBuckets::default();
+        let now = Instant::now();
+        buckets.take("a", BUCKET, now);
+        buckets.take("a", BUCKET, now);
+        assert_none!(buckets.take("b", BUCKET, now));
+    }
+
+    #[test]
+    fn single_use_values_can_be_used_again_once_expired() {
+        let used = InMemoryUsedValues::default();
+        let now = Instant::now();
+        let ttl = Duration::from_secs(10);
+        assert!(used.use_once("a", ttl, now));
+        assert!(!used.use_once("a", ttl, now + Duration::from_secs(9)));
+        assert!(used.use_once("b", ttl, now));
+        assert!(used.use_once("a", ttl, now + Duration::from_secs(10)));
+    }
+}
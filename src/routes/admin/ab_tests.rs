@@ -0,0 +1,124 @@
+use crate::libs::e500;
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+/// Issues listed on the admin page, newest first.
+const AB_TEST_LIST_LENGTH: i64 = 20;
+
+struct VariantRow {
+    issue_id: Uuid,
+    title: String,
+    ab_test_ends_at: Option<DateTime<Utc>>,
+    winning_variant: Option<i32>,
+    position: i32,
+    subject: String,
+    recipients: i64,
+    opens: i64,
+}
+
+/// The subject lines tested on the latest issues, with their open rates: while a test
+/// runs, so far; once it ended, when the winner was picked.
+pub async fn ab_tests(pool: web::Data<PgPool>) -> Result<HttpResponse, actix_web::Error> {
+    let variants = get_latest_variants(&pool).await.map_err(e500)?;
+    let mut tests_html = String::new();
+    let mut current_issue = None;
+    for variant in variants {
+        if current_issue != Some(variant.issue_id) {
+            if current_issue.is_some() {
+                tests_html.push_str("</table>\n");
+            }
+            current_issue = Some(variant.issue_id);
+            let status = match (variant.winning_variant, variant.ab_test_ends_at) {
+                (Some(_), _) => "ended".to_string(),
+                (None, Some(ends_at)) => format!("running until {}", ends_at.to_rfc3339()),
+                (None, None) => "running".to_string(),
+            };
+            writeln!(
+                tests_html,
+                "<h2>{}</h2>\n<p>Test {}.</p>\n<table>\n\
+                <tr><th>Subject</th><th>Sent</th><th>Opens</th><th>Open rate</th><th></th></tr>",
+                htmlescape::encode_minimal(&variant.title),
+                status,
+            )
+            .unwrap();
+        }
+        let open_rate = if variant.recipients == 0 {
+            "-".to_string()
+        } else {
+            format!(
+                "{:.1}%",
+                100.0 * variant.opens as f64 / variant.recipients as f64
+            )
+        };
+        writeln!(
+            tests_html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            htmlescape::encode_minimal(&variant.subject),
+            variant.recipients,
+            variant.opens,
+            open_rate,
+            if variant.winning_variant == Some(variant.position) {
+                "Winner"
+            } else {
+                ""
+            },
+        )
+        .unwrap();
+    }
+    if current_issue.is_some() {
+        tests_html.push_str("</table>\n");
+    } else {
+        tests_html.push_str("<p>No subject lines were tested yet.</p>\n");
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta http-equiv="content-type" content="text/html; charset=utf-8">
+<title>A/B tests</title>
+</head>
+<body>
+<p>Subject lines tested on the latest {AB_TEST_LIST_LENGTH} issues:</p>
+{tests_html}
+<p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
+
+/// Stored results win over the live counts, which keep growing after the test ended.
+#[tracing::instrument(name = "Get the latest A/B tests", skip(pool))]
+async fn get_latest_variants(pool: &PgPool) -> Result<Vec<VariantRow>, anyhow::Error> {
+    sqlx::query_as!(
+        VariantRow,
+        r#"
+    SELECT i.id AS issue_id, i.title, i.ab_test_ends_at, i.winning_variant, v.position, v.subject,
+        COALESCE(v.test_recipients, count(d.id)) AS "recipients!",
+        COALESCE(v.test_opens, count(d.opened_at)) AS "opens!"
+    FROM newsletter_issues i
+    JOIN subject_variants v ON v.issue_id = i.id
+    LEFT JOIN issue_deliveries d
+        ON d.issue_id = i.id AND d.variant = v.position AND d.failed_at IS NULL
+    WHERE i.id IN (
+        SELECT id FROM newsletter_issues
+        WHERE ab_test_ends_at IS NOT NULL
+        ORDER BY published_at DESC
+        LIMIT $1
+    )
+    GROUP BY i.id, v.issue_id, v.position
+    ORDER BY i.published_at DESC, i.id, v.position
+    "#,
+        AB_TEST_LIST_LENGTH,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the latest A/B tests.")
+}
@@ -6,7 +6,7 @@ use anyhow::Context;
 use sqlx::PgPool;
 use uuid::Uuid;
 
-use crate::{libs::e500, session_state::TypedSession};
+use crate::{authentication::csrf_field, libs::e500, session_state::TypedSession};
 
 pub async fn admin_dashboard(
     session: TypedSession,
@@ -19,6 +19,7 @@ pub async fn admin_dashboard(
             .insert_header((LOCATION, "/login"))
             .finish());
     };
+    let csrf_field = csrf_field(&session)?;
     Ok(HttpResponse::Ok()
         .content_type(ContentType::html())
         .body(format!(
@@ -33,8 +34,14 @@ pub async fn admin_dashboard(
 <p>Available actions:</p>
 <ol>
 <li><a href="/admin/password">Change password</a></li>
+<li><a href="/admin/subscribers">Subscribers</a></li>
+<li><a href="/admin/ab-tests">A/B tests</a></li>
+<li><a href="/admin/tokens">API tokens</a></li>
+<li><a href="/admin/sessions">Active sessions</a></li>
+<li><a href="/admin/webhooks">Webhooks</a></li>
 <li>
 <form name="logoutForm" action="/admin/logout" method="post">
+{csrf_field}
 <input type="submit" value="Logout">
 </form>
 </li>
@@ -1,16 +1,23 @@
 use crate::{
+    authentication::{revoke_user_session, UserId},
     libs::{e500, see_other},
     session_state::TypedSession,
 };
-use actix_web::HttpResponse;
+use actix_web::{web, HttpResponse};
 use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
 
-pub async fn log_out(session: TypedSession) -> Result<HttpResponse, actix_web::Error> {
-    if session.get_user_id().map_err(e500)?.is_none() {
-        Ok(see_other("/login"))
-    } else {
-        session.log_out();
-        FlashMessage::info("You have successfully logged out.").send();
-        Ok(see_other("/login"))
+pub async fn log_out(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if let Some(session_id) = session.get_session_id().map_err(e500)? {
+        revoke_user_session(**user_id, session_id, &pool)
+            .await
+            .map_err(e500)?;
     }
+    session.log_out();
+    FlashMessage::info("You have successfully logged out.").send();
+    Ok(see_other("/login"))
 }
@@ -1,7 +1,17 @@
+mod ab_tests;
 mod dashboard;
 mod logout;
 mod password;
+mod sessions;
+mod subscribers;
+mod tokens;
+mod webhooks;
 
+pub use ab_tests::*;
 pub use dashboard::*;
 pub use logout::*;
 pub use password::*;
+pub use sessions::*;
+pub use subscribers::*;
+pub use tokens::*;
+pub use webhooks::*;
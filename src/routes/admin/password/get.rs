@@ -1,3 +1,4 @@
+use crate::authentication::csrf_field;
 use crate::libs::{e500, see_other};
 use crate::session_state::TypedSession;
 use actix_web::http::header::ContentType;
@@ -17,6 +18,7 @@ pub async fn change_password_form(
     for m in flash_messages.iter() {
         writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
     }
+    let csrf_field = csrf_field(&session)?;
     Ok(HttpResponse::Ok()
         .content_type(ContentType::html())
         .body(format!(
@@ -29,6 +31,7 @@ pub async fn change_password_form(
 <body>
 {msg_html}
 <form action="/admin/password" method="post">
+{csrf_field}
 <label>Current password<input
 type="password"
 placeholder="Enter current password"
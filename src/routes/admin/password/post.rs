@@ -4,9 +4,15 @@ use secrecy::{ExposeSecret, Secret};
 use sqlx::PgPool;
 
 use crate::{
-    authentication::{validate_credentials, AuthError, Credentials, UserId},
+    authentication::{
+        revoke_user_sessions, validate_credentials, AuthError, Credentials, DummyPasswordHash,
+        UserId,
+    },
+    configuration::Argon2Settings,
+    domain::new_password::{NewPassword, PasswordPolicy},
     libs::{e500, see_other},
     routes::get_username,
+    session_state::TypedSession,
 };
 
 #[derive(serde::Deserialize)]
@@ -20,6 +26,10 @@ pub async fn change_password(
     form: web::Form<FormData>,
     pool: web::Data<PgPool>,
     user_id: web::ReqData<UserId>,
+    password_policy: web::Data<PasswordPolicy>,
+    argon2_settings: web::Data<Argon2Settings>,
+    dummy_password_hash: web::Data<DummyPasswordHash>,
+    session: TypedSession,
 ) -> Result<HttpResponse, actix_web::Error> {
     let user_id = user_id.into_inner();
     // `Secret<String>` does not implement `Eq`,
@@ -34,23 +44,42 @@ pub async fn change_password(
 
     let username = get_username(*user_id, &pool).await.map_err(e500)?;
 
+    // Checked first, so that the policy is only revealed to the holder of the password.
     let credentials = Credentials {
-        username,
+        username: username.clone(),
         password: form.0.current_password,
     };
-    if let Err(e) = validate_credentials(credentials, &pool).await {
+    if let Err(e) =
+        validate_credentials(credentials, &argon2_settings, &dummy_password_hash, &pool).await
+    {
         return match e {
             AuthError::InvalidCredentials(_) => {
                 FlashMessage::error("The current password is incorrect.").send();
                 Ok(see_other("/admin/password"))
             }
-            AuthError::UnexpectedError(_) => Err(e500(e).into()),
+            AuthError::UnexpectedError(_) => Err(e500(e)),
         };
     }
 
-    crate::authentication::change_password(*user_id, form.0.new_password, &pool)
+    let new_password = match NewPassword::parse(form.0.new_password, &username, &password_policy) {
+        Ok(new_password) => new_password,
+        Err(violations) => {
+            for violation in violations {
+                FlashMessage::error(violation).send();
+            }
+            return Ok(see_other("/admin/password"));
+        }
+    };
+
+    crate::authentication::change_password(*user_id, new_password, &argon2_settings, &pool)
+        .await
+        .map_err(e500)?;
+    // Whoever may be holding one of the other sessions should not outlive the old password.
+    let current_session_id = session.get_session_id().map_err(e500)?;
+    revoke_user_sessions(*user_id, current_session_id, &pool)
         .await
         .map_err(e500)?;
+    session.renew();
     FlashMessage::error("Your password has been changed.").send();
     Ok(see_other("/admin/password"))
 }
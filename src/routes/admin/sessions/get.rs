@@ -0,0 +1,72 @@
+use crate::authentication::{csrf_field, get_active_user_sessions, SessionTtl, UserId};
+use crate::libs::e500;
+use crate::session_state::TypedSession;
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+pub async fn user_sessions(
+    flash_messages: IncomingFlashMessages,
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    session_ttl: web::Data<SessionTtl>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+
+    let current_session_id = session.get_session_id().map_err(e500)?;
+    let csrf_field = csrf_field(&session)?;
+    let sessions = get_active_user_sessions(**user_id, **session_ttl, &pool)
+        .await
+        .map_err(e500)?;
+    let mut rows_html = String::new();
+    for s in sessions {
+        let action = if Some(s.id) == current_session_id {
+            "This device".to_string()
+        } else {
+            format!(
+                r#"<form action="/admin/sessions/{}/revoke" method="post">{}<input type="submit" value="Revoke"></form>"#,
+                s.id, csrf_field
+            )
+        };
+        writeln!(
+            rows_html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            s.created_at.to_rfc3339(),
+            s.last_seen_at.to_rfc3339(),
+            htmlescape::encode_minimal(s.ip_address.as_deref().unwrap_or("Unknown")),
+            htmlescape::encode_minimal(s.user_agent.as_deref().unwrap_or("Unknown")),
+            action,
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta http-equiv="content-type" content="text/html; charset=utf-8">
+<title>Active sessions</title>
+</head>
+<body>
+{msg_html}
+<table>
+<tr><th>Signed in</th><th>Last seen</th><th>IP address</th><th>Device</th><th></th></tr>
+{rows_html}
+</table>
+<form action="/admin/sessions/revoke-all" method="post">
+{csrf_field}
+<input type="submit" value="Log out everywhere">
+</form>
+<p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
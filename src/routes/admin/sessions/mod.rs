@@ -0,0 +1,5 @@
+mod get;
+mod post;
+
+pub use get::user_sessions;
+pub use post::{log_out_everywhere, revoke_session};
@@ -0,0 +1,36 @@
+use crate::authentication::{revoke_user_session, revoke_user_sessions, UserId};
+use crate::libs::{e500, see_other};
+use crate::session_state::TypedSession;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+pub async fn revoke_session(
+    path: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let revoked = revoke_user_session(**user_id, path.into_inner(), &pool)
+        .await
+        .map_err(e500)?;
+    if revoked {
+        FlashMessage::info("The session has been revoked.").send();
+    } else {
+        FlashMessage::error("The session does not exist or was already revoked.").send();
+    }
+    Ok(see_other("/admin/sessions"))
+}
+
+pub async fn log_out_everywhere(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    revoke_user_sessions(**user_id, None, &pool)
+        .await
+        .map_err(e500)?;
+    session.log_out();
+    FlashMessage::info("You have been logged out of all your sessions.").send();
+    Ok(see_other("/login"))
+}
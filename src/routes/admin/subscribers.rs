@@ -0,0 +1,140 @@
+use crate::consent::get_consent_log;
+use crate::libs::e500;
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+/// Subscribers listed on the admin page, newest first.
+const SUBSCRIBER_LIST_LENGTH: i64 = 100;
+
+struct SubscriberRow {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+}
+
+pub async fn subscribers(pool: web::Data<PgPool>) -> Result<HttpResponse, actix_web::Error> {
+    let subscribers = get_latest_subscribers(&pool).await.map_err(e500)?;
+    let mut rows_html = String::new();
+    for subscriber in subscribers {
+        writeln!(
+            rows_html,
+            r#"<tr><td><a href="/admin/subscribers/{}">{}</a></td><td>{}</td><td>{}</td><td>{}</td></tr>"#,
+            subscriber.id,
+            htmlescape::encode_minimal(&subscriber.email),
+            htmlescape::encode_minimal(&subscriber.name),
+            subscriber.status,
+            subscriber.subscribed_at.to_rfc3339(),
+        )
+        .unwrap();
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta http-equiv="content-type" content="text/html; charset=utf-8">
+<title>Subscribers</title>
+</head>
+<body>
+<p>Latest {SUBSCRIBER_LIST_LENGTH} subscribers:</p>
+<table>
+<tr><th>Email</th><th>Name</th><th>Status</th><th>Subscribed</th></tr>
+{rows_html}
+</table>
+<p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
+
+/// The consent log of a subscriber.
+pub async fn subscriber_consents(
+    path: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = path.into_inner();
+    let subscriber = match get_subscriber(subscriber_id, &pool).await.map_err(e500)? {
+        Some(subscriber) => subscriber,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let consent_log = get_consent_log(subscriber_id, &pool).await.map_err(e500)?;
+
+    let mut rows_html = String::new();
+    for record in consent_log {
+        writeln!(
+            rows_html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            record.recorded_at.to_rfc3339(),
+            record.action,
+            record.source,
+            htmlescape::encode_minimal(record.ip.as_deref().unwrap_or("-")),
+            htmlescape::encode_minimal(record.user_agent.as_deref().unwrap_or("-")),
+            htmlescape::encode_minimal(record.consent_text_version.as_deref().unwrap_or("-")),
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta http-equiv="content-type" content="text/html; charset=utf-8">
+<title>Subscriber</title>
+</head>
+<body>
+<p>{email} ({name}), {status} since {subscribed_at}.</p>
+<p>Consent log:</p>
+<table>
+<tr><th>Recorded</th><th>Action</th><th>Source</th><th>IP</th><th>User agent</th><th>Consent text</th></tr>
+{rows_html}
+</table>
+<p><a href="/admin/subscribers">&lt;- Back</a></p>
+</body>
+</html>"#,
+            email = htmlescape::encode_minimal(&subscriber.email),
+            name = htmlescape::encode_minimal(&subscriber.name),
+            status = subscriber.status,
+            subscribed_at = subscriber.subscribed_at.to_rfc3339(),
+        )))
+}
+
+#[tracing::instrument(name = "Get the latest subscribers", skip(pool))]
+async fn get_latest_subscribers(pool: &PgPool) -> Result<Vec<SubscriberRow>, anyhow::Error> {
+    sqlx::query_as!(
+        SubscriberRow,
+        r#"
+    SELECT id, email, name, status, subscribed_at FROM subscriptions
+    ORDER BY subscribed_at DESC, id
+    LIMIT $1
+    "#,
+        SUBSCRIBER_LIST_LENGTH,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the latest subscribers.")
+}
+
+#[tracing::instrument(name = "Get subscriber", skip(pool))]
+async fn get_subscriber(
+    subscriber_id: Uuid,
+    pool: &PgPool,
+) -> Result<Option<SubscriberRow>, anyhow::Error> {
+    sqlx::query_as!(
+        SubscriberRow,
+        r#"SELECT id, email, name, status, subscribed_at FROM subscriptions WHERE id = $1"#,
+        subscriber_id,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve a subscriber.")
+}
@@ -0,0 +1,102 @@
+use crate::authentication::{csrf_field, get_api_tokens, ApiScope, ApiToken, UserId};
+use crate::libs::e500;
+use crate::session_state::TypedSession;
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+pub async fn api_tokens(
+    flash_messages: IncomingFlashMessages,
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    let tokens = get_api_tokens(**user_id, &pool).await.map_err(e500)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(render_api_tokens_page(
+            &msg_html,
+            &tokens,
+            &csrf_field(&session)?,
+        )))
+}
+
+/// Shared with the creation handler, which renders the page directly
+/// so that the new secret never travels through a cookie.
+pub(super) fn render_api_tokens_page(
+    msg_html: &str,
+    tokens: &[ApiToken],
+    csrf_field: &str,
+) -> String {
+    let mut rows_html = String::new();
+    for token in tokens {
+        let status = match token.revoked_at {
+            Some(revoked_at) => format!("Revoked {}", revoked_at.to_rfc3339()),
+            None => format!(
+                r#"<form action="/admin/tokens/{}/revoke" method="post">{}<input type="submit" value="Revoke"></form>"#,
+                token.id, csrf_field
+            ),
+        };
+        writeln!(
+            rows_html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            htmlescape::encode_minimal(&token.name),
+            token.scopes.join(", "),
+            token.created_at.to_rfc3339(),
+            token
+                .last_used_at
+                .map(|t| t.to_rfc3339())
+                .unwrap_or_else(|| "Never".into()),
+            status,
+        )
+        .unwrap();
+    }
+
+    let mut scopes_html = String::new();
+    for scope in ApiScope::ALL {
+        writeln!(
+            scopes_html,
+            r#"<label><input type="checkbox" name="scope" value="{0}">{0}</label>"#,
+            scope.as_str()
+        )
+        .unwrap();
+    }
+
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta http-equiv="content-type" content="text/html; charset=utf-8">
+<title>API tokens</title>
+</head>
+<body>
+{msg_html}
+<table>
+<tr><th>Name</th><th>Scopes</th><th>Created</th><th>Last used</th><th></th></tr>
+{rows_html}
+</table>
+<form action="/admin/tokens" method="post">
+{csrf_field}
+<label>Name
+<input
+type="text"
+placeholder="What is this token for?"
+name="name"
+>
+</label>
+<br>
+{scopes_html}
+<br>
+<button type="submit">Create token</button>
+</form>
+<p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+    )
+}
@@ -0,0 +1,76 @@
+use super::get::render_api_tokens_page;
+use crate::authentication::{self, csrf_field, get_api_tokens, ApiScope, UserId};
+use crate::libs::{e500, see_other};
+use crate::session_state::TypedSession;
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use secrecy::ExposeSecret;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Checkboxes submit one `scope` pair per selected scope,
+/// so the form is read as a list of key-value pairs.
+pub async fn create_api_token(
+    form: web::Form<Vec<(String, String)>>,
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut name = String::new();
+    let mut scopes = Vec::new();
+    for (key, value) in form.into_inner() {
+        match key.as_str() {
+            "name" => name = value.trim().to_string(),
+            "scope" => match ApiScope::try_from(value.as_str()) {
+                Ok(scope) => scopes.push(scope),
+                Err(e) => {
+                    FlashMessage::error(e).send();
+                    return Ok(see_other("/admin/tokens"));
+                }
+            },
+            _ => {}
+        }
+    }
+    if name.is_empty() {
+        FlashMessage::error("The token needs a name.").send();
+        return Ok(see_other("/admin/tokens"));
+    }
+    if scopes.is_empty() {
+        FlashMessage::error("The token needs at least one scope.").send();
+        return Ok(see_other("/admin/tokens"));
+    }
+
+    let token = authentication::create_api_token(**user_id, &name, &scopes, &pool)
+        .await
+        .map_err(e500)?;
+    let tokens = get_api_tokens(**user_id, &pool).await.map_err(e500)?;
+    let msg_html = format!(
+        "<p><i>Your new API token is <code>{}</code>. \
+        Copy it now, it will not be shown again.</i></p>",
+        token.expose_secret()
+    );
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(render_api_tokens_page(
+            &msg_html,
+            &tokens,
+            &csrf_field(&session)?,
+        )))
+}
+
+pub async fn revoke_api_token(
+    path: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let revoked = authentication::revoke_api_token(**user_id, path.into_inner(), &pool)
+        .await
+        .map_err(e500)?;
+    if revoked {
+        FlashMessage::info("The API token has been revoked.").send();
+    } else {
+        FlashMessage::error("The API token does not exist or was already revoked.").send();
+    }
+    Ok(see_other("/admin/tokens"))
+}
@@ -0,0 +1,158 @@
+use crate::authentication::csrf_field;
+use crate::libs::e500;
+use crate::session_state::TypedSession;
+use crate::webhooks::{
+    get_delivery_log, get_webhook_endpoint, get_webhook_endpoints, EventType, WebhookEndpoint,
+};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+/// Attempts shown on the delivery log of an endpoint.
+const DELIVERY_LOG_LENGTH: i64 = 100;
+
+pub async fn webhooks(
+    flash_messages: IncomingFlashMessages,
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    let endpoints = get_webhook_endpoints(&pool).await.map_err(e500)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(render_webhooks_page(
+            &msg_html,
+            &endpoints,
+            &csrf_field(&session)?,
+        )))
+}
+
+/// Shared with the creation handler, which renders the page directly
+/// so that the signing secret never travels through a cookie.
+pub(super) fn render_webhooks_page(
+    msg_html: &str,
+    endpoints: &[WebhookEndpoint],
+    csrf_field: &str,
+) -> String {
+    let mut rows_html = String::new();
+    for endpoint in endpoints {
+        writeln!(
+            rows_html,
+            r#"<tr><td><a href="/admin/webhooks/{id}">{url}</a></td><td>{events}</td><td>{created_at}</td><td>{pending}</td><td>{failed}</td><td><form action="/admin/webhooks/{id}/delete" method="post">{csrf_field}<input type="submit" value="Delete"></form></td></tr>"#,
+            id = endpoint.id,
+            url = htmlescape::encode_minimal(&endpoint.url),
+            events = endpoint.event_types.join(", "),
+            created_at = endpoint.created_at.to_rfc3339(),
+            pending = endpoint.pending_deliveries,
+            failed = endpoint.failed_deliveries,
+        )
+        .unwrap();
+    }
+
+    let mut events_html = String::new();
+    for event_type in EventType::ALL {
+        writeln!(
+            events_html,
+            r#"<label><input type="checkbox" name="event" value="{0}">{0}</label>"#,
+            event_type.as_str()
+        )
+        .unwrap();
+    }
+
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta http-equiv="content-type" content="text/html; charset=utf-8">
+<title>Webhooks</title>
+</head>
+<body>
+{msg_html}
+<table>
+<tr><th>URL</th><th>Events</th><th>Created</th><th>Pending</th><th>Failed</th><th></th></tr>
+{rows_html}
+</table>
+<form action="/admin/webhooks" method="post">
+{csrf_field}
+<label>URL
+<input
+type="url"
+placeholder="https://crm.example.com/hooks/newsletter"
+name="url"
+>
+</label>
+<br>
+{events_html}
+<br>
+<button type="submit">Add endpoint</button>
+</form>
+<p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+    )
+}
+
+pub async fn webhook_deliveries(
+    path: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let endpoint_id = path.into_inner();
+    let endpoint = match get_webhook_endpoint(endpoint_id, &pool)
+        .await
+        .map_err(e500)?
+    {
+        Some(endpoint) => endpoint,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let attempts = get_delivery_log(endpoint_id, DELIVERY_LOG_LENGTH, &pool)
+        .await
+        .map_err(e500)?;
+
+    let mut rows_html = String::new();
+    for attempt in attempts {
+        writeln!(
+            rows_html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{} ms</td><td>{}</td></tr>",
+            attempt.attempted_at.to_rfc3339(),
+            attempt.event_type,
+            attempt.event_id,
+            attempt
+                .response_status
+                .map(|s| s.to_string())
+                .unwrap_or_else(|| "-".into()),
+            htmlescape::encode_minimal(attempt.error.as_deref().unwrap_or("")),
+            attempt.duration_ms,
+            attempt.delivery_status,
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta http-equiv="content-type" content="text/html; charset=utf-8">
+<title>Webhook deliveries</title>
+</head>
+<body>
+<p>Deliveries to {url}: {pending} pending, {failed} failed.</p>
+<table>
+<tr><th>Attempted</th><th>Event</th><th>Event id</th><th>Status code</th><th>Error</th><th>Duration</th><th>Delivery</th></tr>
+{rows_html}
+</table>
+<p><a href="/admin/webhooks">&lt;- Back</a></p>
+</body>
+</html>"#,
+            url = htmlescape::encode_minimal(&endpoint.url),
+            pending = endpoint.pending_deliveries,
+            failed = endpoint.failed_deliveries,
+        )))
+}
@@ -0,0 +1,5 @@
+mod get;
+mod post;
+
+pub use get::{webhook_deliveries, webhooks};
+pub use post::{create_webhook, delete_webhook};
@@ -0,0 +1,81 @@
+use super::get::render_webhooks_page;
+use crate::authentication::csrf_field;
+use crate::libs::{e500, see_other};
+use crate::session_state::TypedSession;
+use crate::webhooks::{
+    create_webhook_endpoint, delete_webhook_endpoint, get_webhook_endpoints, parse_endpoint_url,
+    EventType,
+};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use secrecy::ExposeSecret;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Checkboxes submit one `event` pair per selected event type,
+/// so the form is read as a list of key-value pairs.
+pub async fn create_webhook(
+    form: web::Form<Vec<(String, String)>>,
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut url = String::new();
+    let mut event_types = Vec::new();
+    for (key, value) in form.into_inner() {
+        match key.as_str() {
+            "url" => url = value,
+            "event" => match EventType::try_from(value.as_str()) {
+                Ok(event_type) => event_types.push(event_type),
+                Err(e) => {
+                    FlashMessage::error(e).send();
+                    return Ok(see_other("/admin/webhooks"));
+                }
+            },
+            _ => {}
+        }
+    }
+    let url = match parse_endpoint_url(&url) {
+        Ok(url) => url,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other("/admin/webhooks"));
+        }
+    };
+    if event_types.is_empty() {
+        FlashMessage::error("The endpoint needs at least one event type.").send();
+        return Ok(see_other("/admin/webhooks"));
+    }
+
+    let (_, secret) = create_webhook_endpoint(&url, &event_types, &pool)
+        .await
+        .map_err(e500)?;
+    let endpoints = get_webhook_endpoints(&pool).await.map_err(e500)?;
+    let msg_html = format!(
+        "<p><i>Payloads sent to this endpoint are signed with <code>{}</code>. \
+        Copy it now, it will not be shown again.</i></p>",
+        secret.expose_secret()
+    );
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(render_webhooks_page(
+            &msg_html,
+            &endpoints,
+            &csrf_field(&session)?,
+        )))
+}
+
+pub async fn delete_webhook(
+    path: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let deleted = delete_webhook_endpoint(path.into_inner(), &pool)
+        .await
+        .map_err(e500)?;
+    if deleted {
+        FlashMessage::info("The webhook endpoint has been deleted.").send();
+    } else {
+        FlashMessage::error("The webhook endpoint does not exist.").send();
+    }
+    Ok(see_other("/admin/webhooks"))
+}
@@ -0,0 +1,131 @@
+use crate::{
+    libs::error_chain_fmt,
+    problem::{problem_response, ProblemError},
+    rate_limit::RateLimited,
+    routes::{ConfirmError, PublishError, SubscribeError},
+};
+use actix_web::{
+    error::{JsonPayloadError, PathError, QueryPayloadError},
+    http::{header, StatusCode},
+    HttpRequest, HttpResponse, ResponseError,
+};
+
+/// Errors of the `/api/v1` endpoints, rendered as problem details.
+#[derive(thiserror::Error)]
+pub enum ApiError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("Authentication required.")]
+    Unauthorized(#[source] anyhow::Error),
+    #[error("You are not allowed to perform this operation.")]
+    Forbidden(#[source] anyhow::Error),
+    #[error("{0} not found.")]
+    NotFound(&'static str),
+    #[error("{0}")]
+    Conflict(String),
+    #[error(transparent)]
+    RateLimited(#[from] RateLimited),
+    #[error("Something went wrong.")]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::RateLimited(e) => e.status_code(),
+            ApiError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            ApiError::RateLimited(e) => e.error_response(),
+            ApiError::Unauthorized(_) => {
+                let mut response = problem_response(self);
+                response.headers_mut().insert(
+                    header::WWW_AUTHENTICATE,
+                    header::HeaderValue::from_static(r#"Basic realm="api""#),
+                );
+                response
+            }
+            _ => problem_response(self),
+        }
+    }
+}
+
+impl ProblemError for ApiError {
+    fn code(&self) -> &'static str {
+        match self {
+            ApiError::ValidationError(_) => "validation_error",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::RateLimited(e) => e.code(),
+            ApiError::UnexpectedError(_) => "internal_error",
+        }
+    }
+}
+
+impl From<SubscribeError> for ApiError {
+    fn from(e: SubscribeError) -> Self {
+        match e {
+            SubscribeError::InvalidName(message)
+            | SubscribeError::InvalidEmail(message)
+            | SubscribeError::UndeliverableEmail(message) => ApiError::ValidationError(message),
+            SubscribeError::Erased(_) => ApiError::Conflict(e.to_string()),
+            SubscribeError::RateLimited(e) => ApiError::RateLimited(e),
+            SubscribeError::UnexpectedError(e) => ApiError::UnexpectedError(e),
+        }
+    }
+}
+
+impl From<ConfirmError> for ApiError {
+    fn from(e: ConfirmError) -> Self {
+        match e {
+            ConfirmError::InvalidTokenError => ApiError::Unauthorized(e.into()),
+            ConfirmError::RateLimited(e) => ApiError::RateLimited(e),
+            ConfirmError::UnexpectedError(e) => ApiError::UnexpectedError(e),
+        }
+    }
+}
+
+impl From<PublishError> for ApiError {
+    fn from(e: PublishError) -> Self {
+        match e {
+            PublishError::AuthError(_) | PublishError::InvalidApiToken(_) => {
+                ApiError::Unauthorized(e.into())
+            }
+            PublishError::InsufficientScope(_) => ApiError::Forbidden(e.into()),
+            PublishError::InvalidSubjects(message) => ApiError::ValidationError(message),
+            PublishError::UnknownSegment => ApiError::NotFound("Segment"),
+            PublishError::UnexpectedError(e) => ApiError::UnexpectedError(e),
+        }
+    }
+}
+
+// Malformed requests are rejected by the extractors before reaching the handlers:
+// these keep their error bodies consistent with the rest of the API.
+
+pub fn json_error_handler(e: JsonPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    ApiError::ValidationError(e.to_string()).into()
+}
+
+pub fn query_error_handler(e: QueryPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    ApiError::ValidationError(e.to_string()).into()
+}
+
+pub fn path_error_handler(e: PathError, _req: &HttpRequest) -> actix_web::Error {
+    ApiError::ValidationError(e.to_string()).into()
+}
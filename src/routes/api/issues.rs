@@ -0,0 +1,438 @@
+use super::{
+    pagination::{Cursor, Page, PageParams},
+    ApiError,
+};
+use crate::{
+    delivery::{parse_subject_variants, IssueMailer, OutgoingIssue},
+    segments::get_segment_filter,
+};
+use actix_web::{http::header::LOCATION, web, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize, utoipa::ToSchema, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueStatus {
+    Draft,
+    Published,
+}
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct IssueContent {
+    pub html: String,
+    pub text: String,
+}
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct Issue {
+    pub id: Uuid,
+    pub title: String,
+    pub content: IssueContent,
+    #[schema(value_type = IssueStatus)]
+    pub status: &'static str,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub published_at: Option<DateTime<Utc>>,
+}
+
+struct IssueRow {
+    id: Uuid,
+    title: String,
+    text_content: String,
+    html_content: String,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+    published_at: Option<DateTime<Utc>>,
+}
+
+impl From<IssueRow> for Issue {
+    fn from(row: IssueRow) -> Self {
+        Self {
+            id: row.id,
+            title: row.title,
+            content: IssueContent {
+                html: row.html_content,
+                text: row.text_content,
+            },
+            status: if row.published_at.is_some() {
+                "published"
+            } else {
+                "draft"
+            },
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+            published_at: row.published_at,
+        }
+    }
+}
+
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct IssueFilters {
+    status: Option<IssueStatus>,
+    /// Inclusive.
+    created_after: Option<DateTime<Utc>>,
+    /// Exclusive.
+    created_before: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct NewIssueContent {
+    html: String,
+    text: String,
+}
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct NewIssue {
+    title: String,
+    content: NewIssueContent,
+}
+
+/// Fields left out are not changed.
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct IssueChanges {
+    title: Option<String>,
+    content: Option<IssueContentChanges>,
+}
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct IssueContentChanges {
+    html: Option<String>,
+    text: Option<String>,
+}
+
+/// The body of a publish request is optional.
+#[derive(serde::Deserialize, utoipa::ToSchema, Default)]
+pub struct PublishBody {
+    /// Two to five subject lines to A/B test instead of the title.
+    subject_variants: Option<Vec<String>>,
+}
+
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PublishOptions {
+    /// Only send the issue to the subscribers of this segment.
+    segment: Option<Uuid>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/issues",
+    tag = "issues",
+    params(IssueFilters, PageParams),
+    responses(
+        (status = 200, body = IssuePage),
+        (status = 400, body = Problem, content_type = "application/problem+json"),
+        (status = 401, body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("basic_auth" = []), ("session_cookie" = []))
+)]
+#[tracing::instrument(name = "List newsletter issues", skip(filters, page, pool))]
+pub async fn list_issues(
+    filters: web::Query<IssueFilters>,
+    page: web::Query<PageParams>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let limit = page.limit()?;
+    let cursor = page.cursor()?;
+    let rows = sqlx::query_as!(
+        IssueRow,
+        r#"
+    SELECT id, title, text_content, html_content, created_at, updated_at, published_at
+    FROM newsletter_issues
+    WHERE ($1::bool IS NULL OR (published_at IS NOT NULL) = $1)
+        AND ($2::timestamptz IS NULL OR created_at >= $2)
+        AND ($3::timestamptz IS NULL OR created_at < $3)
+        AND ($4::timestamptz IS NULL OR (created_at, id) > ($4, $5))
+    ORDER BY created_at, id
+    LIMIT $6
+    "#,
+        filters.status.map(|s| s == IssueStatus::Published),
+        filters.created_after,
+        filters.created_before,
+        cursor.as_ref().map(|c| c.timestamp),
+        cursor.as_ref().map(|c| c.id),
+        limit + 1,
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to perform a query to list newsletter issues.")?;
+
+    let issues = rows.into_iter().map(Issue::from).collect();
+    Ok(
+        HttpResponse::Ok().json(Page::new(issues, limit, |i: &Issue| Cursor {
+            timestamp: i.created_at,
+            id: i.id,
+        })),
+    )
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/issues/{issue_id}",
+    tag = "issues",
+    params(("issue_id" = Uuid, Path, description = "Id of the issue.")),
+    responses(
+        (status = 200, body = Issue),
+        (status = 401, body = Problem, content_type = "application/problem+json"),
+        (status = 404, body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("basic_auth" = []), ("session_cookie" = []))
+)]
+#[tracing::instrument(name = "Get newsletter issue", skip(pool))]
+pub async fn get_issue(
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let issue = fetch_issue(*issue_id, &pool).await?;
+    Ok(HttpResponse::Ok().json(Issue::from(issue)))
+}
+
+/// Issues are created as drafts, and only sent once published.
+#[utoipa::path(
+    post,
+    path = "/api/v1/issues",
+    tag = "issues",
+    request_body = NewIssue,
+    responses(
+        (status = 201, body = Issue),
+        (status = 400, body = Problem, content_type = "application/problem+json"),
+        (status = 401, body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("basic_auth" = []), ("session_cookie" = []))
+)]
+#[tracing::instrument(name = "Create newsletter issue", skip(body, pool))]
+pub async fn create_issue(
+    body: web::Json<NewIssue>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let body = body.into_inner();
+    validate_title(&body.title)?;
+    let issue = sqlx::query_as!(
+        IssueRow,
+        r#"
+    INSERT INTO newsletter_issues (id, title, text_content, html_content, created_at, updated_at)
+    VALUES ($1, $2, $3, $4, now(), now())
+    RETURNING id, title, text_content, html_content, created_at, updated_at, published_at
+    "#,
+        Uuid::new_v4(),
+        body.title,
+        body.content.text,
+        body.content.html,
+    )
+    .fetch_one(pool.get_ref())
+    .await
+    .context("Failed to store a newsletter issue.")?;
+
+    Ok(HttpResponse::Created()
+        .insert_header((LOCATION, format!("/api/v1/issues/{}", issue.id)))
+        .json(Issue::from(issue)))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/v1/issues/{issue_id}",
+    tag = "issues",
+    params(("issue_id" = Uuid, Path, description = "Id of the issue.")),
+    request_body = IssueChanges,
+    responses(
+        (status = 200, body = Issue),
+        (status = 400, body = Problem, content_type = "application/problem+json"),
+        (status = 401, body = Problem, content_type = "application/problem+json"),
+        (status = 404, body = Problem, content_type = "application/problem+json"),
+        (status = 409, body = Problem, content_type = "application/problem+json", description = "The issue is already published."),
+    ),
+    security(("basic_auth" = []), ("session_cookie" = []))
+)]
+#[tracing::instrument(name = "Update newsletter issue", skip(changes, pool))]
+pub async fn update_issue(
+    issue_id: web::Path<Uuid>,
+    changes: web::Json<IssueChanges>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let issue_id = issue_id.into_inner();
+    let changes = changes.into_inner();
+    if let Some(title) = &changes.title {
+        validate_title(title)?;
+    }
+    let (html, text) = match changes.content {
+        Some(content) => (content.html, content.text),
+        None => (None, None),
+    };
+
+    let issue = sqlx::query_as!(
+        IssueRow,
+        r#"
+    UPDATE newsletter_issues
+    SET title = COALESCE($2, title),
+        html_content = COALESCE($3, html_content),
+        text_content = COALESCE($4, text_content),
+        updated_at = now()
+    WHERE id = $1 AND published_at IS NULL
+    RETURNING id, title, text_content, html_content, created_at, updated_at, published_at
+    "#,
+        issue_id,
+        changes.title,
+        html,
+        text,
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to update a newsletter issue.")?;
+    match issue {
+        Some(issue) => Ok(HttpResponse::Ok().json(Issue::from(issue))),
+        None => Err(not_a_draft(issue_id, &pool).await),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/issues/{issue_id}",
+    tag = "issues",
+    params(("issue_id" = Uuid, Path, description = "Id of the issue.")),
+    responses(
+        (status = 204),
+        (status = 401, body = Problem, content_type = "application/problem+json"),
+        (status = 404, body = Problem, content_type = "application/problem+json"),
+        (status = 409, body = Problem, content_type = "application/problem+json", description = "The issue is already published."),
+    ),
+    security(("basic_auth" = []), ("session_cookie" = []))
+)]
+#[tracing::instrument(name = "Delete newsletter issue", skip(pool))]
+pub async fn delete_issue(
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let issue_id = issue_id.into_inner();
+    let result = sqlx::query!(
+        r#"DELETE FROM newsletter_issues WHERE id = $1 AND published_at IS NULL"#,
+        issue_id
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to delete a newsletter issue.")?;
+    if result.rows_affected() == 0 {
+        return Err(not_a_draft(issue_id, &pool).await);
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Send a draft to every confirmed subscriber, or to the subscribers of a segment.
+///
+/// With `subject_variants`, each subject goes to a random test slice of the recipients
+/// first; the one with the best open rate goes to the others once the test ends.
+#[utoipa::path(
+    post,
+    path = "/api/v1/issues/{issue_id}/publish",
+    tag = "issues",
+    params(
+        ("issue_id" = Uuid, Path, description = "Id of the issue."),
+        PublishOptions,
+    ),
+    request_body = Option<PublishBody>,
+    responses(
+        (status = 200, body = Issue),
+        (status = 400, body = Problem, content_type = "application/problem+json"),
+        (status = 401, body = Problem, content_type = "application/problem+json"),
+        (status = 404, body = Problem, content_type = "application/problem+json", description = "The issue or the segment does not exist."),
+        (status = 409, body = Problem, content_type = "application/problem+json", description = "The issue is already published."),
+    ),
+    security(("basic_auth" = []), ("session_cookie" = []))
+)]
+#[tracing::instrument(
+    name = "Publish newsletter issue",
+    skip(options, body, pool, issue_mailer)
+)]
+pub async fn publish_issue(
+    issue_id: web::Path<Uuid>,
+    options: web::Query<PublishOptions>,
+    body: web::Bytes,
+    pool: web::Data<PgPool>,
+    issue_mailer: web::Data<IssueMailer>,
+) -> Result<HttpResponse, ApiError> {
+    let issue_id = issue_id.into_inner();
+    // An `Option<web::Json<_>>` extractor would silently ignore a malformed body.
+    let body: PublishBody = if body.is_empty() {
+        PublishBody::default()
+    } else {
+        serde_json::from_slice(&body).map_err(|e| ApiError::ValidationError(e.to_string()))?
+    };
+    let subject_variants = body
+        .subject_variants
+        .map(parse_subject_variants)
+        .transpose()
+        .map_err(ApiError::ValidationError)?
+        .unwrap_or_default();
+    let segment = match options.segment {
+        Some(segment_id) => Some(
+            get_segment_filter(segment_id, &pool)
+                .await?
+                .ok_or(ApiError::NotFound("Segment"))?,
+        ),
+        None => None,
+    };
+    // Marking the issue as published first ensures concurrent requests send it only once.
+    let issue = sqlx::query_as!(
+        IssueRow,
+        r#"
+    UPDATE newsletter_issues
+    SET published_at = now(), updated_at = now(), segment_filter = $2
+    WHERE id = $1 AND published_at IS NULL
+    RETURNING id, title, text_content, html_content, created_at, updated_at, published_at
+    "#,
+        issue_id,
+        segment.as_ref().map(|s| s.to_string()),
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to mark a newsletter issue as published.")?;
+    let issue = match issue {
+        Some(issue) => issue,
+        None => return Err(not_a_draft(issue_id, &pool).await),
+    };
+
+    let outgoing = OutgoingIssue {
+        id: issue.id,
+        title: &issue.title,
+        html_content: &issue.html_content,
+        text_content: &issue.text_content,
+    };
+    issue_mailer
+        .publish(&outgoing, segment.as_ref(), &subject_variants)
+        .await?;
+    Ok(HttpResponse::Ok().json(Issue::from(issue)))
+}
+
+async fn fetch_issue(issue_id: Uuid, pool: &PgPool) -> Result<IssueRow, ApiError> {
+    sqlx::query_as!(
+        IssueRow,
+        r#"
+    SELECT id, title, text_content, html_content, created_at, updated_at, published_at
+    FROM newsletter_issues
+    WHERE id = $1
+    "#,
+        issue_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to retrieve a newsletter issue.")?
+    .ok_or(ApiError::NotFound("Issue"))
+}
+
+/// Tells apart a missing issue from a published one, after a write restricted to drafts
+/// matched no row.
+async fn not_a_draft(issue_id: Uuid, pool: &PgPool) -> ApiError {
+    match fetch_issue(issue_id, pool).await {
+        Ok(_) => ApiError::Conflict("Published issues cannot be changed.".into()),
+        Err(e) => e,
+    }
+}
+
+fn validate_title(title: &str) -> Result<(), ApiError> {
+    if title.trim().is_empty() {
+        return Err(ApiError::ValidationError("`title` cannot be empty.".into()));
+    }
+    Ok(())
+}
@@ -0,0 +1,114 @@
+use super::ApiError;
+use crate::{
+    authentication::{
+        basic_authentication, bearer_token, has_valid_csrf_header, touch_user_session,
+        validate_api_token, validate_credentials, ApiScope, ApiTokenError, AuthError,
+        DummyPasswordHash, SessionTtl,
+    },
+    configuration::Argon2Settings,
+    metrics::Metrics,
+    session_state::TypedSession,
+};
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::{header::AUTHORIZATION, Method},
+    web, FromRequest,
+};
+use actix_web_lab::middleware::Next;
+use anyhow::Context;
+use sqlx::PgPool;
+
+/// API clients authenticate either with the `Basic` credentials of an admin,
+/// with the session cookie of a logged-in admin, or, on the endpoints that accept one,
+/// with a `Bearer` API token carrying the required scope.
+#[tracing::instrument(
+    name = "Authenticate API client",
+    skip_all,
+    fields(user_id = tracing::field::Empty)
+)]
+pub async fn reject_unauthenticated_api_clients(
+    pool: web::Data<PgPool>,
+    argon2_settings: web::Data<Argon2Settings>,
+    dummy_password_hash: web::Data<DummyPasswordHash>,
+    session_ttl: web::Data<SessionTtl>,
+    metrics: web::Data<Metrics>,
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let user_id = if let Some(token) = bearer_token(req.headers()) {
+        let scope = required_token_scope(&req).ok_or_else(|| {
+            ApiError::Forbidden(anyhow::anyhow!(
+                "API tokens are not accepted by this endpoint."
+            ))
+        })?;
+        validate_api_token(token, scope, &pool)
+            .await
+            .map_err(|e| match e {
+                ApiTokenError::InvalidToken(_) => ApiError::Unauthorized(e.into()),
+                ApiTokenError::InsufficientScope(_) => ApiError::Forbidden(e.into()),
+                ApiTokenError::UnexpectedError(_) => ApiError::UnexpectedError(e.into()),
+            })?
+    } else if req.headers().contains_key(AUTHORIZATION) {
+        let credentials = basic_authentication(req.headers()).map_err(ApiError::Unauthorized)?;
+        validate_credentials(credentials, &argon2_settings, &dummy_password_hash, &pool)
+            .await
+            .map_err(|e| match e {
+                AuthError::InvalidCredentials(_) => {
+                    metrics.login_failure("basic");
+                    ApiError::Unauthorized(e.into())
+                }
+                AuthError::UnexpectedError(_) => ApiError::UnexpectedError(e.into()),
+            })?
+    } else {
+        let session = {
+            let (http_request, payload) = req.parts_mut();
+            TypedSession::from_request(http_request, payload).await
+        }?;
+        let user_id = session_user_id(&session, **session_ttl, &pool)
+            .await?
+            .ok_or_else(|| ApiError::Unauthorized(anyhow::anyhow!("No live session.")))?;
+        // Browsers attach the cookie to cross-site requests too.
+        let is_safe = matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+        if !is_safe
+            && !has_valid_csrf_header(req.headers(), &session).map_err(ApiError::UnexpectedError)?
+        {
+            return Err(ApiError::Forbidden(anyhow::anyhow!(
+                "Missing or invalid CSRF token for a session-authenticated request."
+            ))
+            .into());
+        }
+        user_id
+    };
+
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+    next.call(req).await
+}
+
+/// The scope an API token needs for this request, or `None` if tokens are not accepted.
+fn required_token_scope(req: &ServiceRequest) -> Option<ApiScope> {
+    let is_read = matches!(*req.method(), Method::GET | Method::HEAD);
+    let subscribers = req.path().strip_prefix("/api/v1/subscribers")?;
+    (is_read && (subscribers.is_empty() || subscribers.starts_with('/')))
+        .then_some(ApiScope::SubscribersRead)
+}
+
+async fn session_user_id(
+    session: &TypedSession,
+    ttl: SessionTtl,
+    pool: &PgPool,
+) -> Result<Option<uuid::Uuid>, ApiError> {
+    let user_id = session
+        .get_user_id()
+        .context("Failed to read the session.")?;
+    let session_id = session
+        .get_session_id()
+        .context("Failed to read the session.")?;
+    match (user_id, session_id) {
+        (Some(user_id), Some(session_id)) => {
+            let is_live = touch_user_session(user_id, session_id, ttl, pool).await?;
+            Ok(is_live.then_some(user_id))
+        }
+        _ => Ok(None),
+    }
+}
@@ -0,0 +1,13 @@
+mod errors;
+mod issues;
+mod middleware;
+mod pagination;
+mod segments;
+mod subscribers;
+
+pub use errors::*;
+pub use issues::*;
+pub use middleware::*;
+pub use pagination::*;
+pub use segments::*;
+pub use subscribers::*;
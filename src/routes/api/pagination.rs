@@ -0,0 +1,139 @@
+use super::{ApiError, Issue, Segment, Subscriber};
+use base64::Engine;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use uuid::Uuid;
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 100;
+
+/// Listings are sorted by `(timestamp, id)`: the cursor is the position of the last item
+/// of a page, and the next page starts right after it. Unlike offsets, cursors stay
+/// valid while rows are inserted or deleted.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Cursor {
+    pub timestamp: DateTime<Utc>,
+    pub id: Uuid,
+}
+
+impl Cursor {
+    /// Clients must treat cursors as opaque strings.
+    pub fn encode(&self) -> String {
+        let raw = format!("{}:{}", self.timestamp.timestamp_micros(), self.id);
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(raw)
+    }
+
+    pub fn decode(cursor: &str) -> Result<Self, ApiError> {
+        let invalid = || ApiError::ValidationError(format!("{} is not a valid cursor.", cursor));
+        let raw = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .decode(cursor)
+            .ok()
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+            .ok_or_else(invalid)?;
+        let (micros, id) = raw.split_once(':').ok_or_else(invalid)?;
+        let timestamp = micros
+            .parse()
+            .ok()
+            .and_then(NaiveDateTime::from_timestamp_micros)
+            .map(|naive| DateTime::<Utc>::from_utc(naive, Utc))
+            .ok_or_else(invalid)?;
+        let id = id.parse().map_err(|_| invalid())?;
+        Ok(Self { timestamp, id })
+    }
+}
+
+/// The query parameters shared by every listing, extracted alongside its own filters.
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PageParams {
+    /// `next_cursor` of the previous page.
+    cursor: Option<String>,
+    /// Number of items per page, 50 by default.
+    #[param(minimum = 1, maximum = 100)]
+    limit: Option<i64>,
+}
+
+impl PageParams {
+    pub fn cursor(&self) -> Result<Option<Cursor>, ApiError> {
+        self.cursor.as_deref().map(Cursor::decode).transpose()
+    }
+
+    pub fn limit(&self) -> Result<i64, ApiError> {
+        match self.limit {
+            None => Ok(DEFAULT_PAGE_SIZE),
+            Some(limit) if (1..=MAX_PAGE_SIZE).contains(&limit) => Ok(limit),
+            Some(_) => Err(ApiError::ValidationError(format!(
+                "`limit` must be between 1 and {}.",
+                MAX_PAGE_SIZE
+            ))),
+        }
+    }
+}
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+#[aliases(
+    SubscriberPage = Page<Subscriber>,
+    IssuePage = Page<Issue>,
+    SegmentPage = Page<Segment>
+)]
+pub struct Page<T> {
+    pub data: Vec<T>,
+    /// Null on the last page.
+    pub next_cursor: Option<String>,
+}
+
+impl<T> Page<T> {
+    /// `rows` must hold up to `limit + 1` items: the extra one tells whether there is a next page.
+    pub fn new(mut rows: Vec<T>, limit: i64, cursor_of: impl Fn(&T) -> Cursor) -> Self {
+        let has_more = rows.len() as i64 > limit;
+        rows.truncate(limit as usize);
+        let next_cursor = if has_more {
+            rows.last().map(|last| cursor_of(last).encode())
+        } else {
+            None
+        };
+        Self {
+            data: rows,
+            next_cursor,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cursor, Page};
+    use chrono::{TimeZone, Utc};
+    use claims::assert_err;
+    use uuid::Uuid;
+
+    #[test]
+    fn a_cursor_survives_a_round_trip() {
+        let cursor = Cursor {
+            timestamp: Utc.timestamp_opt(1_686_000_000, 123_456_000).unwrap(),
+            id: Uuid::new_v4(),
+        };
+        assert_eq!(Cursor::decode(&cursor.encode()).unwrap(), cursor);
+    }
+
+    #[test]
+    fn a_malformed_cursor_is_rejected() {
+        assert_err!(Cursor::decode("not-a-cursor"));
+        assert_err!(Cursor::decode(""));
+    }
+
+    #[test]
+    fn only_a_page_followed_by_more_rows_has_a_next_cursor() {
+        let cursor_of = |n: &i64| Cursor {
+            timestamp: Utc.timestamp_opt(*n, 0).unwrap(),
+            id: Uuid::nil(),
+        };
+        let page = Page::new(vec![1, 2, 3], 2, cursor_of);
+        assert_eq!(page.data, vec![1, 2]);
+        assert_eq!(
+            page.next_cursor.map(|c| Cursor::decode(&c).unwrap()),
+            Some(cursor_of(&2))
+        );
+
+        let page = Page::new(vec![1, 2], 2, cursor_of);
+        assert_eq!(page.next_cursor, None);
+    }
+}
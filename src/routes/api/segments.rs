@@ -0,0 +1,209 @@
+use super::{
+    pagination::{Cursor, Page, PageParams},
+    ApiError,
+};
+use crate::segments::{count_audience, get_segment_filter, Filter};
+use actix_web::{http::header::LOCATION, web, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+const MAX_NAME_LENGTH: usize = 100;
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct Segment {
+    pub id: Uuid,
+    pub name: String,
+    /// E.g. `tag:vip and (list:weekly_digest or not engaged_within:90d)`.
+    pub filter: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct NewSegment {
+    name: String,
+    filter: String,
+}
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct SegmentPreview {
+    /// Confirmed subscribers in the segment who have not paused delivery: digest
+    /// subscribers among them get an issue with their next digest.
+    pub recipients: i64,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/segments",
+    tag = "segments",
+    params(PageParams),
+    responses(
+        (status = 200, body = SegmentPage),
+        (status = 400, body = Problem, content_type = "application/problem+json"),
+        (status = 401, body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("basic_auth" = []), ("session_cookie" = []))
+)]
+#[tracing::instrument(name = "List segments", skip(page, pool))]
+pub async fn list_segments(
+    page: web::Query<PageParams>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let limit = page.limit()?;
+    let cursor = page.cursor()?;
+    let rows = sqlx::query_as!(
+        Segment,
+        r#"
+    SELECT id, name, filter, created_at
+    FROM segments
+    WHERE ($1::timestamptz IS NULL OR (created_at, id) > ($1, $2))
+    ORDER BY created_at, id
+    LIMIT $3
+    "#,
+        cursor.as_ref().map(|c| c.timestamp),
+        cursor.as_ref().map(|c| c.id),
+        limit + 1,
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to perform a query to list segments.")?;
+
+    Ok(HttpResponse::Ok().json(Page::new(rows, limit, |s| Cursor {
+        timestamp: s.created_at,
+        id: s.id,
+    })))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/segments/{segment_id}",
+    tag = "segments",
+    params(("segment_id" = Uuid, Path, description = "Id of the segment.")),
+    responses(
+        (status = 200, body = Segment),
+        (status = 401, body = Problem, content_type = "application/problem+json"),
+        (status = 404, body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("basic_auth" = []), ("session_cookie" = []))
+)]
+#[tracing::instrument(name = "Get segment", skip(pool))]
+pub async fn get_segment(
+    segment_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let segment = sqlx::query_as!(
+        Segment,
+        r#"SELECT id, name, filter, created_at FROM segments WHERE id = $1"#,
+        *segment_id
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to perform a query to retrieve a segment.")?
+    .ok_or(ApiError::NotFound("Segment"))?;
+    Ok(HttpResponse::Ok().json(segment))
+}
+
+/// The filter is stored in its canonical form.
+#[utoipa::path(
+    post,
+    path = "/api/v1/segments",
+    tag = "segments",
+    request_body = NewSegment,
+    responses(
+        (status = 201, body = Segment),
+        (status = 400, body = Problem, content_type = "application/problem+json", description = "The name is empty or the filter is malformed."),
+        (status = 401, body = Problem, content_type = "application/problem+json"),
+        (status = 409, body = Problem, content_type = "application/problem+json", description = "Another segment has this name."),
+    ),
+    security(("basic_auth" = []), ("session_cookie" = []))
+)]
+#[tracing::instrument(name = "Create segment", skip(body, pool))]
+pub async fn create_segment(
+    body: web::Json<NewSegment>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let body = body.into_inner();
+    let name = body.name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+        return Err(ApiError::ValidationError(format!(
+            "`name` must be between 1 and {} characters long.",
+            MAX_NAME_LENGTH
+        )));
+    }
+    let filter = Filter::parse(&body.filter).map_err(ApiError::ValidationError)?;
+
+    let segment = sqlx::query_as!(
+        Segment,
+        r#"
+    INSERT INTO segments (id, name, filter, created_at)
+    VALUES ($1, $2, $3, now())
+    ON CONFLICT (name) DO NOTHING
+    RETURNING id, name, filter, created_at
+    "#,
+        Uuid::new_v4(),
+        name,
+        filter.to_string(),
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to store a segment.")?
+    .ok_or_else(|| ApiError::Conflict(format!("A segment is already named {}.", name)))?;
+
+    Ok(HttpResponse::Created()
+        .insert_header((LOCATION, format!("/api/v1/segments/{}", segment.id)))
+        .json(segment))
+}
+
+/// Issues already sent to the segment keep their audience.
+#[utoipa::path(
+    delete,
+    path = "/api/v1/segments/{segment_id}",
+    tag = "segments",
+    params(("segment_id" = Uuid, Path, description = "Id of the segment.")),
+    responses(
+        (status = 204),
+        (status = 401, body = Problem, content_type = "application/problem+json"),
+        (status = 404, body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("basic_auth" = []), ("session_cookie" = []))
+)]
+#[tracing::instrument(name = "Delete segment", skip(pool))]
+pub async fn delete_segment(
+    segment_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let result = sqlx::query!(r#"DELETE FROM segments WHERE id = $1"#, *segment_id)
+        .execute(pool.get_ref())
+        .await
+        .context("Failed to delete a segment.")?;
+    if result.rows_affected() == 0 {
+        return Err(ApiError::NotFound("Segment"));
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// How many subscribers an issue published to the segment would reach now.
+#[utoipa::path(
+    get,
+    path = "/api/v1/segments/{segment_id}/preview",
+    tag = "segments",
+    params(("segment_id" = Uuid, Path, description = "Id of the segment.")),
+    responses(
+        (status = 200, body = SegmentPreview),
+        (status = 401, body = Problem, content_type = "application/problem+json"),
+        (status = 404, body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("basic_auth" = []), ("session_cookie" = []))
+)]
+#[tracing::instrument(name = "Preview segment", skip(pool))]
+pub async fn preview_segment(
+    segment_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let filter = get_segment_filter(*segment_id, &pool)
+        .await?
+        .ok_or(ApiError::NotFound("Segment"))?;
+    let recipients = count_audience(&filter, &pool).await?;
+    Ok(HttpResponse::Ok().json(SegmentPreview { recipients }))
+}
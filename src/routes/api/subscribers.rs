@@ -0,0 +1,433 @@
+use super::{
+    pagination::{Cursor, Page, PageParams},
+    ApiError,
+};
+use crate::{
+    consent::{ConsentEvidence, ConsentSource},
+    domain::{
+        new_subscriber::NewSubscriber, subscriber_email::SubscriberEmail,
+        subscriber_name::SubscriberName,
+    },
+    metrics::Metrics,
+    preferences::{request_email_change, unsubscribe_subscriber},
+    routes::{register_subscriber, send_email_change_confirmation, SubscribeError},
+    segments::{parse_tag, validate_attribute_key},
+    services::email::EmailService,
+    startup::ApplicationBaseUrl,
+    subscriber_data::is_erased,
+};
+use actix_web::{http::header::LOCATION, web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+#[derive(serde::Serialize, serde::Deserialize, utoipa::ToSchema, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SubscriberStatus {
+    PendingConfirmation,
+    Confirmed,
+    /// Set by `DELETE`: signing up again is the only way back.
+    Unsubscribed,
+}
+
+impl SubscriberStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SubscriberStatus::PendingConfirmation => "pending_confirmation",
+            SubscriberStatus::Confirmed => "confirmed",
+            SubscriberStatus::Unsubscribed => "unsubscribed",
+        }
+    }
+}
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct Subscriber {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    #[schema(value_type = SubscriberStatus)]
+    pub status: String,
+    pub subscribed_at: DateTime<Utc>,
+    /// Sorted.
+    pub tags: Vec<String>,
+    /// Custom attributes, as string values.
+    #[schema(value_type = Object)]
+    pub attributes: serde_json::Value,
+}
+
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SubscriberFilters {
+    status: Option<SubscriberStatus>,
+    /// Inclusive.
+    subscribed_after: Option<DateTime<Utc>>,
+    /// Exclusive.
+    subscribed_before: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct NewSubscriberBody {
+    email: String,
+    name: String,
+}
+
+/// Fields left out are not changed.
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct SubscriberChanges {
+    /// Only replaces the current address once its owner follows the link mailed to it.
+    email: Option<String>,
+    name: Option<String>,
+    /// Rejected: subscribers confirm through the link mailed to them, and are
+    /// unsubscribed with `DELETE`.
+    status: Option<SubscriberStatus>,
+    /// Replaces every tag of the subscriber.
+    tags: Option<Vec<String>>,
+    /// Replaces every custom attribute of the subscriber.
+    attributes: Option<HashMap<String, String>>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/subscribers",
+    tag = "subscribers",
+    params(SubscriberFilters, PageParams),
+    responses(
+        (status = 200, body = SubscriberPage),
+        (status = 400, body = Problem, content_type = "application/problem+json"),
+        (status = 401, body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("basic_auth" = []), ("session_cookie" = []), ("bearer_token" = ["subscribers:read"]))
+)]
+#[tracing::instrument(name = "List subscribers", skip(filters, page, pool))]
+pub async fn list_subscribers(
+    filters: web::Query<SubscriberFilters>,
+    page: web::Query<PageParams>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let limit = page.limit()?;
+    let cursor = page.cursor()?;
+    let rows = sqlx::query_as!(
+        Subscriber,
+        r#"
+    SELECT id, email, name, status, subscribed_at, attributes,
+        ARRAY(
+            SELECT tag FROM subscriber_tags t WHERE t.subscriber_id = subscriptions.id ORDER BY tag
+        ) AS "tags!"
+    FROM subscriptions
+    WHERE ($1::text IS NULL OR status = $1)
+        AND ($2::timestamptz IS NULL OR subscribed_at >= $2)
+        AND ($3::timestamptz IS NULL OR subscribed_at < $3)
+        AND ($4::timestamptz IS NULL OR (subscribed_at, id) > ($4, $5))
+    ORDER BY subscribed_at, id
+    LIMIT $6
+    "#,
+        filters.status.map(|s| s.as_str()),
+        filters.subscribed_after,
+        filters.subscribed_before,
+        cursor.as_ref().map(|c| c.timestamp),
+        cursor.as_ref().map(|c| c.id),
+        limit + 1,
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to perform a query to list subscribers.")?;
+
+    Ok(HttpResponse::Ok().json(Page::new(rows, limit, |s| Cursor {
+        timestamp: s.subscribed_at,
+        id: s.id,
+    })))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/subscribers/{subscriber_id}",
+    tag = "subscribers",
+    params(("subscriber_id" = Uuid, Path, description = "Id of the subscriber.")),
+    responses(
+        (status = 200, body = Subscriber),
+        (status = 401, body = Problem, content_type = "application/problem+json"),
+        (status = 404, body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("basic_auth" = []), ("session_cookie" = []), ("bearer_token" = ["subscribers:read"]))
+)]
+#[tracing::instrument(name = "Get subscriber", skip(pool))]
+pub async fn get_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let subscriber = fetch_subscriber(*subscriber_id, &pool).await?;
+    Ok(HttpResponse::Ok().json(subscriber))
+}
+
+/// Subscribers created through the API go through the same confirmation flow
+/// as the ones signing up on the website.
+#[utoipa::path(
+    post,
+    path = "/api/v1/subscribers",
+    tag = "subscribers",
+    request_body = NewSubscriberBody,
+    responses(
+        (status = 201, body = Subscriber),
+        (status = 400, body = Problem, content_type = "application/problem+json"),
+        (status = 401, body = Problem, content_type = "application/problem+json"),
+        (status = 409, body = Problem, content_type = "application/problem+json", description = "The email address is already subscribed, or its owner asked for their data to be erased."),
+    ),
+    security(("basic_auth" = []), ("session_cookie" = []))
+)]
+#[tracing::instrument(
+    name = "Create subscriber",
+    skip(body, pool, email_service, base_url, metrics, request)
+)]
+pub async fn create_subscriber(
+    body: web::Json<NewSubscriberBody>,
+    pool: web::Data<PgPool>,
+    email_service: web::Data<EmailService>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    metrics: web::Data<Metrics>,
+    request: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    let body = body.into_inner();
+    let new_subscriber = NewSubscriber {
+        email: SubscriberEmail::parse(body.email).map_err(ApiError::ValidationError)?,
+        name: SubscriberName::parse(body.name).map_err(ApiError::ValidationError)?,
+    };
+    ensure_email_is_available(new_subscriber.email.as_ref(), None, &pool).await?;
+    // No consent text is shown to the subscriber: the client vouches for their consent.
+    let evidence = ConsentEvidence::from_request(ConsentSource::Api, None, &request);
+    let subscriber_id =
+        register_subscriber(new_subscriber, evidence, &pool, email_service, &base_url).await?;
+    metrics.subscription("api");
+
+    let subscriber = fetch_subscriber(subscriber_id, &pool).await?;
+    Ok(HttpResponse::Created()
+        .insert_header((LOCATION, format!("/api/v1/subscribers/{}", subscriber_id)))
+        .json(subscriber))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/v1/subscribers/{subscriber_id}",
+    tag = "subscribers",
+    params(("subscriber_id" = Uuid, Path, description = "Id of the subscriber.")),
+    request_body = SubscriberChanges,
+    responses(
+        (status = 200, body = Subscriber),
+        (status = 400, body = Problem, content_type = "application/problem+json"),
+        (status = 401, body = Problem, content_type = "application/problem+json"),
+        (status = 404, body = Problem, content_type = "application/problem+json"),
+        (status = 409, body = Problem, content_type = "application/problem+json", description = "The email address is taken or erased, or the subscriber unsubscribed."),
+    ),
+    security(("basic_auth" = []), ("session_cookie" = []))
+)]
+#[tracing::instrument(
+    name = "Update subscriber",
+    skip(changes, pool, email_service, base_url)
+)]
+pub async fn update_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    changes: web::Json<SubscriberChanges>,
+    pool: web::Data<PgPool>,
+    email_service: web::Data<EmailService>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, ApiError> {
+    let subscriber_id = subscriber_id.into_inner();
+    let changes = changes.into_inner();
+    let email = changes
+        .email
+        .map(SubscriberEmail::parse)
+        .transpose()
+        .map_err(ApiError::ValidationError)?;
+    let name = changes
+        .name
+        .map(SubscriberName::parse)
+        .transpose()
+        .map_err(ApiError::ValidationError)?;
+    let tags = changes
+        .tags
+        .map(|tags| {
+            tags.iter()
+                .map(|t| parse_tag(t))
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .transpose()
+        .map_err(ApiError::ValidationError)?;
+    if let Some(attributes) = &changes.attributes {
+        for key in attributes.keys() {
+            validate_attribute_key(key).map_err(ApiError::ValidationError)?;
+        }
+    }
+    // Either way would skip the consent log and the webhook events: confirming takes the
+    // subscriber's own click, and unsubscribing records the withdrawal of their consent.
+    if changes.status.is_some() {
+        return Err(ApiError::ValidationError(
+            "The status cannot be changed: subscribers confirm through the link mailed to them, \
+            and are unsubscribed with `DELETE`."
+                .into(),
+        ));
+    }
+    if let Some(email) = &email {
+        ensure_email_is_available(email.as_ref(), Some(subscriber_id), &pool).await?;
+        if is_erased(email.as_ref(), &pool).await? {
+            return Err(SubscribeError::Erased(email.as_ref().to_owned()).into());
+        }
+    }
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    // Locked, so that a concurrent `DELETE` waits for the changes to be written.
+    let status = sqlx::query!(
+        r#"SELECT status FROM subscriptions WHERE id = $1 FOR UPDATE"#,
+        subscriber_id
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to retrieve the status of a subscriber.")?
+    .ok_or(ApiError::NotFound("Subscriber"))?
+    .status;
+    if status == SubscriberStatus::Unsubscribed.as_str() {
+        return Err(ApiError::Conflict(
+            "The subscriber unsubscribed: only signing up again brings them back.".into(),
+        ));
+    }
+    sqlx::query!(
+        r#"
+    UPDATE subscriptions
+    SET name = COALESCE($2, name),
+        attributes = COALESCE($3, attributes)
+    WHERE id = $1
+    "#,
+        subscriber_id,
+        name.as_ref().map(|n| n.as_ref()),
+        changes
+            .attributes
+            .map(|a| serde_json::to_value(a).expect("Failed to serialize attributes.")),
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to update a subscriber in the database.")?;
+    if let Some(tags) = &tags {
+        replace_tags(&mut transaction, subscriber_id, tags).await?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to update a subscriber.")?;
+
+    // Same flow as the preference center: the new address must be confirmed by its owner.
+    if let Some(email) = &email {
+        let token = request_email_change(subscriber_id, email, &pool).await?;
+        send_email_change_confirmation(&email_service, email, &base_url.0, &token)
+            .await
+            .context("Failed to send an email change confirmation.")?;
+    }
+
+    let subscriber = fetch_subscriber(subscriber_id, &pool).await?;
+    Ok(HttpResponse::Ok().json(subscriber))
+}
+
+async fn replace_tags(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    tags: &[String],
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"DELETE FROM subscriber_tags WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to remove the tags of a subscriber.")?;
+    sqlx::query!(
+        r#"
+    INSERT INTO subscriber_tags (subscriber_id, tag)
+    SELECT $1, tag FROM unnest($2::text[]) AS tag
+    ON CONFLICT DO NOTHING
+    "#,
+        subscriber_id,
+        tags,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to tag a subscriber.")?;
+    Ok(())
+}
+
+/// Unsubscribe the subscriber. They are kept as `unsubscribed`, along with their consent
+/// log: only a data erasure request deletes them.
+#[utoipa::path(
+    delete,
+    path = "/api/v1/subscribers/{subscriber_id}",
+    tag = "subscribers",
+    params(("subscriber_id" = Uuid, Path, description = "Id of the subscriber.")),
+    responses(
+        (status = 204),
+        (status = 401, body = Problem, content_type = "application/problem+json"),
+        (status = 404, body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("basic_auth" = []), ("session_cookie" = []))
+)]
+#[tracing::instrument(name = "Delete subscriber", skip(pool, request))]
+pub async fn delete_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    let evidence = ConsentEvidence::from_request(ConsentSource::Api, None, &request);
+    if !unsubscribe_subscriber(subscriber_id.into_inner(), &evidence, &pool).await? {
+        return Err(ApiError::NotFound("Subscriber"));
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+async fn fetch_subscriber(subscriber_id: Uuid, pool: &PgPool) -> Result<Subscriber, ApiError> {
+    sqlx::query_as!(
+        Subscriber,
+        r#"
+    SELECT id, email, name, status, subscribed_at, attributes,
+        ARRAY(
+            SELECT tag FROM subscriber_tags t WHERE t.subscriber_id = subscriptions.id ORDER BY tag
+        ) AS "tags!"
+    FROM subscriptions
+    WHERE id = $1
+    "#,
+        subscriber_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to retrieve a subscriber.")?
+    .ok_or(ApiError::NotFound("Subscriber"))
+}
+
+/// Addresses are unique regardless of case. When creating a subscriber (`except` is `None`),
+/// an unsubscribed one does not count: signing up again brings them back.
+async fn ensure_email_is_available(
+    email: &str,
+    except: Option<Uuid>,
+    pool: &PgPool,
+) -> Result<(), ApiError> {
+    let taken = sqlx::query!(
+        r#"
+    SELECT id FROM subscriptions
+    WHERE lower(email) = lower($1)
+        AND ($2::uuid IS NULL OR id <> $2)
+        AND ($2::uuid IS NOT NULL OR status <> 'unsubscribed')
+    "#,
+        email,
+        except,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to check whether an email address is already subscribed.")?
+    .is_some();
+    if taken {
+        return Err(ApiError::Conflict(format!(
+            "{} is already subscribed.",
+            email
+        )));
+    }
+    Ok(())
+}
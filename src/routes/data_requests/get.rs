@@ -0,0 +1,99 @@
+use super::{DataRequestError, LinkParams};
+use crate::signed_link::LinkSigner;
+use crate::subscriber_data::export_subscriber_data;
+use actix_web::http::header::{ContentDisposition, ContentType, DispositionParam, DispositionType};
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+pub async fn data_request_form(flash_messages: IncomingFlashMessages) -> HttpResponse {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(
+            msg_html,
+            "<p><i>{}</i></p>",
+            htmlescape::encode_minimal(m.content())
+        )
+        .unwrap();
+    }
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Your data</title>
+</head>
+<body>
+    {msg_html}
+    <p>Get a copy of the data we hold about you, or have it erased.
+    We will email you a link to do so.</p>
+    <form action="/privacy" method="post">
+        <label>Email
+            <input
+                type="email"
+                placeholder="Enter your email"
+                name="email"
+            >
+        </label>
+        <button type="submit">Send me a link</button>
+    </form>
+</body>
+</html>"#
+        ))
+}
+
+/// Landing page of the mailed link.
+#[tracing::instrument(name = "Show the data request page", skip(params, link_signer, pool))]
+pub async fn manage_data(
+    params: web::Query<LinkParams>,
+    link_signer: web::Data<LinkSigner>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, DataRequestError> {
+    let subscriber_id = params.subscriber_id(&link_signer)?;
+    let export = export_subscriber_data(subscriber_id, &pool)
+        .await?
+        .ok_or(DataRequestError::NoData)?;
+    let token = htmlescape::encode_attribute(&params.token);
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Your data</title>
+</head>
+<body>
+    <p>Data held about {email}:</p>
+    <p><a href="/privacy/export?token={token}">Download a copy</a></p>
+    <form action="/privacy/erase" method="post">
+        <input type="hidden" name="token" value="{token}">
+        <p>Erasing your data also unsubscribes you. This cannot be undone.</p>
+        <button type="submit">Erase my data</button>
+    </form>
+</body>
+</html>"#,
+            email = htmlescape::encode_minimal(&export.subscriber.email),
+        )))
+}
+
+#[tracing::instrument(name = "Export subscriber data", skip(params, link_signer, pool))]
+pub async fn export_data(
+    params: web::Query<LinkParams>,
+    link_signer: web::Data<LinkSigner>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, DataRequestError> {
+    let subscriber_id = params.subscriber_id(&link_signer)?;
+    let export = export_subscriber_data(subscriber_id, &pool)
+        .await?
+        .ok_or(DataRequestError::NoData)?;
+    Ok(HttpResponse::Ok()
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename("subscriber-data.json".into())],
+        })
+        .json(export))
+}
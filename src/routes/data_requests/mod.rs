@@ -0,0 +1,87 @@
+mod get;
+mod post;
+
+pub use get::{data_request_form, export_data, manage_data};
+pub use post::{erase_data, request_data_link};
+
+use crate::libs::error_chain_fmt;
+use crate::problem::{problem_response, ProblemError};
+use crate::rate_limit::RateLimited;
+use crate::signed_link::{InvalidLink, LinkPurpose, LinkSigner};
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use chrono::Utc;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct LinkParams {
+    token: String,
+}
+
+impl LinkParams {
+    fn subscriber_id(&self, link_signer: &LinkSigner) -> Result<Uuid, DataRequestError> {
+        Ok(link_signer.verify(
+            LinkPurpose::DataRequest,
+            &self.token,
+            Utc::now().timestamp(),
+        )?)
+    }
+}
+
+#[derive(thiserror::Error)]
+pub enum DataRequestError {
+    #[error("{0}")]
+    InvalidEmail(String),
+    #[error(transparent)]
+    InvalidLink(#[from] InvalidLink),
+    #[error("We hold no data about you.")]
+    NoData,
+    #[error(transparent)]
+    RateLimited(#[from] RateLimited),
+    #[error("Something went wrong.")]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for DataRequestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for DataRequestError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            DataRequestError::InvalidEmail(_) => StatusCode::BAD_REQUEST,
+            DataRequestError::InvalidLink(_) => StatusCode::UNAUTHORIZED,
+            DataRequestError::NoData => StatusCode::NOT_FOUND,
+            DataRequestError::RateLimited(e) => e.status_code(),
+            DataRequestError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            DataRequestError::RateLimited(e) => e.error_response(),
+            _ => problem_response(self),
+        }
+    }
+}
+
+impl ProblemError for DataRequestError {
+    fn code(&self) -> &'static str {
+        match self {
+            DataRequestError::InvalidEmail(_) => "invalid_email",
+            DataRequestError::InvalidLink(_) => "invalid_link",
+            DataRequestError::NoData => "not_found",
+            DataRequestError::RateLimited(e) => e.code(),
+            DataRequestError::UnexpectedError(_) => "internal_error",
+        }
+    }
+
+    /// Only the request form can be submitted again: links are mailed.
+    fn redirect_to(&self) -> Option<String> {
+        match self {
+            DataRequestError::InvalidEmail(_) => Some("/privacy".into()),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use super::{DataRequestError, LinkParams};
+use crate::configuration::DataRequestSettings;
+use crate::domain::subscriber_email::SubscriberEmail;
+use crate::libs::see_other;
+use crate::rate_limit::{LimitedRoute, RateLimiter};
+use crate::services::email::{EmailKind, EmailService};
+use crate::signed_link::{LinkPurpose, LinkSigner};
+use crate::startup::ApplicationBaseUrl;
+use crate::subscriber_data::erase_subscriber;
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::PgPool;
+
+#[derive(serde::Deserialize)]
+pub struct DataRequestForm {
+    email: String,
+}
+
+/// Mail a link to the subscriber. The response does not tell whether the address is
+/// subscribed: only its owner learns it.
+// Handlers take one argument per extractor.
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(
+    name = "Request a data request link",
+    skip(
+        form,
+        pool,
+        email_service,
+        base_url,
+        link_signer,
+        settings,
+        rate_limiter,
+        request
+    )
+)]
+pub async fn request_data_link(
+    form: web::Form<DataRequestForm>,
+    pool: web::Data<PgPool>,
+    email_service: web::Data<EmailService>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    link_signer: web::Data<LinkSigner>,
+    settings: web::Data<DataRequestSettings>,
+    rate_limiter: web::Data<RateLimiter>,
+    request: HttpRequest,
+) -> Result<HttpResponse, DataRequestError> {
+    rate_limiter
+        .check(LimitedRoute::DataRequest, &request, Some(&form.email))
+        .await?;
+    let email = SubscriberEmail::parse(form.0.email).map_err(DataRequestError::InvalidEmail)?;
+    let subscriber = sqlx::query!(
+        r#"SELECT id FROM subscriptions WHERE lower(email) = lower($1)"#,
+        email.as_ref(),
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to look up the subscriber making a data request.")?;
+
+    if let Some(subscriber) = subscriber {
+        let expires_at = Utc::now().timestamp() + settings.link_ttl_in_secs;
+        let token = link_signer.sign(LinkPurpose::DataRequest, subscriber.id, expires_at);
+        let link = format!(
+            "{}/privacy/data?token={}",
+            base_url.0,
+            urlencoding::encode(&token)
+        );
+        let hours = settings.link_ttl_in_secs / 3600;
+        let html_body = format!(
+            "Follow <a href=\"{}\">this link</a> to download or erase the data we hold about you.<br />\
+            It expires in {} hours. If you did not ask for it, you can ignore this email.",
+            link, hours
+        );
+        let plain_body = format!(
+            "Visit {} to download or erase the data we hold about you.\n\
+            It expires in {} hours. If you did not ask for it, you can ignore this email.",
+            link, hours
+        );
+        email_service
+            .send_email(
+                EmailKind::DataRequest,
+                &email,
+                "Your data",
+                &html_body,
+                &plain_body,
+            )
+            .await
+            .context("Failed to send a data request link.")?;
+    }
+
+    FlashMessage::info(format!(
+        "If {} is subscribed, we sent it a link to manage your data.",
+        email.as_ref()
+    ))
+    .send();
+    Ok(see_other("/privacy"))
+}
+
+#[tracing::instrument(name = "Erase subscriber data", skip(form, link_signer, pool))]
+pub async fn erase_data(
+    form: web::Form<LinkParams>,
+    link_signer: web::Data<LinkSigner>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, DataRequestError> {
+    let subscriber_id = form.subscriber_id(&link_signer)?;
+    if !erase_subscriber(subscriber_id, &pool).await? {
+        return Err(DataRequestError::NoData);
+    }
+    Ok(HttpResponse::Ok().content_type(ContentType::html()).body(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Your data</title>
+</head>
+<body>
+    <p>Your data has been erased and you will not hear from us again.</p>
+</body>
+</html>"#,
+    ))
+}
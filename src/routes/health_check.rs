@@ -1,5 +1,44 @@
-use actix_web::HttpResponse;
+use crate::health::{HealthChecker, HealthStatus, Liveness, Readiness};
+use actix_web::{http::header::CacheControl, http::header::CacheDirective, web, HttpResponse};
 
+#[utoipa::path(get, path = "/health_check", tag = "health", responses((status = 200)))]
 pub async fn health_check() -> HttpResponse {
     HttpResponse::Ok().finish()
 }
+
+/// The process is up and serving requests. Dependencies are not checked: restarting
+/// the process would not bring them back.
+#[utoipa::path(
+    get,
+    path = "/health/live",
+    tag = "health",
+    responses((status = 200, body = Liveness))
+)]
+pub async fn liveness() -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .json(Liveness {
+            status: HealthStatus::Up,
+        })
+}
+
+/// The application can serve traffic: every required dependency answered in time.
+#[utoipa::path(
+    get,
+    path = "/health/ready",
+    tag = "health",
+    responses(
+        (status = 200, body = Readiness),
+        (status = 503, description = "A required dependency is down.", body = Readiness),
+    )
+)]
+pub async fn readiness(checker: web::Data<HealthChecker>) -> HttpResponse {
+    let readiness: Readiness = checker.check().await;
+    let mut response = match readiness.status {
+        HealthStatus::Up => HttpResponse::Ok(),
+        _ => HttpResponse::ServiceUnavailable(),
+    };
+    response
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .json(readiness)
+}
@@ -1,7 +1,81 @@
-use actix_web::{http::header::ContentType, HttpResponse};
+use crate::bot_protection::{BotProtection, HONEYPOT_FIELD};
+use crate::consent::CONSENT_TEXT;
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use chrono::Utc;
+use std::fmt::Write;
+
+pub async fn home(
+    flash_messages: IncomingFlashMessages,
+    bot_protection: web::Data<BotProtection>,
+) -> HttpResponse {
+    // Messages may quote what was submitted in the form.
+    let mut error_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(
+            error_html,
+            "<p><i>{}</i></p>",
+            htmlescape::encode_minimal(m.content())
+        )
+        .unwrap();
+    }
+    let form_token = bot_protection.issue_form_token(Utc::now().timestamp());
+    // The challenge is solved by the script right before the form is submitted.
+    let (proof_of_work_attribute, proof_of_work_script) =
+        match bot_protection.proof_of_work_difficulty() {
+            Some(difficulty) => (
+                format!(r#" data-pow-difficulty="{}""#, difficulty),
+                r#"<script src="/proof_of_work.js"></script>"#,
+            ),
+            None => (String::new(), ""),
+        };
 
-pub async fn home() -> HttpResponse {
     HttpResponse::Ok()
         .content_type(ContentType::html())
-        .body(include_str!("home.html"))
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Home</title>
+    {proof_of_work_script}
+</head>
+<body>
+    <p>Welcome to our newsletter!</p>
+    {error_html}
+    <form action="/subscriptions" method="post"{proof_of_work_attribute}>
+        <input type="hidden" name="form_token" value="{form_token}">
+        <input type="hidden" name="proof_of_work" value="">
+        <p hidden>
+            <label>Leave this field empty
+                <input type="text" name="{HONEYPOT_FIELD}" tabindex="-1" autocomplete="off">
+            </label>
+        </p>
+        <label>Name
+            <input
+                type="text"
+                placeholder="Enter your name"
+                name="name"
+            >
+        </label>
+        <label>Email
+            <input
+                type="email"
+                placeholder="Enter your email"
+                name="email"
+            >
+        </label>
+        <p>{CONSENT_TEXT}</p>
+        <button type="submit">Subscribe</button>
+    </form>
+    <p><a href="/privacy">Get or erase your data</a></p>
+</body>
+</html>"#
+        ))
+}
+
+pub async fn proof_of_work_script() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/javascript; charset=utf-8")
+        .body(include_str!("proof_of_work.js"))
 }
@@ -0,0 +1,37 @@
+// Solves the proof-of-work challenge of the subscription form before submitting it:
+// find a nonce such that sha256("<form token>:<nonce>") starts with enough zero bits.
+document.addEventListener("DOMContentLoaded", () => {
+  const form = document.querySelector("form[data-pow-difficulty]");
+  if (!form) {
+    return;
+  }
+  form.addEventListener("submit", async (event) => {
+    event.preventDefault();
+    const difficulty = Number(form.dataset.powDifficulty);
+    const challenge = form.elements["form_token"].value;
+    form.elements["proof_of_work"].value = await solve(challenge, difficulty);
+    form.submit();
+  });
+});
+
+async function solve(challenge, difficulty) {
+  const encoder = new TextEncoder();
+  for (let nonce = 0; ; nonce++) {
+    const input = encoder.encode(`${challenge}:${nonce}`);
+    const hash = new Uint8Array(await crypto.subtle.digest("SHA-256", input));
+    if (leadingZeroBits(hash) >= difficulty) {
+      return nonce.toString();
+    }
+  }
+}
+
+function leadingZeroBits(bytes) {
+  let bits = 0;
+  for (const byte of bytes) {
+    if (byte !== 0) {
+      return bits + Math.clz32(byte) - 24;
+    }
+    bits += 8;
+  }
+  return bits;
+}
@@ -1,14 +1,19 @@
+use crate::{authentication::csrf_field, session_state::TypedSession};
 use actix_web::{http::header::ContentType, HttpResponse};
 use actix_web_flash_messages::IncomingFlashMessages;
 use std::fmt::Write;
 
-pub async fn login_form(flash_messages: IncomingFlashMessages) -> HttpResponse {
+pub async fn login_form(
+    flash_messages: IncomingFlashMessages,
+    session: TypedSession,
+) -> Result<HttpResponse, actix_web::Error> {
     let mut error_html = String::new();
     for m in flash_messages.iter() {
         writeln!(error_html, "<p><i>{}</i></p>", m.content()).unwrap();
     }
+    let csrf_field = csrf_field(&session)?;
 
-    HttpResponse::Ok()
+    Ok(HttpResponse::Ok()
         .content_type(ContentType::html())
         .body(format!(
             r#"<!DOCTYPE html>
@@ -20,6 +25,7 @@ pub async fn login_form(flash_messages: IncomingFlashMessages) -> HttpResponse {
 <body>
     {error_html}
     <form action="/login" method="post">
+        {csrf_field}
         <label>Username
             <input
                 type="text"
@@ -38,5 +44,5 @@ pub async fn login_form(flash_messages: IncomingFlashMessages) -> HttpResponse {
     </form>
 </body>
 </html>"#
-        ))
+        )))
 }
@@ -1,10 +1,21 @@
-use crate::authentication::{validate_credentials, AuthError, Credentials};
+use crate::authentication::{
+    create_user_session, validate_credentials, AuthError, Credentials, DummyPasswordHash,
+};
+use crate::client_ip::client_ip;
+use crate::configuration::Argon2Settings;
 use crate::libs::error_chain_fmt;
+use crate::metrics::Metrics;
+use crate::problem::{problem_response, ProblemError};
+use crate::rate_limit::{LimitedRoute, RateLimited, RateLimiter};
 use crate::session_state::TypedSession;
-use actix_web::error::InternalError;
-use actix_web::web;
-use actix_web::{http::header::LOCATION, HttpResponse};
-use actix_web_flash_messages::FlashMessage;
+use actix_web::{
+    http::{
+        header::{LOCATION, USER_AGENT},
+        StatusCode,
+    },
+    HttpResponse,
+};
+use actix_web::{web, HttpRequest, ResponseError};
 use secrecy::Secret;
 use sqlx::PgPool;
 
@@ -18,6 +29,8 @@ pub struct FormData {
 pub enum LoginError {
     #[error("Authentication failed")]
     AuthError(#[source] anyhow::Error),
+    #[error(transparent)]
+    RateLimited(#[from] RateLimited),
     #[error("Something went wrong")]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -28,47 +41,104 @@ impl std::fmt::Debug for LoginError {
     }
 }
 
+impl ResponseError for LoginError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            LoginError::AuthError(_) => StatusCode::UNAUTHORIZED,
+            LoginError::RateLimited(e) => e.status_code(),
+            LoginError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            LoginError::RateLimited(e) => e.error_response(),
+            _ => problem_response(self),
+        }
+    }
+}
+
+impl ProblemError for LoginError {
+    fn code(&self) -> &'static str {
+        match self {
+            LoginError::AuthError(_) => "auth_failed",
+            LoginError::RateLimited(e) => e.code(),
+            LoginError::UnexpectedError(_) => "internal_error",
+        }
+    }
+
+    /// Retrying right away would be rate limited again: browsers get an error page instead.
+    fn redirect_to(&self) -> Option<String> {
+        match self {
+            LoginError::RateLimited(_) => None,
+            _ => Some("/login".into()),
+        }
+    }
+}
+
 #[tracing::instrument(
-    skip(form, pool, session),
+    skip(
+        form,
+        pool,
+        argon2_settings,
+        dummy_password_hash,
+        session,
+        rate_limiter,
+        metrics,
+        request
+    ),
     fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
 )]
+#[allow(clippy::too_many_arguments)]
 pub async fn login(
     form: web::Form<FormData>,
     pool: web::Data<PgPool>,
+    argon2_settings: web::Data<Argon2Settings>,
+    dummy_password_hash: web::Data<DummyPasswordHash>,
     session: TypedSession,
-) -> Result<HttpResponse, InternalError<LoginError>> {
+    rate_limiter: web::Data<RateLimiter>,
+    metrics: web::Data<Metrics>,
+    request: HttpRequest,
+) -> Result<HttpResponse, LoginError> {
+    rate_limiter
+        .check(LimitedRoute::Login, &request, Some(&form.username))
+        .await?;
     let credentials = Credentials {
         username: form.0.username,
         password: form.0.password,
     };
-    tracing::Span::current().record("username", &tracing::field::display(&credentials.username));
+    tracing::Span::current().record("username", tracing::field::display(&credentials.username));
 
-    match validate_credentials(credentials, &pool).await {
+    match validate_credentials(credentials, &argon2_settings, &dummy_password_hash, &pool).await {
         Ok(user_id) => {
-            tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
+            tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+            let ip_address = client_ip(&request).map(|ip| ip.to_string());
+            let user_agent = request
+                .headers()
+                .get(USER_AGENT)
+                .and_then(|h| h.to_str().ok());
+            let session_id =
+                create_user_session(user_id, ip_address.as_deref(), user_agent, &pool).await?;
             session.renew();
             session
                 .insert_user_id(user_id)
-                .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?;
+                .map_err(|e| LoginError::UnexpectedError(e.into()))?;
+            session
+                .insert_session_id(session_id)
+                .map_err(|e| LoginError::UnexpectedError(e.into()))?;
             Ok(HttpResponse::SeeOther()
                 .insert_header((LOCATION, "/admin/dashboard"))
                 .finish())
         }
         Err(e) => {
             let e = match e {
-                AuthError::InvalidCredentials(_) => LoginError::AuthError(e.into()),
+                AuthError::InvalidCredentials(_) => {
+                    metrics.login_failure("form");
+                    LoginError::AuthError(e.into())
+                }
                 AuthError::UnexpectedError(_) => LoginError::UnexpectedError(e.into()),
             };
-
-            Err(login_redirect(e))
+            Err(e)
         }
     }
 }
-
-fn login_redirect(e: LoginError) -> InternalError<LoginError> {
-    FlashMessage::error(e.to_string()).send();
-    let response = HttpResponse::SeeOther()
-        .insert_header((LOCATION, "/login"))
-        .finish();
-    InternalError::from_response(e, response)
-}
@@ -1,15 +1,25 @@
 mod admin;
+mod api;
+mod data_requests;
 mod health_check;
 mod home;
 mod login;
 mod newsletter;
+mod openapi;
+mod opens;
+mod preferences;
 mod subscription_confirm;
 mod subscriptions;
 
 pub use admin::*;
+pub use api::*;
+pub use data_requests::*;
 pub use health_check::*;
 pub use home::*;
 pub use login::*;
 pub use newsletter::*;
+pub use openapi::*;
+pub use opens::*;
+pub use preferences::*;
 pub use subscription_confirm::*;
 pub use subscriptions::*;
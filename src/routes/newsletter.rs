@@ -1,8 +1,14 @@
 use crate::{
-    authentication::{validate_credentials, AuthError, Credentials},
-    domain::subscriber_email::SubscriberEmail,
+    authentication::{
+        basic_authentication, bearer_token, validate_api_token, validate_credentials, ApiScope,
+        ApiTokenError, AuthError, DummyPasswordHash,
+    },
+    configuration::Argon2Settings,
+    delivery::{parse_subject_variants, IssueMailer, OutgoingIssue},
     libs::error_chain_fmt,
-    services::email::EmailService,
+    metrics::Metrics,
+    problem::{problem_response, ProblemError},
+    segments::{get_segment_filter, Filter},
 };
 use actix_web::{
     http::{
@@ -12,19 +18,22 @@ use actix_web::{
     web, HttpRequest, HttpResponse, ResponseError,
 };
 use anyhow::Context;
-use base64::Engine;
-use secrecy::Secret;
 use serde::Deserialize;
 use sqlx::PgPool;
+use uuid::Uuid;
 
-#[derive(Deserialize)]
+#[derive(Deserialize, utoipa::ToSchema)]
 pub struct BodyData {
     title: String,
     content: Content,
+    /// Only send the issue to the subscribers of this segment.
+    segment: Option<Uuid>,
+    /// Subject lines to A/B test instead of the title.
+    subject_variants: Option<Vec<String>>,
 }
 
-#[derive(Deserialize)]
-struct Content {
+#[derive(Deserialize, utoipa::ToSchema)]
+pub struct Content {
     html: String,
     text: String,
 }
@@ -33,6 +42,14 @@ struct Content {
 pub enum PublishError {
     #[error("Authentication failed")]
     AuthError(#[source] anyhow::Error),
+    #[error("Invalid API token")]
+    InvalidApiToken(#[source] anyhow::Error),
+    #[error("Insufficient API token scope")]
+    InsufficientScope(#[source] anyhow::Error),
+    #[error("{0}")]
+    InvalidSubjects(String),
+    #[error("Segment not found.")]
+    UnknownSegment,
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -43,126 +60,192 @@ impl std::fmt::Debug for PublishError {
     }
 }
 impl ResponseError for PublishError {
-    fn error_response(&self) -> HttpResponse {
+    fn status_code(&self) -> StatusCode {
         match self {
-            PublishError::UnexpectedError(_) => {
-                HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR)
+            PublishError::AuthError(_) | PublishError::InvalidApiToken(_) => {
+                StatusCode::UNAUTHORIZED
             }
-            PublishError::AuthError(_) => {
-                let mut response = HttpResponse::new(StatusCode::UNAUTHORIZED);
-                let header_value = HeaderValue::from_str(r#"Basic realm="publish""#).unwrap();
-                response
-                    .headers_mut()
-                    .insert(header::WWW_AUTHENTICATE, header_value);
-                response
+            PublishError::InsufficientScope(_) => StatusCode::FORBIDDEN,
+            PublishError::InvalidSubjects(_) => StatusCode::BAD_REQUEST,
+            PublishError::UnknownSegment => StatusCode::NOT_FOUND,
+            PublishError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = problem_response(self);
+        let challenge = match self {
+            PublishError::InvalidSubjects(_)
+            | PublishError::UnknownSegment
+            | PublishError::UnexpectedError(_) => return response,
+            PublishError::AuthError(_) => r#"Basic realm="publish""#.to_string(),
+            PublishError::InvalidApiToken(_) => {
+                r#"Bearer realm="publish", error="invalid_token""#.to_string()
             }
+            PublishError::InsufficientScope(_) => format!(
+                r#"Bearer realm="publish", error="insufficient_scope", scope="{}""#,
+                ApiScope::IssuesPublish.as_str()
+            ),
+        };
+        response.headers_mut().insert(
+            header::WWW_AUTHENTICATE,
+            HeaderValue::from_str(&challenge).unwrap(),
+        );
+        response
+    }
+}
+
+impl ProblemError for PublishError {
+    fn code(&self) -> &'static str {
+        match self {
+            PublishError::AuthError(_) => "auth_failed",
+            PublishError::InvalidApiToken(_) => "invalid_api_token",
+            PublishError::InsufficientScope(_) => "insufficient_scope",
+            PublishError::InvalidSubjects(_) => "validation_error",
+            PublishError::UnknownSegment => "not_found",
+            PublishError::UnexpectedError(_) => "internal_error",
         }
     }
 }
 
+/// Publish an issue to every confirmed subscriber, or to the subscribers of a segment.
+///
+/// With `subject_variants`, each subject goes to a random test slice of the recipients
+/// first; the one with the best open rate goes to the others once the test ends.
+/// Recipients over the email quota, or whose email failed, get the issue later too.
+#[utoipa::path(
+    post,
+    path = "/newsletters",
+    tag = "newsletters",
+    request_body = BodyData,
+    responses(
+        (status = 200, description = "The issue was sent to every recipient not left for later."),
+        (status = 400, description = "Invalid subject variants.", body = Problem, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or invalid credentials.", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "The API token lacks the `issues:publish` scope.", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "The segment does not exist.", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("basic_auth" = []), ("bearer_token" = ["issues:publish"]))
+)]
 #[tracing::instrument(
     name = "Publish a newsletter issue",
-    skip(body, pool, email_service, req),
+    skip(body, pool, argon2_settings, dummy_password_hash, issue_mailer, metrics, req),
     fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
 )]
 pub async fn post_newsletter(
     body: web::Json<BodyData>,
     pool: web::Data<PgPool>,
-    email_service: web::Data<EmailService>,
+    argon2_settings: web::Data<Argon2Settings>,
+    dummy_password_hash: web::Data<DummyPasswordHash>,
+    issue_mailer: web::Data<IssueMailer>,
+    metrics: web::Data<Metrics>,
     req: HttpRequest,
 ) -> Result<HttpResponse, PublishError> {
-    let credentials = basic_authentication(req.headers()).map_err(PublishError::AuthError)?;
-    tracing::Span::current().record("username", &tracing::field::display(&credentials.username));
-
-    let user_id = validate_credentials(credentials, &pool)
-        .await
-        .map_err(|e| match e {
-            AuthError::InvalidCredentials(_) => PublishError::AuthError(e.into()),
-            AuthError::UnexpectedError(_) => PublishError::UnexpectedError(e.into()),
-        })?;
-    tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
+    let user_id = authenticate_publisher(
+        req.headers(),
+        &argon2_settings,
+        &dummy_password_hash,
+        &pool,
+        &metrics,
+    )
+    .await?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
 
-    let subscribers = get_confirmed_subscribers(&pool).await?;
-    for subscriber in subscribers {
-        match subscriber {
-            Ok(subscriber) => {
-                email_service
-                    .send_email(
-                        &subscriber.email,
-                        &body.title,
-                        &body.content.html,
-                        &body.content.text,
-                    )
-                    .await
-                    .with_context(|| {
-                        format!("Failed to send newsletter issue to {}", subscriber.email)
-                    })?;
-            }
-            Err(error) => {
-                tracing::warn!(
-                // We record the error chain as a structured field
-                // on the log record.
-                error.cause_chain = ?error,
-                // Using `\` to split a long string literal over
-                // two lines, without creating a `\n` character.
-                "Skipping a confirmed subscriber. \
-                Their stored contact details are invalid",
-                );
-            }
-        }
-    }
+    let body = body.into_inner();
+    let subject_variants = body
+        .subject_variants
+        .map(parse_subject_variants)
+        .transpose()
+        .map_err(PublishError::InvalidSubjects)?
+        .unwrap_or_default();
+    let segment = match body.segment {
+        Some(segment_id) => Some(
+            get_segment_filter(segment_id, &pool)
+                .await?
+                .ok_or(PublishError::UnknownSegment)?,
+        ),
+        None => None,
+    };
+    let issue_id = store_published_issue(
+        &pool,
+        &body.title,
+        &body.content.html,
+        &body.content.text,
+        segment.as_ref(),
+    )
+    .await
+    .context("Failed to store the newsletter issue.")?;
+    let issue = OutgoingIssue {
+        id: issue_id,
+        title: &body.title,
+        html_content: &body.content.html,
+        text_content: &body.content.text,
+    };
+    issue_mailer
+        .publish(&issue, segment.as_ref(), &subject_variants)
+        .await?;
     Ok(HttpResponse::Ok().finish())
 }
 
-struct ConfirmedSubscriber {
-    email: SubscriberEmail,
+/// Issues sent straight away are recorded too, so that they show up in the issues API.
+#[tracing::instrument(
+    name = "Store a published newsletter issue",
+    skip(pool, html_content, text_content)
+)]
+async fn store_published_issue(
+    pool: &PgPool,
+    title: &str,
+    html_content: &str,
+    text_content: &str,
+    segment: Option<&Filter>,
+) -> Result<Uuid, sqlx::Error> {
+    let issue_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+    INSERT INTO newsletter_issues
+        (id, title, text_content, html_content, created_at, updated_at, published_at, segment_filter)
+    VALUES ($1, $2, $3, $4, now(), now(), now(), $5)
+    "#,
+        issue_id,
+        title,
+        text_content,
+        html_content,
+        segment.map(|s| s.to_string()),
+    )
+    .execute(pool)
+    .await?;
+    Ok(issue_id)
 }
 
-#[tracing::instrument(name = "Get confirmed subscribers", skip(pool))]
-async fn get_confirmed_subscribers(
+/// Automation authenticates with a scoped `Bearer` API token,
+/// while `Basic` credentials of an admin are still accepted.
+async fn authenticate_publisher(
+    headers: &HeaderMap,
+    argon2_settings: &Argon2Settings,
+    dummy_password_hash: &DummyPasswordHash,
     pool: &PgPool,
-) -> Result<Vec<Result<ConfirmedSubscriber, anyhow::Error>>, anyhow::Error> {
-    let confirmed_subscribers =
-        sqlx::query!(r#"SELECT email FROM subscriptions WHERE status = 'confirmed'"#,)
-            .fetch_all(pool)
-            .await?
-            .into_iter()
-            // No longer using `filter_map`!
-            .map(|r| match SubscriberEmail::parse(r.email) {
-                Ok(email) => Ok(ConfirmedSubscriber { email }),
-                Err(error) => Err(anyhow::anyhow!(error)),
-            })
-            .collect();
-    Ok(confirmed_subscribers)
-}
+    metrics: &Metrics,
+) -> Result<uuid::Uuid, PublishError> {
+    if let Some(token) = bearer_token(headers) {
+        return validate_api_token(token, ApiScope::IssuesPublish, pool)
+            .await
+            .map_err(|e| match e {
+                ApiTokenError::InvalidToken(_) => PublishError::InvalidApiToken(e.into()),
+                ApiTokenError::InsufficientScope(_) => PublishError::InsufficientScope(e.into()),
+                ApiTokenError::UnexpectedError(_) => PublishError::UnexpectedError(e.into()),
+            });
+    }
 
-fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Error> {
-    // The header value, if present, must be a valid UTF8 string
-    let header_value = headers
-        .get("Authorization")
-        .context("The 'Authorization' header was missing")?
-        .to_str()
-        .context("The 'Authorization' header was not a valid UTF8 string.")?;
-    let base64_encoded_segment = header_value
-        .strip_prefix("Basic ")
-        .context("The authorization scheme was not 'Basic'.")?;
-    let decoded_bytes = base64::engine::general_purpose::STANDARD
-        .decode(base64_encoded_segment)
-        .context("Failed to base64-decode 'Basic' credentials.")?;
-    let decoded_credentials = String::from_utf8(decoded_bytes)
-        .context("The decoded credential string is not valid UTF8.")?;
-    // Split into two segments, using ':' as delimiter
-    let mut credentials = decoded_credentials.splitn(2, ':');
-    let username = credentials
-        .next()
-        .ok_or_else(|| anyhow::anyhow!("A username must be provided in 'Basic' auth."))?
-        .to_string();
-    let password = credentials
-        .next()
-        .ok_or_else(|| anyhow::anyhow!("A password must be provided in 'Basic' auth."))?
-        .to_string();
-    Ok(Credentials {
-        username,
-        password: Secret::new(password),
-    })
+    let credentials = basic_authentication(headers).map_err(PublishError::AuthError)?;
+    tracing::Span::current().record("username", tracing::field::display(&credentials.username));
+
+    validate_credentials(credentials, argon2_settings, dummy_password_hash, pool)
+        .await
+        .map_err(|e| match e {
+            AuthError::InvalidCredentials(_) => {
+                metrics.login_failure("basic");
+                PublishError::AuthError(e.into())
+            }
+            AuthError::UnexpectedError(_) => PublishError::UnexpectedError(e.into()),
+        })
 }
@@ -0,0 +1,170 @@
+use crate::{
+    health::{DependencyHealth, HealthStatus, Liveness, Readiness},
+    problem::Problem,
+    routes::{
+        BodyData, Content, FormData, Issue, IssueChanges, IssueContent, IssueContentChanges,
+        IssuePage, IssueStatus, NewIssue, NewIssueContent, NewSegment, NewSubscriberBody,
+        PublishBody, Segment, SegmentPage, SegmentPreview, Subscriber, SubscriberChanges,
+        SubscriberPage, SubscriberStatus,
+    },
+};
+use actix_web::{
+    http::header::{ContentType, CONTENT_SECURITY_POLICY},
+    web, HttpResponse,
+};
+use utoipa::{
+    openapi::security::{ApiKey, ApiKeyValue, Http, HttpAuthScheme, SecurityScheme},
+    Modify, OpenApi,
+};
+
+// Swagger UI 5.17.14, vendored from the `dist` folder of its release along with its licence,
+// so that the documentation page loads no code from third parties.
+const SWAGGER_UI_BUNDLE: &str = include_str!("vendor/swagger-ui-bundle.js");
+const SWAGGER_UI_CSS: &str = include_str!("vendor/swagger-ui.css");
+
+/// The OpenAPI document of the machine-facing routes. Handlers and the types they
+/// (de)serialize carry the annotations: register new ones here.
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "zero2prod", description = "Newsletter delivery service."),
+    paths(
+        crate::routes::health_check,
+        crate::routes::liveness,
+        crate::routes::readiness,
+        crate::routes::subscribe,
+        crate::routes::confirm,
+        crate::routes::post_newsletter,
+        crate::routes::list_subscribers,
+        crate::routes::create_subscriber,
+        crate::routes::get_subscriber,
+        crate::routes::update_subscriber,
+        crate::routes::delete_subscriber,
+        crate::routes::list_issues,
+        crate::routes::create_issue,
+        crate::routes::get_issue,
+        crate::routes::update_issue,
+        crate::routes::delete_issue,
+        crate::routes::publish_issue,
+        crate::routes::list_segments,
+        crate::routes::create_segment,
+        crate::routes::get_segment,
+        crate::routes::delete_segment,
+        crate::routes::preview_segment,
+    ),
+    components(schemas(
+        FormData,
+        BodyData,
+        Content,
+        Subscriber,
+        SubscriberStatus,
+        SubscriberPage,
+        NewSubscriberBody,
+        SubscriberChanges,
+        Issue,
+        IssueStatus,
+        IssueContent,
+        IssuePage,
+        NewIssue,
+        NewIssueContent,
+        IssueChanges,
+        IssueContentChanges,
+        PublishBody,
+        Segment,
+        SegmentPage,
+        NewSegment,
+        SegmentPreview,
+        Problem,
+        HealthStatus,
+        DependencyHealth,
+        Liveness,
+        Readiness,
+    )),
+    modifiers(&SecuritySchemes),
+    tags(
+        (name = "subscriptions", description = "Sign-up flow of the website."),
+        (name = "newsletters", description = "Send an issue right away."),
+        (name = "subscribers", description = "`/api/v1`: manage subscribers."),
+        (name = "issues", description = "`/api/v1`: draft and publish issues."),
+        (name = "segments", description = "`/api/v1`: target issues at some subscribers."),
+        (name = "health"),
+    )
+)]
+pub struct ApiDoc;
+
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "basic_auth",
+            SecurityScheme::Http(Http::new(HttpAuthScheme::Basic)),
+        );
+        components.add_security_scheme(
+            "bearer_token",
+            SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)),
+        );
+    }
+}
+
+/// The OpenAPI document served at `/openapi.json`. The session cookie is named by the
+/// configuration, so its security scheme is only known at startup.
+pub fn api_doc(session_cookie_name: &str) -> utoipa::openapi::OpenApi {
+    let mut openapi = ApiDoc::openapi();
+    // Writes authenticated by the session also need the `X-CSRF-Token` header.
+    openapi
+        .components
+        .get_or_insert_with(Default::default)
+        .add_security_scheme(
+            "session_cookie",
+            SecurityScheme::ApiKey(ApiKey::Cookie(ApiKeyValue::new(session_cookie_name))),
+        );
+    openapi
+}
+
+pub async fn openapi_json(api_doc: web::Data<utoipa::openapi::OpenApi>) -> HttpResponse {
+    HttpResponse::Ok().json(api_doc.as_ref())
+}
+
+pub async fn api_docs() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        // Relaxes the default policy, for this page only, to let Swagger UI inline its icons.
+        .insert_header((
+            CONTENT_SECURITY_POLICY,
+            "default-src 'self'; img-src 'self' data:; frame-ancestors 'none'",
+        ))
+        .body(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>API documentation</title>
+    <link rel="stylesheet" href="/docs/swagger-ui.css">
+    <script src="/docs/swagger-ui-bundle.js"></script>
+    <script src="/docs/swagger_ui.js"></script>
+</head>
+<body>
+    <div id="swagger-ui"></div>
+</body>
+</html>"#,
+        )
+}
+
+pub async fn swagger_ui_script() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/javascript; charset=utf-8")
+        .body(include_str!("swagger_ui.js"))
+}
+
+pub async fn swagger_ui_bundle() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/javascript; charset=utf-8")
+        .body(SWAGGER_UI_BUNDLE)
+}
+
+pub async fn swagger_ui_css() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/css; charset=utf-8")
+        .body(SWAGGER_UI_CSS)
+}
@@ -0,0 +1,8 @@
+// Renders the API documentation of `/docs` from the generated specification.
+window.addEventListener("load", () => {
+  window.ui = SwaggerUIBundle({
+    url: "/openapi.json",
+    dom_id: "#swagger-ui",
+    deepLinking: true,
+  });
+});
@@ -0,0 +1,25 @@
+use crate::delivery::record_open;
+use actix_web::http::header::{CacheControl, CacheDirective};
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// A transparent 1x1 GIF.
+const PIXEL: &[u8] = &[
+    0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xff, 0xff, 0xff, 0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00, 0x00, 0x00,
+    0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+];
+
+/// The tracking pixel at the bottom of every issue. It loads whatever happens, so that
+/// email clients never show a broken image.
+#[tracing::instrument(name = "Track an open", skip(pool))]
+pub async fn track_open(delivery_id: web::Path<Uuid>, pool: web::Data<PgPool>) -> HttpResponse {
+    if let Err(e) = record_open(delivery_id.into_inner(), &pool).await {
+        tracing::error!(error.cause_chain = ?e, "Failed to record an open.");
+    }
+    HttpResponse::Ok()
+        .content_type("image/gif")
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .body(PIXEL)
+}
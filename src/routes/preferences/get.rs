@@ -0,0 +1,176 @@
+use super::{subscriber_id, PreferenceParams, PreferencesError};
+use crate::configuration::PreferenceSettings;
+use crate::consent::{ConsentEvidence, ConsentSource};
+use crate::preferences::{apply_email_change, get_preferences, EmailChange, Frequency};
+use crate::segments::record_engagement;
+use crate::signed_link::LinkSigner;
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use chrono::Utc;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+/// Pause lengths offered on the page, in weeks.
+const PAUSE_OPTIONS: [i64; 4] = [1, 2, 4, 12];
+
+#[tracing::instrument(
+    name = "Show the preference center",
+    skip(params, flash_messages, link_signer, pool)
+)]
+pub async fn preference_center(
+    params: web::Query<PreferenceParams>,
+    flash_messages: IncomingFlashMessages,
+    link_signer: web::Data<LinkSigner>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, PreferencesError> {
+    let subscriber_id = subscriber_id(&params.token, &link_signer)?;
+    let preferences = get_preferences(subscriber_id, &pool)
+        .await?
+        .ok_or(PreferencesError::NotSubscribed)?;
+    record_engagement(subscriber_id, &pool).await?;
+
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(
+            msg_html,
+            "<p><i>{}</i></p>",
+            htmlescape::encode_minimal(m.content())
+        )
+        .unwrap();
+    }
+    let token = htmlescape::encode_attribute(&params.token);
+    let token_field = format!(r#"<input type="hidden" name="token" value="{}">"#, token);
+
+    let mut frequency_html = String::new();
+    for frequency in Frequency::ALL {
+        writeln!(
+            frequency_html,
+            r#"<label><input type="radio" name="frequency" value="{}"{}>{}</label>"#,
+            frequency.as_str(),
+            if frequency.as_str() == preferences.frequency {
+                " checked"
+            } else {
+                ""
+            },
+            frequency.label(),
+        )
+        .unwrap();
+    }
+
+    let pause_status = match preferences.paused_until {
+        Some(paused_until) if paused_until > Utc::now() => format!(
+            "<p>Delivery is paused until {}.</p>",
+            paused_until.format("%B %-d, %Y")
+        ),
+        _ => String::new(),
+    };
+    let mut pause_options_html = String::new();
+    for weeks in PAUSE_OPTIONS {
+        writeln!(
+            pause_options_html,
+            r#"<option value="{0}">{0} week{1}</option>"#,
+            weeks,
+            if weeks == 1 { "" } else { "s" }
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Your preferences</title>
+</head>
+<body>
+    {msg_html}
+    <form action="/preferences/name" method="post">
+        {token_field}
+        <label>Name
+            <input type="text" name="name" value="{name}">
+        </label>
+        <button type="submit">Change name</button>
+    </form>
+    <form action="/preferences/email" method="post">
+        {token_field}
+        <label>Email
+            <input type="email" name="email" value="{email}">
+        </label>
+        <button type="submit">Change email</button>
+    </form>
+    <form action="/preferences/frequency" method="post">
+        {token_field}
+        <p>Send me:</p>
+        {frequency_html}
+        <button type="submit">Save</button>
+    </form>
+    {pause_status}
+    <form action="/preferences/pause" method="post">
+        {token_field}
+        <label>Pause delivery for
+            <select name="weeks">
+                {pause_options_html}
+            </select>
+        </label>
+        <button type="submit">Pause</button>
+    </form>
+    <form action="/preferences/pause" method="post">
+        {token_field}
+        <input type="hidden" name="weeks" value="0">
+        <button type="submit">Resume delivery</button>
+    </form>
+    <form action="/preferences/unsubscribe" method="post">
+        {token_field}
+        <button type="submit">Unsubscribe</button>
+    </form>
+</body>
+</html>"#,
+            name = htmlescape::encode_minimal(&preferences.name),
+            email = htmlescape::encode_minimal(&preferences.email),
+        )))
+}
+
+#[derive(serde::Deserialize)]
+pub struct EmailChangeParams {
+    token: String,
+}
+
+/// Landing page of the link mailed to the new address.
+#[tracing::instrument(
+    name = "Confirm an email change",
+    skip(params, settings, pool, request)
+)]
+pub async fn confirm_email_change(
+    params: web::Query<EmailChangeParams>,
+    settings: web::Data<PreferenceSettings>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, PreferencesError> {
+    let evidence = ConsentEvidence::from_request(ConsentSource::Email, None, &request);
+    let ttl = settings.email_change_ttl();
+    let email = match apply_email_change(&params.token, ttl, &evidence, &pool).await? {
+        EmailChange::Changed { email } => email,
+        EmailChange::Taken { email } => return Err(PreferencesError::EmailTaken(email)),
+        EmailChange::Erased { email } => return Err(PreferencesError::EmailErased(email)),
+        EmailChange::Expired => return Err(PreferencesError::ExpiredToken),
+        EmailChange::UnknownToken => return Err(PreferencesError::InvalidToken),
+    };
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Your preferences</title>
+</head>
+<body>
+    <p>We will now write to {}.</p>
+</body>
+</html>"#,
+            htmlescape::encode_minimal(&email)
+        )))
+}
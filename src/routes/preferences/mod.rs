@@ -0,0 +1,154 @@
+mod get;
+mod post;
+
+pub use get::{confirm_email_change, preference_center};
+pub use post::{
+    change_email, change_frequency, change_name, pause_delivery, send_email_change_confirmation,
+    unsubscribe,
+};
+
+use crate::libs::{error_chain_fmt, see_other};
+use crate::problem::{problem_response, ProblemError};
+use crate::signed_link::{InvalidLink, LinkPurpose, LinkSigner};
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use chrono::Utc;
+use uuid::Uuid;
+
+/// The token of the preference link, signed by `PreferenceLinks`.
+#[derive(serde::Deserialize)]
+pub struct PreferenceParams {
+    token: String,
+}
+
+/// The subscriber the preference link was issued to.
+fn subscriber_id(token: &str, link_signer: &LinkSigner) -> Result<Uuid, PreferencesError> {
+    Ok(link_signer.verify(LinkPurpose::Preferences, token, Utc::now().timestamp())?)
+}
+
+/// Back to the preference center, which shows the outcome as a flash message.
+fn see_preferences(token: &str) -> HttpResponse {
+    see_other(&preferences_path(token))
+}
+
+fn preferences_path(token: &str) -> String {
+    format!("/preferences?token={}", urlencoding::encode(token))
+}
+
+#[derive(thiserror::Error)]
+pub enum PreferencesError {
+    #[error("{0}")]
+    InvalidName(String),
+    #[error("{0}")]
+    InvalidEmail(String),
+    #[error("Delivery can be paused for up to {0} weeks.")]
+    InvalidPause(i64),
+    #[error("{0} is already subscribed.")]
+    EmailTaken(String),
+    #[error("{0} asked for their data to be erased.")]
+    EmailErased(String),
+    #[error(transparent)]
+    InvalidLink(#[from] InvalidLink),
+    #[error("This link is invalid or was already used.")]
+    InvalidToken,
+    #[error("This link has expired: please ask for the change again.")]
+    ExpiredToken,
+    #[error("You are not subscribed anymore.")]
+    NotSubscribed,
+    #[error("Something went wrong.")]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for PreferencesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for PreferencesError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            PreferencesError::InvalidName(_)
+            | PreferencesError::InvalidEmail(_)
+            | PreferencesError::InvalidPause(_) => StatusCode::BAD_REQUEST,
+            PreferencesError::EmailTaken(_) | PreferencesError::EmailErased(_) => {
+                StatusCode::CONFLICT
+            }
+            PreferencesError::InvalidLink(_)
+            | PreferencesError::InvalidToken
+            | PreferencesError::ExpiredToken => StatusCode::UNAUTHORIZED,
+            PreferencesError::NotSubscribed => StatusCode::NOT_FOUND,
+            PreferencesError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        problem_response(self)
+    }
+}
+
+impl ProblemError for PreferencesError {
+    fn code(&self) -> &'static str {
+        match self {
+            PreferencesError::InvalidName(_) => "invalid_name",
+            PreferencesError::InvalidEmail(_) => "invalid_email",
+            PreferencesError::InvalidPause(_) => "invalid_pause",
+            PreferencesError::EmailTaken(_) | PreferencesError::EmailErased(_) => "conflict",
+            PreferencesError::InvalidLink(_) => "invalid_link",
+            PreferencesError::InvalidToken | PreferencesError::ExpiredToken => "invalid_token",
+            PreferencesError::NotSubscribed => "not_found",
+            PreferencesError::UnexpectedError(_) => "internal_error",
+        }
+    }
+}
+
+impl PreferencesError {
+    /// The error of a form of the preference center reached through the link `token`.
+    fn on_form(self, token: &str) -> PreferenceFormError {
+        PreferenceFormError {
+            token: token.to_owned(),
+            error: self,
+        }
+    }
+}
+
+/// Browsers are sent back to the preference center when they can correct what they
+/// submitted; other errors, such as an invalid link, get an error page.
+#[derive(thiserror::Error)]
+#[error("{error}")]
+pub struct PreferenceFormError {
+    token: String,
+    error: PreferencesError,
+}
+
+impl std::fmt::Debug for PreferenceFormError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+impl ResponseError for PreferenceFormError {
+    fn status_code(&self) -> StatusCode {
+        self.error.status_code()
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        problem_response(self)
+    }
+}
+
+impl ProblemError for PreferenceFormError {
+    fn code(&self) -> &'static str {
+        self.error.code()
+    }
+
+    fn redirect_to(&self) -> Option<String> {
+        match self.error {
+            PreferencesError::InvalidName(_)
+            | PreferencesError::InvalidEmail(_)
+            | PreferencesError::InvalidPause(_)
+            | PreferencesError::EmailTaken(_)
+            | PreferencesError::EmailErased(_) => Some(preferences_path(&self.token)),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,256 @@
+use super::{
+    see_preferences, subscriber_id, PreferenceFormError, PreferenceParams, PreferencesError,
+};
+use crate::consent::{ConsentEvidence, ConsentSource};
+use crate::domain::{subscriber_email::SubscriberEmail, subscriber_name::SubscriberName};
+use crate::preferences::{
+    get_preferences, is_email_taken, request_email_change, set_frequency, set_name,
+    set_paused_until, unsubscribe_subscriber, Frequency,
+};
+use crate::services::email::{EmailError, EmailKind, EmailService};
+use crate::signed_link::LinkSigner;
+use crate::startup::ApplicationBaseUrl;
+use crate::subscriber_data::is_erased;
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use chrono::{DateTime, Duration, Utc};
+use sqlx::PgPool;
+
+/// Longest pause, in weeks.
+const MAX_PAUSE_WEEKS: i64 = 52;
+
+#[derive(serde::Deserialize)]
+pub struct NameForm {
+    token: String,
+    name: String,
+}
+
+#[tracing::instrument(
+    name = "Change name from the preference center",
+    skip(form, link_signer, pool)
+)]
+pub async fn change_name(
+    form: web::Form<NameForm>,
+    link_signer: web::Data<LinkSigner>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, PreferenceFormError> {
+    save_name(&form, &link_signer, &pool)
+        .await
+        .map_err(|e| e.on_form(&form.token))?;
+    FlashMessage::info("Your name has been changed.").send();
+    Ok(see_preferences(&form.token))
+}
+
+async fn save_name(
+    form: &NameForm,
+    link_signer: &LinkSigner,
+    pool: &PgPool,
+) -> Result<(), PreferencesError> {
+    let subscriber_id = subscriber_id(&form.token, link_signer)?;
+    let name = SubscriberName::parse(form.name.clone()).map_err(PreferencesError::InvalidName)?;
+    if !set_name(subscriber_id, &name, pool).await? {
+        return Err(PreferencesError::NotSubscribed);
+    }
+    Ok(())
+}
+
+#[derive(serde::Deserialize)]
+pub struct EmailForm {
+    token: String,
+    email: String,
+}
+
+/// The new address only replaces the current one once its owner follows the link
+/// mailed to it.
+#[tracing::instrument(
+    name = "Change email from the preference center",
+    skip(form, link_signer, pool, email_service, base_url)
+)]
+pub async fn change_email(
+    form: web::Form<EmailForm>,
+    link_signer: web::Data<LinkSigner>,
+    pool: web::Data<PgPool>,
+    email_service: web::Data<EmailService>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, PreferenceFormError> {
+    let email = request_new_email(&form, &link_signer, &pool, &email_service, &base_url.0)
+        .await
+        .map_err(|e| e.on_form(&form.token))?;
+    FlashMessage::info(format!(
+        "We sent a link to {}: your address changes once you follow it.",
+        email.as_ref()
+    ))
+    .send();
+    Ok(see_preferences(&form.token))
+}
+
+async fn request_new_email(
+    form: &EmailForm,
+    link_signer: &LinkSigner,
+    pool: &PgPool,
+    email_service: &EmailService,
+    base_url: &str,
+) -> Result<SubscriberEmail, PreferencesError> {
+    let subscriber_id = subscriber_id(&form.token, link_signer)?;
+    let email =
+        SubscriberEmail::parse(form.email.clone()).map_err(PreferencesError::InvalidEmail)?;
+    get_preferences(subscriber_id, pool)
+        .await?
+        .ok_or(PreferencesError::NotSubscribed)?;
+    if is_email_taken(pool, email.as_ref(), subscriber_id).await? {
+        return Err(PreferencesError::EmailTaken(email.as_ref().to_owned()));
+    }
+    if is_erased(email.as_ref(), pool).await? {
+        return Err(PreferencesError::EmailErased(email.as_ref().to_owned()));
+    }
+
+    let token = request_email_change(subscriber_id, &email, pool).await?;
+    send_email_change_confirmation(email_service, &email, base_url, &token)
+        .await
+        .context("Failed to send an email change confirmation.")?;
+    Ok(email)
+}
+
+/// Mail the link that confirms an email change to the new address.
+#[tracing::instrument(
+    name = "Send an email change confirmation",
+    skip(email_service, new_email, token)
+)]
+pub async fn send_email_change_confirmation(
+    email_service: &EmailService,
+    new_email: &SubscriberEmail,
+    base_url: &str,
+    token: &str,
+) -> Result<(), EmailError> {
+    let confirmation_link = format!("{}/preferences/email/confirm?token={}", base_url, token);
+    let html_body = format!(
+        "Click <a href=\"{}\">here</a> to receive our newsletter at this address.",
+        confirmation_link
+    );
+    let plain_body = format!(
+        "Visit {} to receive our newsletter at this address.",
+        confirmation_link
+    );
+    email_service
+        .send_email(
+            EmailKind::EmailChange,
+            new_email,
+            "Confirm your new address",
+            &html_body,
+            &plain_body,
+        )
+        .await
+}
+
+#[derive(serde::Deserialize)]
+pub struct FrequencyForm {
+    token: String,
+    frequency: Frequency,
+}
+
+#[tracing::instrument(
+    name = "Change frequency from the preference center",
+    skip(form, link_signer, pool)
+)]
+pub async fn change_frequency(
+    form: web::Form<FrequencyForm>,
+    link_signer: web::Data<LinkSigner>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, PreferenceFormError> {
+    save_frequency(&form, &link_signer, &pool)
+        .await
+        .map_err(|e| e.on_form(&form.token))?;
+    FlashMessage::info("Your preferences have been saved.").send();
+    Ok(see_preferences(&form.token))
+}
+
+async fn save_frequency(
+    form: &FrequencyForm,
+    link_signer: &LinkSigner,
+    pool: &PgPool,
+) -> Result<(), PreferencesError> {
+    let subscriber_id = subscriber_id(&form.token, link_signer)?;
+    if !set_frequency(subscriber_id, form.frequency, pool).await? {
+        return Err(PreferencesError::NotSubscribed);
+    }
+    Ok(())
+}
+
+#[derive(serde::Deserialize)]
+pub struct PauseForm {
+    token: String,
+    /// `0` resumes delivery.
+    weeks: i64,
+}
+
+#[tracing::instrument(
+    name = "Pause delivery from the preference center",
+    skip(form, link_signer, pool)
+)]
+pub async fn pause_delivery(
+    form: web::Form<PauseForm>,
+    link_signer: web::Data<LinkSigner>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, PreferenceFormError> {
+    let paused_until = save_pause(&form, &link_signer, &pool)
+        .await
+        .map_err(|e| e.on_form(&form.token))?;
+    let message = match paused_until {
+        Some(paused_until) => format!(
+            "Delivery is paused until {}.",
+            paused_until.format("%B %-d, %Y")
+        ),
+        None => "Delivery has resumed.".to_string(),
+    };
+    FlashMessage::info(message).send();
+    Ok(see_preferences(&form.token))
+}
+
+/// Returns the end of the pause, or `None` if delivery resumed.
+async fn save_pause(
+    form: &PauseForm,
+    link_signer: &LinkSigner,
+    pool: &PgPool,
+) -> Result<Option<DateTime<Utc>>, PreferencesError> {
+    let subscriber_id = subscriber_id(&form.token, link_signer)?;
+    if !(0..=MAX_PAUSE_WEEKS).contains(&form.weeks) {
+        return Err(PreferencesError::InvalidPause(MAX_PAUSE_WEEKS));
+    }
+    let paused_until = (form.weeks > 0).then(|| Utc::now() + Duration::weeks(form.weeks));
+    if !set_paused_until(subscriber_id, paused_until, pool).await? {
+        return Err(PreferencesError::NotSubscribed);
+    }
+    Ok(paused_until)
+}
+
+#[tracing::instrument(
+    name = "Unsubscribe from the preference center",
+    skip(form, link_signer, pool, request)
+)]
+pub async fn unsubscribe(
+    form: web::Form<PreferenceParams>,
+    link_signer: web::Data<LinkSigner>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, PreferencesError> {
+    let subscriber_id = subscriber_id(&form.token, &link_signer)?;
+    // The preference center is reached through the link in every issue.
+    let evidence = ConsentEvidence::from_request(ConsentSource::Email, None, &request);
+    if !unsubscribe_subscriber(subscriber_id, &evidence, &pool).await? {
+        return Err(PreferencesError::NotSubscribed);
+    }
+    Ok(HttpResponse::Ok().content_type(ContentType::html()).body(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Your preferences</title>
+</head>
+<body>
+    <p>You have been unsubscribed.</p>
+</body>
+</html>"#,
+    ))
+}
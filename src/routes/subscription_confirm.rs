@@ -1,12 +1,19 @@
+use crate::consent::{record_consent, ConsentAction, ConsentEvidence, ConsentSource};
 use crate::libs::error_chain_fmt;
-use actix_web::{http::StatusCode, web, HttpResponse, ResponseError};
+use crate::metrics::Metrics;
+use crate::problem::{problem_response, ProblemError};
+use crate::rate_limit::{LimitedRoute, RateLimited, RateLimiter};
+use crate::webhooks::{enqueue_event, EventType, SubscriberData};
+use actix_web::{http::StatusCode, web, HttpRequest, HttpResponse, ResponseError};
 use anyhow::Context;
 use serde::Deserialize;
-use sqlx::PgPool;
+use sqlx::{PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
-#[derive(Deserialize)]
+#[derive(Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct Params {
+    /// Sent in the confirmation email.
     token: String,
 }
 
@@ -15,6 +22,8 @@ pub enum ConfirmError {
     #[error("invalid token provided")]
     InvalidTokenError,
     #[error(transparent)]
+    RateLimited(#[from] RateLimited),
+    #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
 
@@ -28,16 +37,54 @@ impl ResponseError for ConfirmError {
     fn status_code(&self) -> StatusCode {
         match self {
             ConfirmError::InvalidTokenError => StatusCode::UNAUTHORIZED,
+            ConfirmError::RateLimited(e) => e.status_code(),
             ConfirmError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            ConfirmError::RateLimited(e) => e.error_response(),
+            _ => problem_response(self),
+        }
+    }
+}
+
+impl ProblemError for ConfirmError {
+    fn code(&self) -> &'static str {
+        match self {
+            ConfirmError::InvalidTokenError => "invalid_token",
+            ConfirmError::RateLimited(e) => e.code(),
+            ConfirmError::UnexpectedError(_) => "internal_error",
+        }
+    }
 }
 
-#[tracing::instrument(name = "Confirm a pending subscriber", skip(params, pool))]
+#[utoipa::path(
+    get,
+    path = "/subscriptions/confirm",
+    tag = "subscriptions",
+    params(Params),
+    responses(
+        (status = 200, description = "The subscription is confirmed."),
+        (status = 401, description = "Unknown token.", body = Problem, content_type = "application/problem+json"),
+        (status = 429, description = "Too many requests, see the `Retry-After` header.", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+#[tracing::instrument(
+    name = "Confirm a pending subscriber",
+    skip(params, pool, rate_limiter, metrics, request)
+)]
 pub async fn confirm(
     params: web::Query<Params>,
     pool: web::Data<PgPool>,
+    rate_limiter: web::Data<RateLimiter>,
+    metrics: web::Data<Metrics>,
+    request: HttpRequest,
 ) -> Result<HttpResponse, ConfirmError> {
+    rate_limiter
+        .check(LimitedRoute::Confirm, &request, None)
+        .await?;
     let id = get_subscriber_id_from_token(&pool, &params.token)
         .await
         .context("Failed to retrieve subscriber id from token")?;
@@ -46,28 +93,79 @@ pub async fn confirm(
         // Non-existing token!
         None => Err(ConfirmError::InvalidTokenError),
         Some(subscriber_id) => {
-            confirm_subscriber(&pool, subscriber_id)
+            let mut transaction = pool
+                .begin()
+                .await
+                .context("Failed to acquire a Postgres connection from the pool")?;
+            let evidence = ConsentEvidence::from_request(ConsentSource::Email, None, &request);
+            let newly_confirmed = confirm_subscriber(&mut transaction, subscriber_id, &evidence)
                 .await
                 .context("Failed to confirm subscriber")?;
+            // Following the link again must not notify the endpoints twice.
+            let was_pending = newly_confirmed.is_some();
+            if let Some(subscriber) = newly_confirmed {
+                enqueue_event(
+                    &mut transaction,
+                    EventType::SubscriberConfirmed,
+                    SubscriberData {
+                        id: subscriber_id,
+                        email: &subscriber.email,
+                        name: &subscriber.name,
+                    },
+                )
+                .await?;
+            }
+            transaction
+                .commit()
+                .await
+                .context("Failed to commit SQL transaction to confirm a subscriber.")?;
+            if was_pending {
+                metrics.confirmation();
+            }
 
             Ok(HttpResponse::Ok().finish())
         }
     }
 }
 
-#[tracing::instrument(name = "Mark subscriber as confirmed", skip(subscriber_id, pool))]
-pub async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
-    sqlx::query!(
-        r#"UPDATE subscriptions SET status = 'confirmed' WHERE id = $1"#,
+pub struct ConfirmedSubscriber {
+    pub email: String,
+    pub name: String,
+}
+
+/// Returns the subscriber if they were pending confirmation, in which case the
+/// confirmation is added to their consent log.
+#[tracing::instrument(
+    name = "Mark subscriber as confirmed",
+    skip(subscriber_id, transaction, evidence)
+)]
+pub async fn confirm_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    evidence: &ConsentEvidence,
+) -> Result<Option<ConfirmedSubscriber>, anyhow::Error> {
+    let confirmed = sqlx::query_as!(
+        ConfirmedSubscriber,
+        r#"
+    UPDATE subscriptions SET status = 'confirmed', last_engaged_at = now()
+    WHERE id = $1 AND status = 'pending_confirmation'
+    RETURNING email, name
+    "#,
         subscriber_id,
     )
-    .execute(pool)
+    .fetch_optional(&mut *transaction)
     .await
-    .map_err(|e| {
-        tracing::error!("Failed to execute query: {:?}", e);
-        e
-    })?;
-    Ok(())
+    .context("Failed to mark the subscriber as confirmed.")?;
+    if confirmed.is_some() {
+        record_consent(
+            &mut *transaction,
+            subscriber_id,
+            ConsentAction::Confirmation,
+            evidence,
+        )
+        .await?;
+    }
+    Ok(confirmed)
 }
 
 #[tracing::instrument(name = "Get subscriber_id from token", skip(subscription_token, pool))]
@@ -1,9 +1,25 @@
 use crate::libs::error_chain_fmt;
 use crate::{
-    domain::new_subscriber::NewSubscriber, services::email::EmailService,
+    bot_protection::{BotProtection, BotSignal},
+    consent::{
+        record_consent, ConsentAction, ConsentEvidence, ConsentSource, CONSENT_TEXT_VERSION,
+    },
+    domain::{
+        new_subscriber::NewSubscriber, subscriber_email::SubscriberEmail,
+        subscriber_name::SubscriberName,
+    },
+    metrics::Metrics,
+    problem::{problem_response, ProblemError},
+    rate_limit::{LimitedRoute, RateLimited, RateLimiter},
+    services::{
+        email::{EmailError, EmailKind, EmailService},
+        mx_resolver::DeliverabilityChecker,
+    },
     startup::ApplicationBaseUrl,
+    subscriber_data::is_erased,
+    webhooks::{enqueue_event, EventType, SubscriberData},
 };
-use actix_web::{http::StatusCode, web, HttpResponse, ResponseError};
+use actix_web::{http::StatusCode, web, HttpRequest, HttpResponse, ResponseError};
 use anyhow::Context;
 use chrono::Utc;
 use rand::distributions::Alphanumeric;
@@ -14,7 +30,19 @@ use uuid::Uuid;
 #[derive(thiserror::Error)]
 pub enum SubscribeError {
     #[error("{0}")]
-    ValidationError(String),
+    InvalidName(String),
+
+    #[error("{0}")]
+    InvalidEmail(String),
+
+    #[error("{0}")]
+    UndeliverableEmail(String),
+
+    #[error("{0} asked for their data to be erased.")]
+    Erased(String),
+
+    #[error(transparent)]
+    RateLimited(#[from] RateLimited),
 
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
@@ -29,21 +57,81 @@ impl std::fmt::Debug for SubscribeError {
 impl ResponseError for SubscribeError {
     fn status_code(&self) -> StatusCode {
         match self {
-            SubscribeError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            SubscribeError::InvalidName(_)
+            | SubscribeError::InvalidEmail(_)
+            | SubscribeError::UndeliverableEmail(_) => StatusCode::BAD_REQUEST,
+            SubscribeError::Erased(_) => StatusCode::CONFLICT,
+            SubscribeError::RateLimited(e) => e.status_code(),
             SubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            SubscribeError::RateLimited(e) => e.error_response(),
+            _ => problem_response(self),
+        }
+    }
 }
 
-#[derive(serde::Deserialize)]
+impl ProblemError for SubscribeError {
+    fn code(&self) -> &'static str {
+        match self {
+            SubscribeError::InvalidName(_) => "invalid_name",
+            SubscribeError::InvalidEmail(_) => "invalid_email",
+            SubscribeError::UndeliverableEmail(_) => "undeliverable_email",
+            SubscribeError::Erased(_) => "conflict",
+            SubscribeError::RateLimited(e) => e.code(),
+            SubscribeError::UnexpectedError(_) => "internal_error",
+        }
+    }
+
+    /// The subscription form is on the home page.
+    fn redirect_to(&self) -> Option<String> {
+        Some("/".into())
+    }
+}
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
 pub struct FormData {
     pub email: String,
     pub name: String,
+    /// Honeypot, see `bot_protection::HONEYPOT_FIELD`.
+    #[serde(default)]
+    pub website: String,
+    /// Embedded in the form of the home page.
+    pub form_token: Option<String>,
+    /// Only required when the proof-of-work challenge is enabled.
+    pub proof_of_work: Option<String>,
 }
 
+/// Submissions that look automated get the same response, but are not stored.
+#[utoipa::path(
+    post,
+    path = "/subscriptions",
+    tag = "subscriptions",
+    request_body(content = FormData, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "A confirmation email was sent."),
+        (status = 400, description = "Invalid name or email address.", body = Problem, content_type = "application/problem+json"),
+        (status = 429, description = "Too many requests, see the `Retry-After` header.", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+// Handlers take one argument per extractor.
+#[allow(clippy::too_many_arguments)]
 #[tracing::instrument(
     name = "Adding a new subscriber",
-    skip(form, pool, email_service, base_url),
+    skip(
+        form,
+        pool,
+        email_service,
+        base_url,
+        rate_limiter,
+        bot_protection,
+        deliverability,
+        metrics,
+        request
+    ),
     fields(
         subscriber_email = %form.email,
         subscriber_name = %form.name
@@ -54,9 +142,76 @@ pub async fn subscribe(
     pool: web::Data<PgPool>,
     email_service: web::Data<EmailService>,
     base_url: web::Data<ApplicationBaseUrl>,
+    rate_limiter: web::Data<RateLimiter>,
+    bot_protection: web::Data<BotProtection>,
+    deliverability: web::Data<DeliverabilityChecker>,
+    metrics: web::Data<Metrics>,
+    request: HttpRequest,
 ) -> Result<HttpResponse, SubscribeError> {
-    let new_subscriber = form.0.try_into().map_err(SubscribeError::ValidationError)?;
+    rate_limiter
+        .check(LimitedRoute::Subscribe, &request, Some(&form.email))
+        .await?;
+    let mut bot_check = bot_protection.inspect(
+        &form.website,
+        form.form_token.as_deref(),
+        form.proof_of_work.as_deref(),
+        Utc::now().timestamp(),
+    );
+    let new_subscriber = NewSubscriber {
+        name: SubscriberName::parse(form.0.name).map_err(SubscribeError::InvalidName)?,
+        email: SubscriberEmail::parse(form.0.email).map_err(SubscribeError::InvalidEmail)?,
+    };
+    // Recorded once the form is valid, so that fixing a typo does not burn the token.
+    if let (Ok(()), Some(form_token)) = (&bot_check, &form.0.form_token) {
+        if !rate_limiter
+            .use_once("form_token", form_token, bot_protection.max_form_age())
+            .await
+        {
+            bot_check = Err(BotSignal::ReusedFormToken);
+        }
+    }
+    // Bots get the same response as everybody else, so that they do not learn to adapt.
+    if let Err(signal) = bot_check {
+        tracing::warn!(%signal, "Suspected bot submission, skipping the subscription.");
+        return Ok(HttpResponse::Ok().finish());
+    }
+    deliverability
+        .check(&new_subscriber.email)
+        .await
+        .map_err(SubscribeError::UndeliverableEmail)?;
 
+    let evidence =
+        ConsentEvidence::from_request(ConsentSource::Form, Some(CONSENT_TEXT_VERSION), &request);
+    match register_subscriber(new_subscriber, evidence, &pool, email_service, &base_url).await {
+        Ok(_) => metrics.subscription("form"),
+        // Same response as a signup, so that the form does not tell who asked to be erased.
+        Err(SubscribeError::Erased(_)) => {
+            tracing::info!("The address was erased, skipping the subscription.")
+        }
+        Err(e) => return Err(e),
+    }
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Store a subscriber pending confirmation, along with the evidence of their consent,
+/// and send them the confirmation email. Addresses whose owner asked for their data to be
+/// erased are refused: adding them back would undo the request.
+#[tracing::instrument(
+    name = "Register a new subscriber",
+    skip(new_subscriber, evidence, pool, email_service, base_url)
+)]
+pub async fn register_subscriber(
+    new_subscriber: NewSubscriber,
+    evidence: ConsentEvidence,
+    pool: &PgPool,
+    email_service: web::Data<EmailService>,
+    base_url: &ApplicationBaseUrl,
+) -> Result<Uuid, SubscribeError> {
+    if is_erased(new_subscriber.email.as_ref(), pool).await? {
+        return Err(SubscribeError::Erased(
+            new_subscriber.email.as_ref().to_owned(),
+        ));
+    }
     let mut transaction = pool
         .begin()
         .await
@@ -64,7 +219,25 @@ pub async fn subscribe(
 
     let subscriber_id = insert_subscriber(&mut transaction, &new_subscriber)
         .await
-        .context("Failed to insert new subscriber in the database.")?;
+        .context("Failed to insert new subscriber in the database.")?
+        .ok_or_else(|| anyhow::anyhow!("{} is already subscribed.", new_subscriber.email))?;
+    record_consent(
+        &mut transaction,
+        subscriber_id,
+        ConsentAction::Signup,
+        &evidence,
+    )
+    .await?;
+    enqueue_event(
+        &mut transaction,
+        EventType::SubscriberCreated,
+        SubscriberData {
+            id: subscriber_id,
+            email: new_subscriber.email.as_ref(),
+            name: new_subscriber.name.as_ref(),
+        },
+    )
+    .await?;
 
     let token = generate_subscription_token();
 
@@ -81,9 +254,11 @@ pub async fn subscribe(
         .await
         .context("Failed to send a confirmation email.")?;
 
-    Ok(HttpResponse::Ok().finish())
+    Ok(subscriber_id)
 }
 
+/// A subscriber who unsubscribed signs up again under their former id, so that their
+/// consent log carries on. Returns `None` if the address is already subscribed.
 #[tracing::instrument(
     name = "Saving new subscriber details in the database",
     skip(new_subscriber, transaction)
@@ -91,26 +266,33 @@ pub async fn subscribe(
 pub async fn insert_subscriber(
     transaction: &mut Transaction<'_, Postgres>,
     new_subscriber: &NewSubscriber,
-) -> Result<Uuid, sqlx::Error> {
-    let subscriber_id = Uuid::new_v4();
-    sqlx::query!(
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let subscriber = sqlx::query!(
         r#"
     INSERT INTO subscriptions (id, email, name, subscribed_at, status)
     VALUES ($1, $2, $3, $4, 'pending_confirmation')
+    ON CONFLICT (lower(email)) DO UPDATE
+    SET email = EXCLUDED.email,
+        name = EXCLUDED.name,
+        subscribed_at = EXCLUDED.subscribed_at,
+        status = EXCLUDED.status,
+        paused_until = NULL
+    WHERE subscriptions.status = 'unsubscribed'
+    RETURNING id
     "#,
-        &subscriber_id,
+        Uuid::new_v4(),
         new_subscriber.email.as_ref(),
         new_subscriber.name.as_ref(),
         Utc::now(),
     )
-    .execute(transaction)
+    .fetch_optional(transaction)
     .await
     .map_err(|e| {
         tracing::error!("Failed to execute query: {:?}", e);
         e
     })?;
 
-    Ok(subscriber_id)
+    Ok(subscriber.map(|s| s.id))
 }
 
 #[tracing::instrument(
@@ -145,7 +327,7 @@ pub async fn send_confirmation_email(
     new_subscriber: NewSubscriber,
     base_url: &str,
     token: &str,
-) -> Result<(), reqwest::Error> {
+) -> Result<(), EmailError> {
     let confirmation_link = format!("{}/subscriptions/confirm?token={}", base_url, token);
     let html_body = format!(
         "Welcome to our newsletter!<br />\
@@ -158,12 +340,18 @@ pub async fn send_confirmation_email(
     );
 
     email_service
-        .send_email(&new_subscriber.email, "Welcome!", &html_body, &plain_body)
+        .send_email(
+            EmailKind::Confirmation,
+            &new_subscriber.email,
+            "Welcome!",
+            &html_body,
+            &plain_body,
+        )
         .await
 }
 
 /// Generate a random 25-characters-long case-sensitive subscription token.
-fn generate_subscription_token() -> String {
+pub fn generate_subscription_token() -> String {
     let mut rng = thread_rng();
     std::iter::repeat_with(|| rng.sample(Alphanumeric))
         .map(char::from)
@@ -0,0 +1,101 @@
+use crate::configuration::{CookieSettings, SecurityHeadersSettings};
+use actix_web::{
+    body::MessageBody,
+    cookie::{Cookie, SameSite},
+    dev::{ServiceRequest, ServiceResponse},
+    error::InternalError,
+    http::header::{
+        HeaderMap, HeaderName, HeaderValue, CONTENT_SECURITY_POLICY, REFERRER_POLICY, SET_COOKIE,
+        STRICT_TRANSPORT_SECURITY, X_CONTENT_TYPE_OPTIONS, X_FRAME_OPTIONS,
+    },
+    web,
+};
+use actix_web_lab::middleware::Next;
+
+/// What `harden_responses` applies to every response.
+#[derive(Clone)]
+pub struct ResponseHardening {
+    pub headers: SecurityHeadersSettings,
+    /// The flash messages store pins the attributes of its cookie,
+    /// so they are rewritten on the way out instead.
+    pub flash_cookie: CookieSettings,
+}
+
+pub async fn harden_responses(
+    hardening: web::Data<ResponseHardening>,
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    match next.call(req).await {
+        Ok(mut response) => {
+            insert_security_headers(response.headers_mut(), &hardening.headers);
+            harden_flash_cookie(response.headers_mut(), &hardening.flash_cookie);
+            Ok(response)
+        }
+        // Error responses are only rendered further up, so we render them here to decorate them.
+        Err(e) => {
+            let mut response = e.error_response();
+            insert_security_headers(response.headers_mut(), &hardening.headers);
+            harden_flash_cookie(response.headers_mut(), &hardening.flash_cookie);
+            Err(InternalError::from_response(e, response).into())
+        }
+    }
+}
+
+fn insert_security_headers(headers: &mut HeaderMap, settings: &SecurityHeadersSettings) {
+    let mut insert = |name: HeaderName, value: &str| {
+        if headers.contains_key(&name) {
+            return;
+        }
+        match HeaderValue::from_str(value) {
+            Ok(value) => {
+                headers.insert(name, value);
+            }
+            Err(e) => tracing::error!(error = %e, header = %name, "Invalid security header value."),
+        }
+    };
+    insert(X_CONTENT_TYPE_OPTIONS, "nosniff");
+    if let Some(csp) = &settings.content_security_policy {
+        insert(CONTENT_SECURITY_POLICY, csp);
+    }
+    if let Some(frame_options) = &settings.frame_options {
+        insert(X_FRAME_OPTIONS, frame_options);
+    }
+    if let Some(referrer_policy) = &settings.referrer_policy {
+        insert(REFERRER_POLICY, referrer_policy);
+    }
+    if let Some(max_age) = settings.hsts_max_age_in_secs {
+        insert(
+            STRICT_TRANSPORT_SECURITY,
+            &format!("max-age={}; includeSubDomains", max_age),
+        );
+    }
+}
+
+fn harden_flash_cookie(headers: &mut HeaderMap, settings: &CookieSettings) {
+    let cookies: Vec<HeaderValue> = headers.get_all(SET_COOKIE).cloned().collect();
+    if cookies.is_empty() {
+        return;
+    }
+    headers.remove(SET_COOKIE);
+    for value in cookies {
+        let hardened = value
+            .to_str()
+            .ok()
+            .and_then(|raw| Cookie::parse(raw).ok())
+            .filter(|cookie| cookie.name() == settings.name)
+            .map(|mut cookie| {
+                cookie.set_same_site(SameSite::from(settings.same_site));
+                cookie.set_secure(settings.secure);
+                // Leave removal cookies (max-age=0) alone.
+                if cookie.max_age() != Some(actix_web::cookie::time::Duration::ZERO) {
+                    if let Some(max_age) = settings.max_age() {
+                        cookie.set_max_age(max_age);
+                    }
+                }
+                cookie.to_string()
+            })
+            .and_then(|cookie| HeaderValue::from_str(&cookie).ok());
+        headers.append(SET_COOKIE, hardened.unwrap_or(value));
+    }
+}
@@ -0,0 +1,407 @@
+use super::{parse_tag, validate_attribute_key};
+use crate::preferences::Frequency;
+use chrono::{DateTime, NaiveDate, Utc};
+use sqlx::{Postgres, QueryBuilder};
+use std::fmt;
+
+const MAX_FILTER_LENGTH: usize = 1000;
+/// Deepest nesting of parentheses and `not`s.
+const MAX_DEPTH: usize = 20;
+const MAX_ENGAGEMENT_DAYS: i32 = 3650;
+
+/// The filter of a segment, e.g. `tag:vip and (list:weekly_digest or not engaged_within:90d)`.
+///
+/// Conditions are joined with `and`, `or` and `not`, `and` binding tighter than `or`.
+/// Values with spaces or parentheses are quoted: `attr.city:"New York"`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Filter {
+    /// `tag:<tag>`
+    Tag(String),
+    /// `attr.<key>:<value>`: the custom attribute is set to this value.
+    Attribute {
+        key: String,
+        value: String,
+    },
+    /// `subscribed_after:<YYYY-MM-DD>`, inclusive.
+    SubscribedAfter(NaiveDate),
+    /// `subscribed_before:<YYYY-MM-DD>`, exclusive.
+    SubscribedBefore(NaiveDate),
+    /// `engaged_within:<days>d`: followed one of our links in the last days.
+    EngagedWithin(i32),
+    /// `list:every_issue` or `list:weekly_digest`.
+    List(Frequency),
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+    Not(Box<Filter>),
+}
+
+impl Filter {
+    pub fn parse(input: &str) -> Result<Filter, String> {
+        if input.len() > MAX_FILTER_LENGTH {
+            return Err(format!(
+                "Filters are limited to {} characters.",
+                MAX_FILTER_LENGTH
+            ));
+        }
+        let mut parser = Parser {
+            tokens: tokenize(input)?,
+            position: 0,
+        };
+        if parser.tokens.is_empty() {
+            return Err("A filter needs at least one condition.".into());
+        }
+        let filter = parser.parse_or(0)?;
+        match parser.tokens.get(parser.position) {
+            None => Ok(filter),
+            Some(Token::Close) => Err("Unexpected `)`.".into()),
+            Some(_) => Err("Conditions must be joined with `and` or `or`.".into()),
+        }
+    }
+
+    /// Append the filter as a boolean SQL expression over the `subscriptions` row aliased
+    /// `s`. Values are bound, and the expression is never null, so `not` keeps its meaning
+    /// when a subscriber lacks an attribute.
+    pub fn push_sql(&self, query: &mut QueryBuilder<'_, Postgres>) {
+        match self {
+            Filter::Tag(tag) => {
+                query
+                    .push("EXISTS (SELECT 1 FROM subscriber_tags t WHERE t.subscriber_id = s.id AND t.tag = ")
+                    .push_bind(tag.clone())
+                    .push(")");
+            }
+            Filter::Attribute { key, value } => {
+                query
+                    .push("COALESCE(s.attributes ->> ")
+                    .push_bind(key.clone())
+                    .push(" = ")
+                    .push_bind(value.clone())
+                    .push(", false)");
+            }
+            Filter::SubscribedAfter(date) => {
+                query.push("s.subscribed_at >= ").push_bind(midnight(date));
+            }
+            Filter::SubscribedBefore(date) => {
+                query.push("s.subscribed_at < ").push_bind(midnight(date));
+            }
+            Filter::EngagedWithin(days) => {
+                query
+                    .push("COALESCE(s.last_engaged_at >= now() - make_interval(days => ")
+                    .push_bind(*days)
+                    .push("), false)");
+            }
+            Filter::List(frequency) => {
+                query.push("s.frequency = ").push_bind(frequency.as_str());
+            }
+            Filter::And(left, right) => {
+                query.push("(");
+                left.push_sql(query);
+                query.push(" AND ");
+                right.push_sql(query);
+                query.push(")");
+            }
+            Filter::Or(left, right) => {
+                query.push("(");
+                left.push_sql(query);
+                query.push(" OR ");
+                right.push_sql(query);
+                query.push(")");
+            }
+            Filter::Not(filter) => {
+                query.push("NOT ");
+                filter.push_sql(query);
+            }
+        }
+    }
+}
+
+/// The canonical form of the filter, which parses back to the same filter.
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Filter::Tag(tag) => write!(f, "tag:{}", tag),
+            Filter::Attribute { key, value } => write!(f, "attr.{}:{}", key, quote(value)),
+            Filter::SubscribedAfter(date) => write!(f, "subscribed_after:{}", date),
+            Filter::SubscribedBefore(date) => write!(f, "subscribed_before:{}", date),
+            Filter::EngagedWithin(days) => write!(f, "engaged_within:{}d", days),
+            Filter::List(frequency) => write!(f, "list:{}", frequency.as_str()),
+            Filter::And(left, right) => write!(f, "({} and {})", left, right),
+            Filter::Or(left, right) => write!(f, "({} or {})", left, right),
+            Filter::Not(filter) => write!(f, "not {}", filter),
+        }
+    }
+}
+
+fn midnight(date: &NaiveDate) -> DateTime<Utc> {
+    DateTime::<Utc>::from_utc(date.and_hms_opt(0, 0, 0).unwrap(), Utc)
+}
+
+fn quote(value: &str) -> String {
+    if value.is_empty() || value.contains(|c: char| c.is_whitespace() || c == '(' || c == ')') {
+        format!("\"{}\"", value)
+    } else {
+        value.to_string()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Token {
+    Open,
+    Close,
+    And,
+    Or,
+    Not,
+    Condition { name: String, value: String },
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::Open);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::Close);
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == '(' || c == ')' {
+                        break;
+                    }
+                    chars.next();
+                    if c != '"' {
+                        word.push(c);
+                        continue;
+                    }
+                    loop {
+                        match chars.next() {
+                            Some('"') => break,
+                            Some(c) => word.push(c),
+                            None => return Err("A quote is not closed.".into()),
+                        }
+                    }
+                }
+                tokens.push(match word.split_once(':') {
+                    Some((name, value)) => Token::Condition {
+                        name: name.to_string(),
+                        value: value.to_string(),
+                    },
+                    None => match word.to_lowercase().as_str() {
+                        "and" => Token::And,
+                        "or" => Token::Or,
+                        "not" => Token::Not,
+                        _ => return Err(format!("`{}` is not a condition.", word)),
+                    },
+                });
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<&Token> {
+        let token = self.tokens.get(self.position);
+        self.position += 1;
+        token
+    }
+
+    fn next_is(&mut self, expected: &Token) -> bool {
+        if self.tokens.get(self.position) == Some(expected) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_or(&mut self, depth: usize) -> Result<Filter, String> {
+        let mut filter = self.parse_and(depth)?;
+        while self.next_is(&Token::Or) {
+            let right = self.parse_and(depth)?;
+            filter = Filter::Or(Box::new(filter), Box::new(right));
+        }
+        Ok(filter)
+    }
+
+    fn parse_and(&mut self, depth: usize) -> Result<Filter, String> {
+        let mut filter = self.parse_unary(depth)?;
+        while self.next_is(&Token::And) {
+            let right = self.parse_unary(depth)?;
+            filter = Filter::And(Box::new(filter), Box::new(right));
+        }
+        Ok(filter)
+    }
+
+    fn parse_unary(&mut self, depth: usize) -> Result<Filter, String> {
+        if depth > MAX_DEPTH {
+            return Err(format!(
+                "Filters are limited to {} levels of nesting.",
+                MAX_DEPTH
+            ));
+        }
+        match self.next() {
+            Some(Token::Not) => Ok(Filter::Not(Box::new(self.parse_unary(depth + 1)?))),
+            Some(Token::Open) => {
+                let filter = self.parse_or(depth + 1)?;
+                if !self.next_is(&Token::Close) {
+                    return Err("A `(` is not closed.".into());
+                }
+                Ok(filter)
+            }
+            Some(Token::Condition { name, value }) => condition(name, value),
+            _ => Err("A condition is missing.".into()),
+        }
+    }
+}
+
+fn condition(name: &str, value: &str) -> Result<Filter, String> {
+    let date = || {
+        NaiveDate::parse_from_str(value, "%Y-%m-%d")
+            .map_err(|_| format!("`{}` is not a date: use YYYY-MM-DD.", value))
+    };
+    if let Some(key) = name.strip_prefix("attr.") {
+        validate_attribute_key(key)?;
+        return Ok(Filter::Attribute {
+            key: key.to_string(),
+            value: value.to_string(),
+        });
+    }
+    match name {
+        "tag" => Ok(Filter::Tag(parse_tag(value)?)),
+        "subscribed_after" => Ok(Filter::SubscribedAfter(date()?)),
+        "subscribed_before" => Ok(Filter::SubscribedBefore(date()?)),
+        "engaged_within" => value
+            .strip_suffix('d')
+            .and_then(|days| days.parse().ok())
+            .filter(|days| (1..=MAX_ENGAGEMENT_DAYS).contains(days))
+            .map(Filter::EngagedWithin)
+            .ok_or_else(|| {
+                format!(
+                    "`{}` is not a number of days between 1d and {}d.",
+                    value, MAX_ENGAGEMENT_DAYS
+                )
+            }),
+        "list" => Frequency::ALL
+            .into_iter()
+            .find(|frequency| frequency.as_str() == value)
+            .map(Filter::List)
+            .ok_or_else(|| {
+                format!(
+                    "`{}` is not a list: use `every_issue` or `weekly_digest`.",
+                    value
+                )
+            }),
+        _ => Err(format!("`{}` is not a known condition.", name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Filter;
+    use crate::preferences::Frequency;
+    use chrono::NaiveDate;
+    use claims::assert_err;
+    use sqlx::{Postgres, QueryBuilder};
+
+    fn tag(tag: &str) -> Box<Filter> {
+        Box::new(Filter::Tag(tag.into()))
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_eq!(
+            Filter::parse("tag:a or tag:b and not tag:c").unwrap(),
+            Filter::Or(
+                tag("a"),
+                Box::new(Filter::And(tag("b"), Box::new(Filter::Not(tag("c")))))
+            )
+        );
+        assert_eq!(
+            Filter::parse("(tag:a OR tag:b) AND tag:c").unwrap(),
+            Filter::And(Box::new(Filter::Or(tag("a"), tag("b"))), tag("c"))
+        );
+    }
+
+    #[test]
+    fn every_condition_is_parsed() {
+        assert_eq!(
+            Filter::parse(r#"attr.city:"New York""#).unwrap(),
+            Filter::Attribute {
+                key: "city".into(),
+                value: "New York".into()
+            }
+        );
+        assert_eq!(
+            Filter::parse("subscribed_after:2023-06-01").unwrap(),
+            Filter::SubscribedAfter(NaiveDate::from_ymd_opt(2023, 6, 1).unwrap())
+        );
+        assert_eq!(
+            Filter::parse("subscribed_before:2023-07-01").unwrap(),
+            Filter::SubscribedBefore(NaiveDate::from_ymd_opt(2023, 7, 1).unwrap())
+        );
+        assert_eq!(
+            Filter::parse("engaged_within:30d").unwrap(),
+            Filter::EngagedWithin(30)
+        );
+        assert_eq!(
+            Filter::parse("list:weekly_digest").unwrap(),
+            Filter::List(Frequency::WeeklyDigest)
+        );
+    }
+
+    #[test]
+    fn malformed_filters_are_rejected() {
+        for filter in [
+            "",
+            "tag:a tag:b",
+            "tag:a and",
+            "(tag:a",
+            "tag:a)",
+            "vip",
+            "color:red",
+            r#"attr.city:"New York"#,
+            "tag:early adopter",
+            "subscribed_after:yesterday",
+            "engaged_within:30",
+            "engaged_within:0d",
+            "list:monthly",
+            &"not ".repeat(30),
+        ] {
+            assert_err!(Filter::parse(filter), "{} was accepted", filter);
+        }
+    }
+
+    #[test]
+    fn the_canonical_form_parses_back_to_the_same_filter() {
+        let filter = Filter::parse(
+            r#"not tag:vip and (attr.city:"New York" or engaged_within:90d) or list:every_issue"#,
+        )
+        .unwrap();
+        assert_eq!(Filter::parse(&filter.to_string()).unwrap(), filter);
+    }
+
+    #[test]
+    fn values_are_bound_rather_than_inlined() {
+        let filter = Filter::parse("attr.plan:pro or not tag:vip").unwrap();
+        let mut query = QueryBuilder::<Postgres>::new("");
+        filter.push_sql(&mut query);
+        assert_eq!(
+            query.sql(),
+            "(COALESCE(s.attributes ->> $1 = $2, false) OR NOT EXISTS \
+            (SELECT 1 FROM subscriber_tags t WHERE t.subscriber_id = s.id AND t.tag = $3))"
+        );
+    }
+}
@@ -0,0 +1,70 @@
+//! Segments: saved filters over the subscribers, based on their tags, custom attributes,
+//! sign-up date, engagement and list. Issues can be sent to a segment only.
+mod filter;
+mod store;
+
+pub use filter::*;
+pub use store::*;
+
+const MAX_TAG_LENGTH: usize = 50;
+const MAX_ATTRIBUTE_KEY_LENGTH: usize = 50;
+
+/// Tags are lowercased, and made of letters, digits, `-` and `_`.
+pub fn parse_tag(tag: &str) -> Result<String, String> {
+    let tag = tag.trim().to_lowercase();
+    if tag.is_empty()
+        || tag.chars().count() > MAX_TAG_LENGTH
+        || !tag
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(format!(
+            "`{}` is not a valid tag: use up to {} letters, digits, `-` and `_`.",
+            tag, MAX_TAG_LENGTH
+        ));
+    }
+    Ok(tag)
+}
+
+/// Attribute keys are made of lowercase ASCII letters, digits and `_`.
+pub fn validate_attribute_key(key: &str) -> Result<(), String> {
+    if key.is_empty()
+        || key.len() > MAX_ATTRIBUTE_KEY_LENGTH
+        || !key
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+    {
+        return Err(format!(
+            "`{}` is not a valid attribute name: use up to {} lowercase letters, digits and `_`.",
+            key, MAX_ATTRIBUTE_KEY_LENGTH
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_tag, validate_attribute_key};
+    use claims::{assert_err, assert_ok};
+
+    #[test]
+    fn tags_are_lowercased() {
+        assert_eq!(parse_tag(" Early-Adopter ").unwrap(), "early-adopter");
+    }
+
+    #[test]
+    fn tags_with_spaces_or_punctuation_are_rejected() {
+        assert_err!(parse_tag(""));
+        assert_err!(parse_tag("early adopter"));
+        assert_err!(parse_tag("vip:gold"));
+        assert_err!(parse_tag(&"a".repeat(51)));
+    }
+
+    #[test]
+    fn attribute_keys_are_lowercase_identifiers() {
+        assert_ok!(validate_attribute_key("plan_2023"));
+        assert_err!(validate_attribute_key("Plan"));
+        assert_err!(validate_attribute_key("plan.name"));
+        assert_err!(validate_attribute_key(""));
+    }
+}
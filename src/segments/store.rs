@@ -0,0 +1,77 @@
+use super::Filter;
+use anyhow::Context;
+use sqlx::{PgPool, Postgres, QueryBuilder, Row};
+use uuid::Uuid;
+
+/// `SELECT <columns> FROM subscriptions s` restricted to the confirmed subscribers who
+/// have not paused delivery and, if any, to the segment. More conditions can be pushed
+/// after it with `AND`.
+pub fn audience_query(columns: &str, segment: Option<&Filter>) -> QueryBuilder<'static, Postgres> {
+    let mut query = QueryBuilder::new(format!(
+        "SELECT {} FROM subscriptions s \
+        WHERE s.status = 'confirmed' AND (s.paused_until IS NULL OR s.paused_until <= now())",
+        columns
+    ));
+    if let Some(segment) = segment {
+        query.push(" AND ");
+        segment.push_sql(&mut query);
+    }
+    query
+}
+
+/// How many subscribers an issue sent to the segment would reach, right away or with
+/// their next digest.
+#[tracing::instrument(name = "Count the audience of a segment", skip(pool))]
+pub async fn count_audience(segment: &Filter, pool: &PgPool) -> Result<i64, anyhow::Error> {
+    let row = audience_query("count(*)", Some(segment))
+        .build()
+        .fetch_one(pool)
+        .await
+        .context("Failed to count the audience of a segment.")?;
+    Ok(row.get(0))
+}
+
+#[tracing::instrument(name = "Check segment membership", skip(pool))]
+pub async fn is_in_segment(
+    segment: &Filter,
+    subscriber_id: Uuid,
+    pool: &PgPool,
+) -> Result<bool, anyhow::Error> {
+    let mut query = QueryBuilder::new("SELECT EXISTS (SELECT 1 FROM subscriptions s WHERE s.id = ");
+    query.push_bind(subscriber_id).push(" AND ");
+    segment.push_sql(&mut query);
+    query.push(")");
+    let row = query
+        .build()
+        .fetch_one(pool)
+        .await
+        .context("Failed to check whether a subscriber belongs to a segment.")?;
+    Ok(row.get(0))
+}
+
+#[tracing::instrument(name = "Get the filter of a segment", skip(pool))]
+pub async fn get_segment_filter(
+    segment_id: Uuid,
+    pool: &PgPool,
+) -> Result<Option<Filter>, anyhow::Error> {
+    let row = sqlx::query!(r#"SELECT filter FROM segments WHERE id = $1"#, segment_id)
+        .fetch_optional(pool)
+        .await
+        .context("Failed to retrieve a segment.")?;
+    row.map(|r| Filter::parse(&r.filter).map_err(anyhow::Error::msg))
+        .transpose()
+        .context("A stored segment filter is invalid.")
+}
+
+/// The subscriber followed one of our links.
+#[tracing::instrument(name = "Record subscriber engagement", skip(pool))]
+pub async fn record_engagement(subscriber_id: Uuid, pool: &PgPool) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"UPDATE subscriptions SET last_engaged_at = now() WHERE id = $1"#,
+        subscriber_id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to record the engagement of a subscriber.")?;
+    Ok(())
+}
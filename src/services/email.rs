@@ -1,14 +1,70 @@
-use reqwest::Client;
+use chrono::{DateTime, Utc};
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{Client, StatusCode};
 use secrecy::{ExposeSecret, Secret};
 use serde::Serialize;
+use std::time::Duration;
 
+use crate::configuration::EmailThrottleSettings;
 use crate::domain::subscriber_email::SubscriberEmail;
+use crate::metrics::Metrics;
+use crate::services::throttle::{QuotaExhausted, SendRateStats, SendThrottle};
+use crate::telemetry::trace_context_headers;
+
+/// Attempts per email when the provider keeps answering 429.
+const MAX_ATTEMPTS: u32 = 3;
+/// Wait used when a 429 comes without a usable `Retry-After`.
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(1);
 
 pub struct EmailService {
     client: Client,
     base_url: String,
     sender: SubscriberEmail,
     auth_token: Secret<String>,
+    throttle: SendThrottle,
+    metrics: Metrics,
+}
+
+/// What an email is for, as counted in the metrics.
+#[derive(Clone, Copy, Debug)]
+pub enum EmailKind {
+    Confirmation,
+    Issue,
+    Digest,
+    DataRequest,
+    EmailChange,
+}
+
+impl EmailKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EmailKind::Confirmation => "confirmation",
+            EmailKind::Issue => "issue",
+            EmailKind::Digest => "digest",
+            EmailKind::DataRequest => "data_request",
+            EmailKind::EmailChange => "email_change",
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum EmailError {
+    #[error(transparent)]
+    QuotaExhausted(#[from] QuotaExhausted),
+    #[error("The email provider asked to wait {0:?} before sending more emails.")]
+    RateLimited(Duration),
+    #[error("Failed to send an email.")]
+    Request(#[from] reqwest::Error),
+}
+
+impl EmailError {
+    fn reason(&self) -> &'static str {
+        match self {
+            EmailError::QuotaExhausted(_) => "quota_exhausted",
+            EmailError::RateLimited(_) => "rate_limited",
+            EmailError::Request(_) => "request",
+        }
+    }
 }
 
 #[derive(Serialize)]
@@ -27,6 +83,8 @@ impl EmailService {
         sender: SubscriberEmail,
         auth_token: Secret<String>,
         timeout: std::time::Duration,
+        throttle: EmailThrottleSettings,
+        metrics: Metrics,
     ) -> Self {
         let client = Client::builder().timeout(timeout).build().unwrap();
 
@@ -35,19 +93,31 @@ impl EmailService {
             base_url,
             sender,
             auth_token,
+            throttle: SendThrottle::new(throttle),
+            metrics,
         }
     }
 
+    /// Send an email within the limits of `email.throttle`. Waits for a free slot and
+    /// for the send rate, and retries when the provider answers 429 with a short enough
+    /// `Retry-After`. Fails right away once the quota of the window is used up.
+    #[tracing::instrument(
+        name = "Send an email",
+        skip_all,
+        fields(
+            kind = kind.as_str(),
+            emails_sent_last_minute = tracing::field::Empty,
+            emails_sent_in_quota_window = tracing::field::Empty,
+        )
+    )]
     pub async fn send_email(
         &self,
+        kind: EmailKind,
         recipient: &SubscriberEmail,
         subject: &str,
         html_content: &str,
         text_content: &str,
-    ) -> Result<(), reqwest::Error> {
-        // TODO: use `reqwest::Url::join` by changing `base_url`'s type from `String` to `reqwest::Url`.
-        let url = format!("{}/email", self.base_url);
-
+    ) -> Result<(), EmailError> {
         let request_body = SendEmailRequest {
             from: self.sender.as_ref(),
             to: recipient.as_ref(),
@@ -56,23 +126,99 @@ impl EmailService {
             text_body: text_content,
         };
 
-        let _ = self
-            .client
-            .post(url)
-            .header("X-Postmark-Server-Token", self.auth_token.expose_secret())
-            .json(&request_body)
-            .send()
-            .await?
-            .error_for_status()?;
+        let _slot = self.throttle.acquire_slot().await;
+        let outcome = match self.throttle.reserve_quota() {
+            Ok(reservation) => {
+                let outcome = self.send_with_retries(&request_body).await;
+                match outcome {
+                    Ok(()) => self.throttle.record_sent(),
+                    Err(_) => self.throttle.refund_quota(reservation),
+                }
+                outcome
+            }
+            Err(e) => {
+                tracing::warn!(resets_at = %e.resets_at, "The email quota is used up.");
+                Err(e.into())
+            }
+        };
+        match &outcome {
+            Ok(()) => self.metrics.email_sent(kind.as_str()),
+            Err(e) => self.metrics.email_failed(kind.as_str(), e.reason()),
+        }
+
+        let stats = self.stats();
+        let span = tracing::Span::current();
+        span.record("emails_sent_last_minute", stats.sent_last_minute);
+        span.record("emails_sent_in_quota_window", stats.sent_in_quota_window);
+        outcome
+    }
+
+    /// Recent throughput, and how much of the quota is used.
+    pub fn stats(&self) -> SendRateStats {
+        self.throttle.stats()
+    }
+
+    async fn send_with_retries(
+        &self,
+        request_body: &SendEmailRequest<'_>,
+    ) -> Result<(), EmailError> {
+        // TODO: use `reqwest::Url::join` by changing `base_url`'s type from `String` to `reqwest::Url`.
+        let url = format!("{}/email", self.base_url);
 
-        Ok(())
+        let mut attempt = 1;
+        loop {
+            self.throttle.pace().await;
+            let response = self
+                .client
+                .post(&url)
+                .header("X-Postmark-Server-Token", self.auth_token.expose_secret())
+                .headers(trace_context_headers())
+                .json(request_body)
+                .send()
+                .await?;
+            if response.status() != StatusCode::TOO_MANY_REQUESTS {
+                response.error_for_status()?;
+                return Ok(());
+            }
+
+            let retry_after =
+                parse_retry_after(response.headers(), Utc::now()).unwrap_or(DEFAULT_RETRY_AFTER);
+            tracing::warn!(
+                ?retry_after,
+                attempt,
+                "The email provider is rate limiting us."
+            );
+            // The provider limits the account, not this email: every send waits.
+            // Waits beyond `max_retry_after` fail this email instead.
+            if !self.throttle.pause_for(retry_after) || attempt >= MAX_ATTEMPTS {
+                return Err(EmailError::RateLimited(retry_after));
+            }
+            attempt += 1;
+        }
+    }
+}
+
+/// `Retry-After` holds either a number of seconds or an HTTP date.
+fn parse_retry_after(headers: &HeaderMap, now: DateTime<Utc>) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
     }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        (date.with_timezone(&Utc) - now)
+            .to_std()
+            .unwrap_or(Duration::ZERO),
+    )
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::configuration::EmailThrottleSettings;
     use crate::domain::subscriber_email::SubscriberEmail;
-    use crate::services::email::EmailService;
+    use crate::metrics::Metrics;
+    use crate::services::email::{parse_retry_after, EmailError, EmailKind, EmailService};
+    use chrono::{TimeZone, Utc};
     use claims::{assert_err, assert_ok};
     use fake::{
         faker::{
@@ -116,12 +262,29 @@ mod tests {
         SubscriberEmail::parse(SafeEmail().fake()).unwrap()
     }
 
+    fn throttle_settings() -> EmailThrottleSettings {
+        EmailThrottleSettings {
+            messages_per_second: 100,
+            max_concurrent_sends: 10,
+            quota_per_window: None,
+            quota_window_in_secs: 86400,
+            max_retry_after_in_secs: 5,
+            resume_poll_interval_in_milli: 1000,
+        }
+    }
+
     fn email_service(base_url: String) -> EmailService {
+        throttled_email_service(base_url, throttle_settings())
+    }
+
+    fn throttled_email_service(base_url: String, throttle: EmailThrottleSettings) -> EmailService {
         EmailService::new(
             base_url,
             email(),
             Secret::new(Faker.fake()),
             std::time::Duration::from_millis(200),
+            throttle,
+            Metrics::new(),
         )
     }
 
@@ -143,7 +306,13 @@ mod tests {
 
         // Act
         let _ = email_service
-            .send_email(&email(), &subject(), &content(), &content())
+            .send_email(
+                EmailKind::Issue,
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+            )
             .await;
 
         // Assert
@@ -163,7 +332,13 @@ mod tests {
 
         // Act
         let response = email_service
-            .send_email(&email(), &subject(), &content(), &content())
+            .send_email(
+                EmailKind::Issue,
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+            )
             .await;
 
         // Asset
@@ -184,7 +359,13 @@ mod tests {
 
         // Act
         let response = email_service
-            .send_email(&email(), &subject(), &content(), &content())
+            .send_email(
+                EmailKind::Issue,
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+            )
             .await;
 
         // Asset
@@ -207,10 +388,143 @@ mod tests {
 
         // Act
         let response = email_service
-            .send_email(&email(), &subject(), &content(), &content())
+            .send_email(
+                EmailKind::Issue,
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+            )
             .await;
 
         // Assert
         assert_err!(response);
     }
+
+    #[tokio::test]
+    async fn send_email_retries_after_the_delay_asked_by_a_429() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_service = email_service(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "1"))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let started = std::time::Instant::now();
+        let response = email_service
+            .send_email(
+                EmailKind::Issue,
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+            )
+            .await;
+
+        // Assert
+        assert_ok!(response);
+        assert!(started.elapsed() >= std::time::Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_the_provider_asks_to_wait_too_long() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_service = email_service(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "3600"))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let response = email_service
+            .send_email(
+                EmailKind::Issue,
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+            )
+            .await;
+
+        // Assert
+        assert!(matches!(response, Err(EmailError::RateLimited(_))));
+    }
+
+    #[tokio::test]
+    async fn send_email_stops_once_the_quota_is_used_up() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_service = throttled_email_service(
+            mock_server.uri(),
+            EmailThrottleSettings {
+                quota_per_window: Some(1),
+                ..throttle_settings()
+            },
+        );
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let first = email_service
+            .send_email(
+                EmailKind::Issue,
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+            )
+            .await;
+        let second = email_service
+            .send_email(
+                EmailKind::Issue,
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+            )
+            .await;
+
+        // Assert
+        assert_ok!(first);
+        assert!(matches!(second, Err(EmailError::QuotaExhausted(_))));
+        assert_eq!(email_service.stats().sent_in_quota_window, 1);
+    }
+
+    #[test]
+    fn retry_after_can_be_a_delay_or_a_date() {
+        let now = Utc.with_ymd_and_hms(2015, 10, 21, 7, 27, 0).unwrap();
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert("Retry-After", "120".parse().unwrap());
+        assert_eq!(
+            parse_retry_after(&headers, now),
+            Some(std::time::Duration::from_secs(120))
+        );
+        headers.insert(
+            "Retry-After",
+            "Wed, 21 Oct 2015 07:28:00 GMT".parse().unwrap(),
+        );
+        assert_eq!(
+            parse_retry_after(&headers, now),
+            Some(std::time::Duration::from_secs(60))
+        );
+        headers.insert("Retry-After", "soon".parse().unwrap());
+        assert_eq!(parse_retry_after(&headers, now), None);
+    }
 }
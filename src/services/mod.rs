@@ -1 +1,3 @@
 pub mod email;
+pub mod mx_resolver;
+pub mod throttle;
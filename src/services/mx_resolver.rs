@@ -0,0 +1,105 @@
+use crate::configuration::{DeliverabilitySettings, MxLookup};
+use crate::domain::subscriber_email::SubscriberEmail;
+use anyhow::Context;
+use std::collections::HashSet;
+use trust_dns_resolver::{error::ResolveErrorKind, TokioAsyncResolver};
+
+/// Tells whether a domain has a mail server.
+#[async_trait::async_trait]
+pub trait MxResolver: Send + Sync {
+    async fn accepts_email(&self, domain: &str) -> Result<bool, anyhow::Error>;
+}
+
+/// Looks up MX records through the system DNS configuration.
+pub struct DnsMxResolver(TokioAsyncResolver);
+
+impl DnsMxResolver {
+    pub fn from_system_conf() -> Result<Self, anyhow::Error> {
+        let resolver = TokioAsyncResolver::tokio_from_system_conf()
+            .context("Failed to read the system DNS configuration.")?;
+        Ok(Self(resolver))
+    }
+}
+
+#[async_trait::async_trait]
+impl MxResolver for DnsMxResolver {
+    async fn accepts_email(&self, domain: &str) -> Result<bool, anyhow::Error> {
+        // The trailing dot keeps the resolver from trying the system search domains.
+        let domain = format!("{}.", domain);
+        match self.0.mx_lookup(domain.as_str()).await {
+            // A "null MX" (RFC 7505) explicitly states that the domain accepts no email.
+            Ok(records) => return Ok(records.iter().any(|mx| !mx.exchange().is_root())),
+            Err(e) if matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. }) => {}
+            Err(e) => return Err(e).context("Failed to look up MX records."),
+        }
+        // Without MX records, mail goes to the domain's own address (RFC 5321, section 5.1).
+        match self.0.lookup_ip(domain.as_str()).await {
+            Ok(addresses) => Ok(addresses.iter().next().is_some()),
+            Err(e) if matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. }) => Ok(false),
+            Err(e) => Err(e).context("Failed to look up A and AAAA records."),
+        }
+    }
+}
+
+/// Resolver with a fixed set of domains accepting email, for tests and offline environments.
+pub struct StaticMxResolver(HashSet<String>);
+
+impl StaticMxResolver {
+    pub fn new(domains: impl IntoIterator<Item = String>) -> Self {
+        Self(domains.into_iter().map(|d| d.to_lowercase()).collect())
+    }
+}
+
+#[async_trait::async_trait]
+impl MxResolver for StaticMxResolver {
+    async fn accepts_email(&self, domain: &str) -> Result<bool, anyhow::Error> {
+        Ok(self.0.contains(domain))
+    }
+}
+
+/// Checks that new subscribers gave us an address we can actually deliver to.
+pub struct DeliverabilityChecker {
+    resolver: Option<Box<dyn MxResolver>>,
+    timeout: std::time::Duration,
+}
+
+impl DeliverabilityChecker {
+    pub fn build(settings: &DeliverabilitySettings) -> Result<Self, anyhow::Error> {
+        let resolver: Option<Box<dyn MxResolver>> = match settings.mx_lookup {
+            MxLookup::Disabled => None,
+            MxLookup::Dns => Some(Box::new(DnsMxResolver::from_system_conf()?)),
+            MxLookup::Static => Some(Box::new(StaticMxResolver::new(
+                settings.static_mx_domains.clone(),
+            ))),
+        };
+        Ok(Self {
+            resolver,
+            timeout: settings.timeout(),
+        })
+    }
+
+    /// Lookup failures are not held against the subscriber: we would rather send
+    /// a confirmation email that bounces than turn away a valid address.
+    #[tracing::instrument(name = "Check email deliverability", skip(self, email), fields(domain = %email.domain()))]
+    pub async fn check(&self, email: &SubscriberEmail) -> Result<(), String> {
+        let Some(resolver) = &self.resolver else {
+            return Ok(());
+        };
+        let domain = email.domain();
+        match tokio::time::timeout(self.timeout, resolver.accepts_email(domain)).await {
+            Ok(Ok(true)) => Ok(()),
+            Ok(Ok(false)) => Err(format!(
+                "{} does not accept email, please check the address for typos",
+                domain
+            )),
+            Ok(Err(e)) => {
+                tracing::warn!(error.cause_chain = ?e, "Failed to check the email domain.");
+                Ok(())
+            }
+            Err(_) => {
+                tracing::warn!("Timed out checking the email domain.");
+                Ok(())
+            }
+        }
+    }
+}
@@ -0,0 +1,279 @@
+use crate::configuration::EmailThrottleSettings;
+use chrono::{DateTime, TimeZone, Utc};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+/// Throughput is reported over this period.
+const THROUGHPUT_PERIOD: Duration = Duration::from_secs(60);
+
+#[derive(thiserror::Error, Debug)]
+#[error("The email quota is used up until {resets_at}.")]
+pub struct QuotaExhausted {
+    pub resets_at: DateTime<Utc>,
+}
+
+/// A unit of quota, given back if the email it was taken for is not sent.
+#[derive(Debug)]
+pub struct QuotaReservation {
+    window: i64,
+}
+
+/// What the throttle let through lately.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SendRateStats {
+    pub sent_last_minute: usize,
+    pub sent_in_quota_window: u64,
+    pub quota_per_window: Option<u64>,
+}
+
+/// Paces outgoing emails: a token bucket refilled at `messages_per_second`, a cap on
+/// concurrent sends, pauses requested by the provider and a quota per window.
+///
+/// The state lives in memory: with several instances, each gets the full limits.
+pub struct SendThrottle {
+    settings: EmailThrottleSettings,
+    concurrency: Semaphore,
+    state: Mutex<ThrottleState>,
+}
+
+struct ThrottleState {
+    /// Goes negative when senders are queued for future tokens.
+    tokens: f64,
+    updated_at: Instant,
+    paused_until: Option<Instant>,
+    window: i64,
+    used_in_window: u64,
+    sent_at: VecDeque<Instant>,
+}
+
+impl SendThrottle {
+    pub fn new(settings: EmailThrottleSettings) -> Self {
+        let now = Instant::now();
+        Self {
+            concurrency: Semaphore::new(settings.max_concurrent_sends),
+            state: Mutex::new(ThrottleState {
+                tokens: f64::from(settings.messages_per_second),
+                updated_at: now,
+                paused_until: None,
+                window: 0,
+                used_in_window: 0,
+                sent_at: VecDeque::new(),
+            }),
+            settings,
+        }
+    }
+
+    pub fn settings(&self) -> &EmailThrottleSettings {
+        &self.settings
+    }
+
+    /// Wait for one of the `max_concurrent_sends` slots.
+    pub async fn acquire_slot(&self) -> SemaphorePermit<'_> {
+        self.concurrency
+            .acquire()
+            .await
+            .expect("The send semaphore is never closed.")
+    }
+
+    pub fn reserve_quota(&self) -> Result<QuotaReservation, QuotaExhausted> {
+        self.reserve_quota_at(Utc::now())
+    }
+
+    fn reserve_quota_at(&self, now: DateTime<Utc>) -> Result<QuotaReservation, QuotaExhausted> {
+        let window = self.window_of(now);
+        let mut state = self.state.lock().unwrap();
+        if state.window != window {
+            state.window = window;
+            state.used_in_window = 0;
+        }
+        if let Some(quota) = self.settings.quota_per_window {
+            if state.used_in_window >= quota {
+                let window_length = self.settings.quota_window_in_secs as i64;
+                return Err(QuotaExhausted {
+                    resets_at: Utc.timestamp_opt((window + 1) * window_length, 0).unwrap(),
+                });
+            }
+        }
+        state.used_in_window += 1;
+        Ok(QuotaReservation { window })
+    }
+
+    pub fn refund_quota(&self, reservation: QuotaReservation) {
+        let mut state = self.state.lock().unwrap();
+        if state.window == reservation.window {
+            state.used_in_window = state.used_in_window.saturating_sub(1);
+        }
+    }
+
+    /// Wait for a token, and for the end of any pause requested by the provider.
+    pub async fn pace(&self) {
+        let wait = self.take_token_at(Instant::now());
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Returns how long to wait before sending.
+    fn take_token_at(&self, now: Instant) -> Duration {
+        let rate = f64::from(self.settings.messages_per_second);
+        let mut state = self.state.lock().unwrap();
+        let refilled = now
+            .saturating_duration_since(state.updated_at)
+            .as_secs_f64()
+            * rate;
+        state.tokens = rate.min(state.tokens + refilled);
+        state.updated_at = now;
+        state.tokens -= 1.0;
+        let wait = if state.tokens < 0.0 {
+            Duration::from_secs_f64(-state.tokens / rate)
+        } else {
+            Duration::ZERO
+        };
+        match state.paused_until {
+            Some(paused_until) => wait.max(paused_until.saturating_duration_since(now)),
+            None => wait,
+        }
+    }
+
+    /// Hold every send back for a while, as asked by a `Retry-After` header.
+    /// Returns `false`, without pausing, if the provider asks for more than `max_retry_after`:
+    /// one unreasonable answer must not park every send.
+    pub fn pause_for(&self, delay: Duration) -> bool {
+        self.pause_at(Instant::now(), delay)
+    }
+
+    fn pause_at(&self, now: Instant, delay: Duration) -> bool {
+        if delay > self.settings.max_retry_after() {
+            return false;
+        }
+        let Some(until) = now.checked_add(delay) else {
+            return false;
+        };
+        let mut state = self.state.lock().unwrap();
+        state.paused_until = Some(state.paused_until.map_or(until, |u| u.max(until)));
+        true
+    }
+
+    pub fn record_sent(&self) {
+        self.record_sent_at(Instant::now());
+    }
+
+    fn record_sent_at(&self, now: Instant) {
+        let mut state = self.state.lock().unwrap();
+        state.sent_at.push_back(now);
+        while state
+            .sent_at
+            .front()
+            .is_some_and(|t| now.saturating_duration_since(*t) > THROUGHPUT_PERIOD)
+        {
+            state.sent_at.pop_front();
+        }
+    }
+
+    pub fn stats(&self) -> SendRateStats {
+        self.stats_at(Instant::now(), Utc::now())
+    }
+
+    fn stats_at(&self, now: Instant, utc_now: DateTime<Utc>) -> SendRateStats {
+        let state = self.state.lock().unwrap();
+        SendRateStats {
+            sent_last_minute: state
+                .sent_at
+                .iter()
+                .filter(|t| now.saturating_duration_since(**t) <= THROUGHPUT_PERIOD)
+                .count(),
+            sent_in_quota_window: if state.window == self.window_of(utc_now) {
+                state.used_in_window
+            } else {
+                0
+            },
+            quota_per_window: self.settings.quota_per_window,
+        }
+    }
+
+    fn window_of(&self, now: DateTime<Utc>) -> i64 {
+        now.timestamp()
+            .div_euclid(self.settings.quota_window_in_secs as i64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SendThrottle;
+    use crate::configuration::EmailThrottleSettings;
+    use chrono::{TimeZone, Utc};
+    use claims::{assert_err, assert_ok};
+    use std::time::{Duration, Instant};
+
+    fn throttle(messages_per_second: u32, quota_per_window: Option<u64>) -> SendThrottle {
+        SendThrottle::new(EmailThrottleSettings {
+            messages_per_second,
+            max_concurrent_sends: 1,
+            quota_per_window,
+            quota_window_in_secs: 86400,
+            max_retry_after_in_secs: 60,
+            resume_poll_interval_in_milli: 1000,
+        })
+    }
+
+    #[test]
+    fn a_full_bucket_lets_a_burst_through_then_paces_sends() {
+        let throttle = throttle(2, None);
+        let now = Instant::now();
+        assert_eq!(throttle.take_token_at(now), Duration::ZERO);
+        assert_eq!(throttle.take_token_at(now), Duration::ZERO);
+        assert_eq!(throttle.take_token_at(now), Duration::from_millis(500));
+        assert_eq!(throttle.take_token_at(now), Duration::from_millis(1000));
+        assert_eq!(
+            throttle.take_token_at(now + Duration::from_secs(1)),
+            Duration::from_millis(500)
+        );
+    }
+
+    #[test]
+    fn pauses_longer_than_max_retry_after_are_refused() {
+        let throttle = throttle(10, None);
+        let now = Instant::now();
+        assert!(!throttle.pause_at(now, Duration::MAX));
+        assert!(!throttle.pause_at(now, Duration::from_secs(3600)));
+        assert_eq!(throttle.take_token_at(now), Duration::ZERO);
+
+        assert!(throttle.pause_at(now, Duration::from_secs(30)));
+        assert_eq!(throttle.take_token_at(now), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn the_quota_resets_at_the_next_window() {
+        let throttle = throttle(10, Some(2));
+        let morning = Utc.with_ymd_and_hms(2023, 7, 10, 8, 0, 0).unwrap();
+        let first = assert_ok!(throttle.reserve_quota_at(morning));
+        assert_ok!(throttle.reserve_quota_at(morning));
+        let exhausted = assert_err!(throttle.reserve_quota_at(morning));
+        assert_eq!(
+            exhausted.resets_at,
+            Utc.with_ymd_and_hms(2023, 7, 11, 0, 0, 0).unwrap()
+        );
+
+        // Emails that were not sent give their quota back.
+        throttle.refund_quota(first);
+        assert_ok!(throttle.reserve_quota_at(morning));
+
+        let next_day = Utc.with_ymd_and_hms(2023, 7, 11, 0, 0, 1).unwrap();
+        assert_ok!(throttle.reserve_quota_at(next_day));
+    }
+
+    #[test]
+    fn throughput_is_counted_over_the_last_minute() {
+        let throttle = throttle(10, Some(100));
+        let now = Instant::now();
+        assert_ok!(throttle.reserve_quota());
+        throttle.record_sent_at(now);
+        throttle.record_sent_at(now + Duration::from_secs(30));
+        let stats = throttle.stats_at(now + Duration::from_secs(70), Utc::now());
+        assert_eq!(stats.sent_last_minute, 1);
+        assert_eq!(stats.sent_in_quota_window, 1);
+        assert_eq!(stats.quota_per_window, Some(100));
+    }
+}
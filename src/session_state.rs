@@ -1,5 +1,7 @@
 use actix_session::{Session, SessionExt, SessionGetError, SessionInsertError};
 use actix_web::{dev::Payload, FromRequest, HttpRequest};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
 use std::future::{ready, Ready};
 use uuid::Uuid;
 
@@ -7,6 +9,8 @@ pub struct TypedSession(Session);
 
 impl TypedSession {
     const USER_ID_KEY: &'static str = "user_id";
+    const SESSION_ID_KEY: &'static str = "session_id";
+    const CSRF_TOKEN_KEY: &'static str = "csrf_token";
     pub fn renew(&self) {
         self.0.renew();
     }
@@ -16,6 +20,28 @@ impl TypedSession {
     pub fn get_user_id(&self) -> Result<Option<Uuid>, SessionGetError> {
         self.0.get(Self::USER_ID_KEY)
     }
+    pub fn insert_session_id(&self, session_id: Uuid) -> Result<(), SessionInsertError> {
+        self.0.insert(Self::SESSION_ID_KEY, session_id)
+    }
+    pub fn get_session_id(&self) -> Result<Option<Uuid>, SessionGetError> {
+        self.0.get(Self::SESSION_ID_KEY)
+    }
+    pub fn get_csrf_token(&self) -> Result<Option<String>, SessionGetError> {
+        self.0.get(Self::CSRF_TOKEN_KEY)
+    }
+    /// The anti-forgery token of this session, generated on first use.
+    pub fn get_or_insert_csrf_token(&self) -> Result<String, anyhow::Error> {
+        if let Some(token) = self.get_csrf_token()? {
+            return Ok(token);
+        }
+        let mut rng = thread_rng();
+        let token: String = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+            .map(char::from)
+            .take(43)
+            .collect();
+        self.0.insert(Self::CSRF_TOKEN_KEY, &token)?;
+        Ok(token)
+    }
     pub fn log_out(self) {
         self.0.purge()
     }
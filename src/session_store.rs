@@ -0,0 +1,238 @@
+use crate::authentication::{delete_expired_user_sessions, SessionTtl};
+use crate::shutdown::Shutdown;
+use actix_session::storage::{
+    LoadError, RedisSessionStore, SaveError, SessionKey, SessionStore, UpdateError,
+};
+use actix_web::cookie::time::Duration;
+use anyhow::Context;
+use chrono::Utc;
+use rand::distributions::Alphanumeric;
+use rand::{rngs::OsRng, Rng};
+use sqlx::PgPool;
+use std::collections::HashMap;
+use tokio::task::JoinHandle;
+
+type SessionState = HashMap<String, String>;
+
+/// The session store selected in the configuration.
+/// `SessionMiddleware` is generic over its store, so we pick the backend at runtime by delegation.
+#[derive(Clone)]
+pub enum AppSessionStore {
+    Redis(RedisSessionStore),
+    Postgres(PgSessionStore),
+}
+
+#[async_trait::async_trait(?Send)]
+impl SessionStore for AppSessionStore {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
+        match self {
+            AppSessionStore::Redis(store) => store.load(session_key).await,
+            AppSessionStore::Postgres(store) => store.load(session_key).await,
+        }
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        match self {
+            AppSessionStore::Redis(store) => store.save(session_state, ttl).await,
+            AppSessionStore::Postgres(store) => store.save(session_state, ttl).await,
+        }
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        match self {
+            AppSessionStore::Redis(store) => store.update(session_key, session_state, ttl).await,
+            AppSessionStore::Postgres(store) => store.update(session_key, session_state, ttl).await,
+        }
+    }
+
+    async fn update_ttl(
+        &self,
+        session_key: &SessionKey,
+        ttl: &Duration,
+    ) -> Result<(), anyhow::Error> {
+        match self {
+            AppSessionStore::Redis(store) => store.update_ttl(session_key, ttl).await,
+            AppSessionStore::Postgres(store) => store.update_ttl(session_key, ttl).await,
+        }
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
+        match self {
+            AppSessionStore::Redis(store) => store.delete(session_key).await,
+            AppSessionStore::Postgres(store) => store.delete(session_key).await,
+        }
+    }
+}
+
+/// Session store backed by the `http_sessions` table, for deployments without Redis.
+/// Expired rows are never returned and are removed by `spawn_session_cleanup`.
+#[derive(Clone)]
+pub struct PgSessionStore {
+    pool: PgPool,
+}
+
+impl PgSessionStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+/// Periodically delete expired and revoked `user_sessions` records, along with expired
+/// `http_sessions` when `purge_store` is set (the `postgres` store is in use), until
+/// shutdown starts.
+pub fn spawn_session_cleanup(
+    pool: PgPool,
+    purge_store: bool,
+    ttl: SessionTtl,
+    interval: std::time::Duration,
+    shutdown: &Shutdown,
+) -> JoinHandle<()> {
+    let stop = shutdown.clone();
+    shutdown.spawn("session cleanup", async move {
+        let mut interval = tokio::time::interval(interval);
+        while stop.tick(&mut interval).await {
+            if purge_store {
+                if let Err(e) = delete_expired_sessions(&pool).await {
+                    tracing::error!(error.cause_chain = ?e, "Failed to delete expired sessions.");
+                }
+            }
+            if let Err(e) = delete_expired_user_sessions(ttl, &pool).await {
+                tracing::error!(error.cause_chain = ?e, "Failed to delete expired user sessions.");
+            }
+        }
+    })
+}
+
+#[async_trait::async_trait(?Send)]
+impl SessionStore for PgSessionStore {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
+        let row = sqlx::query!(
+            r#"SELECT state FROM http_sessions WHERE session_key = $1 AND expires_at > now()"#,
+            session_key.as_ref(),
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to load the session state.")
+        .map_err(LoadError::Other)?;
+
+        row.map(|row| serde_json::from_str(&row.state))
+            .transpose()
+            .context("Failed to deserialize the session state.")
+            .map_err(LoadError::Deserialization)
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        let state = serde_json::to_string(&session_state)
+            .context("Failed to serialize the session state.")
+            .map_err(SaveError::Serialization)?;
+        let session_key = generate_session_key();
+        sqlx::query!(
+            r#"INSERT INTO http_sessions (session_key, state, expires_at) VALUES ($1, $2, $3)"#,
+            session_key.as_ref(),
+            state,
+            expires_at(ttl),
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to save the session state.")
+        .map_err(SaveError::Other)?;
+        Ok(session_key)
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        let state = serde_json::to_string(&session_state)
+            .context("Failed to serialize the session state.")
+            .map_err(UpdateError::Serialization)?;
+        let result = sqlx::query!(
+            r#"
+        UPDATE http_sessions SET state = $2, expires_at = $3
+        WHERE session_key = $1 AND expires_at > now()
+        "#,
+            session_key.as_ref(),
+            state,
+            expires_at(ttl),
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to update the session state.")
+        .map_err(UpdateError::Other)?;
+
+        if result.rows_affected() == 1 {
+            Ok(session_key)
+        } else {
+            // The session expired in the meantime: start a new one,
+            // like the Redis store does.
+            self.save(session_state, ttl).await.map_err(|e| match e {
+                SaveError::Serialization(e) => UpdateError::Serialization(e),
+                SaveError::Other(e) => UpdateError::Other(e),
+            })
+        }
+    }
+
+    async fn update_ttl(
+        &self,
+        session_key: &SessionKey,
+        ttl: &Duration,
+    ) -> Result<(), anyhow::Error> {
+        sqlx::query!(
+            r#"UPDATE http_sessions SET expires_at = $2 WHERE session_key = $1"#,
+            session_key.as_ref(),
+            expires_at(ttl),
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to update the session TTL.")?;
+        Ok(())
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
+        sqlx::query!(
+            r#"DELETE FROM http_sessions WHERE session_key = $1"#,
+            session_key.as_ref(),
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to delete the session.")?;
+        Ok(())
+    }
+}
+
+#[tracing::instrument(name = "Delete expired sessions", skip(pool))]
+async fn delete_expired_sessions(pool: &PgPool) -> Result<u64, anyhow::Error> {
+    let result = sqlx::query!(r#"DELETE FROM http_sessions WHERE expires_at <= now()"#)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+fn expires_at(ttl: &Duration) -> chrono::DateTime<Utc> {
+    Utc::now() + chrono::Duration::seconds(ttl.whole_seconds())
+}
+
+/// Same shape as the keys generated by the Redis store: 64 alphanumeric characters.
+fn generate_session_key() -> SessionKey {
+    let key: String = std::iter::repeat_with(|| OsRng.sample(Alphanumeric))
+        .map(char::from)
+        .take(64)
+        .collect();
+    key.try_into()
+        .expect("A 64 characters alphanumeric string is a valid session key.")
+}
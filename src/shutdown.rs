@@ -0,0 +1,264 @@
+//! Coordinated shutdown. On SIGTERM or Ctrl-C, the servers stop accepting connections
+//! and background tasks stop picking up work. In-flight requests, deliveries and passes
+//! get until `application.shutdown_timeout_in_secs` to finish; past it, deliveries stop
+//! after their current email and leave the rest for the next start, and whatever still
+//! runs after `WIND_DOWN` is dropped.
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::ConnectionType,
+    web,
+};
+use actix_web_lab::middleware::Next;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+use tokio::time::Interval;
+use uuid::Uuid;
+
+/// How long work still running at the deadline gets to stop and record what it leaves.
+pub const WIND_DOWN: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Phase {
+    Running,
+    Draining,
+    DeadlinePassed,
+    Aborting,
+}
+
+/// Shared by the servers, the background tasks and issue deliveries. Clones share their
+/// state.
+#[derive(Clone)]
+pub struct Shutdown {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    phase: watch::Sender<Phase>,
+    /// Every background task holds a clone: the channel closes once they are all done.
+    tasks_running: Mutex<Option<mpsc::Sender<()>>>,
+    tasks_done: tokio::sync::Mutex<mpsc::Receiver<()>>,
+    requests_in_flight: watch::Sender<usize>,
+    summary: Mutex<ShutdownSummary>,
+}
+
+/// What shutdown left unfinished.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ShutdownSummary {
+    /// Requests still running after the wind-down, dropped with their connection.
+    pub requests_cut_off: usize,
+    /// Background tasks dropped in the middle of a pass.
+    pub tasks_aborted: Vec<&'static str>,
+    /// Issues whose delivery stopped at the deadline, with the number of recipients left.
+    /// `DeliveryResumer` sends them the issue after the restart.
+    pub issues_deferred: Vec<(Uuid, usize)>,
+    /// Digests claimed but not sent at the deadline, released for after the restart.
+    pub digests_postponed: usize,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        let (tasks_running, tasks_done) = mpsc::channel(1);
+        Self {
+            inner: Arc::new(Inner {
+                phase: watch::channel(Phase::Running).0,
+                tasks_running: Mutex::new(Some(tasks_running)),
+                tasks_done: tokio::sync::Mutex::new(tasks_done),
+                requests_in_flight: watch::channel(0).0,
+                summary: Mutex::new(ShutdownSummary::default()),
+            }),
+        }
+    }
+
+    /// Start shutting down, as SIGTERM or Ctrl-C do.
+    pub fn trigger(&self) {
+        self.advance_to(Phase::Draining);
+    }
+
+    /// Resolves once shutdown starts.
+    pub async fn started(&self) {
+        self.reached(Phase::Draining).await
+    }
+
+    /// Past the deadline, loops stop before their next email.
+    pub fn deadline_passed(&self) -> bool {
+        *self.inner.phase.borrow() >= Phase::DeadlinePassed
+    }
+
+    /// Wait for the next tick of a polling loop. Returns `false` once shutdown starts.
+    pub async fn tick(&self, interval: &mut Interval) -> bool {
+        tokio::select! {
+            _ = interval.tick() => true,
+            _ = self.started() => false,
+        }
+    }
+
+    /// Spawn a background task that shutdown waits for, and drops after the wind-down.
+    pub fn spawn(
+        &self,
+        name: &'static str,
+        task: impl Future<Output = ()> + Send + 'static,
+    ) -> JoinHandle<()> {
+        let running = self.inner.tasks_running.lock().unwrap().clone();
+        let shutdown = self.clone();
+        tokio::spawn(async move {
+            let _running = running;
+            tokio::select! {
+                _ = task => {}
+                _ = shutdown.reached(Phase::Aborting) => {
+                    shutdown.inner.summary.lock().unwrap().tasks_aborted.push(name);
+                }
+            }
+        })
+    }
+
+    pub fn record_deferred_issue(&self, issue_id: Uuid, recipients_left: usize) {
+        let mut summary = self.inner.summary.lock().unwrap();
+        summary.issues_deferred.push((issue_id, recipients_left));
+    }
+
+    pub fn record_postponed_digests(&self, count: usize) {
+        self.inner.summary.lock().unwrap().digests_postponed += count;
+    }
+
+    /// Wait for the requests in flight and the background tasks: until `timeout`, then for
+    /// `WIND_DOWN` while work in progress stops. The tasks still running are then dropped;
+    /// the requests are, when the servers stop.
+    pub async fn drain(&self, timeout: Duration) -> ShutdownSummary {
+        self.trigger();
+        // Only the tasks hold a sender now.
+        self.inner.tasks_running.lock().unwrap().take();
+        let mut work = Box::pin(async {
+            let mut tasks_done = self.inner.tasks_done.lock().await;
+            tokio::join!(self.requests_done(), tasks_done.recv());
+        });
+        if tokio::time::timeout(timeout, &mut work).await.is_err() {
+            tracing::warn!("The shutdown deadline passed: stopping the work in progress.");
+            self.advance_to(Phase::DeadlinePassed);
+            if tokio::time::timeout(WIND_DOWN, &mut work).await.is_err() {
+                self.advance_to(Phase::Aborting);
+                drop(work);
+                // The tasks stop as soon as they see the phase change.
+                self.inner.tasks_done.lock().await.recv().await;
+            }
+        }
+        let mut summary = self.inner.summary.lock().unwrap().clone();
+        summary.requests_cut_off = *self.inner.requests_in_flight.borrow();
+        summary
+    }
+
+    async fn requests_done(&self) {
+        let mut in_flight = self.inner.requests_in_flight.subscribe();
+        while *in_flight.borrow_and_update() > 0 {
+            // The sender lives as long as `self`.
+            let _ = in_flight.changed().await;
+        }
+    }
+
+    fn advance_to(&self, phase: Phase) {
+        self.inner.phase.send_if_modified(|current| {
+            let advanced = *current < phase;
+            if advanced {
+                *current = phase;
+            }
+            advanced
+        });
+    }
+
+    async fn reached(&self, phase: Phase) {
+        let mut current = self.inner.phase.subscribe();
+        while *current.borrow_and_update() < phase {
+            // The sender lives as long as `self`.
+            let _ = current.changed().await;
+        }
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ShutdownSummary {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn log(&self) {
+        if self.is_empty() {
+            tracing::info!("Shutdown complete: no work was abandoned.");
+        } else {
+            tracing::warn!(
+                requests_cut_off = self.requests_cut_off,
+                tasks_aborted = ?self.tasks_aborted,
+                issues_deferred = ?self.issues_deferred,
+                digests_postponed = self.digests_postponed,
+                "Shutdown complete: some work was abandoned."
+            );
+        }
+    }
+}
+
+/// Keeps count of the requests in flight, for shutdown to wait for them. Once shutdown has
+/// started, connections are closed after their response rather than kept alive.
+pub async fn track_requests(
+    shutdown: web::Data<Shutdown>,
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let _in_flight = InFlightRequest::new(shutdown.get_ref());
+    let mut response = next.call(req).await?;
+    if *shutdown.inner.phase.borrow() >= Phase::Draining {
+        response
+            .response_mut()
+            .head_mut()
+            .set_connection_type(ConnectionType::Close);
+    }
+    Ok(response)
+}
+
+struct InFlightRequest<'a> {
+    shutdown: &'a Shutdown,
+}
+
+impl<'a> InFlightRequest<'a> {
+    fn new(shutdown: &'a Shutdown) -> Self {
+        shutdown.inner.requests_in_flight.send_modify(|n| *n += 1);
+        Self { shutdown }
+    }
+}
+
+impl Drop for InFlightRequest<'_> {
+    fn drop(&mut self) {
+        self.shutdown
+            .inner
+            .requests_in_flight
+            .send_modify(|n| *n -= 1);
+    }
+}
+
+/// Resolves on SIGTERM or Ctrl-C.
+pub async fn wait_for_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to listen for Ctrl-C.");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM.")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
@@ -0,0 +1,136 @@
+use crate::startup::HmacSecret;
+use hmac::{Hmac, Mac};
+use secrecy::ExposeSecret;
+use sha2::Sha256;
+use uuid::Uuid;
+
+/// What a link lets its holder do: a link signed for one purpose is rejected for another.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LinkPurpose {
+    DataRequest,
+    Preferences,
+}
+
+impl LinkPurpose {
+    fn as_str(&self) -> &'static str {
+        match self {
+            LinkPurpose::DataRequest => "data_request",
+            LinkPurpose::Preferences => "preferences",
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum InvalidLink {
+    #[error("This link is invalid.")]
+    BadSignature,
+    #[error("This link has expired. Please ask for a new one.")]
+    Expired,
+}
+
+/// Signs the tokens of the links mailed to subscribers, which act on their behalf
+/// without an account.
+///
+/// A token is `<subscriber id>.<expires at>.<signature>`: nothing is stored, so links
+/// cannot be revoked and must expire.
+#[derive(Clone)]
+pub struct LinkSigner {
+    secret: HmacSecret,
+}
+
+impl LinkSigner {
+    pub fn new(secret: HmacSecret) -> Self {
+        Self { secret }
+    }
+
+    /// `expires_at` is a UNIX timestamp, in seconds.
+    pub fn sign(&self, purpose: LinkPurpose, subscriber_id: Uuid, expires_at: i64) -> String {
+        let payload = format!("{}.{}", subscriber_id, expires_at);
+        let signature = hex::encode(self.mac(purpose, &payload).finalize().into_bytes());
+        format!("{}.{}", payload, signature)
+    }
+
+    /// Returns the subscriber the link was issued to.
+    pub fn verify(&self, purpose: LinkPurpose, token: &str, now: i64) -> Result<Uuid, InvalidLink> {
+        let (payload, signature) = token.rsplit_once('.').ok_or(InvalidLink::BadSignature)?;
+        let signature = hex::decode(signature).map_err(|_| InvalidLink::BadSignature)?;
+        self.mac(purpose, payload)
+            .verify_slice(&signature)
+            .map_err(|_| InvalidLink::BadSignature)?;
+        let (subscriber_id, expires_at) = payload
+            .split_once('.')
+            .and_then(|(id, expires_at)| Some((id.parse().ok()?, expires_at.parse::<i64>().ok()?)))
+            .ok_or(InvalidLink::BadSignature)?;
+        if now > expires_at {
+            return Err(InvalidLink::Expired);
+        }
+        Ok(subscriber_id)
+    }
+
+    fn mac(&self, purpose: LinkPurpose, payload: &str) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.secret.0.expose_secret().as_bytes())
+            .expect("HMAC can take a key of any size.");
+        mac.update(purpose.as_str().as_bytes());
+        mac.update(b":");
+        mac.update(payload.as_bytes());
+        mac
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{InvalidLink, LinkPurpose, LinkSigner};
+    use crate::startup::HmacSecret;
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    fn signer(secret: &str) -> LinkSigner {
+        LinkSigner::new(HmacSecret(Secret::new(secret.into())))
+    }
+
+    #[test]
+    fn a_signed_link_identifies_the_subscriber_until_it_expires() {
+        let signer = signer("secret");
+        let subscriber_id = Uuid::new_v4();
+        let token = signer.sign(LinkPurpose::DataRequest, subscriber_id, 1_000);
+
+        assert_eq!(
+            signer.verify(LinkPurpose::DataRequest, &token, 1_000),
+            Ok(subscriber_id)
+        );
+        assert_eq!(
+            signer.verify(LinkPurpose::DataRequest, &token, 1_001),
+            Err(InvalidLink::Expired)
+        );
+    }
+
+    #[test]
+    fn tampered_or_foreign_links_are_rejected() {
+        let token = signer("secret").sign(LinkPurpose::DataRequest, Uuid::new_v4(), 1_000);
+        let (_, rest) = token.split_once('.').unwrap();
+        let tampered = format!("{}.{}", Uuid::new_v4(), rest);
+
+        for (signer, token) in [
+            (signer("secret"), tampered.as_str()),
+            (signer("another-secret"), token.as_str()),
+            (signer("secret"), "garbage"),
+        ] {
+            assert_eq!(
+                signer.verify(LinkPurpose::DataRequest, token, 0),
+                Err(InvalidLink::BadSignature)
+            );
+        }
+    }
+
+    #[test]
+    fn links_only_work_for_their_purpose() {
+        let signer = signer("secret");
+        let token = signer.sign(LinkPurpose::Preferences, Uuid::new_v4(), 1_000);
+
+        assert!(signer.verify(LinkPurpose::Preferences, &token, 0).is_ok());
+        assert_eq!(
+            signer.verify(LinkPurpose::DataRequest, &token, 0),
+            Err(InvalidLink::BadSignature)
+        );
+    }
+}
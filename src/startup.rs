@@ -1,24 +1,64 @@
 use crate::{
-    authentication::reject_anonymous_users,
-    configuration::{DatabaseSettings, Settings},
+    authentication::{
+        reject_anonymous_users, reject_invalid_csrf_tokens, DummyPasswordHash, SessionTtl,
+    },
+    bot_protection::BotProtection,
+    client_ip::TrustedProxies,
+    configuration::{DatabaseSettings, RateLimitBackend, SessionStoreBackend, Settings},
+    delivery::{AbTestFinisher, DeliveryResumer, IssueMailer},
+    health::HealthChecker,
+    metrics::{record_http_metrics, serve_metrics, Metrics},
+    preferences::{DigestSender, PreferenceLinks},
+    problem::{self, render_problems},
+    rate_limit::RateLimiter,
     routes::{
-        admin_dashboard, change_password, change_password_form, confirm, health_check, home,
-        log_out, login, login_form, post_newsletter, subscribe,
+        ab_tests, admin_dashboard, api_doc, api_docs, api_tokens, change_email, change_frequency,
+        change_name, change_password, change_password_form, confirm, confirm_email_change,
+        create_api_token, create_issue, create_segment, create_subscriber, create_webhook,
+        data_request_form, delete_issue, delete_segment, delete_subscriber, delete_webhook,
+        erase_data, export_data, get_issue, get_segment, get_subscriber, health_check, home,
+        json_error_handler, list_issues, list_segments, list_subscribers, liveness, log_out,
+        log_out_everywhere, login, login_form, manage_data, openapi_json, path_error_handler,
+        pause_delivery, post_newsletter, preference_center, preview_segment, proof_of_work_script,
+        publish_issue, query_error_handler, readiness, reject_unauthenticated_api_clients,
+        request_data_link, revoke_api_token, revoke_session, subscribe, subscriber_consents,
+        subscribers, swagger_ui_bundle, swagger_ui_css, swagger_ui_script, track_open, unsubscribe,
+        update_issue, update_subscriber, user_sessions, webhook_deliveries, webhooks,
     },
-    services::email::EmailService,
+    security::{harden_responses, ResponseHardening},
+    services::{email::EmailService, mx_resolver::DeliverabilityChecker},
+    session_store::{spawn_session_cleanup, AppSessionStore, PgSessionStore},
+    shutdown::{track_requests, wait_for_signal, Shutdown, ShutdownSummary},
+    signed_link::LinkSigner,
+    webhooks::WebhookDispatcher,
+};
+use actix_session::{
+    config::{BrowserSession, PersistentSession, SessionLifecycle, TtlExtensionPolicy},
+    storage::RedisSessionStore,
+    SessionMiddleware,
+};
+use actix_web::{
+    cookie::{time, Key},
+    dev::Server,
+    web, App, HttpServer,
 };
-use actix_session::{storage::RedisSessionStore, SessionMiddleware};
-use actix_web::{cookie::Key, dev::Server, web, App, HttpServer};
 use actix_web_flash_messages::{storage::CookieMessageStore, FlashMessagesFramework};
 use actix_web_lab::middleware::from_fn;
+use anyhow::Context;
 use secrecy::{ExposeSecret, Secret};
 use sqlx::{postgres::PgPoolOptions, PgPool};
 use std::net::TcpListener;
+use std::time::Duration;
 use tracing_actix_web::TracingLogger;
 
 pub struct Application {
     port: u16,
     server: Server,
+    metrics_port: Option<u16>,
+    metrics_server: Option<Server>,
+    pool: PgPool,
+    shutdown: Shutdown,
+    shutdown_timeout: Duration,
 }
 
 #[derive(Clone)]
@@ -35,89 +75,435 @@ impl Application {
         let url = format!("{}:{}", config.application.host, port);
         let listener = TcpListener::bind(url)?;
 
-        let email_sender = config
-            .email
-            .sender()
-            .expect("Invalid sender email address.");
+        let email_sender = config.email.sender().map_err(anyhow::Error::msg)?;
         let timeout = config.email.timeout();
+        let metrics = Metrics::new();
         let email_service = EmailService::new(
-            config.email.base_url,
+            config.email.base_url.clone(),
             email_sender,
-            config.email.auth_token,
+            config.email.auth_token.clone(),
             timeout,
+            config.email.throttle.clone(),
+            metrics.clone(),
         );
+        let metrics_listener = config
+            .metrics
+            .port
+            .map(|port| TcpListener::bind(format!("{}:{}", config.application.host, port)))
+            .transpose()?;
+        let metrics_port = metrics_listener
+            .as_ref()
+            .map(|listener| listener.local_addr().unwrap().port());
 
         let port = listener.local_addr().unwrap().port();
-        let server = run(
+        let shutdown = Shutdown::new();
+        let shutdown_timeout = config.application.shutdown_timeout();
+        let (server, metrics_server) = run(
             listener,
-            pool,
+            metrics_listener,
+            pool.clone(),
             email_service,
-            config.application.base_url,
-            config.application.hmac_secret,
-            config.redis_uri,
+            metrics,
+            shutdown.clone(),
+            config,
         )
         .await?;
 
-        Ok(Self { server, port })
+        Ok(Self {
+            server,
+            port,
+            metrics_port,
+            metrics_server,
+            pool,
+            shutdown,
+            shutdown_timeout,
+        })
     }
 
     pub fn port(&self) -> u16 {
         self.port
     }
 
-    pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
-        self.server.await
+    /// The port of `/metrics`, when it is served apart from the application.
+    pub fn metrics_port(&self) -> Option<u16> {
+        self.metrics_port
+    }
+
+    /// Starts shutting down the application when triggered, as SIGTERM and Ctrl-C do.
+    pub fn shutdown(&self) -> Shutdown {
+        self.shutdown.clone()
+    }
+
+    /// Serve until SIGTERM or Ctrl-C, then shut down as described in `shutdown`: stop
+    /// accepting connections, let in-flight work finish within
+    /// `application.shutdown_timeout_in_secs`, and close the database pool.
+    pub async fn run_until_stopped(self) -> Result<ShutdownSummary, std::io::Error> {
+        let handles: Vec<_> = std::iter::once(&self.server)
+            .chain(&self.metrics_server)
+            .map(Server::handle)
+            .collect();
+        // Spawned, as the servers only act on the commands of their handles while polled.
+        let mut servers = tokio::spawn(async move {
+            match self.metrics_server {
+                Some(metrics_server) => tokio::try_join!(self.server, metrics_server).map(|_| ()),
+                None => self.server.await,
+            }
+        });
+        let shutdown = self.shutdown.clone();
+        tokio::spawn(async move {
+            wait_for_signal().await;
+            shutdown.trigger();
+        });
+
+        let stopped_early = tokio::select! {
+            result = &mut servers => Some(result),
+            _ = self.shutdown.started() => None,
+        };
+        tracing::info!(
+            timeout_in_secs = self.shutdown_timeout.as_secs(),
+            "Shutting down."
+        );
+        for handle in &handles {
+            drop(handle.pause());
+        }
+        let summary = self.shutdown.drain(self.shutdown_timeout).await;
+        // Closes the idle connections, and those of the requests still running. A graceful
+        // stop would wait for the connections kept alive.
+        for handle in &handles {
+            drop(handle.stop(false));
+        }
+        let result = match stopped_early {
+            Some(result) => result,
+            None => servers.await,
+        };
+        self.pool.close().await;
+        summary.log();
+        result.map_err(std::io::Error::other)?.map(|()| summary)
     }
 }
 
 async fn run(
     listener: TcpListener,
+    metrics_listener: Option<TcpListener>,
     db_pool: PgPool,
     email_service: EmailService,
-    base_url: String,
-    hmac_secret: Secret<String>,
-    redis_uri: Secret<String>,
-) -> Result<Server, anyhow::Error> {
+    metrics: Metrics,
+    shutdown: Shutdown,
+    config: Settings,
+) -> Result<(Server, Option<Server>), anyhow::Error> {
     let db_pool = web::Data::new(db_pool);
     let email_service = web::Data::new(email_service);
-    let base_url = web::Data::new(ApplicationBaseUrl(base_url));
+    let metrics = web::Data::new(metrics);
+    let serve_metrics_on_app = metrics_listener.is_none();
+    let metrics_server = metrics_listener
+        .map(|listener| {
+            run_metrics_server(
+                listener,
+                metrics.clone(),
+                db_pool.clone(),
+                email_service.clone(),
+            )
+        })
+        .transpose()?;
+    let base_url = web::Data::new(ApplicationBaseUrl(config.application.base_url.clone()));
+    let hmac_secret = config.application.hmac_secret;
     let secret_key = Key::from(hmac_secret.expose_secret().as_bytes());
-    let message_store = CookieMessageStore::builder(secret_key.clone()).build();
+    let flash_cookie = &config.application.flash_cookie;
+    let mut message_store =
+        CookieMessageStore::builder(secret_key.clone()).cookie_name(flash_cookie.name.clone());
+    if let Some(domain) = &flash_cookie.domain {
+        message_store = message_store.domain(domain.clone());
+    }
+    let message_store = message_store.build();
     let message_framework = FlashMessagesFramework::builder(message_store).build();
-    let hmac_secret = web::Data::new(HmacSecret(hmac_secret.clone()));
-    let redis_store = RedisSessionStore::new(redis_uri.expose_secret()).await?;
+    let bot_protection = web::Data::new(BotProtection::new(
+        config.bot_protection,
+        HmacSecret(hmac_secret.clone()),
+    ));
+    let link_signer = LinkSigner::new(HmacSecret(hmac_secret.clone()));
+    let preference_links = PreferenceLinks::new(
+        config.application.base_url.clone(),
+        link_signer.clone(),
+        config.preferences.link_ttl_in_secs,
+    );
+    let link_signer = web::Data::new(link_signer);
+    let data_request_settings = web::Data::new(config.data_requests);
+    let preference_settings = web::Data::new(config.preferences.clone());
+    let hmac_secret = web::Data::new(HmacSecret(hmac_secret));
+    let deliverability = web::Data::new(DeliverabilityChecker::build(&config.deliverability)?);
+    let uses_redis = matches!(config.session.store, SessionStoreBackend::Redis)
+        || matches!(config.rate_limit.backend, RateLimitBackend::Redis);
+    let health_checker = web::Data::new(HealthChecker::new(
+        db_pool.get_ref().clone(),
+        config.redis_uri.as_ref().filter(|_| uses_redis),
+        config.email.base_url.clone(),
+        config.health,
+    )?);
+    let rate_limiter =
+        web::Data::new(RateLimiter::build(config.rate_limit, config.redis_uri.as_ref()).await?);
+    let session_store = match config.session.store {
+        SessionStoreBackend::Redis => {
+            let redis_uri = config
+                .redis_uri
+                .context("`redis_uri` must be set to use the `redis` session store.")?;
+            AppSessionStore::Redis(RedisSessionStore::new(redis_uri.expose_secret()).await?)
+        }
+        SessionStoreBackend::Postgres => {
+            AppSessionStore::Postgres(PgSessionStore::new(db_pool.get_ref().clone()))
+        }
+    };
+    let session_cookie = config.application.session_cookie;
+    // Both lifecycles extend the TTL on every request, so a session expires once it has
+    // been idle for `session_ttl`: the same rule `user_sessions` records follow.
+    let session_ttl = session_cookie.max_age().unwrap_or(time::Duration::days(1));
+    let session_lifecycle: SessionLifecycle = match session_cookie.max_age() {
+        Some(_) => PersistentSession::default()
+            .session_ttl(session_ttl)
+            .session_ttl_extension_policy(TtlExtensionPolicy::OnEveryRequest)
+            .into(),
+        None => BrowserSession::default()
+            .state_ttl(session_ttl)
+            .state_ttl_extension_policy(TtlExtensionPolicy::OnEveryRequest)
+            .into(),
+    };
+    let session_ttl = SessionTtl(chrono::Duration::seconds(session_ttl.whole_seconds()));
+    spawn_session_cleanup(
+        db_pool.get_ref().clone(),
+        matches!(session_store, AppSessionStore::Postgres(_)),
+        session_ttl,
+        config.session.cleanup_interval(),
+        &shutdown,
+    );
+    let session_ttl = web::Data::new(session_ttl);
+    WebhookDispatcher::new(db_pool.get_ref().clone(), config.webhooks).spawn(&shutdown);
+    DigestSender::new(
+        db_pool.get_ref().clone(),
+        email_service.clone(),
+        preference_links.clone(),
+        config.preferences,
+        shutdown.clone(),
+    )
+    .spawn(&shutdown);
+    let issue_mailer = IssueMailer::new(
+        db_pool.get_ref().clone(),
+        email_service.clone(),
+        preference_links,
+        config.application.base_url,
+        config.ab_testing.clone(),
+        config.email.delivery_retries.clone(),
+        shutdown.clone(),
+    );
+    AbTestFinisher::new(
+        db_pool.get_ref().clone(),
+        issue_mailer.clone(),
+        config.ab_testing,
+    )
+    .spawn(&shutdown);
+    DeliveryResumer::new(
+        db_pool.get_ref().clone(),
+        issue_mailer.clone(),
+        config.email.throttle,
+    )
+    .spawn(&shutdown);
+    let issue_mailer = web::Data::new(issue_mailer);
+    let shutdown_data = web::Data::new(shutdown);
+    let api_doc = web::Data::new(api_doc(&session_cookie.name));
+    let response_hardening = web::Data::new(ResponseHardening {
+        headers: config.application.security_headers,
+        flash_cookie: config.application.flash_cookie,
+    });
+    if config.application.trusted_proxies.is_empty() {
+        tracing::warn!(
+            "`application.trusted_proxies` is empty: behind a reverse proxy, every request \
+            looks like it comes from the proxy, to rate limits and consent records alike."
+        );
+    }
+    let trusted_proxies = web::Data::new(TrustedProxies::new(
+        config.application.trusted_proxies.clone(),
+    ));
+    let password_policy = web::Data::new(config.password_policy);
+    let dummy_password_hash = web::Data::new(DummyPasswordHash::new(&config.argon2)?);
+    let argon2_settings = web::Data::new(config.argon2);
 
     let server = HttpServer::new(move || {
         App::new()
+            .wrap(from_fn(render_problems))
             .wrap(message_framework.clone())
-            .wrap(SessionMiddleware::new(
-                redis_store.clone(),
-                secret_key.clone(),
-            ))
+            .wrap(
+                SessionMiddleware::builder(session_store.clone(), secret_key.clone())
+                    .cookie_name(session_cookie.name.clone())
+                    .cookie_same_site(session_cookie.same_site.into())
+                    .cookie_secure(session_cookie.secure)
+                    .cookie_domain(session_cookie.domain.clone())
+                    .session_lifecycle(session_lifecycle.clone())
+                    .build(),
+            )
+            .wrap(from_fn(harden_responses))
             .wrap(TracingLogger::default())
+            .wrap(from_fn(record_http_metrics))
+            .wrap(from_fn(track_requests))
+            .configure(|cfg| {
+                if serve_metrics_on_app {
+                    cfg.route("/metrics", web::get().to(serve_metrics));
+                }
+            })
             .route("/health_check", web::get().to(health_check))
+            .route("/health/live", web::get().to(liveness))
+            .route("/health/ready", web::get().to(readiness))
             .route("/subscriptions", web::post().to(subscribe))
             .route("/subscriptions/confirm", web::get().to(confirm))
             .route("/newsletters", web::post().to(post_newsletter))
             .route("/", web::get().to(home))
-            .route("/login", web::get().to(login_form))
-            .route("/login", web::post().to(login))
+            .route("/proof_of_work.js", web::get().to(proof_of_work_script))
+            .route("/privacy", web::get().to(data_request_form))
+            .route("/privacy", web::post().to(request_data_link))
+            .route("/privacy/data", web::get().to(manage_data))
+            .route("/privacy/export", web::get().to(export_data))
+            .route("/privacy/erase", web::post().to(erase_data))
+            .route("/open/{delivery_id}", web::get().to(track_open))
+            .route("/preferences", web::get().to(preference_center))
+            .route("/preferences/name", web::post().to(change_name))
+            .route("/preferences/email", web::post().to(change_email))
+            .route(
+                "/preferences/email/confirm",
+                web::get().to(confirm_email_change),
+            )
+            .route("/preferences/frequency", web::post().to(change_frequency))
+            .route("/preferences/pause", web::post().to(pause_delivery))
+            .route("/preferences/unsubscribe", web::post().to(unsubscribe))
+            .route("/openapi.json", web::get().to(openapi_json))
+            .route("/docs", web::get().to(api_docs))
+            .route("/docs/swagger_ui.js", web::get().to(swagger_ui_script))
+            .route(
+                "/docs/swagger-ui-bundle.js",
+                web::get().to(swagger_ui_bundle),
+            )
+            .route("/docs/swagger-ui.css", web::get().to(swagger_ui_css))
+            .service(
+                web::resource("/login")
+                    .wrap(from_fn(reject_invalid_csrf_tokens))
+                    .route(web::get().to(login_form))
+                    .route(web::post().to(login)),
+            )
             .service(
                 web::scope("/admin")
+                    // Anonymous users are redirected to the login page before the token is checked.
+                    .wrap(from_fn(reject_invalid_csrf_tokens))
                     .wrap(from_fn(reject_anonymous_users))
                     .route("/dashboard", web::get().to(admin_dashboard))
                     .route("/password", web::get().to(change_password_form))
                     .route("/password", web::post().to(change_password))
-                    .route("/logout", web::post().to(log_out)),
+                    .route("/logout", web::post().to(log_out))
+                    .route("/subscribers", web::get().to(subscribers))
+                    .route(
+                        "/subscribers/{subscriber_id}",
+                        web::get().to(subscriber_consents),
+                    )
+                    .route("/ab-tests", web::get().to(ab_tests))
+                    .route("/tokens", web::get().to(api_tokens))
+                    .route("/tokens", web::post().to(create_api_token))
+                    .route(
+                        "/tokens/{token_id}/revoke",
+                        web::post().to(revoke_api_token),
+                    )
+                    .route("/webhooks", web::get().to(webhooks))
+                    .route("/webhooks", web::post().to(create_webhook))
+                    .route("/webhooks/{endpoint_id}", web::get().to(webhook_deliveries))
+                    .route(
+                        "/webhooks/{endpoint_id}/delete",
+                        web::post().to(delete_webhook),
+                    )
+                    .route("/sessions", web::get().to(user_sessions))
+                    .route("/sessions/revoke-all", web::post().to(log_out_everywhere))
+                    .route(
+                        "/sessions/{session_id}/revoke",
+                        web::post().to(revoke_session),
+                    ),
             )
+            .service(
+                web::scope("/api/v1")
+                    .wrap(from_fn(reject_unauthenticated_api_clients))
+                    .app_data(web::JsonConfig::default().error_handler(json_error_handler))
+                    .app_data(web::QueryConfig::default().error_handler(query_error_handler))
+                    .app_data(web::PathConfig::default().error_handler(path_error_handler))
+                    .route("/subscribers", web::get().to(list_subscribers))
+                    .route("/subscribers", web::post().to(create_subscriber))
+                    .route(
+                        "/subscribers/{subscriber_id}",
+                        web::get().to(get_subscriber),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}",
+                        web::patch().to(update_subscriber),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}",
+                        web::delete().to(delete_subscriber),
+                    )
+                    .route("/issues", web::get().to(list_issues))
+                    .route("/issues", web::post().to(create_issue))
+                    .route("/issues/{issue_id}", web::get().to(get_issue))
+                    .route("/issues/{issue_id}", web::patch().to(update_issue))
+                    .route("/issues/{issue_id}", web::delete().to(delete_issue))
+                    .route("/issues/{issue_id}/publish", web::post().to(publish_issue))
+                    .route("/segments", web::get().to(list_segments))
+                    .route("/segments", web::post().to(create_segment))
+                    .route("/segments/{segment_id}", web::get().to(get_segment))
+                    .route("/segments/{segment_id}", web::delete().to(delete_segment))
+                    .route(
+                        "/segments/{segment_id}/preview",
+                        web::get().to(preview_segment),
+                    ),
+            )
+            .app_data(web::FormConfig::default().error_handler(problem::form_error_handler))
+            .app_data(web::JsonConfig::default().error_handler(problem::json_error_handler))
+            .app_data(web::QueryConfig::default().error_handler(problem::query_error_handler))
+            .app_data(response_hardening.clone())
             .app_data(db_pool.clone())
             .app_data(email_service.clone())
             .app_data(base_url.clone())
             .app_data(hmac_secret.clone())
+            .app_data(password_policy.clone())
+            .app_data(argon2_settings.clone())
+            .app_data(dummy_password_hash.clone())
+            .app_data(session_ttl.clone())
+            .app_data(api_doc.clone())
+            .app_data(trusted_proxies.clone())
+            .app_data(rate_limiter.clone())
+            .app_data(bot_protection.clone())
+            .app_data(deliverability.clone())
+            .app_data(link_signer.clone())
+            .app_data(data_request_settings.clone())
+            .app_data(preference_settings.clone())
+            .app_data(issue_mailer.clone())
+            .app_data(metrics.clone())
+            .app_data(health_checker.clone())
+            .app_data(shutdown_data.clone())
     })
+    .disable_signals()
     .listen(listener)?
     .run();
 
+    Ok((server, metrics_server))
+}
+
+/// Serve `/metrics` alone, on the admin port.
+fn run_metrics_server(
+    listener: TcpListener,
+    metrics: web::Data<Metrics>,
+    db_pool: web::Data<PgPool>,
+    email_service: web::Data<EmailService>,
+) -> Result<Server, std::io::Error> {
+    let server = HttpServer::new(move || {
+        App::new()
+            .route("/metrics", web::get().to(serve_metrics))
+            .app_data(metrics.clone())
+            .app_data(db_pool.clone())
+            .app_data(email_service.clone())
+    })
+    .disable_signals()
+    .listen(listener)?
+    .run();
     Ok(server)
 }
 
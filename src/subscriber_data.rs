@@ -0,0 +1,191 @@
+//! Data subject requests: subscribers can get a copy of what we store about them,
+//! or have it erased.
+use crate::consent::{get_consent_log, ConsentRecord};
+use crate::webhooks::{enqueue_event, ErasedSubscriberData, EventType};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(serde::Serialize)]
+pub struct SubscriberExport {
+    pub exported_at: DateTime<Utc>,
+    pub subscriber: ExportedSubscriber,
+    pub consent_log: Vec<ConsentRecord>,
+    /// The issues sent to the subscriber, and whether they opened them.
+    pub deliveries: Vec<ExportedDelivery>,
+    /// Events about the subscriber, as sent to the webhook endpoints.
+    pub webhook_events: Vec<serde_json::Value>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ExportedSubscriber {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub status: String,
+    pub subscribed_at: DateTime<Utc>,
+    pub frequency: String,
+    pub paused_until: Option<DateTime<Utc>>,
+    pub tags: Vec<String>,
+    pub attributes: serde_json::Value,
+    pub last_engaged_at: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ExportedDelivery {
+    pub issue_id: Uuid,
+    pub sent_at: DateTime<Utc>,
+    pub opened_at: Option<DateTime<Utc>>,
+}
+
+/// Tombstones are keyed by the hash of the lowercased address, which is all we keep
+/// of erased subscribers.
+pub fn email_hash(email: &str) -> String {
+    hex::encode(Sha256::digest(email.trim().to_lowercase().as_bytes()))
+}
+
+#[tracing::instrument(name = "Export the data of a subscriber", skip(pool))]
+pub async fn export_subscriber_data(
+    subscriber_id: Uuid,
+    pool: &PgPool,
+) -> Result<Option<SubscriberExport>, anyhow::Error> {
+    let subscriber = sqlx::query_as!(
+        ExportedSubscriber,
+        r#"
+    SELECT id, email, name, status, subscribed_at, frequency, paused_until, attributes,
+        last_engaged_at,
+        ARRAY(
+            SELECT tag FROM subscriber_tags t WHERE t.subscriber_id = subscriptions.id ORDER BY tag
+        ) AS "tags!"
+    FROM subscriptions
+    WHERE id = $1
+    "#,
+        subscriber_id,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the subscriber to export.")?;
+    let subscriber = match subscriber {
+        Some(subscriber) => subscriber,
+        None => return Ok(None),
+    };
+    let consent_log = get_consent_log(subscriber_id, pool).await?;
+    let deliveries = sqlx::query_as!(
+        ExportedDelivery,
+        r#"
+    SELECT issue_id, sent_at, opened_at FROM issue_deliveries
+    WHERE subscriber_id = $1 AND failed_at IS NULL
+    ORDER BY sent_at
+    "#,
+        subscriber_id,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the deliveries of the subscriber to export.")?;
+    let webhook_events = sqlx::query!(
+        r#"
+    SELECT payload FROM webhook_events
+    WHERE event_type LIKE 'subscriber.%' AND payload->'data'->>'id' = $1::text
+    ORDER BY created_at
+    "#,
+        subscriber_id.to_string(),
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the webhook events of the subscriber to export.")?
+    .into_iter()
+    .map(|r| r.payload)
+    .collect();
+    Ok(Some(SubscriberExport {
+        exported_at: Utc::now(),
+        subscriber,
+        consent_log,
+        deliveries,
+        webhook_events,
+    }))
+}
+
+/// Delete the subscriber along with their tokens, strip their contact details from the
+/// webhook events and leave a tombstone. Returns whether the subscriber existed.
+#[tracing::instrument(name = "Erase a subscriber", skip(pool))]
+pub async fn erase_subscriber(subscriber_id: Uuid, pool: &PgPool) -> Result<bool, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let deleted = sqlx::query!(
+        r#"DELETE FROM subscriptions WHERE id = $1 RETURNING email"#,
+        subscriber_id
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to delete the subscriber to erase.")?;
+    let email = match deleted {
+        Some(row) => row.email,
+        None => return Ok(false),
+    };
+    sqlx::query!(
+        r#"
+    UPDATE webhook_events
+    SET payload = payload #- '{data,email}' #- '{data,name}'
+    WHERE event_type LIKE 'subscriber.%' AND payload->'data'->>'id' = $1::text
+    "#,
+        subscriber_id.to_string(),
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to anonymize the webhook events of the erased subscriber.")?;
+    sqlx::query!(
+        r#"
+    INSERT INTO erased_subscribers (email_hash, erased_at) VALUES ($1, now())
+    ON CONFLICT (email_hash) DO UPDATE SET erased_at = EXCLUDED.erased_at
+    "#,
+        email_hash(&email),
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to store the tombstone of an erased subscriber.")?;
+    enqueue_event(
+        &mut transaction,
+        EventType::SubscriberErased,
+        ErasedSubscriberData { id: subscriber_id },
+    )
+    .await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to erase a subscriber.")?;
+    Ok(true)
+}
+
+/// Whether the owner of `email` asked for their data to be erased.
+#[tracing::instrument(name = "Check for an erasure tombstone", skip(email, pool))]
+pub async fn is_erased(email: &str, pool: &PgPool) -> Result<bool, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT email_hash FROM erased_subscribers WHERE email_hash = $1"#,
+        email_hash(email),
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up the erasure tombstones.")?;
+    Ok(row.is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::email_hash;
+
+    #[test]
+    fn the_tombstone_ignores_case_and_whitespace() {
+        assert_eq!(
+            email_hash(" Ursula_Le_Guin@GMail.com "),
+            email_hash("ursula_le_guin@gmail.com")
+        );
+        assert_ne!(
+            email_hash("ursula_le_guin@gmail.com"),
+            email_hash("ursula@gmail.com")
+        );
+    }
+}
@@ -1,26 +1,68 @@
+use crate::configuration::TracingSettings;
+use anyhow::Context;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::{global, KeyValue};
+use opentelemetry_http::HeaderInjector;
+use opentelemetry_otlp::{SpanExporterBuilder, WithExportConfig};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::TracerProvider;
+use opentelemetry_sdk::{runtime, Resource};
+use reqwest::header::HeaderMap;
 use tokio::task::JoinHandle;
 use tracing::{subscriber::set_global_default, Subscriber};
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_log::LogTracer;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 use tracing_subscriber::fmt::MakeWriter;
 use tracing_subscriber::{prelude::__tracing_subscriber_SubscriberExt, EnvFilter, Registry};
 
+/// Spans get a trace context whether or not they are exported, so that it can be
+/// propagated to the services we call. They are sent in batches to
+/// `tracing.otlp_endpoint` when it is set.
+///
+/// Must be called within a Tokio runtime, and kept alive for as long as spans are
+/// recorded: dropping the provider flushes the spans not exported yet.
+pub fn get_tracer_provider(settings: &TracingSettings) -> Result<TracerProvider, anyhow::Error> {
+    let resource = Resource::new([KeyValue::new("service.name", settings.service_name.clone())]);
+    let mut builder = TracerProvider::builder()
+        .with_config(opentelemetry_sdk::trace::config().with_resource(resource));
+    if let Some(endpoint) = &settings.otlp_endpoint {
+        let client = reqwest::Client::builder()
+            .timeout(settings.export_timeout())
+            .build()
+            .context("Failed to build the OTLP client.")?;
+        let exporter = opentelemetry_otlp::new_exporter()
+            .http()
+            .with_endpoint(endpoint)
+            .with_timeout(settings.export_timeout())
+            .with_http_client(client);
+        let exporter = SpanExporterBuilder::from(exporter)
+            .build_span_exporter()
+            .context("Failed to build the OTLP exporter.")?;
+        builder = builder.with_batch_exporter(exporter, runtime::Tokio);
+    }
+    Ok(builder.build())
+}
+
 pub fn get_subscriber<Sink>(
     name: String,
     env_filter: String,
     sink: Sink,
+    tracer_provider: &TracerProvider,
 ) -> impl Subscriber + Send + Sync
 where
     Sink: for<'a> MakeWriter<'a> + Send + Sync + 'static,
 {
     let env_filter =
         EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(env_filter));
+    let tracer = tracer_provider.tracer(name.clone());
     let formatting_layer = BunyanFormattingLayer::new(name, sink);
 
     Registry::default()
         .with(env_filter)
         .with(JsonStorageLayer)
         .with(formatting_layer)
+        .with(tracing_opentelemetry::layer().with_tracer(tracer))
 }
 
 pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) {
@@ -28,6 +70,18 @@ pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) {
     LogTracer::init().expect("Failed to set logger");
 
     set_global_default(subscriber).expect("Failed to set subscriber");
+    // Read and write the W3C `traceparent` header, on incoming and outgoing requests.
+    global::set_text_map_propagator(TraceContextPropagator::new());
+}
+
+/// The headers carrying the context of the current span, for an outgoing request.
+pub fn trace_context_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let context = tracing::Span::current().context();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut HeaderInjector(&mut headers))
+    });
+    headers
 }
 
 pub fn spawn_blocking_with_tracing<F, R>(f: F) -> JoinHandle<R>
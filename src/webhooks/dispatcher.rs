@@ -0,0 +1,285 @@
+use crate::configuration::WebhookSettings;
+use crate::shutdown::Shutdown;
+use anyhow::Context;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use reqwest::Client;
+use sha2::Sha256;
+use sqlx::PgPool;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+pub const EVENT_ID_HEADER: &str = "X-Webhook-Id";
+pub const EVENT_TYPE_HEADER: &str = "X-Webhook-Event";
+pub const TIMESTAMP_HEADER: &str = "X-Webhook-Timestamp";
+/// `sha256=` followed by the hex HMAC-SHA256 of `<timestamp>.<body>`, keyed with the
+/// secret of the endpoint. See `sign_payload`.
+pub const SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+
+/// Delivers the events of the outbox to the endpoints.
+///
+/// Deliveries are claimed for a lease before being attempted, so that several instances
+/// can poll the same outbox. A delivery interrupted by a crash is retried once its lease
+/// expires: receivers must expect duplicates and deduplicate on `X-Webhook-Id`.
+pub struct WebhookDispatcher {
+    pool: PgPool,
+    client: Client,
+    settings: WebhookSettings,
+}
+
+struct DueDelivery {
+    event_id: Uuid,
+    endpoint_id: Uuid,
+    attempts: i32,
+    event_type: String,
+    payload: serde_json::Value,
+    url: String,
+    secret: String,
+}
+
+struct Outcome {
+    response_status: Option<u16>,
+    error: Option<String>,
+    duration: Duration,
+}
+
+impl WebhookDispatcher {
+    pub fn new(pool: PgPool, settings: WebhookSettings) -> Self {
+        // Redirects are reported as failures rather than followed with the signed payload.
+        let client = Client::builder()
+            .timeout(settings.timeout())
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap();
+        Self {
+            pool,
+            client,
+            settings,
+        }
+    }
+
+    /// Poll the outbox until shutdown starts. Deliveries claimed by an interrupted pass
+    /// are retried once their lease expires.
+    pub fn spawn(self, shutdown: &Shutdown) -> JoinHandle<()> {
+        let stop = shutdown.clone();
+        shutdown.spawn("webhook dispatcher", async move {
+            let mut interval = tokio::time::interval(self.settings.poll_interval());
+            while stop.tick(&mut interval).await {
+                if let Err(e) = self.dispatch_due_deliveries().await {
+                    tracing::error!(error.cause_chain = ?e, "Failed to dispatch webhooks.");
+                }
+            }
+        })
+    }
+
+    /// Attempt the deliveries that are due, and return how many were attempted.
+    pub async fn dispatch_due_deliveries(&self) -> Result<usize, anyhow::Error> {
+        let deliveries = self.claim_due_deliveries().await?;
+        for delivery in &deliveries {
+            let outcome = self.attempt(delivery).await;
+            self.record(delivery, outcome).await?;
+        }
+        Ok(deliveries.len())
+    }
+
+    #[tracing::instrument(name = "Claim due webhook deliveries", skip(self))]
+    async fn claim_due_deliveries(&self) -> Result<Vec<DueDelivery>, anyhow::Error> {
+        // Long enough for every claimed delivery to time out.
+        let lease = self.settings.timeout() * (self.settings.batch_size as u32 + 1);
+        sqlx::query_as!(
+            DueDelivery,
+            r#"
+        UPDATE webhook_deliveries d
+        SET next_attempt_at = now() + make_interval(secs => $1)
+        FROM webhook_events e, webhook_endpoints w
+        WHERE (d.event_id, d.endpoint_id) IN (
+            SELECT event_id, endpoint_id FROM webhook_deliveries
+            WHERE status = 'pending' AND next_attempt_at <= now()
+            ORDER BY next_attempt_at
+            LIMIT $2
+            FOR UPDATE SKIP LOCKED
+        )
+            AND e.id = d.event_id
+            AND w.id = d.endpoint_id
+        RETURNING d.event_id, d.endpoint_id, d.attempts, e.event_type, e.payload, w.url, w.secret
+        "#,
+            lease.as_secs_f64(),
+            self.settings.batch_size,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to claim due webhook deliveries.")
+    }
+
+    #[tracing::instrument(
+        name = "Deliver a webhook",
+        skip(self, delivery),
+        fields(event_id = %delivery.event_id, endpoint_id = %delivery.endpoint_id)
+    )]
+    async fn attempt(&self, delivery: &DueDelivery) -> Outcome {
+        let body = delivery.payload.to_string();
+        let timestamp = Utc::now().timestamp();
+        let started = Instant::now();
+        let result = self
+            .client
+            .post(&delivery.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(EVENT_ID_HEADER, delivery.event_id.to_string())
+            .header(EVENT_TYPE_HEADER, &delivery.event_type)
+            .header(TIMESTAMP_HEADER, timestamp)
+            .header(
+                SIGNATURE_HEADER,
+                format!(
+                    "sha256={}",
+                    sign_payload(&delivery.secret, timestamp, body.as_bytes())
+                ),
+            )
+            .body(body)
+            .send()
+            .await;
+        let duration = started.elapsed();
+        match result {
+            Ok(response) if response.status().is_success() => Outcome {
+                response_status: Some(response.status().as_u16()),
+                error: None,
+                duration,
+            },
+            Ok(response) => Outcome {
+                response_status: Some(response.status().as_u16()),
+                error: Some(format!("Unexpected status code {}.", response.status())),
+                duration,
+            },
+            Err(e) => Outcome {
+                response_status: None,
+                error: Some(e.to_string()),
+                duration,
+            },
+        }
+    }
+
+    /// Log the attempt, then mark the delivery as done or schedule the next one.
+    #[tracing::instrument(
+        name = "Record a webhook delivery attempt",
+        skip(self, delivery, outcome)
+    )]
+    async fn record(&self, delivery: &DueDelivery, outcome: Outcome) -> Result<(), anyhow::Error> {
+        let attempts = delivery.attempts + 1;
+        let status = match &outcome.error {
+            None => "delivered",
+            Some(_) if attempts >= self.settings.max_attempts => "failed",
+            Some(_) => "pending",
+        };
+        if let Some(error) = &outcome.error {
+            tracing::warn!(%error, attempts, "A webhook delivery attempt failed.");
+        }
+        let retry_delay = retry_delay(&self.settings, attempts);
+
+        let mut transaction = self
+            .pool
+            .begin()
+            .await
+            .context("Failed to acquire a Postgres connection from the pool")?;
+        sqlx::query!(
+            r#"
+        INSERT INTO webhook_delivery_attempts
+            (id, event_id, endpoint_id, attempted_at, response_status, error, duration_ms)
+        VALUES ($1, $2, $3, now(), $4, $5, $6)
+        "#,
+            Uuid::new_v4(),
+            delivery.event_id,
+            delivery.endpoint_id,
+            outcome.response_status.map(|s| s as i16),
+            outcome.error,
+            outcome.duration.as_millis() as i32,
+        )
+        .execute(&mut transaction)
+        .await
+        .context("Failed to log a webhook delivery attempt.")?;
+        sqlx::query!(
+            r#"
+        UPDATE webhook_deliveries
+        SET
+            status = $3,
+            attempts = $4,
+            next_attempt_at = now() + make_interval(secs => $5),
+            delivered_at = CASE WHEN $3 = 'delivered' THEN now() END
+        WHERE event_id = $1 AND endpoint_id = $2
+        "#,
+            delivery.event_id,
+            delivery.endpoint_id,
+            status,
+            attempts,
+            retry_delay.as_secs_f64(),
+        )
+        .execute(&mut transaction)
+        .await
+        .context("Failed to update a webhook delivery.")?;
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit SQL transaction to record a webhook delivery attempt.")?;
+        Ok(())
+    }
+}
+
+/// Hex HMAC-SHA256 of `<timestamp>.<body>`. Receivers recompute it with the secret of the
+/// endpoint, and reject old timestamps to thwart replays.
+pub fn sign_payload(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .expect("HMAC can take a key of any size.");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Exponential backoff: the delay after `attempts` failed attempts.
+fn retry_delay(settings: &WebhookSettings, attempts: i32) -> Duration {
+    let exponent = attempts.saturating_sub(1).clamp(0, 31) as u32;
+    let delay = settings
+        .initial_backoff_in_secs
+        .saturating_mul(2u64.saturating_pow(exponent));
+    Duration::from_secs(delay.min(settings.max_backoff_in_secs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{retry_delay, sign_payload};
+    use crate::configuration::WebhookSettings;
+    use std::time::Duration;
+
+    fn settings() -> WebhookSettings {
+        WebhookSettings {
+            poll_interval_in_milli: 1000,
+            timeout_in_milli: 5000,
+            batch_size: 20,
+            max_attempts: 8,
+            initial_backoff_in_secs: 30,
+            max_backoff_in_secs: 3600,
+        }
+    }
+
+    #[test]
+    fn the_retry_delay_doubles_up_to_the_maximum() {
+        let settings = settings();
+        assert_eq!(retry_delay(&settings, 1), Duration::from_secs(30));
+        assert_eq!(retry_delay(&settings, 2), Duration::from_secs(60));
+        assert_eq!(retry_delay(&settings, 4), Duration::from_secs(240));
+        assert_eq!(retry_delay(&settings, 8), Duration::from_secs(3600));
+        assert_eq!(retry_delay(&settings, 100), Duration::from_secs(3600));
+    }
+
+    #[test]
+    fn the_signature_covers_the_timestamp_and_the_body() {
+        let body = br#"{"type":"issue.sent"}"#;
+        assert_eq!(
+            sign_payload("whsec_test", 1_686_000_000, body),
+            "a80c9af9fe3d1fac5e1f2e97a7c4ddebcb62d808b0359752dc7c414e5237f922"
+        );
+        assert_ne!(
+            sign_payload("whsec_test", 1_686_000_001, body),
+            sign_payload("whsec_test", 1_686_000_000, body)
+        );
+    }
+}
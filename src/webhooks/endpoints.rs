@@ -0,0 +1,182 @@
+use super::EventType;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use secrecy::Secret;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Prefix of the signing secrets, to tell them apart from API tokens.
+const SECRET_PREFIX: &str = "whsec_";
+
+pub struct WebhookEndpoint {
+    pub id: Uuid,
+    pub url: String,
+    pub event_types: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub pending_deliveries: i64,
+    pub failed_deliveries: i64,
+}
+
+pub struct DeliveryAttempt {
+    pub event_id: Uuid,
+    pub event_type: String,
+    pub attempted_at: DateTime<Utc>,
+    pub response_status: Option<i16>,
+    pub error: Option<String>,
+    pub duration_ms: i32,
+    /// Status of the delivery as a whole: `pending`, `delivered` or `failed`.
+    pub delivery_status: String,
+}
+
+/// Endpoints receive POST requests: only `http` and `https` URLs make sense.
+pub fn parse_endpoint_url(url: &str) -> Result<reqwest::Url, String> {
+    let parsed = reqwest::Url::parse(url.trim())
+        .map_err(|_| format!("{} is not a valid URL.", url.trim()))?;
+    match parsed.scheme() {
+        "http" | "https" => Ok(parsed),
+        _ => Err("Webhook URLs must use http or https.".into()),
+    }
+}
+
+/// Register an endpoint and return its id and the secret signing its payloads.
+/// Events recorded before its creation are not delivered to it.
+#[tracing::instrument(name = "Create a webhook endpoint", skip(pool))]
+pub async fn create_webhook_endpoint(
+    url: &reqwest::Url,
+    event_types: &[EventType],
+    pool: &PgPool,
+) -> Result<(Uuid, Secret<String>), anyhow::Error> {
+    let id = Uuid::new_v4();
+    let secret = generate_signing_secret();
+    let event_types: Vec<String> = event_types.iter().map(|t| t.as_str().into()).collect();
+    sqlx::query!(
+        r#"
+    INSERT INTO webhook_endpoints (id, url, secret, event_types, created_at)
+    VALUES ($1, $2, $3, $4, now())
+    "#,
+        id,
+        url.as_str(),
+        secret,
+        &event_types,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store a webhook endpoint.")?;
+    Ok((id, Secret::new(secret)))
+}
+
+#[tracing::instrument(name = "Get webhook endpoints", skip(pool))]
+pub async fn get_webhook_endpoints(pool: &PgPool) -> Result<Vec<WebhookEndpoint>, anyhow::Error> {
+    sqlx::query_as!(
+        WebhookEndpoint,
+        r#"
+    SELECT
+        e.id, e.url, e.event_types, e.created_at,
+        count(*) FILTER (WHERE d.status = 'pending') AS "pending_deliveries!",
+        count(*) FILTER (WHERE d.status = 'failed') AS "failed_deliveries!"
+    FROM webhook_endpoints e
+    LEFT JOIN webhook_deliveries d ON d.endpoint_id = e.id
+    GROUP BY e.id
+    ORDER BY e.created_at
+    "#,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to perform a query to list webhook endpoints.")
+}
+
+#[tracing::instrument(name = "Get a webhook endpoint", skip(pool))]
+pub async fn get_webhook_endpoint(
+    endpoint_id: Uuid,
+    pool: &PgPool,
+) -> Result<Option<WebhookEndpoint>, anyhow::Error> {
+    sqlx::query_as!(
+        WebhookEndpoint,
+        r#"
+    SELECT
+        e.id, e.url, e.event_types, e.created_at,
+        count(*) FILTER (WHERE d.status = 'pending') AS "pending_deliveries!",
+        count(*) FILTER (WHERE d.status = 'failed') AS "failed_deliveries!"
+    FROM webhook_endpoints e
+    LEFT JOIN webhook_deliveries d ON d.endpoint_id = e.id
+    WHERE e.id = $1
+    GROUP BY e.id
+    "#,
+        endpoint_id,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to retrieve a webhook endpoint.")
+}
+
+/// Pending deliveries and the delivery log go along with the endpoint.
+/// Returns whether the endpoint existed.
+#[tracing::instrument(name = "Delete a webhook endpoint", skip(pool))]
+pub async fn delete_webhook_endpoint(
+    endpoint_id: Uuid,
+    pool: &PgPool,
+) -> Result<bool, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"DELETE FROM webhook_endpoints WHERE id = $1"#,
+        endpoint_id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete a webhook endpoint.")?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// The most recent delivery attempts to an endpoint, newest first.
+#[tracing::instrument(name = "Get the delivery log of a webhook endpoint", skip(pool))]
+pub async fn get_delivery_log(
+    endpoint_id: Uuid,
+    limit: i64,
+    pool: &PgPool,
+) -> Result<Vec<DeliveryAttempt>, anyhow::Error> {
+    sqlx::query_as!(
+        DeliveryAttempt,
+        r#"
+    SELECT
+        a.event_id, e.event_type, a.attempted_at, a.response_status, a.error, a.duration_ms,
+        d.status AS delivery_status
+    FROM webhook_delivery_attempts a
+    JOIN webhook_deliveries d ON d.event_id = a.event_id AND d.endpoint_id = a.endpoint_id
+    JOIN webhook_events e ON e.id = a.event_id
+    WHERE a.endpoint_id = $1
+    ORDER BY a.attempted_at DESC
+    LIMIT $2
+    "#,
+        endpoint_id,
+        limit,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to perform a query to retrieve the webhook delivery log.")
+}
+
+fn generate_signing_secret() -> String {
+    let mut rng = thread_rng();
+    let random: String = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(32)
+        .collect();
+    format!("{}{}", SECRET_PREFIX, random)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_endpoint_url;
+    use claims::{assert_err, assert_ok};
+
+    #[test]
+    fn only_http_urls_are_accepted() {
+        assert_ok!(parse_endpoint_url(
+            "https://crm.example.com/hooks/newsletter"
+        ));
+        assert_ok!(parse_endpoint_url(" http://127.0.0.1:9000/ "));
+        assert_err!(parse_endpoint_url("ftp://crm.example.com/hooks"));
+        assert_err!(parse_endpoint_url("crm.example.com/hooks"));
+    }
+}
@@ -0,0 +1,48 @@
+//! Outbound webhooks: lifecycle events are written to an outbox in the transaction of the
+//! change they describe, then delivered to the subscribed endpoints by `WebhookDispatcher`.
+mod dispatcher;
+mod endpoints;
+mod outbox;
+
+pub use dispatcher::*;
+pub use endpoints::*;
+pub use outbox::*;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EventType {
+    SubscriberCreated,
+    SubscriberConfirmed,
+    SubscriberUnsubscribed,
+    SubscriberErased,
+    IssueSent,
+}
+
+impl EventType {
+    pub const ALL: [EventType; 5] = [
+        EventType::SubscriberCreated,
+        EventType::SubscriberConfirmed,
+        EventType::SubscriberUnsubscribed,
+        EventType::SubscriberErased,
+        EventType::IssueSent,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventType::SubscriberCreated => "subscriber.created",
+            EventType::SubscriberConfirmed => "subscriber.confirmed",
+            EventType::SubscriberUnsubscribed => "subscriber.unsubscribed",
+            EventType::SubscriberErased => "subscriber.erased",
+            EventType::IssueSent => "issue.sent",
+        }
+    }
+}
+
+impl TryFrom<&str> for EventType {
+    type Error = String;
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        EventType::ALL
+            .into_iter()
+            .find(|event_type| event_type.as_str() == value)
+            .ok_or_else(|| format!("{} is not a supported event type.", value))
+    }
+}
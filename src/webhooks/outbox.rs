@@ -0,0 +1,76 @@
+use super::EventType;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+/// `data` of the `subscriber.*` events.
+#[derive(serde::Serialize)]
+pub struct SubscriberData<'a> {
+    pub id: Uuid,
+    pub email: &'a str,
+    pub name: &'a str,
+}
+
+/// `data` of the `subscriber.erased` event: receivers are expected to erase their copy.
+#[derive(serde::Serialize)]
+pub struct ErasedSubscriberData {
+    pub id: Uuid,
+}
+
+/// `data` of the `issue.sent` event.
+#[derive(serde::Serialize)]
+pub struct IssueData<'a> {
+    pub id: Uuid,
+    pub title: &'a str,
+}
+
+/// The JSON body POSTed to the endpoints.
+#[derive(serde::Serialize)]
+struct Event<T> {
+    id: Uuid,
+    #[serde(rename = "type")]
+    event_type: &'static str,
+    created_at: DateTime<Utc>,
+    data: T,
+}
+
+/// Record an event and schedule its delivery to every endpoint subscribed to its type.
+///
+/// Pass the transaction of the change the event describes: the event is then delivered
+/// if and only if the change is committed.
+#[tracing::instrument(name = "Enqueue a webhook event", skip(executor, data))]
+pub async fn enqueue_event(
+    executor: impl PgExecutor<'_>,
+    event_type: EventType,
+    data: impl serde::Serialize,
+) -> Result<Uuid, anyhow::Error> {
+    let event = Event {
+        id: Uuid::new_v4(),
+        event_type: event_type.as_str(),
+        created_at: Utc::now(),
+        data,
+    };
+    let payload = serde_json::to_value(&event).context("Failed to serialize a webhook event.")?;
+    sqlx::query!(
+        r#"
+    WITH event AS (
+        INSERT INTO webhook_events (id, event_type, payload, created_at)
+        VALUES ($1, $2, $3, $4)
+        RETURNING id, created_at
+    )
+    INSERT INTO webhook_deliveries (event_id, endpoint_id, status, attempts, next_attempt_at)
+    SELECT event.id, webhook_endpoints.id, 'pending', 0, event.created_at
+    FROM event CROSS JOIN webhook_endpoints
+    WHERE $2 = ANY(webhook_endpoints.event_types)
+    "#,
+        event.id,
+        event.event_type,
+        payload,
+        event.created_at,
+    )
+    .execute(executor)
+    .await
+    .context("Failed to store a webhook event in the outbox.")?;
+    Ok(event.id)
+}
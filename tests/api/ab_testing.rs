@@ -0,0 +1,185 @@
+use crate::helpers::AppBootstrap;
+use reqwest::Method;
+use std::time::Duration;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+/// Half of the recipients take part in the test, which ends within a second.
+async fn spawn_app() -> AppBootstrap {
+    let app = AppBootstrap::with_config(|c| {
+        c.ab_testing.test_percent = 50;
+        c.ab_testing.wait_in_secs = 1;
+        c.ab_testing.poll_interval_in_milli = 50;
+    })
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app
+}
+
+async fn create_confirmed_subscriber(app: &AppBootstrap, email: &str) {
+    app.api_create_confirmed_subscriber(email).await;
+}
+
+async fn sent_emails(app: &AppBootstrap) -> Vec<serde_json::Value> {
+    app.email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|r| serde_json::from_slice(&r.body).unwrap())
+        .collect()
+}
+
+/// Wait for the email server to get `count` emails.
+async fn wait_for_emails(app: &AppBootstrap, count: usize) -> Vec<serde_json::Value> {
+    for _ in 0..100 {
+        let emails = sent_emails(app).await;
+        if emails.len() >= count {
+            return emails;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("The email server did not get {} emails.", count);
+}
+
+async fn issue_sent_events(app: &AppBootstrap) -> i64 {
+    sqlx::query!(
+        r#"SELECT count(*) AS "count!" FROM webhook_events WHERE event_type = 'issue.sent'"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .count
+}
+
+/// The tracking pixel of an email, on the test server.
+fn pixel_url(app: &AppBootstrap, email: &serde_json::Value) -> String {
+    let html = email["HtmlBody"].as_str().unwrap();
+    let start = html
+        .find("/open/")
+        .expect("No tracking pixel in the email.");
+    let delivery_id = &html[start + "/open/".len()..][..36];
+    format!("{}/open/{}", app.address, delivery_id)
+}
+
+#[tokio::test]
+async fn an_ab_test_needs_two_to_five_subjects() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app, "ursula_le_guin@gmail.com").await;
+    let issue = app.api_create_issue("Title").await;
+    let publish = format!("/issues/{}/publish", issue["id"].as_str().unwrap());
+    let emails_sent = sent_emails(&app).await.len();
+
+    for body in [
+        serde_json::json!({"subject_variants": ["Only one"]}),
+        serde_json::json!({"subject_variants": ["A", "B", "C", "D", "E", "F"]}),
+        serde_json::json!({"subject_variants": ["A", ""]}),
+    ] {
+        // Act
+        let response = app
+            .api_request(Method::POST, &publish)
+            .json(&body)
+            .send()
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 400, "{} was accepted", body);
+    }
+    assert_eq!(sent_emails(&app).await.len(), emails_sent);
+}
+
+#[tokio::test]
+async fn the_subject_with_the_best_open_rate_goes_to_the_remaining_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    for email in [
+        "ursula_le_guin@gmail.com",
+        "octavia_butler@gmail.com",
+        "n_k_jemisin@gmail.com",
+        "ted_chiang@gmail.com",
+    ] {
+        create_confirmed_subscriber(&app, email).await;
+    }
+    let issue = app.api_create_issue("Title").await;
+    let emails_sent = sent_emails(&app).await.len();
+
+    // Act - Part 1 - Publish
+    let response = app
+        .api_request(
+            Method::POST,
+            &format!("/issues/{}/publish", issue["id"].as_str().unwrap()),
+        )
+        .json(&serde_json::json!({"subject_variants": ["Subject A", "Subject B"]}))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert - Part 1 - One test recipient per variant
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(issue_sent_events(&app).await, 0);
+    let emails = sent_emails(&app).await;
+    let mut test_subjects: Vec<_> = emails[emails_sent..]
+        .iter()
+        .map(|e| e["Subject"].as_str().unwrap())
+        .collect();
+    test_subjects.sort();
+    assert_eq!(test_subjects, ["Subject A", "Subject B"]);
+
+    // Act - Part 2 - Only the recipient of B opens it
+    let opened = emails[emails_sent..]
+        .iter()
+        .find(|e| e["Subject"] == "Subject B")
+        .unwrap();
+    let response = reqwest::get(pixel_url(&app, opened)).await.unwrap();
+
+    // Assert - Part 2
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["Content-Type"], "image/gif");
+
+    // Act - Part 3 - The test ends
+    let emails = wait_for_emails(&app, emails_sent + 4).await;
+
+    // Assert - Part 3
+    for email in &emails[emails_sent + 2..] {
+        assert_eq!(email["Subject"], "Subject B");
+    }
+    let mut recipients: Vec<_> = emails[emails_sent..]
+        .iter()
+        .map(|e| e["To"].as_str().unwrap())
+        .collect();
+    recipients.sort();
+    recipients.dedup();
+    assert_eq!(recipients.len(), 4);
+    // The event follows the last email
+    for _ in 0..100 {
+        if issue_sent_events(&app).await > 0 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert_eq!(issue_sent_events(&app).await, 1);
+
+    // Assert - Part 4 - The results are shown to admins
+    app.login().await;
+    let html = app
+        .api_client
+        .get(format!("{}/admin/ab-tests", &app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html.contains("Test ended."));
+    assert!(html
+        .contains("<tr><td>Subject B</td><td>1</td><td>1</td><td>100.0%</td><td>Winner</td></tr>"));
+    assert!(html.contains("<tr><td>Subject A</td><td>1</td><td>0</td><td>0.0%</td><td></td></tr>"));
+}
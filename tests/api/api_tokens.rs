@@ -0,0 +1,206 @@
+use crate::helpers::{assert_is_redirect_to, AppBootstrap};
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_api_tokens() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    // Act
+    let response = app
+        .post_create_api_token(&[("name", "ci"), ("scope", "issues:publish")])
+        .await;
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn a_token_with_the_publish_scope_can_publish_a_newsletter() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    app.login().await;
+    let token = app.create_api_token(&["issues:publish"]).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletter_with_token(&token, newsletter_request_body())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let last_used_at = sqlx::query!("SELECT last_used_at FROM api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .last_used_at;
+    assert!(last_used_at.is_some());
+}
+
+#[tokio::test]
+async fn tokens_are_stored_hashed_and_shown_only_once() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    app.login().await;
+    // Act
+    let token = app.create_api_token(&["issues:publish"]).await;
+    // Assert
+    let stored = sqlx::query!("SELECT token_hash FROM api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_ne!(stored.token_hash, token);
+    let html_page = app.get_api_tokens_html().await;
+    assert!(html_page.contains("test token"));
+    assert!(!html_page.contains(&token));
+}
+
+#[tokio::test]
+async fn a_token_without_the_publish_scope_is_forbidden() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    app.login().await;
+    let token = app.create_api_token(&["subscribers:read"]).await;
+
+    // Act
+    let response = app
+        .post_newsletter_with_token(&token, newsletter_request_body())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+    assert!(response.headers()["WWW-Authenticate"]
+        .to_str()
+        .unwrap()
+        .contains(r#"error="insufficient_scope""#));
+}
+
+#[tokio::test]
+async fn unknown_tokens_are_rejected() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    // Act
+    let response = app
+        .post_newsletter_with_token("z2p_not-a-real-token", newsletter_request_body())
+        .await;
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(
+        r#"Bearer realm="publish", error="invalid_token""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[tokio::test]
+async fn revoked_tokens_are_rejected() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    app.login().await;
+    let token = app.create_api_token(&["issues:publish"]).await;
+    let token_id = sqlx::query!("SELECT id FROM api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id;
+
+    // Act - Part 1 - Revoke
+    let response = app.post_revoke_api_token(token_id).await;
+    assert_is_redirect_to(&response, "/admin/tokens");
+    let html_page = app.get_api_tokens_html().await;
+    assert!(html_page.contains("<p><i>The API token has been revoked.</i></p>"));
+
+    // Act - Part 2 - Use the revoked token
+    let response = app
+        .post_newsletter_with_token(&token, newsletter_request_body())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn a_token_with_the_read_scope_can_list_subscribers() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    app.login().await;
+    let token = app.create_api_token(&["subscribers:read"]).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let subscriber = app.api_create_subscriber("ursula_le_guin@gmail.com").await;
+    let client = reqwest::Client::new();
+
+    // Act
+    let list = client
+        .get(app.api_url("/subscribers"))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .unwrap();
+    let single = client
+        .get(app.api_url(&format!(
+            "/subscribers/{}",
+            subscriber["id"].as_str().unwrap()
+        )))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(list.status().as_u16(), 200);
+    assert_eq!(single.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn a_token_with_the_read_scope_cannot_change_subscribers() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    app.login().await;
+    let token = app.create_api_token(&["subscribers:read"]).await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(app.api_url("/subscribers"))
+        .bearer_auth(&token)
+        .json(&serde_json::json!({ "name": "le guin", "email": "ursula_le_guin@gmail.com" }))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn a_token_without_the_read_scope_cannot_list_subscribers() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    app.login().await;
+    let token = app.create_api_token(&["issues:publish"]).await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .get(app.api_url("/subscribers"))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+}
@@ -0,0 +1,625 @@
+use crate::helpers::AppBootstrap;
+use uuid::Uuid;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+impl AppBootstrap {
+    pub fn api_url(&self, resource: &str) -> String {
+        format!("{}/api/v1{}", &self.address, resource)
+    }
+
+    /// A request to the JSON API, authenticated with the `Basic` credentials of the test user.
+    pub fn api_request(&self, method: reqwest::Method, resource: &str) -> reqwest::RequestBuilder {
+        self.api_client
+            .request(method, self.api_url(resource))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+    }
+
+    pub async fn api_create_subscriber(&self, email: &str) -> serde_json::Value {
+        let response = self
+            .api_request(reqwest::Method::POST, "/subscribers")
+            .json(&serde_json::json!({ "name": "le guin", "email": email }))
+            .send()
+            .await
+            .expect("Failed to execute request.");
+        assert_eq!(response.status().as_u16(), 201);
+        response.json().await.unwrap()
+    }
+
+    /// A subscriber confirmed the only way there is: by following the link mailed to them.
+    /// The email server must accept the confirmation email.
+    pub async fn api_create_confirmed_subscriber(&self, email: &str) -> serde_json::Value {
+        let created = self.api_create_subscriber(email).await;
+        let email_request = self
+            .email_server
+            .received_requests()
+            .await
+            .unwrap()
+            .into_iter()
+            .rev()
+            .find(|r| {
+                let body: serde_json::Value = serde_json::from_slice(&r.body).unwrap();
+                body["To"] == email
+            })
+            .expect("No confirmation email was sent.");
+        let confirmation_link = self.get_confirmation_links(&email_request).html;
+        reqwest::get(confirmation_link)
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+        self.api_request(
+            reqwest::Method::GET,
+            &format!("/subscribers/{}", created["id"].as_str().unwrap()),
+        )
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap()
+    }
+
+    pub async fn api_create_issue(&self, title: &str) -> serde_json::Value {
+        let response = self
+            .api_request(reqwest::Method::POST, "/issues")
+            .json(&serde_json::json!({
+                "title": title,
+                "content": {"html": "<p>Body</p>", "text": "Body"}
+            }))
+            .send()
+            .await
+            .expect("Failed to execute request.");
+        assert_eq!(response.status().as_u16(), 201);
+        response.json().await.unwrap()
+    }
+}
+
+async fn mount_email_server(app: &AppBootstrap) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+}
+
+async fn assert_error_code(response: reqwest::Response, status: u16, code: &str) {
+    assert_eq!(response.status().as_u16(), status);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "application/problem+json"
+    );
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], status);
+    assert_eq!(body["code"], code);
+    assert!(body["detail"].is_string());
+    assert!(body["request_id"].is_string());
+}
+
+#[tokio::test]
+async fn anonymous_clients_are_rejected_with_a_json_error() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(app.api_url("/subscribers"))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(
+        response.headers()["WWW-Authenticate"],
+        r#"Basic realm="api""#
+    );
+    assert_error_code(response, 401, "unauthorized").await;
+}
+
+#[tokio::test]
+async fn invalid_credentials_are_rejected() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(app.api_url("/issues"))
+        .basic_auth(&app.test_user.username, Some("wrong-password"))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_error_code(response, 401, "unauthorized").await;
+}
+
+#[tokio::test]
+async fn logged_in_admins_can_use_their_session() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    app.login().await;
+    let new_issue = serde_json::json!({
+        "title": "Title",
+        "content": {"html": "<p>Body</p>", "text": "Body"}
+    });
+
+    // Act - Part 1 - Reads only need the session cookie
+    let response = app
+        .api_client
+        .get(app.api_url("/issues"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Act - Part 2 - Writes need the CSRF token too
+    let response = app
+        .api_client
+        .post(app.api_url("/issues"))
+        .json(&new_issue)
+        .send()
+        .await
+        .unwrap();
+    assert_error_code(response, 403, "forbidden").await;
+    let response = app
+        .api_client
+        .post(app.api_url("/issues"))
+        .header("X-CSRF-Token", app.csrf_token().await)
+        .json(&new_issue)
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 201);
+}
+
+#[tokio::test]
+async fn subscribers_can_be_created_read_updated_and_deleted() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Create, which sends the confirmation email
+    let created = app.api_create_subscriber("ursula_le_guin@gmail.com").await;
+    assert_eq!(created["email"], "ursula_le_guin@gmail.com");
+    assert_eq!(created["status"], "pending_confirmation");
+    let resource = format!("/subscribers/{}", created["id"].as_str().unwrap());
+
+    // Act - Part 2 - Read
+    let fetched: serde_json::Value = app
+        .api_request(reqwest::Method::GET, &resource)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(fetched, created);
+
+    // Act - Part 3 - Update
+    let response = app
+        .api_request(reqwest::Method::PATCH, &resource)
+        .json(&serde_json::json!({"name": "Ursula"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    let updated: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(updated["name"], "Ursula");
+    assert_eq!(updated["status"], "pending_confirmation");
+    assert_eq!(updated["email"], "ursula_le_guin@gmail.com");
+
+    // Act - Part 4 - Delete
+    let response = app
+        .api_request(reqwest::Method::DELETE, &resource)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 204);
+
+    // Assert
+    // Kept as unsubscribed, along with their consent log.
+    let response = app
+        .api_request(reqwest::Method::GET, &resource)
+        .send()
+        .await
+        .unwrap();
+    let deleted: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(deleted["status"], "unsubscribed");
+    let response = app
+        .api_request(reqwest::Method::DELETE, &resource)
+        .send()
+        .await
+        .unwrap();
+    assert_error_code(response, 404, "not_found").await;
+    let response = app
+        .api_request(reqwest::Method::PATCH, &resource)
+        .json(&serde_json::json!({"name": "Ursula Le Guin"}))
+        .send()
+        .await
+        .unwrap();
+    assert_error_code(response, 409, "conflict").await;
+}
+
+#[tokio::test]
+async fn the_status_of_a_subscriber_cannot_be_patched() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    mount_email_server(&app).await;
+    let created = app.api_create_subscriber("ursula_le_guin@gmail.com").await;
+    let resource = format!("/subscribers/{}", created["id"].as_str().unwrap());
+
+    for status in ["confirmed", "unsubscribed", "pending_confirmation"] {
+        // Act
+        let response = app
+            .api_request(reqwest::Method::PATCH, &resource)
+            .json(&serde_json::json!({"name": "Ursula", "status": status}))
+            .send()
+            .await
+            .unwrap();
+
+        // Assert
+        assert_error_code(response, 400, "validation_error").await;
+    }
+    let subscriber = sqlx::query!("SELECT name, status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscriber.name, "le guin");
+    assert_eq!(subscriber.status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn a_patched_email_only_applies_once_the_new_address_is_confirmed() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    mount_email_server(&app).await;
+    let subscriber = app
+        .api_create_confirmed_subscriber("ursula_le_guin@gmail.com")
+        .await;
+    let resource = format!("/subscribers/{}", subscriber["id"].as_str().unwrap());
+
+    // Act - Part 1 - Ask for a new address
+    let response = app
+        .api_request(reqwest::Method::PATCH, &resource)
+        .json(&serde_json::json!({"email": "ursula@gmail.com"}))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert - Part 1
+    assert_eq!(response.status().as_u16(), 200);
+    let updated: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(updated["email"], "ursula_le_guin@gmail.com");
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["To"], "ursula@gmail.com");
+
+    // Act - Part 2 - Its owner follows the link
+    let confirmation_link = app.get_confirmation_links(&email_request).html;
+    reqwest::get(confirmation_link)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Assert - Part 2
+    let fetched: serde_json::Value = app
+        .api_request(reqwest::Method::GET, &resource)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(fetched["email"], "ursula@gmail.com");
+    assert_eq!(fetched["status"], "confirmed");
+    let event = sqlx::query!(
+        "SELECT event_type, payload FROM webhook_events ORDER BY created_at DESC LIMIT 1"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(event.event_type, "subscriber.confirmed");
+    assert_eq!(event.payload["data"]["email"], "ursula@gmail.com");
+}
+
+#[tokio::test]
+async fn creating_a_subscriber_with_a_taken_email_is_a_conflict() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    mount_email_server(&app).await;
+    app.api_create_subscriber("ursula_le_guin@gmail.com").await;
+
+    // Act
+    let response = app
+        .api_request(reqwest::Method::POST, "/subscribers")
+        .json(&serde_json::json!({"name": "le guin", "email": "Ursula_Le_Guin@gmail.com"}))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_error_code(response, 409, "conflict").await;
+}
+
+#[tokio::test]
+async fn invalid_payloads_are_rejected_with_a_json_error() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    let test_cases = vec![
+        (
+            reqwest::Method::POST,
+            "/subscribers".to_string(),
+            serde_json::json!({"name": "le guin", "email": "definitely-not-an-email"}),
+        ),
+        (
+            reqwest::Method::POST,
+            "/subscribers".to_string(),
+            serde_json::json!({"name": "le guin"}),
+        ),
+        (
+            reqwest::Method::POST,
+            "/issues".to_string(),
+            serde_json::json!({"title": " ", "content": {"html": "", "text": ""}}),
+        ),
+        (
+            reqwest::Method::PATCH,
+            format!("/subscribers/{}", Uuid::new_v4()),
+            serde_json::json!({"status": "unknown"}),
+        ),
+    ];
+
+    for (method, resource, body) in test_cases {
+        // Act
+        let response = app
+            .api_request(method, &resource)
+            .json(&body)
+            .send()
+            .await
+            .unwrap();
+
+        // Assert
+        assert_error_code(response, 400, "validation_error").await;
+    }
+}
+
+#[tokio::test]
+async fn malformed_ids_and_query_parameters_are_rejected_with_a_json_error() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+
+    for resource in [
+        "/subscribers/not-a-uuid",
+        "/subscribers?status=unknown",
+        "/issues?limit=0",
+        "/issues?cursor=garbage",
+    ] {
+        // Act
+        let response = app
+            .api_request(reqwest::Method::GET, resource)
+            .send()
+            .await
+            .unwrap();
+
+        // Assert
+        assert_error_code(response, 400, "validation_error").await;
+    }
+}
+
+#[tokio::test]
+async fn subscribers_are_paginated_with_a_cursor() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    mount_email_server(&app).await;
+    for i in 0..5 {
+        app.api_create_subscriber(&format!("ursula{}@gmail.com", i))
+            .await;
+    }
+
+    // Act
+    let mut emails = Vec::new();
+    let mut resource = "/subscribers?limit=2".to_string();
+    let mut pages = 0;
+    loop {
+        let page: serde_json::Value = app
+            .api_request(reqwest::Method::GET, &resource)
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        pages += 1;
+        for subscriber in page["data"].as_array().unwrap() {
+            emails.push(subscriber["email"].as_str().unwrap().to_string());
+        }
+        match page["next_cursor"].as_str() {
+            Some(cursor) => resource = format!("/subscribers?limit=2&cursor={}", cursor),
+            None => break,
+        }
+    }
+
+    // Assert
+    assert_eq!(pages, 3);
+    let expected: Vec<_> = (0..5).map(|i| format!("ursula{}@gmail.com", i)).collect();
+    assert_eq!(emails, expected);
+}
+
+#[tokio::test]
+async fn subscribers_can_be_filtered_by_status_and_date() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    mount_email_server(&app).await;
+    let pending = app.api_create_subscriber("pending@gmail.com").await;
+    let confirmed = app
+        .api_create_confirmed_subscriber("confirmed@gmail.com")
+        .await;
+
+    let list = |query: String| {
+        let request = app.api_request(reqwest::Method::GET, &format!("/subscribers?{}", query));
+        async move {
+            let page: serde_json::Value = request.send().await.unwrap().json().await.unwrap();
+            page["data"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|s| s["email"].as_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        }
+    };
+
+    // Act & Assert
+    assert_eq!(
+        list("status=confirmed".into()).await,
+        vec!["confirmed@gmail.com"]
+    );
+    assert_eq!(
+        list("status=pending_confirmation".into()).await,
+        vec!["pending@gmail.com"]
+    );
+    let boundary = urlencoding(confirmed["subscribed_at"].as_str().unwrap());
+    assert_eq!(
+        list(format!("subscribed_before={}", boundary)).await,
+        vec![pending["email"].as_str().unwrap()]
+    );
+    assert_eq!(
+        list(format!("subscribed_after={}", boundary)).await,
+        vec!["confirmed@gmail.com"]
+    );
+}
+
+/// RFC 3339 timestamps contain a `+` that would otherwise be decoded as a space.
+fn urlencoding(value: &str) -> String {
+    value.replace('+', "%2B")
+}
+
+#[tokio::test]
+async fn draft_issues_can_be_edited_published_and_then_frozen() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        // The confirmation email, then the issue
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    app.api_create_confirmed_subscriber("ursula_le_guin@gmail.com")
+        .await;
+
+    // Act - Part 1 - Create and edit a draft
+    let draft = app.api_create_issue("Draft title").await;
+    assert_eq!(draft["status"], "draft");
+    assert!(draft["published_at"].is_null());
+    let resource = format!("/issues/{}", draft["id"].as_str().unwrap());
+    let response = app
+        .api_request(reqwest::Method::PATCH, &resource)
+        .json(&serde_json::json!({"title": "Final title", "content": {"text": "New body"}}))
+        .send()
+        .await
+        .unwrap();
+    let edited: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(edited["title"], "Final title");
+    assert_eq!(edited["content"]["text"], "New body");
+    assert_eq!(edited["content"]["html"], "<p>Body</p>");
+
+    // Act - Part 2 - Publish, which sends it to confirmed subscribers
+    let response = app
+        .api_request(reqwest::Method::POST, &format!("{}/publish", resource))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    let published: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(published["status"], "published");
+
+    // Assert
+    let response = app
+        .api_request(reqwest::Method::POST, &format!("{}/publish", resource))
+        .send()
+        .await
+        .unwrap();
+    assert_error_code(response, 409, "conflict").await;
+    let response = app
+        .api_request(reqwest::Method::PATCH, &resource)
+        .json(&serde_json::json!({"title": "Too late"}))
+        .send()
+        .await
+        .unwrap();
+    assert_error_code(response, 409, "conflict").await;
+    let response = app
+        .api_request(reqwest::Method::DELETE, &resource)
+        .send()
+        .await
+        .unwrap();
+    assert_error_code(response, 409, "conflict").await;
+    // Mock verifies on Drop that we have sent the issue once
+}
+
+#[tokio::test]
+async fn issues_can_be_filtered_by_status() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    app.api_create_issue("Draft").await;
+    let response = app
+        .post_newsletter(serde_json::json!({
+            "title": "Sent right away",
+            "content": {"html": "<p>Body</p>", "text": "Body"}
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    for (status, title) in [("draft", "Draft"), ("published", "Sent right away")] {
+        // Act
+        let page: serde_json::Value = app
+            .api_request(reqwest::Method::GET, &format!("/issues?status={}", status))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        // Assert
+        let issues = page["data"].as_array().unwrap();
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0]["title"], title);
+        assert!(page["next_cursor"].is_null());
+    }
+}
+
+#[tokio::test]
+async fn deleting_an_unknown_issue_is_not_found() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+
+    // Act
+    let response = app
+        .api_request(
+            reqwest::Method::DELETE,
+            &format!("/issues/{}", Uuid::new_v4()),
+        )
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_error_code(response, 404, "not_found").await;
+}
@@ -0,0 +1,249 @@
+use sha2::{Digest, Sha256};
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::AppBootstrap;
+use uuid::Uuid;
+use zero2prod::configuration::RateLimitBackend;
+
+async fn post_subscription_form(app: &AppBootstrap, form: &[(&str, &str)]) -> reqwest::Response {
+    let mut body = vec![("name", "le guin"), ("email", "ursula_le_guin@gmail.com")];
+    body.extend_from_slice(form);
+    app.api_client
+        .post(format!("{}/subscriptions", &app.address))
+        .form(&body)
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+async fn get_home_html(app: &AppBootstrap) -> String {
+    app.api_client
+        .get(&app.address)
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .text()
+        .await
+        .unwrap()
+}
+
+/// `form_token` is a hidden input of the home page form.
+fn extract_form_token(html: &str) -> String {
+    let start = html.find(r#"name="form_token" value=""#).unwrap() + 25;
+    let end = start + html[start..].find('"').unwrap();
+    html[start..end].to_string()
+}
+
+async fn expect_no_email(app: &AppBootstrap) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+}
+
+async fn assert_no_subscriber(app: &AppBootstrap) {
+    let saved = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_optional(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(saved.is_none());
+}
+
+#[tokio::test]
+async fn the_home_page_embeds_a_signed_form_token_and_a_honeypot() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+
+    // Act
+    let html = get_home_html(&app).await;
+
+    // Assert
+    assert!(html.contains(r#"<form action="/subscriptions" method="post">"#));
+    assert_eq!(extract_form_token(&html).split('.').count(), 3);
+    assert!(html.contains(r#"name="website""#));
+    assert!(!html.contains("proof_of_work.js"));
+}
+
+#[tokio::test]
+async fn a_filled_honeypot_is_accepted_but_not_emailed() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    expect_no_email(&app).await;
+
+    // Act
+    let form_token = app.form_token();
+    let response = post_subscription_form(
+        &app,
+        &[("form_token", &form_token), ("website", "http://spam.com")],
+    )
+    .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_no_subscriber(&app).await;
+}
+
+#[tokio::test]
+async fn a_submission_without_form_token_is_accepted_but_not_emailed() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    expect_no_email(&app).await;
+
+    // Act
+    let response = post_subscription_form(&app, &[]).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_no_subscriber(&app).await;
+}
+
+#[tokio::test]
+async fn a_form_submitted_right_after_loading_is_accepted_but_not_emailed() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    expect_no_email(&app).await;
+    let form_token = extract_form_token(&get_home_html(&app).await);
+
+    // Act
+    let response = post_subscription_form(&app, &[("form_token", &form_token)]).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_no_subscriber(&app).await;
+}
+
+#[tokio::test]
+async fn a_tampered_form_token_is_accepted_but_not_emailed() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    expect_no_email(&app).await;
+    let form_token = app.form_token();
+    let (_, signature) = form_token.split_once('.').unwrap();
+    let backdated = format!("0.{}", signature);
+
+    // Act
+    let response = post_subscription_form(&app, &[("form_token", &backdated)]).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_no_subscriber(&app).await;
+}
+
+#[tokio::test]
+async fn a_form_token_is_only_accepted_once() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let form_token = app.form_token();
+    post_subscription_form(&app, &[("form_token", &form_token)]).await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/subscriptions", &app.address))
+        .form(&[
+            ("name", "octavia"),
+            ("email", "octavia_butler@gmail.com"),
+            ("form_token", &form_token),
+        ])
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved: Vec<String> = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.email)
+        .collect();
+    assert_eq!(saved, ["ursula_le_guin@gmail.com"]);
+}
+
+#[tokio::test]
+async fn used_form_tokens_are_shared_through_redis() {
+    // Arrange
+    let key_prefix = format!("rate_limit_{}", Uuid::new_v4());
+    let configure = |c: &mut zero2prod::configuration::Settings| {
+        c.rate_limit.backend = RateLimitBackend::Redis;
+        c.rate_limit.key_prefix = key_prefix.clone();
+    };
+    let first_instance = AppBootstrap::with_config(configure).await;
+    let second_instance = AppBootstrap::with_config(configure).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&first_instance.email_server)
+        .await;
+    expect_no_email(&second_instance).await;
+    let form_token = first_instance.form_token();
+    let response = post_subscription_form(&first_instance, &[("form_token", &form_token)]).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Act
+    let response = post_subscription_form(&second_instance, &[("form_token", &form_token)]).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_no_subscriber(&second_instance).await;
+}
+
+#[tokio::test]
+async fn a_proof_of_work_is_required_when_enabled() {
+    // Arrange
+    let app = AppBootstrap::with_config(|c| {
+        c.bot_protection.proof_of_work_difficulty = Some(8);
+    })
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let form_token = app.form_token();
+    let nonce = (0..)
+        .map(|n: u32| n.to_string())
+        .find(|n| Sha256::digest(format!("{}:{}", form_token, n).as_bytes())[0] == 0)
+        .unwrap();
+
+    // Act - Part 1 - The form loads the solver
+    let html = get_home_html(&app).await;
+    assert!(html.contains(r#"data-pow-difficulty="8""#));
+    assert!(html.contains(r#"<script src="/proof_of_work.js"></script>"#));
+    let script = app
+        .api_client
+        .get(format!("{}/proof_of_work.js", &app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(script.status().as_u16(), 200);
+
+    // Act - Part 2 - Submit without solving the challenge
+    let response = post_subscription_form(&app, &[("form_token", &form_token)]).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_no_subscriber(&app).await;
+
+    // Act - Part 3 - Submit with a solution
+    let response = post_subscription_form(
+        &app,
+        &[("form_token", &form_token), ("proof_of_work", &nonce)],
+    )
+    .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    // Mock verifies on Drop that we have sent a single email
+}
@@ -122,3 +122,56 @@ async fn changing_password_works() {
     let response = app.post_login(&login_body).await;
     assert_is_redirect_to(&response, "/admin/dashboard")
 }
+
+#[tokio::test]
+async fn new_password_must_satisfy_the_password_policy() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    // Act - Part 1 - Login
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    }))
+    .await;
+    // Act - Part 2 - Try to change password
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": &app.test_user.password,
+            "new_password": "admin",
+            "new_password_check": "admin",
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/password");
+    // Act - Part 3 - Follow the redirect
+    let html_page = app.get_change_password_html().await;
+    assert!(
+        html_page.contains("<p><i>The new password must be at least 12 characters long.</i></p>")
+    );
+    assert!(html_page.contains("<p><i>The new password is too common.</i></p>"));
+}
+
+#[tokio::test]
+async fn the_password_policy_is_not_checked_without_the_current_password() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    let wrong_password = Uuid::new_v4().to_string();
+    // Act - Part 1 - Login
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    }))
+    .await;
+    // Act - Part 2 - Try to change password
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": &wrong_password,
+            "new_password": "admin",
+            "new_password_check": "admin",
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/password");
+    // Act - Part 3 - Follow the redirect
+    let html_page = app.get_change_password_html().await;
+    assert!(html_page.contains("<p><i>The current password is incorrect.</i></p>"));
+    assert!(!html_page.contains("The new password"));
+}
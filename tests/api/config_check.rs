@@ -0,0 +1,60 @@
+use std::process::{Command, Output};
+
+fn config_check(env: &[(&str, &str)]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_zero2prod"))
+        .args(["config", "check"])
+        .envs(env.iter().copied())
+        .output()
+        .expect("Failed to run `zero2prod config check`")
+}
+
+#[test]
+fn config_check_prints_the_merged_configuration_without_secrets() {
+    // Act
+    let output = config_check(&[("APP_EMAIL__AUTH_TOKEN", "super-secret-token")]);
+
+    // Assert
+    assert!(output.status.success());
+    let config: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    // From `local.yaml`, over `base.yaml`.
+    assert_eq!(config["application"]["host"], "127.0.0.1");
+    assert_eq!(config["email"]["auth_token"], "[REDACTED]");
+    assert_eq!(config["application"]["hmac_secret"], "[REDACTED]");
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("super-secret-token"));
+}
+
+#[test]
+fn config_check_fails_with_every_problem_found() {
+    // Act
+    let output = config_check(&[
+        ("APP_EMAIL__SENDER_EMAIL", "not-an-email"),
+        ("APP_APPLICATION__BASE_URL", "localhost"),
+    ]);
+
+    // Assert
+    assert_eq!(output.status.code(), Some(1));
+    let report = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        report.contains("The configuration is invalid:"),
+        "{}",
+        report
+    );
+    assert!(report.contains("`application.base_url` is not a valid URL"));
+    assert!(report.contains("`email.sender_email` is invalid"));
+}
+
+#[test]
+fn config_check_reports_an_unknown_environment_without_panicking() {
+    // Act
+    let output = config_check(&[("APP_ENVIRONMENT", "staging")]);
+
+    // Assert
+    assert_eq!(output.status.code(), Some(1));
+    let report = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        report.contains("Failed to parse APP_ENVIRONMENT: staging is not a supported environment"),
+        "{}",
+        report
+    );
+    assert!(!report.contains("panicked"), "{}", report);
+}
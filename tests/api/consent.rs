@@ -0,0 +1,204 @@
+use crate::helpers::{assert_is_redirect_to, AppBootstrap};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::consent::CONSENT_TEXT_VERSION;
+
+impl AppBootstrap {
+    async fn get_subscriber_admin_page(&self, subscriber_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/subscribers/{}",
+                &self.address, subscriber_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+}
+
+async fn mount_email_server(app: &AppBootstrap) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+}
+
+async fn subscriber_id(app: &AppBootstrap) -> Uuid {
+    sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id
+}
+
+#[tokio::test]
+async fn signup_and_confirmation_are_recorded_with_their_evidence() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    mount_email_server(&app).await;
+
+    // Act
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_link = app.get_confirmation_links(email_request).html;
+    let client = reqwest::Client::builder()
+        .user_agent("mail-client/1.0")
+        .build()
+        .unwrap();
+    client
+        .get(confirmation_link.clone())
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    // Following the link again confirms nothing new.
+    client.get(confirmation_link).send().await.unwrap();
+
+    // Assert
+    let records = sqlx::query!(
+        r#"
+        SELECT action, source, ip, user_agent, consent_text_version
+        FROM subscriber_consents ORDER BY recorded_at
+        "#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].action, "signup");
+    assert_eq!(records[0].source, "form");
+    assert_eq!(records[0].ip.as_deref(), Some("127.0.0.1"));
+    assert_eq!(
+        records[0].consent_text_version.as_deref(),
+        Some(CONSENT_TEXT_VERSION)
+    );
+    assert_eq!(records[1].action, "confirmation");
+    assert_eq!(records[1].source, "email");
+    assert_eq!(records[1].user_agent.as_deref(), Some("mail-client/1.0"));
+}
+
+#[tokio::test]
+async fn subscribers_created_through_the_api_are_recorded_without_a_consent_text() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    mount_email_server(&app).await;
+
+    // Act
+    app.api_client
+        .post(format!("{}/api/v1/subscribers", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .json(&serde_json::json!({ "name": "le guin", "email": "ursula_le_guin@gmail.com" }))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    let record = sqlx::query!("SELECT source, consent_text_version FROM subscriber_consents")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(record.source, "api");
+    assert_eq!(record.consent_text_version, None);
+}
+
+#[tokio::test]
+async fn the_consent_log_is_append_only() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    mount_email_server(&app).await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let update = sqlx::query!("UPDATE subscriber_consents SET ip = '10.0.0.1'")
+        .execute(&app.db_pool)
+        .await;
+    let delete = sqlx::query!("DELETE FROM subscriber_consents")
+        .execute(&app.db_pool)
+        .await;
+
+    // Assert
+    assert!(update.is_err());
+    assert!(delete.is_err());
+    // Records still go away with their subscriber.
+    sqlx::query!("DELETE FROM subscriptions")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let remaining = sqlx::query!("SELECT id FROM subscriber_consents")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(remaining.is_empty());
+}
+
+#[tokio::test]
+async fn admins_can_see_the_consent_log_of_a_subscriber() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    mount_email_server(&app).await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    let subscriber_id = subscriber_id(&app).await;
+
+    // Act - Part 1 - Anonymous
+    let response = app.get_subscriber_admin_page(subscriber_id).await;
+
+    // Assert - Part 1
+    assert_is_redirect_to(&response, "/login");
+
+    // Act - Part 2 - Logged in
+    app.login().await;
+    let html_page = app
+        .get_subscriber_admin_page(subscriber_id)
+        .await
+        .text()
+        .await
+        .unwrap();
+
+    // Assert - Part 2
+    assert!(html_page.contains("ursula_le_guin@gmail.com"));
+    assert!(html_page.contains("<td>signup</td><td>form</td><td>127.0.0.1</td>"));
+    assert!(html_page.contains(CONSENT_TEXT_VERSION));
+}
+
+#[tokio::test]
+async fn the_recorded_ip_is_only_taken_from_the_header_of_a_trusted_proxy() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    mount_email_server(&app).await;
+
+    // Act
+    app.api_client
+        .post(format!("{}/subscriptions", &app.address))
+        .header("X-Forwarded-For", "203.0.113.7")
+        .form(&[
+            ("name", "le guin"),
+            ("email", "ursula_le_guin@gmail.com"),
+            ("form_token", &app.form_token()),
+        ])
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    let record = sqlx::query!("SELECT ip FROM subscriber_consents")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(record.ip.as_deref(), Some("127.0.0.1"));
+}
@@ -0,0 +1,78 @@
+use crate::helpers::{assert_is_redirect_to, extract_csrf_token, AppBootstrap};
+
+#[tokio::test]
+async fn every_form_embeds_a_csrf_token() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    // Act
+    let login_page = app.get_login_html().await;
+    app.login().await;
+    let dashboard_page = app.get_admin_dashboard_html().await;
+    let password_page = app.get_change_password_html().await;
+    // Assert - the token is per session, so every page carries the same one
+    let token = extract_csrf_token(&login_page);
+    assert_eq!(extract_csrf_token(&dashboard_page), token);
+    assert_eq!(extract_csrf_token(&password_page), token);
+}
+
+#[tokio::test]
+async fn login_without_a_csrf_token_is_rejected() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/login", &app.address))
+        .form(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .send()
+        .await
+        .unwrap();
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>Your form has expired. Please try again.</i></p>"));
+}
+
+#[tokio::test]
+async fn login_with_the_csrf_token_in_the_form_body_works() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    let token = app.csrf_token().await;
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/login", &app.address))
+        .form(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password,
+            "csrf_token": token,
+        }))
+        .send()
+        .await
+        .unwrap();
+    // Assert
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn admin_forms_with_a_wrong_csrf_token_are_rejected() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    app.login().await;
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/admin/logout", &app.address))
+        .form(&serde_json::json!({ "csrf_token": "forged" }))
+        .send()
+        .await
+        .unwrap();
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+    // We are still logged in
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+}
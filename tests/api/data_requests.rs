@@ -0,0 +1,243 @@
+use crate::helpers::{assert_is_redirect_to, AppBootstrap};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+impl AppBootstrap {
+    async fn post_data_request(&self, email: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/privacy", &self.address))
+            .form(&[("email", email)])
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    async fn get_data_request_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/privacy", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    /// Subscribe, then ask for a data request link and return its token.
+    async fn data_request_token(&self) -> String {
+        Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&self.email_server)
+            .await;
+        self.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+            .await
+            .error_for_status()
+            .unwrap();
+        self.post_data_request("ursula_le_guin@gmail.com").await;
+        let email_requests = self.email_server.received_requests().await.unwrap();
+        let link = self
+            .get_confirmation_links(email_requests.last().unwrap())
+            .html;
+        assert_eq!(link.path(), "/privacy/data");
+        link.query_pairs()
+            .find(|(key, _)| key == "token")
+            .map(|(_, token)| token.into_owned())
+            .expect("No token in the data request link.")
+    }
+}
+
+#[tokio::test]
+async fn subscribers_are_emailed_a_link_and_strangers_are_not() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    let token = app.data_request_token().await;
+    let emails_sent = app.email_server.received_requests().await.unwrap().len();
+
+    // Act - Part 1 - Ask for an address that is not subscribed
+    let response = app.post_data_request("stranger@gmail.com").await;
+
+    // Assert - Part 1
+    assert_is_redirect_to(&response, "/privacy");
+    let html_page = app.get_data_request_html().await;
+    assert!(html_page.contains("If stranger@gmail.com is subscribed, we sent it a link"));
+    assert_eq!(
+        app.email_server.received_requests().await.unwrap().len(),
+        emails_sent
+    );
+
+    // Act - Part 2 - Follow the link mailed to the subscriber
+    let html_page = app
+        .api_client
+        .get(format!("{}/privacy/data?token={}", &app.address, token))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+
+    // Assert - Part 2
+    assert!(html_page.contains("Data held about ursula_le_guin@gmail.com"));
+}
+
+#[tokio::test]
+async fn the_export_is_a_json_attachment() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    let token = app.data_request_token().await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/privacy/export?token={}", &app.address, token))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["Content-Disposition"],
+        "attachment; filename=\"subscriber-data.json\""
+    );
+    let export: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(export["subscriber"]["email"], "ursula_le_guin@gmail.com");
+    assert_eq!(export["subscriber"]["status"], "pending_confirmation");
+    assert_eq!(export["consent_log"][0]["action"], "signup");
+    assert_eq!(export["webhook_events"][0]["type"], "subscriber.created");
+}
+
+#[tokio::test]
+async fn erasure_removes_the_subscriber_and_blocks_re_adding_them() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    let token = app.data_request_token().await;
+
+    // Act - Part 1 - Erase
+    let response = app
+        .api_client
+        .post(format!("{}/privacy/erase", &app.address))
+        .form(&[("token", &token)])
+        .send()
+        .await
+        .unwrap();
+
+    // Assert - Part 1
+    assert_eq!(response.status().as_u16(), 200);
+    let subscribers = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(subscribers.is_empty());
+    let tokens = sqlx::query!("SELECT subscription_token FROM subscription_tokens")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(tokens.is_empty());
+    let events = sqlx::query!("SELECT event_type, payload FROM webhook_events ORDER BY created_at")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[1].event_type, "subscriber.erased");
+    assert!(!events[0].payload.to_string().contains("ursula_le_guin"));
+
+    // Act - Part 2 - The link is spent
+    let response = app
+        .api_client
+        .get(format!("{}/privacy/export?token={}", &app.address, token))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert - Part 2
+    assert_eq!(response.status().as_u16(), 404);
+
+    // Act - Part 3 - The API will not re-add them
+    let response = app
+        .api_client
+        .post(format!("{}/api/v1/subscribers", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .json(&serde_json::json!({ "name": "le guin", "email": "Ursula_Le_Guin@gmail.com" }))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert - Part 3
+    assert_eq!(response.status().as_u16(), 409);
+
+    // Act - Part 4 - Nor move another subscriber to their address
+    let other = app.api_create_subscriber("le_guin@gmail.com").await;
+    let response = app
+        .api_client
+        .patch(format!(
+            "{}/api/v1/subscribers/{}",
+            &app.address,
+            other["id"].as_str().unwrap()
+        ))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .json(&serde_json::json!({ "email": "ursula_le_guin@gmail.com" }))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert - Part 4
+    assert_eq!(response.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn the_subscription_form_does_not_re_add_erased_subscribers() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    let token = app.data_request_token().await;
+    app.api_client
+        .post(format!("{}/privacy/erase", &app.address))
+        .form(&[("token", &token)])
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let emails_sent = app.email_server.received_requests().await.unwrap().len();
+
+    // Act
+    let response = app
+        .post_subscriptions("name=le%20guin&email=Ursula_Le_Guin%40gmail.com".into())
+        .await;
+
+    // Assert
+    // Answered like any signup, so that the form does not tell who was erased.
+    assert_eq!(response.status().as_u16(), 200);
+    let subscribers = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(subscribers.is_empty());
+    assert_eq!(
+        app.email_server.received_requests().await.unwrap().len(),
+        emails_sent
+    );
+}
+
+#[tokio::test]
+async fn tampered_links_are_rejected() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    let token = app.data_request_token().await;
+    let (payload, _signature) = token.rsplit_once('.').unwrap();
+    let forged = format!("{}.{}", payload, "0".repeat(64));
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/privacy/export?token={}", &app.address, forged))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["code"], "invalid_link");
+}
@@ -0,0 +1,156 @@
+use crate::helpers::AppBootstrap;
+use wiremock::{
+    matchers::{method, path},
+    Mock, MockServer, ResponseTemplate,
+};
+use zero2prod::{
+    configuration::{get_config, TracingSettings},
+    metrics::Metrics,
+    services::email::{EmailKind, EmailService},
+    telemetry::{get_subscriber, get_tracer_provider},
+};
+
+const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+const PARENT_ID: &str = "00f067aa0ba902b7";
+
+async fn spawn_app() -> AppBootstrap {
+    let app = AppBootstrap::new().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app
+}
+
+/// The `traceparent` sent to the email provider, split into its fields.
+async fn outgoing_traceparent(app: &AppBootstrap) -> Vec<String> {
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    email_request.headers.get(&"traceparent".into()).unwrap()[0]
+        .as_str()
+        .split('-')
+        .map(str::to_owned)
+        .collect()
+}
+
+#[tokio::test]
+async fn the_trace_of_an_incoming_request_continues_in_calls_to_the_email_provider() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    app.api_client
+        .post(format!("{}/subscriptions", &app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("traceparent", format!("00-{}-{}-01", TRACE_ID, PARENT_ID))
+        .body(format!(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&form_token={}",
+            urlencoding::encode(&app.form_token())
+        ))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    let traceparent = outgoing_traceparent(&app).await;
+    assert_eq!(traceparent[0], "00");
+    assert_eq!(traceparent[1], TRACE_ID);
+    // The parent is now our own span.
+    assert_eq!(traceparent[2].len(), 16);
+    assert_ne!(traceparent[2], PARENT_ID);
+    assert_eq!(traceparent[3], "01");
+}
+
+#[tokio::test]
+async fn requests_without_a_trace_context_start_a_new_trace() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    let traceparent = outgoing_traceparent(&app).await;
+    assert_eq!(traceparent.len(), 4);
+    assert_eq!(traceparent[1].len(), 32);
+    assert_ne!(traceparent[1], "0".repeat(32));
+}
+
+#[tokio::test]
+async fn spans_are_exported_to_the_otlp_endpoint() {
+    // Arrange
+    let collector = MockServer::start().await;
+    Mock::given(path("/v1/traces"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&collector)
+        .await;
+    let email_server = MockServer::start().await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&email_server)
+        .await;
+    let config = get_config().expect("Failed to read config");
+    let tracer_provider = get_tracer_provider(&TracingSettings {
+        otlp_endpoint: Some(format!("{}/v1/traces", collector.uri())),
+        service_name: "newsletter-under-test".into(),
+        ..config.tracing
+    })
+    .unwrap();
+    // Scoped to this test: the global subscriber is shared by the whole suite.
+    let subscriber = get_subscriber(
+        "test".into(),
+        "info".into(),
+        std::io::sink,
+        &tracer_provider,
+    );
+    let email_service = EmailService::new(
+        email_server.uri(),
+        config.email.sender().unwrap(),
+        config.email.auth_token.clone(),
+        config.email.timeout(),
+        config.email.throttle.clone(),
+        Metrics::new(),
+    );
+
+    // Act
+    {
+        let _guard = tracing::subscriber::set_default(subscriber);
+        email_service
+            .send_email(
+                EmailKind::Confirmation,
+                &config.email.sender().unwrap(),
+                "Subject",
+                "<p>Body</p>",
+                "Body",
+            )
+            .await
+            .unwrap();
+    }
+    // Dropping the provider flushes the batch; it blocks until the export is done.
+    tokio::task::spawn_blocking(move || drop(tracer_provider))
+        .await
+        .unwrap();
+
+    // Assert
+    let exports = collector.received_requests().await.unwrap();
+    assert_eq!(exports.len(), 1);
+    assert_eq!(
+        exports[0].headers.get(&"Content-Type".into()).unwrap()[0].as_str(),
+        "application/x-protobuf"
+    );
+    // Strings are stored as is in the protobuf payload.
+    let contains = |needle: &str| {
+        exports[0]
+            .body
+            .windows(needle.len())
+            .any(|w| w == needle.as_bytes())
+    };
+    assert!(contains("Send an email"));
+    assert!(contains("newsletter-under-test"));
+}
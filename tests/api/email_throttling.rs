@@ -0,0 +1,212 @@
+use crate::helpers::AppBootstrap;
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use reqwest::Method;
+use std::time::Duration;
+use uuid::Uuid;
+use wiremock::{
+    matchers::{body_string_contains, method, path},
+    Mock, ResponseTemplate,
+};
+
+async fn spawn_app(quota_per_window: u64, quota_window_in_secs: u64) -> AppBootstrap {
+    let app = AppBootstrap::with_config(|c| {
+        c.email.throttle.quota_per_window = Some(quota_per_window);
+        c.email.throttle.quota_window_in_secs = quota_window_in_secs;
+        c.email.throttle.resume_poll_interval_in_milli = 50;
+    })
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app
+}
+
+/// Confirmed subscribers written straight to the database, as an import would: going
+/// through the API would spend the quota on confirmation emails.
+async fn import_confirmed_subscribers(app: &AppBootstrap, count: usize) {
+    for i in 0..count {
+        sqlx::query!(
+            r#"
+            INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+            VALUES ($1, $2, 'reader', now(), 'confirmed')
+            "#,
+            Uuid::new_v4(),
+            format!("reader{}@example.com", i),
+        )
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    }
+}
+
+async fn publish_issue(app: &AppBootstrap) -> Uuid {
+    let issue = app.api_create_issue("Title").await;
+    let issue_id = issue["id"].as_str().unwrap();
+    let response = app
+        .api_request(Method::POST, &format!("/issues/{}/publish", issue_id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    issue_id.parse().unwrap()
+}
+
+async fn recipients(app: &AppBootstrap) -> Vec<String> {
+    app.email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|r| {
+            let body: serde_json::Value = serde_json::from_slice(&r.body).unwrap();
+            body["To"].as_str().unwrap().to_owned()
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn delivery_pauses_when_the_quota_is_used_up() {
+    // Arrange
+    let app = spawn_app(1, 86400).await;
+    import_confirmed_subscribers(&app, 3).await;
+
+    // Act
+    let issue_id = publish_issue(&app).await;
+
+    // Assert
+    assert_eq!(recipients(&app).await.len(), 1);
+    let issue = sqlx::query!(
+        "SELECT delivery_resumes_at FROM newsletter_issues WHERE id = $1",
+        issue_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    let tomorrow = (Utc::now() + ChronoDuration::days(1)).date_naive();
+    assert_eq!(
+        issue.delivery_resumes_at,
+        Some(DateTime::<Utc>::from_utc(
+            tomorrow.and_hms_opt(0, 0, 0).unwrap(),
+            Utc
+        ))
+    );
+}
+
+#[tokio::test]
+async fn paused_deliveries_resume_in_the_next_quota_window() {
+    // Arrange
+    let app = spawn_app(2, 1).await;
+    import_confirmed_subscribers(&app, 5).await;
+
+    // Act
+    publish_issue(&app).await;
+
+    // Assert
+    assert!(recipients(&app).await.len() < 5);
+    for _ in 0..200 {
+        if recipients(&app).await.len() >= 5 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    let mut recipients = recipients(&app).await;
+    assert_eq!(recipients.len(), 5);
+    recipients.sort();
+    recipients.dedup();
+    assert_eq!(recipients.len(), 5, "A subscriber got the issue twice.");
+}
+
+async fn spawn_app_retrying_after(delay_in_secs: u64, max_attempts: i32) -> AppBootstrap {
+    AppBootstrap::with_config(|c| {
+        c.email.delivery_retries.delay_in_secs = delay_in_secs;
+        c.email.delivery_retries.max_attempts = max_attempts;
+        c.email.throttle.resume_poll_interval_in_milli = 50;
+    })
+    .await
+}
+
+async fn failed_attempts(app: &AppBootstrap, issue_id: Uuid) -> Vec<(i32, bool)> {
+    sqlx::query!(
+        "SELECT failed_attempts, failed_at IS NOT NULL AS \"failed!\" FROM issue_deliveries \
+        WHERE issue_id = $1 ORDER BY failed_attempts DESC",
+        issue_id
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|r| (r.failed_attempts, r.failed))
+    .collect()
+}
+
+#[tokio::test]
+async fn a_failed_email_is_retried_without_holding_the_others_back() {
+    // Arrange
+    let app = spawn_app_retrying_after(0, 3).await;
+    import_confirmed_subscribers(&app, 3).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .and(body_string_contains("reader0@example.com"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let issue_id = publish_issue(&app).await;
+
+    // Assert
+    for _ in 0..200 {
+        if recipients(&app).await.len() >= 4 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    let recipients = recipients(&app).await;
+    assert_eq!(recipients.len(), 4);
+    assert_eq!(
+        recipients
+            .iter()
+            .filter(|r| *r == "reader0@example.com")
+            .count(),
+        2
+    );
+    assert_eq!(
+        failed_attempts(&app, issue_id).await,
+        [(1, false), (0, false), (0, false)]
+    );
+}
+
+#[tokio::test]
+async fn recipients_are_given_up_on_once_out_of_attempts() {
+    // Arrange
+    let app = spawn_app_retrying_after(0, 2).await;
+    import_confirmed_subscribers(&app, 1).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let issue_id = publish_issue(&app).await;
+
+    // Assert
+    for _ in 0..200 {
+        if recipients(&app).await.len() >= 2 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    // Leave the resumer a few more polls to try again, which it must not.
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(recipients(&app).await.len(), 2);
+    assert_eq!(failed_attempts(&app, issue_id).await, [(2, true)]);
+}
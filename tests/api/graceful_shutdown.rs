@@ -0,0 +1,155 @@
+use crate::helpers::AppBootstrap;
+use reqwest::Method;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+/// An application whose email provider takes `email_delay` to answer.
+async fn spawn_app(shutdown_timeout_in_secs: u64, email_delay: Duration) -> AppBootstrap {
+    let app = AppBootstrap::with_config(|c| {
+        c.application.shutdown_timeout_in_secs = shutdown_timeout_in_secs;
+    })
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(email_delay))
+        .mount(&app.email_server)
+        .await;
+    app
+}
+
+async fn import_confirmed_subscribers(app: &AppBootstrap, count: usize) {
+    for i in 0..count {
+        sqlx::query!(
+            r#"
+            INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+            VALUES ($1, $2, 'reader', now(), 'confirmed')
+            "#,
+            Uuid::new_v4(),
+            format!("reader{}@example.com", i),
+        )
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    }
+}
+
+/// Start publishing an issue, and return once its first email is on its way.
+async fn start_publishing(
+    app: &AppBootstrap,
+) -> (
+    Uuid,
+    tokio::task::JoinHandle<reqwest::Result<reqwest::Response>>,
+) {
+    let issue = app.api_create_issue("Title").await;
+    let issue_id: Uuid = issue["id"].as_str().unwrap().parse().unwrap();
+    let publish = app
+        .api_request(Method::POST, &format!("/issues/{}/publish", issue_id))
+        .send();
+    let publish = tokio::spawn(publish);
+    for _ in 0..100 {
+        if !app
+            .email_server
+            .received_requests()
+            .await
+            .unwrap()
+            .is_empty()
+        {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    (issue_id, publish)
+}
+
+async fn delivery_count(app: &AppBootstrap, issue_id: Uuid) -> i64 {
+    sqlx::query!(
+        r#"SELECT COUNT(*) AS "count!" FROM issue_deliveries WHERE issue_id = $1"#,
+        issue_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .count
+}
+
+#[tokio::test]
+async fn an_idle_application_shuts_down_promptly() {
+    // Arrange
+    let mut app = AppBootstrap::new().await;
+    let started = Instant::now();
+
+    // Act
+    let summary = app.shut_down().await;
+
+    // Assert
+    assert!(summary.is_empty(), "{:?}", summary);
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+#[tokio::test]
+async fn new_connections_are_refused_once_shutdown_starts() {
+    // Arrange
+    let mut app = AppBootstrap::new().await;
+
+    // Act
+    app.shut_down().await;
+
+    // Assert
+    let outcome = reqwest::Client::new()
+        .get(format!("{}/health_check", app.address))
+        .send()
+        .await;
+    assert!(outcome.unwrap_err().is_connect());
+}
+
+#[tokio::test]
+async fn an_issue_being_published_is_sent_to_everyone_before_shutdown_completes() {
+    // Arrange
+    let mut app = spawn_app(30, Duration::from_millis(200)).await;
+    import_confirmed_subscribers(&app, 3).await;
+    let (issue_id, publish) = start_publishing(&app).await;
+
+    // Act
+    let summary = app.shut_down().await;
+
+    // Assert
+    assert!(summary.is_empty(), "{:?}", summary);
+    let response = publish.await.unwrap().unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(delivery_count(&app, issue_id).await, 3);
+}
+
+#[tokio::test]
+async fn deliveries_still_running_at_the_deadline_are_left_for_the_restart() {
+    // Arrange
+    let mut app = spawn_app(1, Duration::from_millis(400)).await;
+    import_confirmed_subscribers(&app, 10).await;
+    let (issue_id, publish) = start_publishing(&app).await;
+
+    // Act
+    let summary = app.shut_down().await;
+
+    // Assert
+    let response = publish.await.unwrap().unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    let delivered = delivery_count(&app, issue_id).await;
+    assert!(delivered < 10);
+    assert_eq!(
+        summary.issues_deferred,
+        vec![(issue_id, 10 - delivered as usize)]
+    );
+    assert_eq!(summary.requests_cut_off, 0);
+    // `DeliveryResumer` picks it up as soon as the application is back.
+    let issue = sqlx::query!(
+        "SELECT delivery_resumes_at FROM newsletter_issues WHERE id = $1",
+        issue_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert!(issue.delivery_resumes_at.unwrap() <= chrono::Utc::now());
+}
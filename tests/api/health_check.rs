@@ -1,4 +1,21 @@
 use crate::helpers::AppBootstrap;
+use std::time::Duration;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+use zero2prod::configuration::{DependencyCheck, SessionStoreBackend};
+
+async fn get_readiness(app: &AppBootstrap) -> (u16, serde_json::Value) {
+    let response = app
+        .api_client
+        .get(format!("{}/health/ready", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    let status = response.status().as_u16();
+    (status, response.json().await.unwrap())
+}
 
 #[tokio::test]
 async fn health_check_works() {
@@ -6,7 +23,7 @@ async fn health_check_works() {
     let client = reqwest::Client::new();
 
     let response = client
-        .get(&format!("{}/health_check", app.address))
+        .get(format!("{}/health_check", app.address))
         .send()
         .await
         .expect("Failed to execute request.");
@@ -14,3 +31,113 @@ async fn health_check_works() {
     assert!(response.status().is_success());
     assert_eq!(Some(0), response.content_length())
 }
+
+#[tokio::test]
+async fn liveness_only_reports_that_the_process_is_up() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/health/live", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body, serde_json::json!({ "status": "up" }));
+}
+
+#[tokio::test]
+async fn readiness_reports_each_dependency() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+
+    // Act
+    let (status, body) = get_readiness(&app).await;
+
+    // Assert
+    assert_eq!(status, 200);
+    assert_eq!(body["status"], "up");
+    for dependency in ["database", "redis"] {
+        let check = &body["checks"][dependency];
+        assert_eq!(check["status"], "up", "{} is not up: {}", dependency, check);
+        assert_eq!(check["required"], true);
+        assert!(check["latency_ms"].is_u64());
+        assert_eq!(check["timeout_ms"], 2000);
+    }
+    // Not checked by default.
+    assert!(body["checks"].get("email_provider").is_none());
+}
+
+#[tokio::test]
+async fn redis_is_not_checked_when_nothing_uses_it() {
+    // Arrange
+    let app = AppBootstrap::with_config(|c| c.session.store = SessionStoreBackend::Postgres).await;
+
+    // Act
+    let (status, body) = get_readiness(&app).await;
+
+    // Assert
+    assert_eq!(status, 200);
+    assert!(body["checks"].get("redis").is_none());
+    assert_eq!(body["checks"]["database"]["status"], "up");
+}
+
+#[tokio::test]
+async fn readiness_fails_when_a_required_dependency_is_down() {
+    // Arrange
+    let app =
+        AppBootstrap::with_config(|c| c.health.email_provider = DependencyCheck::Required).await;
+    Mock::given(path("/"))
+        .and(method("GET"))
+        .respond_with(ResponseTemplate::new(503))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let (status, body) = get_readiness(&app).await;
+
+    // Assert
+    assert_eq!(status, 503);
+    assert_eq!(body["status"], "down");
+    let check = &body["checks"]["email_provider"];
+    assert_eq!(check["status"], "down");
+    assert_eq!(check["required"], true);
+    assert_eq!(
+        check["error"],
+        "The email provider answered 503 Service Unavailable."
+    );
+    assert_eq!(body["checks"]["database"]["status"], "up");
+}
+
+#[tokio::test]
+async fn an_optional_dependency_that_times_out_does_not_fail_readiness() {
+    // Arrange
+    let app = AppBootstrap::with_config(|c| {
+        c.health.email_provider = DependencyCheck::Optional;
+        c.health.timeout_in_milli = 100;
+    })
+    .await;
+    Mock::given(path("/"))
+        .and(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(2)))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let (status, body) = get_readiness(&app).await;
+
+    // Assert
+    assert_eq!(status, 200);
+    assert_eq!(body["status"], "up");
+    let check = &body["checks"]["email_provider"];
+    assert_eq!(check["status"], "timeout");
+    assert_eq!(check["required"], false);
+    assert_eq!(check["timeout_ms"], 100);
+    assert!(check["latency_ms"].as_u64().unwrap() < 2000);
+}
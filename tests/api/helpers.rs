@@ -1,34 +1,68 @@
 use argon2::{password_hash::SaltString, Algorithm, Argon2, Params, PasswordHasher, Version};
 use once_cell::sync::Lazy;
+use opentelemetry_sdk::trace::TracerProvider;
 use sqlx::{Connection, Executor, PgConnection, PgPool};
+use tokio::task::JoinHandle;
 use uuid::Uuid;
 use wiremock::MockServer;
 use zero2prod::{
-    configuration::{get_config, DatabaseSettings},
-    startup::{get_pool, Application},
-    telemetry::{get_subscriber, init_subscriber},
+    bot_protection::BotProtection,
+    configuration::{get_config, DatabaseSettings, Settings, TracingSettings},
+    shutdown::{Shutdown, ShutdownSummary},
+    startup::{get_pool, Application, HmacSecret},
+    telemetry::{get_subscriber, get_tracer_provider, init_subscriber},
 };
 
+/// Spans are not exported by the test suite, but still get a trace context to propagate.
+/// Kept for the whole run: the subscriber only holds a weak reference to it.
+static TRACER_PROVIDER: Lazy<TracerProvider> = Lazy::new(|| {
+    let config = get_config().expect("Failed to read config");
+    get_tracer_provider(&TracingSettings {
+        otlp_endpoint: None,
+        ..config.tracing
+    })
+    .expect("Failed to build the tracer provider")
+});
+
 static TRACING: Lazy<()> = Lazy::new(|| {
     let default_filter_level = "info".to_string();
     let subscriber_name = "test".to_string();
 
     if std::env::var("TEST_LOG").is_ok() {
-        let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::stdout);
+        let subscriber = get_subscriber(
+            subscriber_name,
+            default_filter_level,
+            std::io::stdout,
+            &TRACER_PROVIDER,
+        );
         init_subscriber(subscriber);
     } else {
-        let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::sink);
+        let subscriber = get_subscriber(
+            subscriber_name,
+            default_filter_level,
+            std::io::sink,
+            &TRACER_PROVIDER,
+        );
         init_subscriber(subscriber);
     }
 });
 
+/// The `Accept` header of a browser navigating or submitting a form: errors are then
+/// rendered as redirects or HTML pages rather than problem details.
+pub const BROWSER_ACCEPT: &str = "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8";
+
 pub struct AppBootstrap {
     pub address: String,
     pub port: u16,
+    /// Set when `/metrics` is served on its own port.
+    pub metrics_address: Option<String>,
     pub db_pool: PgPool,
     pub email_server: MockServer,
     pub test_user: TestUser,
     pub api_client: reqwest::Client,
+    pub bot_protection: BotProtection,
+    pub shutdown: Shutdown,
+    pub server: JoinHandle<Result<ShutdownSummary, std::io::Error>>,
 }
 
 pub struct ConfirmationLinks {
@@ -66,6 +100,11 @@ impl AppBootstrap {
     }
 
     pub async fn new() -> Self {
+        Self::with_config(|_| {}).await
+    }
+
+    /// Spawn the application after letting the test adjust its configuration.
+    pub async fn with_config(customise: impl FnOnce(&mut Settings)) -> Self {
         Lazy::force(&TRACING);
 
         let email_server = MockServer::start().await;
@@ -76,6 +115,7 @@ impl AppBootstrap {
             c.database.database_name = Uuid::new_v4().to_string();
             c.application.port = 0;
             c.email.base_url = email_server.uri();
+            customise(&mut c);
             c
         };
 
@@ -89,21 +129,29 @@ impl AppBootstrap {
         // get port before spawing app
         let port = application.port();
         let address = format!("http://127.0.0.1:{}", port);
-        let _ = tokio::spawn(application.run_until_stopped());
-
-        let api_client = reqwest::Client::builder()
-            .redirect(reqwest::redirect::Policy::none())
-            .cookie_store(true)
-            .build()
-            .unwrap();
+        let metrics_address = application
+            .metrics_port()
+            .map(|port| format!("http://127.0.0.1:{}", port));
+        let shutdown = application.shutdown();
+        let server = tokio::spawn(application.run_until_stopped());
+
+        let api_client = build_api_client();
+        let bot_protection = BotProtection::new(
+            config.bot_protection.clone(),
+            HmacSecret(config.application.hmac_secret.clone()),
+        );
 
         let app = AppBootstrap {
             address,
             port,
+            metrics_address,
             db_pool: get_pool(&config.database),
             email_server,
             test_user: TestUser::new(),
             api_client,
+            bot_protection,
+            shutdown,
+            server,
         };
 
         app.test_user.save(&app.db_pool).await;
@@ -111,9 +159,24 @@ impl AppBootstrap {
         app
     }
 
+    /// Shut the application down, as SIGTERM would, and wait until it has.
+    pub async fn shut_down(&mut self) -> ShutdownSummary {
+        self.shutdown.trigger();
+        (&mut self.server)
+            .await
+            .expect("The application panicked")
+            .expect("Failed to shut down")
+    }
+
+    /// Submit the subscription form as a human would: with a form token issued a minute ago.
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
+        let body = format!(
+            "{}&form_token={}",
+            body,
+            urlencoding::encode(&self.form_token())
+        );
         self.api_client
-            .post(&format!("{}/subscriptions", &self.address))
+            .post(format!("{}/subscriptions", &self.address))
             .header("Content-Type", "application/x-www-form-urlencoded")
             .body(body)
             .send()
@@ -121,6 +184,11 @@ impl AppBootstrap {
             .expect("Failed to execute request")
     }
 
+    pub fn form_token(&self) -> String {
+        self.bot_protection
+            .issue_form_token(chrono::Utc::now().timestamp() - 60)
+    }
+
     pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
         let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
 
@@ -129,6 +197,8 @@ impl AppBootstrap {
             let links: Vec<_> = linkify::LinkFinder::new()
                 .links(s)
                 .filter(|l| *l.kind() == linkify::LinkKind::Url)
+                // Issues also embed a tracking pixel, which is no link to follow.
+                .filter(|l| !l.as_str().contains("/open/"))
                 .collect();
             assert_eq!(links.len(), 1);
             let raw_link = links[0].as_str().to_owned();
@@ -139,14 +209,14 @@ impl AppBootstrap {
             confirmation_link
         };
 
-        let html = get_link(&body["HtmlBody"].as_str().unwrap());
-        let plain_text = get_link(&body["TextBody"].as_str().unwrap());
+        let html = get_link(body["HtmlBody"].as_str().unwrap());
+        let plain_text = get_link(body["TextBody"].as_str().unwrap());
         ConfirmationLinks { html, plain_text }
     }
 
     pub async fn post_newsletter(&self, body: serde_json::Value) -> reqwest::Response {
         self.api_client
-            .post(&format!("{}/newsletters", &self.address))
+            .post(format!("{}/newsletters", &self.address))
             .basic_auth(&self.test_user.username, Some(&self.test_user.password))
             .json(&body)
             .send()
@@ -159,16 +229,34 @@ impl AppBootstrap {
         Body: serde::Serialize,
     {
         self.api_client
-            .post(&format!("{}/login", &self.address))
+            .post(format!("{}/login", &self.address))
+            .header("Accept", BROWSER_ACCEPT)
+            .header("X-CSRF-Token", self.csrf_token().await)
             .form(body)
             .send()
             .await
             .expect("Failed to execute request.")
     }
 
+    /// The anti-forgery token of the current session, as embedded in the login form.
+    pub async fn csrf_token(&self) -> String {
+        csrf_token(&self.api_client, &self.address).await
+    }
+
+    /// Log the test user in and check that it worked.
+    pub async fn login(&self) {
+        let response = self
+            .post_login(&serde_json::json!({
+                "username": &self.test_user.username,
+                "password": &self.test_user.password
+            }))
+            .await;
+        assert_is_redirect_to(&response, "/admin/dashboard");
+    }
+
     pub async fn get_login_html(&self) -> String {
         self.api_client
-            .get(&format!("{}/login", &self.address))
+            .get(format!("{}/login", &self.address))
             .send()
             .await
             .expect("Failed to execute request.")
@@ -179,7 +267,7 @@ impl AppBootstrap {
 
     pub async fn get_admin_dashboard(&self) -> reqwest::Response {
         self.api_client
-            .get(&format!("{}/admin/dashboard", &self.address))
+            .get(format!("{}/admin/dashboard", &self.address))
             .send()
             .await
             .expect("Failed to execute request.")
@@ -191,7 +279,7 @@ impl AppBootstrap {
 
     pub async fn get_change_password(&self) -> reqwest::Response {
         self.api_client
-            .get(&format!("{}/admin/password", &self.address))
+            .get(format!("{}/admin/password", &self.address))
             .send()
             .await
             .expect("Failed to execute request.")
@@ -202,7 +290,8 @@ impl AppBootstrap {
         Body: serde::Serialize,
     {
         self.api_client
-            .post(&format!("{}/admin/password", &self.address))
+            .post(format!("{}/admin/password", &self.address))
+            .header("X-CSRF-Token", self.csrf_token().await)
             .form(body)
             .send()
             .await
@@ -213,9 +302,130 @@ impl AppBootstrap {
         self.get_change_password().await.text().await.unwrap()
     }
 
+    pub async fn post_newsletter_with_token(
+        &self,
+        token: &str,
+        body: serde_json::Value,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/newsletters", &self.address))
+            .bearer_auth(token)
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to post newsletter")
+    }
+
+    pub async fn get_api_tokens_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/tokens", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_create_api_token(&self, body: &[(&str, &str)]) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/tokens", &self.address))
+            .header("X-CSRF-Token", self.csrf_token().await)
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Create a token through the admin page and scrape the secret from the response.
+    pub async fn create_api_token(&self, scopes: &[&str]) -> String {
+        let mut body = vec![("name", "test token")];
+        body.extend(scopes.iter().map(|scope| ("scope", *scope)));
+        let html_page = self
+            .post_create_api_token(&body)
+            .await
+            .text()
+            .await
+            .unwrap();
+        let start = html_page.find("z2p_").expect("No API token in the page.");
+        html_page[start..]
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric() || *c == '_')
+            .collect()
+    }
+
+    pub async fn post_revoke_api_token(&self, token_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/tokens/{}/revoke",
+                &self.address, token_id
+            ))
+            .header("X-CSRF-Token", self.csrf_token().await)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_create_webhook(&self, body: &[(&str, &str)]) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/webhooks", &self.address))
+            .header("X-CSRF-Token", self.csrf_token().await)
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Register an endpoint through the admin page and scrape its signing secret.
+    pub async fn create_webhook(&self, url: &str, event_types: &[&str]) -> String {
+        let mut body = vec![("url", url)];
+        body.extend(event_types.iter().map(|event_type| ("event", *event_type)));
+        let html_page = self.post_create_webhook(&body).await.text().await.unwrap();
+        let start = html_page
+            .find("whsec_")
+            .expect("No signing secret in the page.");
+        html_page[start..]
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric() || *c == '_')
+            .collect()
+    }
+
+    pub async fn get_sessions_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/sessions", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_revoke_session(&self, session_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/sessions/{}/revoke",
+                &self.address, session_id
+            ))
+            .header("X-CSRF-Token", self.csrf_token().await)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_log_out_everywhere(&self) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/sessions/revoke-all", &self.address))
+            .header("X-CSRF-Token", self.csrf_token().await)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     pub async fn post_logout(&self) -> reqwest::Response {
         self.api_client
-            .post(&format!("{}/admin/logout", &self.address))
+            .post(format!("{}/admin/logout", &self.address))
+            .header("X-CSRF-Token", self.csrf_token().await)
             .send()
             .await
             .expect("Failed to execute request.")
@@ -256,6 +466,36 @@ impl TestUser {
     }
 }
 
+pub async fn csrf_token(client: &reqwest::Client, address: &str) -> String {
+    let html_page = client
+        .get(format!("{}/login", address))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .text()
+        .await
+        .unwrap();
+    extract_csrf_token(&html_page)
+}
+
+pub fn extract_csrf_token(html_page: &str) -> String {
+    let marker = r#"name="csrf_token" value=""#;
+    let start = html_page.find(marker).expect("No CSRF token in the page.") + marker.len();
+    html_page[start..]
+        .chars()
+        .take_while(|c| *c != '"')
+        .collect()
+}
+
+/// A client with its own cookie jar, i.e. a separate browser session.
+pub fn build_api_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .build()
+        .unwrap()
+}
+
 pub fn assert_is_redirect_to(response: &reqwest::Response, location: &str) {
     assert_eq!(response.status().as_u16(), 303);
     assert_eq!(response.headers().get("Location").unwrap(), location);
@@ -1,4 +1,5 @@
 use crate::helpers::{assert_is_redirect_to, AppBootstrap};
+use argon2::{password_hash::SaltString, Algorithm, Argon2, Params, PasswordHasher, Version};
 
 #[tokio::test]
 async fn an_error_flash_message_is_set_on_failure() {
@@ -40,3 +41,65 @@ async fn redirect_to_admin_dashboard_after_login_success() {
     let html_page = app.get_admin_dashboard_html().await;
     assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
 }
+
+#[tokio::test]
+async fn outdated_password_hashes_are_upgraded_after_a_successful_login() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    let outdated_hash = Argon2::new(
+        Algorithm::Argon2id,
+        Version::V0x13,
+        Params::new(4096, 1, 1, None).unwrap(),
+    )
+    .hash_password(app.test_user.password.as_bytes(), &salt)
+    .unwrap()
+    .to_string();
+    sqlx::query!(
+        "UPDATE users SET password_hash = $1 WHERE user_id = $2",
+        outdated_hash,
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    // Assert - the rehash happens in the background, so we poll for it
+    let mut upgraded_hash = None;
+    for _ in 0..50 {
+        let stored_hash = sqlx::query!(
+            "SELECT password_hash FROM users WHERE user_id = $1",
+            app.test_user.user_id
+        )
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .password_hash;
+        if stored_hash != outdated_hash {
+            upgraded_hash = Some(stored_hash);
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    let upgraded_hash = upgraded_hash.expect("The password hash was not upgraded.");
+    assert!(upgraded_hash.starts_with("$argon2id$v=19$m=15000,t=2,p=1$"));
+
+    // The upgraded hash still verifies the same password
+    app.post_logout().await;
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
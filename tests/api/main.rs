@@ -1,8 +1,29 @@
+mod ab_testing;
 mod admin_dashboard;
+mod api_tokens;
+mod api_v1;
+mod bot_protection;
 mod change_password;
+mod config_check;
+mod consent;
+mod csrf;
+mod data_requests;
+mod distributed_tracing;
+mod email_throttling;
+mod graceful_shutdown;
 mod health_check;
 mod helpers;
 mod login;
+mod metrics;
 mod newsletter;
+mod openapi;
+mod preferences;
+mod problems;
+mod rate_limit;
+mod security_headers;
+mod segments;
+mod session_store;
+mod sessions;
 mod subscription_confirm;
 mod subscriptions;
+mod webhooks;
@@ -0,0 +1,159 @@
+use crate::helpers::AppBootstrap;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+async fn get_metrics(address: &str) -> reqwest::Response {
+    reqwest::get(format!("{}/metrics", address))
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn get_metrics_text(app: &AppBootstrap) -> String {
+    let response = get_metrics(&app.address).await;
+    assert_eq!(response.status().as_u16(), 200);
+    response.text().await.unwrap()
+}
+
+#[tokio::test]
+async fn http_requests_are_counted_and_timed_per_route() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    reqwest::get(format!("{}/health_check", &app.address))
+        .await
+        .unwrap();
+    reqwest::get(format!("{}/wp-login.php", &app.address))
+        .await
+        .unwrap();
+    reqwest::Client::new()
+        .request(
+            reqwest::Method::from_bytes(b"PROPFIND").unwrap(),
+            format!("{}/health_check", &app.address),
+        )
+        .send()
+        .await
+        .unwrap();
+
+    // Act
+    let response = get_metrics(&app.address).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.headers()["Content-Type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/plain; version=0.0.4"));
+    let metrics = response.text().await.unwrap();
+    assert!(metrics
+        .contains(r#"http_requests_total{method="GET",route="/health_check",status="200"} 1"#));
+    assert!(metrics
+        .contains(r#"http_request_duration_seconds_count{method="GET",route="/health_check"} 1"#));
+    // Unknown paths do not get a series each.
+    assert!(
+        metrics.contains(r#"http_requests_total{method="GET",route="unmatched",status="404"} 1"#)
+    );
+    assert!(!metrics.contains("wp-login"));
+    // Nor do non-standard methods.
+    assert!(metrics.contains(r#"http_requests_total{method="other",route="/health_check""#));
+    assert!(!metrics.contains("PROPFIND"));
+    assert!(metrics.contains(r#"db_pool_connections{state="in_use"}"#));
+}
+
+#[tokio::test]
+async fn signups_confirmations_and_emails_are_counted() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_link = app.get_confirmation_links(email_request).html;
+    // Following the link twice confirms once.
+    for _ in 0..2 {
+        reqwest::get(confirmation_link.clone())
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+    }
+
+    // Assert
+    let metrics = get_metrics_text(&app).await;
+    assert!(metrics.contains(r#"subscriptions_total{source="form"} 1"#));
+    assert!(metrics.contains("subscription_confirmations_total 1"));
+    assert!(metrics.contains(r#"emails_sent_total{type="confirmation"} 1"#));
+    assert!(metrics.contains("emails_sent_last_minute 1"));
+}
+
+#[tokio::test]
+async fn failed_emails_are_counted_with_the_reason() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Assert
+    let metrics = get_metrics_text(&app).await;
+    assert!(metrics.contains(r#"emails_failed_total{reason="request",type="confirmation"} 1"#));
+}
+
+#[tokio::test]
+async fn login_failures_are_counted() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+
+    // Act
+    app.post_login(&serde_json::json!({
+        "username": "random-username",
+        "password": "random-password"
+    }))
+    .await;
+    app.api_client
+        .get(format!("{}/api/v1/subscribers", &app.address))
+        .basic_auth("random-username", Some("random-password"))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    let metrics = get_metrics_text(&app).await;
+    assert!(metrics.contains(r#"login_failures_total{method="form"} 1"#));
+    assert!(metrics.contains(r#"login_failures_total{method="basic"} 1"#));
+}
+
+#[tokio::test]
+async fn metrics_can_be_moved_to_a_separate_port() {
+    // Arrange
+    let app = AppBootstrap::with_config(|c| c.metrics.port = Some(0)).await;
+    let metrics_address = app.metrics_address.clone().unwrap();
+    reqwest::get(format!("{}/health_check", &app.address))
+        .await
+        .unwrap();
+
+    // Act
+    let on_app_port = get_metrics(&app.address).await;
+    let on_metrics_port = get_metrics(&metrics_address).await;
+
+    // Assert
+    assert_eq!(on_app_port.status().as_u16(), 404);
+    assert_eq!(on_metrics_port.status().as_u16(), 200);
+    let metrics = on_metrics_port.text().await.unwrap();
+    assert!(metrics
+        .contains(r#"http_requests_total{method="GET",route="/health_check",status="200"} 1"#));
+}
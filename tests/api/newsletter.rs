@@ -98,7 +98,7 @@ async fn requests_missing_authorization_are_rejected() {
     // Arrange
     let app = AppBootstrap::new().await;
     let response = reqwest::Client::new()
-        .post(&format!("{}/newsletters", &app.address))
+        .post(format!("{}/newsletters", &app.address))
         .json(&serde_json::json!({
             "title": "Newsletter title",
             "content": {
@@ -142,7 +142,7 @@ async fn create_unconfirmed_subscriber(app: &AppBootstrap) -> crate::helpers::Co
         .unwrap()
         .pop()
         .unwrap();
-    app.get_confirmation_links(&email_request)
+    app.get_confirmation_links(email_request)
 }
 
 async fn create_confirmed_subscriber(app: &AppBootstrap) {
@@ -164,7 +164,7 @@ async fn non_existing_user_is_rejected() {
     let username = Uuid::new_v4().to_string();
     let password = Uuid::new_v4().to_string();
     let response = reqwest::Client::new()
-        .post(&format!("{}/newsletters", &app.address))
+        .post(format!("{}/newsletters", &app.address))
         .basic_auth(username, Some(password))
         .json(&serde_json::json!({
             "title": "Newsletter title",
@@ -193,7 +193,7 @@ async fn invalid_password_is_rejected() {
     let password = Uuid::new_v4().to_string();
     assert_ne!(app.test_user.password, password);
     let response = reqwest::Client::new()
-        .post(&format!("{}/newsletters", &app.address))
+        .post(format!("{}/newsletters", &app.address))
         .basic_auth(username, Some(password))
         .json(&serde_json::json!({
         "title": "Newsletter title",
@@ -0,0 +1,157 @@
+use crate::helpers::AppBootstrap;
+use uuid::Uuid;
+
+/// Committed, so that integrators can read it and reviewers can see how a change affects the API.
+const SNAPSHOT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/openapi.json");
+
+async fn get_openapi_json(app: &AppBootstrap) -> serde_json::Value {
+    let response = app
+        .api_client
+        .get(format!("{}/openapi.json", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(response.status().as_u16(), 200);
+    response.json().await.unwrap()
+}
+
+#[tokio::test]
+async fn the_openapi_document_matches_the_committed_snapshot() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+
+    // Act
+    let spec = get_openapi_json(&app).await;
+
+    // Assert
+    let pretty = format!("{}\n", serde_json::to_string_pretty(&spec).unwrap());
+    if std::env::var("UPDATE_OPENAPI_SNAPSHOT").is_ok() {
+        std::fs::write(SNAPSHOT, pretty).expect("Failed to write the snapshot.");
+        return;
+    }
+    let snapshot: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(SNAPSHOT).expect("Missing snapshot."))
+            .expect("The snapshot is not valid JSON.");
+    assert!(
+        spec == snapshot,
+        "The OpenAPI document drifted from `openapi.json`. If the change is intended, run \
+        `UPDATE_OPENAPI_SNAPSHOT=1 cargo test openapi` and commit the result."
+    );
+}
+
+#[tokio::test]
+async fn every_documented_operation_is_routed() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    let spec = get_openapi_json(&app).await;
+
+    for (path, operations) in spec["paths"].as_object().unwrap() {
+        let url = path
+            .split('/')
+            .map(|segment| {
+                if segment.starts_with('{') {
+                    Uuid::new_v4().to_string()
+                } else {
+                    segment.to_string()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("/");
+        for method in operations.as_object().unwrap().keys() {
+            // Act
+            let response = app
+                .api_client
+                .request(
+                    method.to_uppercase().parse().unwrap(),
+                    format!("{}{}", &app.address, url),
+                )
+                .send()
+                .await
+                .expect("Failed to execute request.");
+
+            // Assert
+            assert!(
+                ![404, 405].contains(&response.status().as_u16()),
+                "{} {} is documented but not routed.",
+                method,
+                path
+            );
+        }
+    }
+}
+
+#[tokio::test]
+async fn the_docs_page_renders_the_openapi_document() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/docs", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    let script = app
+        .api_client
+        .get(format!("{}/docs/swagger_ui.js", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let csp = response.headers()["Content-Security-Policy"]
+        .to_str()
+        .unwrap()
+        .to_owned();
+    assert!(!csp.contains("https:"), "{}", csp);
+    let html = response.text().await.unwrap();
+    assert!(html.contains(r#"<script src="/docs/swagger_ui.js"></script>"#));
+    assert_eq!(script.status().as_u16(), 200);
+    assert!(script.text().await.unwrap().contains("/openapi.json"));
+}
+
+#[tokio::test]
+async fn swagger_ui_is_served_by_the_application() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+
+    for (asset, content_type) in [
+        ("swagger-ui-bundle.js", "text/javascript"),
+        ("swagger-ui.css", "text/css"),
+    ] {
+        // Act
+        let response = app
+            .api_client
+            .get(format!("{}/docs/{}", &app.address, asset))
+            .send()
+            .await
+            .expect("Failed to execute request.");
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 200);
+        assert!(response.headers()["Content-Type"]
+            .to_str()
+            .unwrap()
+            .starts_with(content_type));
+    }
+}
+
+#[tokio::test]
+async fn the_session_cookie_scheme_uses_the_configured_cookie_name() {
+    // Arrange
+    let app = AppBootstrap::with_config(|c| {
+        c.application.session_cookie.name = "__Host-session".into();
+    })
+    .await;
+
+    // Act
+    let spec = get_openapi_json(&app).await;
+
+    // Assert
+    assert_eq!(
+        spec["components"]["securitySchemes"]["session_cookie"]["name"],
+        "__Host-session"
+    );
+}
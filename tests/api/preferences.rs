@@ -0,0 +1,495 @@
+use crate::helpers::{AppBootstrap, BROWSER_ACCEPT};
+use std::time::Duration;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::subscriber_data::email_hash;
+
+impl AppBootstrap {
+    async fn post_preferences(&self, action: &str, body: &[(&str, &str)]) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/preferences/{}", &self.address, action))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    async fn publish_newsletter(&self, title: &str) {
+        self.post_newsletter(serde_json::json!({
+            "title": title,
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .await
+        .error_for_status()
+        .unwrap();
+    }
+
+    async fn emails_sent(&self) -> Vec<serde_json::Value> {
+        self.email_server
+            .received_requests()
+            .await
+            .unwrap()
+            .iter()
+            .map(|r| serde_json::from_slice(&r.body).unwrap())
+            .collect()
+    }
+}
+
+/// Subscribe, confirm, and return the token of the preference link of the first issue.
+async fn preference_token(app: &AppBootstrap) -> String {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    reqwest::get(app.get_confirmation_links(email_request).html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    app.publish_newsletter("First issue").await;
+
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    let link = app
+        .get_confirmation_links(email_requests.last().unwrap())
+        .html;
+    assert_eq!(link.path(), "/preferences");
+    link.query_pairs()
+        .find(|(key, _)| key == "token")
+        .map(|(_, token)| token.into_owned())
+        .expect("No token in the preference link.")
+}
+
+async fn store_tombstone(app: &AppBootstrap, email: &str) {
+    sqlx::query!(
+        "INSERT INTO erased_subscribers (email_hash, erased_at) VALUES ($1, now())",
+        email_hash(email),
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+fn assert_is_redirect_to_preferences(response: &reqwest::Response) {
+    assert_eq!(response.status().as_u16(), 303);
+    assert!(response.headers()["Location"]
+        .to_str()
+        .unwrap()
+        .starts_with("/preferences?token="));
+}
+
+#[tokio::test]
+async fn issues_link_to_the_preference_center() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    let token = preference_token(&app).await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/preferences", &app.address))
+        .query(&[("token", &token)])
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains(r#"value="le guin""#));
+    assert!(html_page.contains(r#"value="ursula_le_guin@gmail.com""#));
+}
+
+#[tokio::test]
+async fn subscribers_can_change_their_name() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    let token = preference_token(&app).await;
+
+    // Act - Part 1 - Invalid name
+    let response = app
+        .post_preferences("name", &[("token", &token), ("name", "")])
+        .await;
+
+    // Assert - Part 1
+    assert_eq!(response.status().as_u16(), 400);
+
+    // Act - Part 2 - Valid name
+    let response = app
+        .post_preferences("name", &[("token", &token), ("name", "Ursula K. Le Guin")])
+        .await;
+
+    // Assert - Part 2
+    assert_is_redirect_to_preferences(&response);
+    let saved = sqlx::query!("SELECT name FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.name, "Ursula K. Le Guin");
+}
+
+#[tokio::test]
+async fn a_new_email_address_must_be_confirmed() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    let token = preference_token(&app).await;
+
+    // Act - Part 1 - Ask for the change
+    let response = app
+        .post_preferences(
+            "email",
+            &[("token", &token), ("email", "ursula@earthsea.com")],
+        )
+        .await;
+
+    // Assert - Part 1
+    assert_is_redirect_to_preferences(&response);
+    let saved = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.email, "ursula_le_guin@gmail.com");
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    let email_request = email_requests.last().unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["To"], "ursula@earthsea.com");
+
+    // Act - Part 2 - Follow the link mailed to the new address
+    let confirmation_link = app.get_confirmation_links(email_request).html;
+    let response = reqwest::get(confirmation_link.clone()).await.unwrap();
+
+    // Assert - Part 2
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.email, "ursula@earthsea.com");
+    let confirmations =
+        sqlx::query!("SELECT id FROM subscriber_consents WHERE action = 'confirmation'")
+            .fetch_all(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(confirmations.len(), 2);
+
+    // Act - Part 3 - The link only works once
+    let response = reqwest::get(confirmation_link).await.unwrap();
+
+    // Assert - Part 3
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn an_expired_email_change_is_not_applied() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    let token = preference_token(&app).await;
+    app.post_preferences(
+        "email",
+        &[("token", &token), ("email", "ursula@earthsea.com")],
+    )
+    .await;
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    let confirmation_link = app
+        .get_confirmation_links(email_requests.last().unwrap())
+        .html;
+    sqlx::query!("UPDATE email_change_requests SET requested_at = now() - interval '2 days'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = reqwest::get(confirmation_link).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+    let saved = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.email, "ursula_le_guin@gmail.com");
+    let requests = sqlx::query!("SELECT token FROM email_change_requests")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(requests.is_empty());
+}
+
+#[tokio::test]
+async fn browsers_are_sent_back_to_the_preference_center_with_the_error() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    let token = preference_token(&app).await;
+
+    // Act - Part 1 - Submit an invalid name
+    let response = app
+        .api_client
+        .post(format!("{}/preferences/name", &app.address))
+        .header("Accept", BROWSER_ACCEPT)
+        .form(&[("token", token.as_str()), ("name", "")])
+        .send()
+        .await
+        .unwrap();
+
+    // Assert - Part 1
+    assert_is_redirect_to_preferences(&response);
+    let location = response.headers()["Location"].to_str().unwrap().to_owned();
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app
+        .api_client
+        .get(format!("{}{}", &app.address, location))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+
+    // Assert - Part 2
+    assert!(html_page.contains("is not a valid subscriber name."));
+    assert!(html_page.contains(r#"value="le guin""#));
+}
+
+#[tokio::test]
+async fn the_address_cannot_be_changed_to_an_erased_one() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    let token = preference_token(&app).await;
+    store_tombstone(&app, "ursula@earthsea.com").await;
+
+    // Act
+    let response = app
+        .post_preferences(
+            "email",
+            &[("token", &token), ("email", "ursula@earthsea.com")],
+        )
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 409);
+    let requests = sqlx::query!("SELECT token FROM email_change_requests")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(requests.is_empty());
+}
+
+#[tokio::test]
+async fn a_pending_change_to_an_address_erased_since_is_not_applied() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    let token = preference_token(&app).await;
+    app.post_preferences(
+        "email",
+        &[("token", &token), ("email", "ursula@earthsea.com")],
+    )
+    .await;
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    let confirmation_link = app
+        .get_confirmation_links(email_requests.last().unwrap())
+        .html;
+    store_tombstone(&app, "ursula@earthsea.com").await;
+
+    // Act
+    let response = reqwest::get(confirmation_link).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 409);
+    let saved = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.email, "ursula_le_guin@gmail.com");
+}
+
+#[tokio::test]
+async fn paused_subscribers_do_not_get_issues() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    let token = preference_token(&app).await;
+    let response = app
+        .post_preferences("pause", &[("token", &token), ("weeks", "2")])
+        .await;
+    assert_is_redirect_to_preferences(&response);
+    let emails_sent = app.emails_sent().await.len();
+
+    // Act - Part 1 - Publish while paused
+    app.publish_newsletter("Second issue").await;
+
+    // Assert - Part 1
+    assert_eq!(app.emails_sent().await.len(), emails_sent);
+
+    // Act - Part 2 - Resume and publish again
+    app.post_preferences("pause", &[("token", &token), ("weeks", "0")])
+        .await;
+    app.publish_newsletter("Third issue").await;
+
+    // Assert - Part 2
+    let emails = app.emails_sent().await;
+    assert_eq!(emails.len(), emails_sent + 1);
+    assert_eq!(emails.last().unwrap()["Subject"], "Third issue");
+}
+
+#[tokio::test]
+async fn pauses_are_capped() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    let token = preference_token(&app).await;
+
+    // Act
+    let response = app
+        .post_preferences("pause", &[("token", &token), ("weeks", "500")])
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn digest_subscribers_get_the_issues_of_the_week_in_one_email() {
+    // Arrange
+    // Long enough to publish both issues before the digest is due.
+    let app = AppBootstrap::with_config(|c| {
+        c.preferences.digest_interval_in_secs = 5;
+        c.preferences.digest_poll_interval_in_milli = 50;
+    })
+    .await;
+    let token = preference_token(&app).await;
+    let response = app
+        .post_preferences(
+            "frequency",
+            &[("token", &token), ("frequency", "weekly_digest")],
+        )
+        .await;
+    assert_is_redirect_to_preferences(&response);
+    let emails_sent = app.emails_sent().await.len();
+
+    // Act
+    app.publish_newsletter("Second issue").await;
+    app.publish_newsletter("Third issue").await;
+
+    // Assert
+    assert_eq!(app.emails_sent().await.len(), emails_sent);
+    let mut digest = None;
+    for _ in 0..200 {
+        let emails = app.emails_sent().await;
+        if emails.len() > emails_sent {
+            digest = emails.last().cloned();
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    let digest = digest.expect("No digest was sent.");
+    assert_eq!(digest["Subject"], "Your weekly digest");
+    let text = digest["TextBody"].as_str().unwrap();
+    assert!(text.contains("Second issue"));
+    assert!(text.contains("Third issue"));
+    assert!(!text.contains("First issue"));
+    assert!(text.contains("/preferences?token="));
+}
+
+#[tokio::test]
+async fn subscribers_can_unsubscribe() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    let token = preference_token(&app).await;
+
+    // Act - Part 1 - Unsubscribe
+    let response = app
+        .post_preferences("unsubscribe", &[("token", &token)])
+        .await;
+
+    // Assert - Part 1
+    assert_eq!(response.status().as_u16(), 200);
+    // Kept, along with the withdrawal of their consent.
+    let subscriber = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscriber.status, "unsubscribed");
+    let consents =
+        sqlx::query!("SELECT action, source FROM subscriber_consents ORDER BY recorded_at")
+            .fetch_all(&app.db_pool)
+            .await
+            .unwrap();
+    let last = consents.last().unwrap();
+    assert_eq!(
+        (last.action.as_str(), last.source.as_str()),
+        ("withdrawal", "email")
+    );
+
+    // Act - Part 2 - The link leads nowhere now
+    let response = app
+        .api_client
+        .get(format!("{}/preferences", &app.address))
+        .query(&[("token", &token)])
+        .send()
+        .await
+        .unwrap();
+
+    // Assert - Part 2
+    assert_eq!(response.status().as_u16(), 404);
+    let response = app
+        .post_preferences("name", &[("token", &token), ("name", "Ursula")])
+        .await;
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn unsubscribed_subscribers_get_no_issues_and_can_sign_up_again() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    let token = preference_token(&app).await;
+    let subscriber_id = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id;
+    app.post_preferences("unsubscribe", &[("token", &token)])
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Act - Part 1 - Publish
+    let emails_sent = app.emails_sent().await.len();
+    app.publish_newsletter("Second issue").await;
+
+    // Assert - Part 1
+    assert_eq!(app.emails_sent().await.len(), emails_sent);
+
+    // Act - Part 2 - Sign up again
+    app.post_subscriptions("name=ursula&email=Ursula_Le_Guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Assert - Part 2
+    let subscriber = sqlx::query!("SELECT id, email, status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscriber.id, subscriber_id);
+    assert_eq!(subscriber.email, "Ursula_Le_Guin@gmail.com");
+    assert_eq!(subscriber.status, "pending_confirmation");
+    let actions: Vec<String> =
+        sqlx::query!("SELECT action FROM subscriber_consents ORDER BY recorded_at")
+            .fetch_all(&app.db_pool)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|r| r.action)
+            .collect();
+    assert_eq!(
+        actions,
+        ["signup", "confirmation", "withdrawal", "signup"].map(String::from)
+    );
+}
@@ -0,0 +1,168 @@
+use crate::helpers::{assert_is_redirect_to, AppBootstrap, BROWSER_ACCEPT};
+use sqlx::Executor;
+
+#[tokio::test]
+async fn an_invalid_subscription_returns_problem_details() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+
+    // Act
+    let response = app
+        .post_subscriptions("name=le%20guin&email=definitely-not-an-email".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "application/problem+json"
+    );
+    let request_id = response.headers()["X-Request-Id"]
+        .to_str()
+        .unwrap()
+        .to_owned();
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["type"], "about:blank");
+    assert_eq!(problem["title"], "Bad Request");
+    assert_eq!(problem["status"], 400);
+    assert_eq!(problem["code"], "invalid_email");
+    assert_eq!(problem["instance"], "/subscriptions");
+    assert_eq!(problem["request_id"], request_id.as_str());
+    assert!(problem["detail"]
+        .as_str()
+        .unwrap()
+        .contains("definitely-not-an-email"));
+}
+
+#[tokio::test]
+async fn browsers_are_sent_back_to_the_form_with_a_flash_message() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    let body = format!(
+        "name=%20&email=ursula_le_guin%40gmail.com&form_token={}",
+        urlencoding::encode(&app.form_token())
+    );
+
+    // Act - Part 1 - Submit the form
+    let response = app
+        .api_client
+        .post(format!("{}/subscriptions", &app.address))
+        .header("Accept", BROWSER_ACCEPT)
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(body)
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app
+        .api_client
+        .get(&app.address)
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("is not a valid subscriber name.</i></p>"));
+
+    // Act - Part 3 - Reload the home page
+    let html_page = app
+        .api_client
+        .get(&app.address)
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(!html_page.contains("is not a valid subscriber name."));
+}
+
+#[tokio::test]
+async fn an_unknown_confirmation_token_is_negotiated() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    let url = format!("{}/subscriptions/confirm?token=unknown", app.address);
+
+    // Act
+    let api_response = app.api_client.get(&url).send().await.unwrap();
+    let browser_response = app
+        .api_client
+        .get(&url)
+        .header("Accept", BROWSER_ACCEPT)
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(api_response.status().as_u16(), 401);
+    let problem: serde_json::Value = api_response.json().await.unwrap();
+    assert_eq!(problem["code"], "invalid_token");
+
+    // There is no form to go back to: browsers get a page with the same status.
+    assert_eq!(browser_response.status().as_u16(), 401);
+    let request_id = browser_response.headers()["X-Request-Id"]
+        .to_str()
+        .unwrap()
+        .to_owned();
+    assert!(browser_response.headers()["Content-Type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/html"));
+    let html_page = browser_response.text().await.unwrap();
+    assert!(html_page.contains(&format!("Reference: {}", request_id)));
+}
+
+#[tokio::test]
+async fn failed_authentication_keeps_the_challenge() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/newsletters", &app.address))
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": { "text": "Newsletter body", "html": "<p>Newsletter body</p>" }
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(
+        response.headers()["WWW-Authenticate"],
+        r#"Basic realm="publish""#
+    );
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["code"], "auth_failed");
+}
+
+#[tokio::test]
+async fn server_errors_do_not_leak_their_cause() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    app.db_pool
+        .execute("ALTER TABLE subscription_tokens DROP COLUMN subscription_token;")
+        .await
+        .unwrap();
+
+    // Act
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 500);
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["code"], "internal_error");
+    assert_eq!(
+        problem["detail"],
+        "Something went wrong. Please try again later."
+    );
+    assert!(problem["request_id"].is_string());
+}
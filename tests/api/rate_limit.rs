@@ -0,0 +1,226 @@
+use uuid::Uuid;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+use zero2prod::configuration::{RateLimitBackend, TokenBucketSettings};
+
+use crate::helpers::{AppBootstrap, BROWSER_ACCEPT};
+
+/// The test client connects from the loopback address, standing in for a reverse proxy.
+fn trust_the_loopback_proxy(c: &mut zero2prod::configuration::Settings) {
+    c.application.trusted_proxies = vec!["127.0.0.1".parse().unwrap()];
+}
+
+fn bucket(capacity: u32) -> Option<TokenBucketSettings> {
+    Some(TokenBucketSettings {
+        capacity,
+        refill_interval_in_secs: 60,
+    })
+}
+
+async fn post_subscriptions_from(app: &AppBootstrap, ip: &str, email: &str) -> reqwest::Response {
+    app.api_client
+        .post(format!("{}/subscriptions", &app.address))
+        .header("X-Forwarded-For", ip)
+        .form(&[
+            ("name", "le guin"),
+            ("email", email),
+            ("form_token", &app.form_token()),
+        ])
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+async fn post_login_from(
+    app: &AppBootstrap,
+    ip: &str,
+    body: &serde_json::Value,
+) -> reqwest::Response {
+    app.api_client
+        .post(format!("{}/login", &app.address))
+        .header("X-Forwarded-For", ip)
+        .header("Accept", BROWSER_ACCEPT)
+        .header("X-CSRF-Token", app.csrf_token().await)
+        .form(body)
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+async fn get_confirm(app: &AppBootstrap) -> reqwest::Response {
+    app.api_client
+        .get(format!(
+            "{}/subscriptions/confirm?token=unknown",
+            &app.address
+        ))
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+async fn mount_email_server(app: &AppBootstrap) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+}
+
+#[tokio::test]
+async fn subscribe_is_rate_limited_per_ip() {
+    // Arrange
+    let app = AppBootstrap::with_config(|c| {
+        trust_the_loopback_proxy(c);
+        c.rate_limit.subscribe.per_ip = bucket(2);
+        c.rate_limit.subscribe.per_target = None;
+    })
+    .await;
+    mount_email_server(&app).await;
+
+    // Act
+    for i in 0..2 {
+        let email = format!("ursula{}@gmail.com", i);
+        let response = post_subscriptions_from(&app, "10.0.0.1", &email).await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+    let response = post_subscriptions_from(&app, "10.0.0.1", "ursula2@gmail.com").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 429);
+    assert_eq!(response.headers()["Retry-After"], "60");
+    // Other clients are not affected
+    let response = post_subscriptions_from(&app, "10.0.0.2", "ursula3@gmail.com").await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn forwarded_addresses_are_ignored_unless_sent_by_a_trusted_proxy() {
+    // Arrange
+    let app = AppBootstrap::with_config(|c| {
+        c.rate_limit.subscribe.per_ip = bucket(1);
+        c.rate_limit.subscribe.per_target = None;
+    })
+    .await;
+    mount_email_server(&app).await;
+
+    // Act
+    let response = post_subscriptions_from(&app, "10.0.0.1", "ursula@gmail.com").await;
+    assert_eq!(response.status().as_u16(), 200);
+    let response = post_subscriptions_from(&app, "10.0.0.2", "le_guin@gmail.com").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 429);
+}
+
+#[tokio::test]
+async fn subscribe_is_rate_limited_per_email_across_ips() {
+    // Arrange
+    let app = AppBootstrap::with_config(|c| {
+        trust_the_loopback_proxy(c);
+        c.rate_limit.subscribe.per_ip = None;
+        c.rate_limit.subscribe.per_target = bucket(1);
+    })
+    .await;
+    mount_email_server(&app).await;
+
+    // Act
+    let response = post_subscriptions_from(&app, "10.0.0.1", "ursula@gmail.com").await;
+    assert_eq!(response.status().as_u16(), 200);
+    let response = post_subscriptions_from(&app, "10.0.0.2", " Ursula@Gmail.com").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 429);
+    assert!(response.headers().contains_key("Retry-After"));
+}
+
+#[tokio::test]
+async fn a_rate_limited_subscription_does_not_send_an_email() {
+    // Arrange
+    let app = AppBootstrap::with_config(|c| {
+        c.rate_limit.subscribe.per_ip = bucket(1);
+    })
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    post_subscriptions_from(&app, "10.0.0.1", "ursula@gmail.com").await;
+    let response = post_subscriptions_from(&app, "10.0.0.1", "le_guin@gmail.com").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 429);
+    // Mock verifies on Drop that we have sent a single email
+}
+
+#[tokio::test]
+async fn confirm_is_rate_limited_per_ip() {
+    // Arrange
+    let app = AppBootstrap::with_config(|c| {
+        c.rate_limit.confirm.per_ip = bucket(1);
+    })
+    .await;
+
+    // Act
+    let response = get_confirm(&app).await;
+    assert_eq!(response.status().as_u16(), 401);
+    let response = get_confirm(&app).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 429);
+    assert_eq!(response.headers()["Retry-After"], "60");
+}
+
+#[tokio::test]
+async fn login_is_rate_limited_per_username_and_ip() {
+    // Arrange
+    let app = AppBootstrap::with_config(|c| {
+        trust_the_loopback_proxy(c);
+        c.rate_limit.login.per_ip = None;
+        c.rate_limit.login.per_target = bucket(1);
+    })
+    .await;
+    let login_body = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": "wrong-password"
+    });
+
+    // Act
+    let response = post_login_from(&app, "10.0.0.1", &login_body).await;
+    assert_eq!(response.status().as_u16(), 303);
+    let response = post_login_from(&app, "10.0.0.1", &login_body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 429);
+    assert_eq!(response.headers()["Retry-After"], "60");
+    // A stranger guessing passwords does not lock the admin out
+    let response = post_login_from(&app, "10.0.0.2", &login_body).await;
+    assert_eq!(response.status().as_u16(), 303);
+}
+
+#[tokio::test]
+async fn buckets_can_be_shared_through_redis() {
+    // Arrange
+    let key_prefix = format!("rate_limit_{}", Uuid::new_v4());
+    let configure = |c: &mut zero2prod::configuration::Settings| {
+        c.rate_limit.backend = RateLimitBackend::Redis;
+        c.rate_limit.key_prefix = key_prefix.clone();
+        c.rate_limit.confirm.per_ip = bucket(1);
+    };
+    let first_instance = AppBootstrap::with_config(configure).await;
+    let second_instance = AppBootstrap::with_config(configure).await;
+
+    // Act
+    let response = get_confirm(&first_instance).await;
+    assert_eq!(response.status().as_u16(), 401);
+    let response = get_confirm(&second_instance).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 429);
+    assert!(response.headers().contains_key("Retry-After"));
+}
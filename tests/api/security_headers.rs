@@ -0,0 +1,97 @@
+use crate::helpers::AppBootstrap;
+use zero2prod::configuration::SameSiteSetting;
+
+fn set_cookie_for<'a>(response: &'a reqwest::Response, name: &str) -> &'a str {
+    response
+        .headers()
+        .get_all("Set-Cookie")
+        .iter()
+        .map(|h| h.to_str().unwrap())
+        .find(|c| c.starts_with(&format!("{}=", name)))
+        .unwrap_or_else(|| panic!("No `{}` cookie was set.", name))
+}
+
+#[tokio::test]
+async fn responses_carry_the_configured_security_headers() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    // Act
+    let response = app.api_client.get(&app.address).send().await.unwrap();
+    // Assert
+    let headers = response.headers();
+    assert_eq!(
+        headers["Content-Security-Policy"],
+        "default-src 'self'; frame-ancestors 'none'; form-action 'self'"
+    );
+    assert_eq!(headers["X-Frame-Options"], "DENY");
+    assert_eq!(headers["Referrer-Policy"], "no-referrer");
+    assert_eq!(headers["X-Content-Type-Options"], "nosniff");
+    // Not configured locally
+    assert!(headers.get("Strict-Transport-Security").is_none());
+}
+
+#[tokio::test]
+async fn error_responses_carry_the_security_headers_too() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    // Act
+    let response = app.get_admin_dashboard().await;
+    // Assert
+    assert_eq!(response.status().as_u16(), 303);
+    assert_eq!(response.headers()["X-Frame-Options"], "DENY");
+}
+
+#[tokio::test]
+async fn hsts_is_sent_when_configured() {
+    // Arrange
+    let app = AppBootstrap::with_config(|c| {
+        c.application.security_headers.hsts_max_age_in_secs = Some(600);
+    })
+    .await;
+    // Act
+    let response = app.api_client.get(&app.address).send().await.unwrap();
+    // Assert
+    assert_eq!(
+        response.headers()["Strict-Transport-Security"],
+        "max-age=600; includeSubDomains"
+    );
+}
+
+#[tokio::test]
+async fn session_and_flash_cookies_follow_the_configuration() {
+    // Arrange
+    let app = AppBootstrap::with_config(|c| {
+        c.application.session_cookie.name = "z2p_session".into();
+        c.application.session_cookie.max_age_in_secs = Some(3600);
+        c.application.flash_cookie.name = "z2p_flash".into();
+        c.application.flash_cookie.same_site = SameSiteSetting::Strict;
+    })
+    .await;
+
+    // Act - the login form stores a CSRF token in a fresh session
+    let response = app
+        .api_client
+        .get(format!("{}/login", &app.address))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    let session_cookie = set_cookie_for(&response, "z2p_session");
+    assert!(session_cookie.contains("SameSite=Strict"));
+    assert!(session_cookie.contains("HttpOnly"));
+    assert!(session_cookie.contains("Max-Age=3600"));
+
+    // Act - a failed login sets a flash cookie
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": "random-username",
+            "password": "random-password"
+        }))
+        .await;
+
+    // Assert
+    let flash_cookie = set_cookie_for(&response, "z2p_flash");
+    assert!(flash_cookie.contains("SameSite=Strict"));
+    assert!(!flash_cookie.contains("Secure"));
+}
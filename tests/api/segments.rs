@@ -0,0 +1,283 @@
+use crate::helpers::AppBootstrap;
+use reqwest::Method;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+impl AppBootstrap {
+    /// A confirmed subscriber with the given tags.
+    async fn api_create_tagged_subscriber(&self, email: &str, tags: &[&str]) -> serde_json::Value {
+        let created = self.api_create_confirmed_subscriber(email).await;
+        let response = self
+            .api_request(
+                Method::PATCH,
+                &format!("/subscribers/{}", created["id"].as_str().unwrap()),
+            )
+            .json(&serde_json::json!({"tags": tags}))
+            .send()
+            .await
+            .expect("Failed to execute request.");
+        assert_eq!(response.status().as_u16(), 200);
+        response.json().await.unwrap()
+    }
+
+    async fn api_create_segment(&self, name: &str, filter: &str) -> reqwest::Response {
+        self.api_request(Method::POST, "/segments")
+            .json(&serde_json::json!({"name": name, "filter": filter}))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    async fn emails_sent_to(&self) -> Vec<String> {
+        self.email_server
+            .received_requests()
+            .await
+            .unwrap()
+            .iter()
+            .map(|r| {
+                let body: serde_json::Value = serde_json::from_slice(&r.body).unwrap();
+                body["To"].as_str().unwrap().to_owned()
+            })
+            .collect()
+    }
+}
+
+async fn mount_email_server(app: &AppBootstrap) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+}
+
+#[tokio::test]
+async fn subscribers_can_be_given_tags_and_attributes() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    mount_email_server(&app).await;
+    let created = app.api_create_subscriber("ursula_le_guin@gmail.com").await;
+    assert_eq!(created["tags"], serde_json::json!([]));
+    assert_eq!(created["attributes"], serde_json::json!({}));
+    let resource = format!("/subscribers/{}", created["id"].as_str().unwrap());
+
+    // Act - Part 1 - Valid tags and attributes
+    let response = app
+        .api_request(Method::PATCH, &resource)
+        .json(&serde_json::json!({
+            "tags": ["VIP", "early-adopter", "vip"],
+            "attributes": {"city": "Portland", "plan": "pro"}
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert - Part 1
+    assert_eq!(response.status().as_u16(), 200);
+    let updated: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(updated["tags"], serde_json::json!(["early-adopter", "vip"]));
+    assert_eq!(
+        updated["attributes"],
+        serde_json::json!({"city": "Portland", "plan": "pro"})
+    );
+
+    // Act - Part 2 - Invalid ones
+    for changes in [
+        serde_json::json!({"tags": ["early adopter"]}),
+        serde_json::json!({"attributes": {"Plan": "pro"}}),
+        serde_json::json!({"attributes": {"plan": 3}}),
+    ] {
+        let response = app
+            .api_request(Method::PATCH, &resource)
+            .json(&changes)
+            .send()
+            .await
+            .unwrap();
+
+        // Assert - Part 2
+        assert_eq!(response.status().as_u16(), 400, "{} was accepted", changes);
+    }
+}
+
+#[tokio::test]
+async fn segments_are_saved_with_a_valid_filter_and_a_unique_name() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+
+    // Act - Part 1 - Malformed filter
+    let response = app.api_create_segment("VIPs", "tag:vip and").await;
+
+    // Assert - Part 1
+    assert_eq!(response.status().as_u16(), 400);
+
+    // Act - Part 2 - Valid filter
+    let response = app
+        .api_create_segment("VIPs", "tag:vip AND NOT list:weekly_digest")
+        .await;
+
+    // Assert - Part 2
+    assert_eq!(response.status().as_u16(), 201);
+    let segment: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(segment["filter"], "(tag:vip and not list:weekly_digest)");
+    let fetched: serde_json::Value = app
+        .api_request(
+            Method::GET,
+            &format!("/segments/{}", segment["id"].as_str().unwrap()),
+        )
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(fetched, segment);
+
+    // Act - Part 3 - Taken name
+    let response = app.api_create_segment("VIPs", "tag:gold").await;
+
+    // Assert - Part 3
+    assert_eq!(response.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn issues_published_to_a_segment_only_reach_its_subscribers() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    mount_email_server(&app).await;
+    app.api_create_tagged_subscriber("ursula_le_guin@gmail.com", &["vip"])
+        .await;
+    app.api_create_tagged_subscriber("octavia_butler@gmail.com", &[])
+        .await;
+    let segment: serde_json::Value = app
+        .api_create_segment("VIPs", "tag:vip")
+        .await
+        .json()
+        .await
+        .unwrap();
+    let segment_id = segment["id"].as_str().unwrap();
+    let issue = app.api_create_issue("For VIPs only").await;
+    let emails_sent = app.emails_sent_to().await.len();
+
+    // Act - Part 1 - Preview
+    let preview: serde_json::Value = app
+        .api_request(Method::GET, &format!("/segments/{}/preview", segment_id))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    // Assert - Part 1
+    assert_eq!(preview["recipients"], 1);
+
+    // Act - Part 2 - Publish
+    let response = app
+        .api_request(
+            Method::POST,
+            &format!("/issues/{}/publish", issue["id"].as_str().unwrap()),
+        )
+        .query(&[("segment", segment_id)])
+        .send()
+        .await
+        .unwrap();
+
+    // Assert - Part 2
+    assert_eq!(response.status().as_u16(), 200);
+    let recipients = app.emails_sent_to().await;
+    assert_eq!(recipients[emails_sent..], ["ursula_le_guin@gmail.com"]);
+}
+
+#[tokio::test]
+async fn publishing_to_an_unknown_segment_sends_nothing() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    mount_email_server(&app).await;
+    app.api_create_tagged_subscriber("ursula_le_guin@gmail.com", &[])
+        .await;
+    let issue = app.api_create_issue("Title").await;
+    let issue_resource = format!("/issues/{}", issue["id"].as_str().unwrap());
+    let emails_sent = app.emails_sent_to().await.len();
+
+    // Act
+    let response = app
+        .api_request(Method::POST, &format!("{}/publish", issue_resource))
+        .query(&[("segment", uuid::Uuid::new_v4().to_string())])
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+    assert_eq!(app.emails_sent_to().await.len(), emails_sent);
+    let issue: serde_json::Value = app
+        .api_request(Method::GET, &issue_resource)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(issue["status"], "draft");
+}
+
+#[tokio::test]
+async fn segments_can_target_custom_attributes_and_engagement() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    mount_email_server(&app).await;
+    let subscriber = app
+        .api_create_tagged_subscriber("ursula_le_guin@gmail.com", &[])
+        .await;
+    app.api_request(
+        Method::PATCH,
+        &format!("/subscribers/{}", subscriber["id"].as_str().unwrap()),
+    )
+    .json(&serde_json::json!({"attributes": {"city": "Portland"}}))
+    .send()
+    .await
+    .unwrap()
+    .error_for_status()
+    .unwrap();
+    app.api_create_tagged_subscriber("octavia_butler@gmail.com", &[])
+        .await;
+    // Following the confirmation link counts as engagement: only keep Octavia's.
+    sqlx::query!(
+        "UPDATE subscriptions SET last_engaged_at = CASE WHEN email = 'octavia_butler@gmail.com' \
+        THEN now() END"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    for (filter, expected) in [
+        ("attr.city:Portland", 1),
+        ("not attr.city:Portland", 1),
+        ("engaged_within:30d", 1),
+        ("attr.city:Portland or engaged_within:30d", 2),
+        ("subscribed_before:2000-01-01", 0),
+    ] {
+        // Act
+        let segment: serde_json::Value = app
+            .api_create_segment(filter, filter)
+            .await
+            .json()
+            .await
+            .unwrap();
+        let preview: serde_json::Value = app
+            .api_request(
+                Method::GET,
+                &format!("/segments/{}/preview", segment["id"].as_str().unwrap()),
+            )
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(preview["recipients"], expected, "{}", filter);
+    }
+}
@@ -0,0 +1,73 @@
+use crate::helpers::{assert_is_redirect_to, AppBootstrap};
+use zero2prod::configuration::SessionStoreBackend;
+
+async fn spawn_app_with_postgres_sessions(cleanup_interval_in_secs: u64) -> AppBootstrap {
+    AppBootstrap::with_config(|c| {
+        c.session.store = SessionStoreBackend::Postgres;
+        c.session.cleanup_interval_in_secs = cleanup_interval_in_secs;
+        // Redis must not be needed at all
+        c.redis_uri = None;
+    })
+    .await
+}
+
+#[tokio::test]
+async fn login_works_with_the_postgres_session_store() {
+    // Arrange
+    let app = spawn_app_with_postgres_sessions(600).await;
+    // Act
+    app.login().await;
+    // Assert
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+    let stored_sessions = sqlx::query!("SELECT COUNT(*) AS count FROM http_sessions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(stored_sessions, Some(1));
+}
+
+#[tokio::test]
+async fn logout_deletes_the_session_from_the_postgres_store() {
+    // Arrange
+    let app = spawn_app_with_postgres_sessions(600).await;
+    app.login().await;
+    // Act
+    let response = app.post_logout().await;
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+    let stored_sessions = sqlx::query!("SELECT COUNT(*) AS count FROM http_sessions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(stored_sessions, Some(0));
+}
+
+#[tokio::test]
+async fn expired_sessions_are_cleaned_up_periodically() {
+    // Arrange
+    let app = spawn_app_with_postgres_sessions(1).await;
+    sqlx::query!(
+        "INSERT INTO http_sessions (session_key, state, expires_at) \
+        VALUES ('expired', '{}', now() - interval '1 minute')"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+
+    // Assert
+    let expired_sessions =
+        sqlx::query!("SELECT COUNT(*) AS count FROM http_sessions WHERE session_key = 'expired'")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap()
+            .count;
+    assert_eq!(expired_sessions, Some(0));
+}
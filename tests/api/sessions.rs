@@ -0,0 +1,186 @@
+use crate::helpers::{assert_is_redirect_to, build_api_client, csrf_token, AppBootstrap};
+use uuid::Uuid;
+
+/// Log the test user in from a separate client, as if from another device.
+async fn login_from_another_device(app: &AppBootstrap) -> reqwest::Client {
+    let client = build_api_client();
+    let response = client
+        .post(format!("{}/login", &app.address))
+        .header("User-Agent", "another-device")
+        .header("X-CSRF-Token", csrf_token(&client, &app.address).await)
+        .form(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    client
+}
+
+async fn get_admin_dashboard(app: &AppBootstrap, client: &reqwest::Client) -> reqwest::Response {
+    client
+        .get(format!("{}/admin/dashboard", &app.address))
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn session_id_for(app: &AppBootstrap, user_agent: &str) -> Uuid {
+    sqlx::query!(
+        "SELECT id FROM user_sessions WHERE user_agent = $1",
+        user_agent
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .id
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_your_sessions() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/admin/sessions", &app.address))
+        .send()
+        .await
+        .unwrap();
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn active_sessions_are_listed_with_their_metadata() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    app.login().await;
+    login_from_another_device(&app).await;
+    // Act
+    let html_page = app.get_sessions_html().await;
+    // Assert
+    assert!(html_page.contains("another-device"));
+    assert!(html_page.contains("127.0.0.1"));
+    assert!(html_page.contains("This device"));
+}
+
+#[tokio::test]
+async fn revoking_a_session_logs_that_device_out() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    app.login().await;
+    let other_device = login_from_another_device(&app).await;
+    let session_id = session_id_for(&app, "another-device").await;
+
+    // Act
+    let response = app.post_revoke_session(session_id).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/sessions");
+    let response = get_admin_dashboard(&app, &other_device).await;
+    assert_is_redirect_to(&response, "/login");
+    // The current session is unaffected
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn logging_out_everywhere_revokes_every_session() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    app.login().await;
+    let other_device = login_from_another_device(&app).await;
+
+    // Act
+    let response = app.post_log_out_everywhere().await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    let response = get_admin_dashboard(&app, &other_device).await;
+    assert_is_redirect_to(&response, "/login");
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn changing_password_revokes_other_sessions() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    app.login().await;
+    let other_device = login_from_another_device(&app).await;
+    let new_password = Uuid::new_v4().to_string();
+
+    // Act
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": &app.test_user.password,
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/password");
+    let response = get_admin_dashboard(&app, &other_device).await;
+    assert_is_redirect_to(&response, "/login");
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn sessions_idle_past_their_ttl_are_not_listed_and_log_the_device_out() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    app.login().await;
+    let other_device = login_from_another_device(&app).await;
+    sqlx::query!(
+        "UPDATE user_sessions SET last_seen_at = now() - interval '2 days' WHERE user_agent = 'another-device'"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let html_page = app.get_sessions_html().await;
+
+    // Assert
+    assert!(!html_page.contains("another-device"));
+    let response = get_admin_dashboard(&app, &other_device).await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn expired_and_revoked_session_records_are_cleaned_up_periodically() {
+    // Arrange
+    let app = AppBootstrap::with_config(|c| c.session.cleanup_interval_in_secs = 1).await;
+    app.login().await;
+    login_from_another_device(&app).await;
+    let session_id = session_id_for(&app, "another-device").await;
+    app.post_revoke_session(session_id).await;
+    sqlx::query!(
+        "INSERT INTO user_sessions (id, user_id, created_at, last_seen_at) \
+        VALUES ($1, $2, now() - interval '3 days', now() - interval '2 days')",
+        Uuid::new_v4(),
+        app.test_user.user_id,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+
+    // Assert
+    let remaining_sessions = sqlx::query!("SELECT COUNT(*) AS count FROM user_sessions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    // Only the current session is left
+    assert_eq!(remaining_sessions, Some(1));
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+}
@@ -4,6 +4,7 @@ use wiremock::{
 };
 
 use crate::helpers::AppBootstrap;
+use zero2prod::configuration::MxLookup;
 
 #[tokio::test]
 async fn subscribe_returns_a_200_for_valid_form_data() {
@@ -133,7 +134,7 @@ async fn subscribe_sends_a_confirmation_email_with_a_link() {
     // Assert
     // Get the first intercepted request
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
-    let confirmation_links = app.get_confirmation_links(&email_request);
+    let confirmation_links = app.get_confirmation_links(email_request);
 
     assert_eq!(confirmation_links.html, confirmation_links.plain_text)
 }
@@ -153,3 +154,103 @@ async fn subscribe_fails_if_there_is_a_fatal_database_error() {
     // Assert
     assert_eq!(response.status().as_u16(), 500);
 }
+
+#[tokio::test]
+async fn subscribe_normalizes_the_email_address() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    let body = "name=le%20guin&email=%20Ursula_Le_Guin%40GMail.COM%20";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_subscriptions(body.into()).await;
+
+    // Assert
+    let saved = sqlx::query!("SELECT email FROM subscriptions",)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.email, "Ursula_Le_Guin@gmail.com");
+}
+
+#[tokio::test]
+async fn email_addresses_differing_only_by_case_are_the_same_subscriber() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    app.post_subscriptions("name=le%20guin&email=Ursula_Le_Guin%40gmail.com".into())
+        .await;
+
+    // Assert
+    let saved = sqlx::query!("SELECT email FROM subscriptions",)
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscriptions.");
+    assert_eq!(saved.len(), 1);
+}
+
+#[tokio::test]
+async fn subscribe_rejects_disposable_email_providers() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    let body = "name=le%20guin&email=ursula%40mailinator.com";
+
+    // Act
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("disposable email provider"));
+}
+
+#[tokio::test]
+async fn subscribe_rejects_domains_without_a_mail_server() {
+    // Arrange
+    let app = AppBootstrap::with_config(|c| {
+        c.deliverability.mx_lookup = MxLookup::Static;
+        c.deliverability.static_mx_domains = vec!["gmail.com".into()];
+    })
+    .await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let typo = app
+        .post_subscriptions("name=le%20guin&email=ursula%40gmial.com".into())
+        .await;
+    let valid = app
+        .post_subscriptions("name=le%20guin&email=ursula%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(typo.status().as_u16(), 400);
+    assert!(typo
+        .text()
+        .await
+        .unwrap()
+        .contains("gmial.com does not accept email"));
+    assert_eq!(valid.status().as_u16(), 200);
+}
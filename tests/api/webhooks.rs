@@ -0,0 +1,200 @@
+use crate::helpers::{assert_is_redirect_to, AppBootstrap};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::time::Duration;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+/// Poll the outbox often, so that deliveries happen within the test.
+async fn spawn_app(
+    customise: impl FnOnce(&mut zero2prod::configuration::Settings),
+) -> AppBootstrap {
+    AppBootstrap::with_config(|c| {
+        c.webhooks.poll_interval_in_milli = 50;
+        customise(c);
+    })
+    .await
+}
+
+/// Wait for the receiver to get `count` requests.
+async fn wait_for_webhooks(receiver: &MockServer, count: usize) -> Vec<wiremock::Request> {
+    for _ in 0..100 {
+        let requests = receiver.received_requests().await.unwrap();
+        if requests.len() >= count {
+            return requests;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("The webhook receiver did not get {} requests.", count);
+}
+
+async fn subscribe_and_confirm(app: &AppBootstrap) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_webhooks() {
+    // Arrange
+    let app = AppBootstrap::new().await;
+    // Act
+    let response = app
+        .post_create_webhook(&[("url", "https://crm.example.com"), ("event", "issue.sent")])
+        .await;
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn subscriber_events_are_delivered_signed() {
+    // Arrange
+    let app = spawn_app(|_| {}).await;
+    let receiver = MockServer::start().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(204))
+        .mount(&receiver)
+        .await;
+    app.login().await;
+    let secret = app
+        .create_webhook(
+            &receiver.uri(),
+            &["subscriber.created", "subscriber.confirmed"],
+        )
+        .await;
+
+    // Act
+    subscribe_and_confirm(&app).await;
+
+    // Assert
+    let requests = wait_for_webhooks(&receiver, 2).await;
+    let mut event_types = Vec::new();
+    for request in &requests {
+        let timestamp = request.headers.get(&"X-Webhook-Timestamp".into()).unwrap()[0].as_str();
+        let signature = request.headers.get(&"X-Webhook-Signature".into()).unwrap()[0].as_str();
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(format!("{}.", timestamp).as_bytes());
+        mac.update(&request.body);
+        mac.verify_slice(&hex::decode(signature.strip_prefix("sha256=").unwrap()).unwrap())
+            .expect("Invalid webhook signature.");
+
+        let event: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(
+            request.headers.get(&"X-Webhook-Id".into()).unwrap()[0].as_str(),
+            event["id"]
+        );
+        assert_eq!(event["data"]["email"], "ursula_le_guin@gmail.com");
+        event_types.push(event["type"].as_str().unwrap().to_owned());
+    }
+    event_types.sort();
+    assert_eq!(event_types, ["subscriber.confirmed", "subscriber.created"]);
+}
+
+#[tokio::test]
+async fn endpoints_only_receive_the_events_they_subscribed_to() {
+    // Arrange
+    let app = spawn_app(|_| {}).await;
+    app.login().await;
+    app.create_webhook("https://crm.example.com/hooks", &["issue.sent"])
+        .await;
+
+    // Act
+    subscribe_and_confirm(&app).await;
+
+    // Assert
+    let deliveries = sqlx::query!("SELECT count(*) AS \"count!\" FROM webhook_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(deliveries.count, 0);
+}
+
+#[tokio::test]
+async fn failed_deliveries_are_retried_then_given_up() {
+    // Arrange
+    let app = spawn_app(|c| {
+        c.webhooks.max_attempts = 2;
+        c.webhooks.initial_backoff_in_secs = 0;
+    })
+    .await;
+    let receiver = MockServer::start().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(500))
+        .expect(2)
+        .mount(&receiver)
+        .await;
+    app.login().await;
+    app.create_webhook(&receiver.uri(), &["subscriber.created"])
+        .await;
+
+    // Act
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    wait_for_webhooks(&receiver, 2).await;
+    // Leave time to record the last attempt, and to retry once more if it were wrongly allowed.
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    // Assert
+    let delivery = sqlx::query!("SELECT status, attempts FROM webhook_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(delivery.status, "failed");
+    assert_eq!(delivery.attempts, 2);
+
+    let endpoint = sqlx::query!("SELECT id FROM webhook_endpoints")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    let html_page = app
+        .api_client
+        .get(format!("{}/admin/webhooks/{}", &app.address, endpoint.id))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert_eq!(html_page.matches("<td>500</td>").count(), 2);
+    assert!(html_page.contains("0 pending, 1 failed"));
+}
+
+#[tokio::test]
+async fn events_are_not_recorded_when_the_change_is_rolled_back() {
+    // Arrange
+    let app = spawn_app(|_| {}).await;
+    app.login().await;
+    app.create_webhook("https://crm.example.com/hooks", &["subscriber.created"])
+        .await;
+    // Sabotage the database: storing the confirmation token fails after the subscriber is inserted.
+    sqlx::query!("ALTER TABLE subscription_tokens DROP COLUMN subscription_token;",)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 500);
+    let events = sqlx::query!("SELECT count(*) AS \"count!\" FROM webhook_events")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(events.count, 0);
+}